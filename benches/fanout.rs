@@ -0,0 +1,189 @@
+//! Throughput and fan-out latency of the broadcaster, as the number of consumers grows.
+//!
+//! A synthetic remote writes fixed size records, stamped with the time they were written, as fast
+//! as the broadcaster takes them, and every consumer reads them all back over loopback TCP.
+//! Prints one JSON object per line and number of consumers, so runs can be tracked over time:
+//!
+//! ```text
+//! cargo bench --bench fanout            # 1, 10, 100 and 1000 consumers, then 10 and a stalled one
+//! cargo bench --bench fanout -- 1 50    # just these
+//! cargo bench --bench fanout -- 10+1    # 10 consumers along with one that never reads
+//! ```
+//!
+//! Stalled consumers show that the remote is read, and the others served, regardless: their
+//! latency stays that of a run without them.
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use udp_tcp_spmc_broadcast::{Broadcaster, DropPolicy};
+
+/// Size of each record, the first 8 bytes hold when it was written.
+const RECORD_SIZE: usize = 1024;
+
+/// Records written on each run, every consumer gets all of them.
+const RECORDS: usize = 1000;
+
+/// Consumers reading, and stalled ones, of each run.
+const DEFAULT_RUNS: [(usize, usize); 5] = [(1, 0), (10, 0), (100, 0), (1000, 0), (10, 1)];
+
+/// Time consumers get to connect before the remote starts writing.
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// Shared origin of the timestamps in the records.
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+fn now_nanos() -> u64 {
+    epoch().elapsed().as_nanos() as u64
+}
+
+/// A port nothing listens on right now.
+async fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// What a single consumer measured.
+struct Received {
+    records: usize,
+    latencies: Vec<u64>,
+    last_at: u64,
+}
+
+async fn consume(stream: TcpStream) -> Received {
+    let mut stream = BufReader::with_capacity(64 * 1024, stream);
+    let mut record = [0u8; RECORD_SIZE];
+    let mut latencies = Vec::with_capacity(RECORDS);
+    let mut last_at = 0;
+
+    while stream.read_exact(&mut record).await.is_ok() {
+        last_at = now_nanos();
+        let sent_at = u64::from_le_bytes(record[..8].try_into().unwrap());
+        latencies.push(last_at.saturating_sub(sent_at));
+    }
+
+    Received {
+        records: latencies.len(),
+        latencies,
+        last_at,
+    }
+}
+
+fn percentile(sorted: &[u64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((p / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank] as f64 / 1000.0
+}
+
+async fn run(clients: usize, stalled: usize) -> String {
+    let remote = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let remote_addr = remote.local_addr().unwrap().to_string();
+    let local_addr = format!("127.0.0.1:{}", free_port().await);
+
+    // nobody gets dropped, so every consumer gets every record
+    let broadcaster = Broadcaster::builder()
+        .local(&local_addr)
+        .remote(&remote_addr)
+        .reconnect(false)
+        .max_clients(clients + stalled + 1)
+        .broadcast_capacity(RECORDS * 2)
+        .client_queue_size(RECORDS * 2)
+        .drop_policy(DropPolicy::Oldest)
+        .write_timeout(Duration::from_secs(60))
+        .shutdown_grace(Duration::from_secs(60))
+        .build()
+        .unwrap();
+    let running = tokio::spawn(broadcaster.run(CancellationToken::new()));
+
+    let (mut source, _) = remote.accept().await.unwrap();
+
+    // connected and never read from
+    let mut stalled_streams = Vec::with_capacity(stalled);
+    for _ in 0..stalled {
+        stalled_streams.push(TcpStream::connect(&local_addr).await.unwrap());
+    }
+
+    let mut consumers = Vec::with_capacity(clients);
+    for _ in 0..clients {
+        let stream = TcpStream::connect(&local_addr).await.unwrap();
+        consumers.push(tokio::spawn(consume(stream)));
+    }
+    tokio::time::sleep(SETTLE).await;
+
+    let started_at = now_nanos();
+    let mut record = [0u8; RECORD_SIZE];
+    for _ in 0..RECORDS {
+        record[..8].copy_from_slice(&now_nanos().to_le_bytes());
+        source.write_all(&record).await.unwrap();
+    }
+    // the broadcaster ends with the remote, and closes the consumers once they got everything
+    drop(source);
+
+    let mut delivered = 0;
+    let mut finished_at = started_at;
+    let mut latencies = Vec::with_capacity(clients * RECORDS);
+    for consumer in consumers {
+        let received = consumer.await.unwrap();
+        delivered += received.records;
+        finished_at = finished_at.max(received.last_at);
+        latencies.extend(received.latencies);
+    }
+    // so the broadcaster does not wait for them to drain
+    drop(stalled_streams);
+    running.await.unwrap().unwrap();
+
+    latencies.sort_unstable();
+    let elapsed = (finished_at - started_at) as f64 / 1e9;
+    let throughput = (delivered * RECORD_SIZE) as f64 / elapsed;
+
+    format!(
+        concat!(
+            r#"{{"bench":"fanout","clients":{},"stalled_clients":{},"record_bytes":{},"records":{},"#,
+            r#""delivered_records":{},"elapsed_secs":{:.6},"throughput_bytes_per_sec":{:.0},"#,
+            r#""latency_us":{{"p50":{:.1},"p90":{:.1},"p99":{:.1},"max":{:.1}}}}}"#
+        ),
+        clients,
+        stalled,
+        RECORD_SIZE,
+        RECORDS,
+        delivered,
+        elapsed,
+        throughput,
+        percentile(&latencies, 50.0),
+        percentile(&latencies, 90.0),
+        percentile(&latencies, 99.0),
+        percentile(&latencies, 100.0),
+    )
+}
+
+#[tokio::main]
+async fn main() {
+    epoch();
+
+    // cargo passes --bench, anything else is a number of consumers, plus stalled ones
+    let runs: Vec<(usize, usize)> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .map(|arg| {
+            let (clients, stalled) = arg.split_once('+').unwrap_or((&arg, "0"));
+            let count = |n: &str| n.parse().expect("number of consumers");
+            (count(clients), count(stalled))
+        })
+        .collect();
+    let runs = if runs.is_empty() {
+        DEFAULT_RUNS.to_vec()
+    } else {
+        runs
+    };
+
+    for (clients, stalled) in runs {
+        println!("{}", run(clients, stalled).await);
+    }
+}
@@ -0,0 +1,29 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// Rules on which peers can connect as consumers, by IP network.
+///
+/// Deny rules take precedence over allow rules, and an empty allowlist allows everyone not denied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessList {
+    /// networks allowed to connect, everyone if empty
+    pub allow: Vec<IpNet>,
+    /// networks never allowed to connect
+    pub deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    /// Whether a peer with the given IP can connect.
+    ///
+    /// IPv4 peers connecting to a dual-stack listener show up as IPv4-mapped IPv6 addresses, those
+    /// are checked against the IPv4 rules.
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
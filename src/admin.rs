@@ -0,0 +1,176 @@
+use crate::{resolve, Hub, Remote};
+use std::fmt::Write as _;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, instrument, warn};
+
+/// Longest line taken as an admin command.
+const MAX_COMMAND: u64 = 1024;
+
+/// Time an admin session can go without sending a command.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Serves the admin commands over a line based protocol, see [`run_command`].
+#[instrument(skip_all)]
+pub(crate) async fn serve_admin(listener: TcpListener, hub: Hub) {
+    while let Ok((stream, addr)) = listener.accept().await {
+        let hub = hub.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_session(stream, &hub).await {
+                warn!("serving admin commands to {addr}: {e}");
+            }
+        });
+    }
+}
+
+/// Runs the commands of a session, dropping it once a line is longer than [`MAX_COMMAND`] or none
+/// came for [`IDLE_TIMEOUT`].
+async fn handle_session(stream: TcpStream, hub: &Hub) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let mut line = String::new();
+        let mut limited = (&mut reader).take(MAX_COMMAND);
+        let read = tokio::time::timeout(IDLE_TIMEOUT, limited.read_line(&mut line))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "idle for too long"))??;
+
+        if read == 0 {
+            return Ok(());
+        }
+        if read as u64 == MAX_COMMAND && !line.ends_with('\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("command longer than {MAX_COMMAND} bytes"),
+            ));
+        }
+
+        debug!("admin command {:?}", line.trim_end());
+
+        let reply = run_command(&line, hub).await;
+        writer.write_all(reply.as_bytes()).await?;
+    }
+}
+
+/// Runs one admin command, the reply is a line per item followed by an empty line.
+///
+/// - `list`: the connected consumers, with their address, bytes sent and uptime
+/// - `kick <addr>`: disconnects the consumer at `addr`
+/// - `stats`: the global counters
+/// - `pause`: stops relaying to the consumers, they stay connected
+/// - `resume`: relays to the consumers again
+/// - `remote set <host:port>`: pulls from the TCP remote at `host:port` instead, the consumers
+///   stay connected
+pub(crate) async fn run_command(line: &str, hub: &Hub) -> String {
+    let mut out = String::new();
+    let mut words = line.split_whitespace();
+
+    match (words.next(), words.next()) {
+        (Some("list"), None) => {
+            for client in hub.connected() {
+                let _ = writeln!(
+                    out,
+                    "{} bytes_sent={} uptime={:.1}s",
+                    client.addr,
+                    client.bytes_sent,
+                    client.connected_for.as_secs_f64()
+                );
+            }
+        }
+        (Some("kick"), Some(addr)) => match addr.parse::<SocketAddr>() {
+            Ok(addr) => match hub.kick(addr) {
+                0 => {
+                    let _ = writeln!(out, "error: no client at {addr}");
+                }
+                kicked => {
+                    info!("kicked {addr} out");
+                    let _ = writeln!(out, "kicked {kicked} client(s) at {addr}");
+                }
+            },
+            Err(e) => {
+                let _ = writeln!(out, "error: invalid address {addr:?}: {e}");
+            }
+        },
+        (Some("stats"), None) => {
+            let metrics = hub.metrics();
+            let stats = [
+                ("clients_connected", metrics.clients_connected() as u64),
+                ("bytes_received", metrics.bytes_received()),
+                ("bytes_sent", metrics.bytes_sent()),
+                ("clients_dropped", metrics.clients_dropped()),
+                ("remote_reconnects", metrics.remote_reconnects()),
+                ("chunks_filtered", metrics.chunks_filtered()),
+                ("paused", hub.is_paused().into()),
+            ];
+
+            for (name, value) in stats {
+                let _ = writeln!(out, "{name} {value}");
+            }
+        }
+        (Some("pause"), None) => match hub.pause() {
+            true => {
+                info!("paused");
+                let _ = writeln!(out, "paused");
+            }
+            false => {
+                let _ = writeln!(out, "error: already paused");
+            }
+        },
+        (Some("resume"), None) => match hub.resume() {
+            true => {
+                info!("resumed");
+                let _ = writeln!(out, "resumed");
+            }
+            false => {
+                let _ = writeln!(out, "error: not paused");
+            }
+        },
+        (Some("remote"), Some("set")) => match words.next() {
+            Some(remote) => match checked_remote(remote).await {
+                Ok(remote) => {
+                    info!("switching to remote {remote}");
+                    let _ = writeln!(out, "switching to remote {remote}");
+                    hub.swap_remote(remote);
+                }
+                Err(e) => {
+                    let _ = writeln!(out, "error: invalid remote {remote:?}: {e}");
+                }
+            },
+            None => {
+                let _ = writeln!(
+                    out,
+                    "error: missing remote, expected remote set <host:port>"
+                );
+            }
+        },
+        _ => {
+            let _ = writeln!(
+                out,
+                "error: unknown command {:?}, expected list, kick <addr>, stats, pause, resume or \
+                 remote set <host:port>",
+                line.trim()
+            );
+        }
+    }
+
+    out.push('\n');
+    out
+}
+
+/// Parses the TCP remote to switch to, checking it resolves so a typo does not get the current one
+/// dropped for nothing. Remotes of any other protocol are refused, the admin socket is not
+/// authenticated and must not get local files or other sources broadcast.
+async fn checked_remote(remote: &str) -> Result<Remote, String> {
+    if let Some((proto, _)) = remote.split_once("://") {
+        return Err(format!(
+            "unsupported protocol {proto}, expected a TCP host:port"
+        ));
+    }
+
+    resolve(remote).await.map_err(|e| e.to_string())?;
+    Ok(Remote::Tcp(remote.to_string()))
+}
@@ -0,0 +1,72 @@
+use crate::timed_out;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Shared secret TCP consumers have to send, followed by a newline, before they get any data.
+#[derive(Debug, Clone)]
+pub(crate) struct Auth {
+    token: Arc<[u8]>,
+    timeout: Duration,
+}
+
+impl Auth {
+    /// Expects `token` within `timeout` of the consumer connecting.
+    pub(crate) fn new(token: &str, timeout: Duration) -> Self {
+        Self {
+            token: token.as_bytes().into(),
+            timeout,
+        }
+    }
+
+    /// Reads the token line from the consumer, failing if it is wrong or late.
+    ///
+    /// The stream is read a byte at a time, so nothing the consumer sends after the newline is
+    /// taken, and at most the length of the token plus a `\r`.
+    pub(crate) async fn check<S: AsyncRead + Unpin>(&self, stream: &mut S) -> io::Result<()> {
+        let line = tokio::time::timeout(self.timeout, read_line(stream, self.token.len() + 1))
+            .await
+            .map_err(|_| timed_out("authentication", self.timeout))??
+            .ok_or_else(|| Error::new(ErrorKind::PermissionDenied, "token too long"))?;
+
+        if constant_time_eq(&line, &self.token) {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::PermissionDenied, "wrong token"))
+        }
+    }
+}
+
+/// Reads up to a newline, without it and a `\r` before it, none if it goes on past `max` bytes.
+pub(crate) async fn read_line<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::with_capacity(max);
+
+    loop {
+        match stream.read_u8().await? {
+            b'\n' => break,
+            _ if line.len() == max => return Ok(None),
+            byte => line.push(byte),
+        }
+    }
+
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    Ok(Some(line))
+}
+
+/// Compares in a time that depends on the lengths only, not on where the first difference is, so
+/// the token can not be guessed byte by byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let difference = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(difference) == 0
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+/// Exponential backoff policy used when (re)connecting to the remote host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    /// delay before the second attempt
+    pub initial: Duration,
+    /// upper bound for any delay
+    pub max: Duration,
+    /// growth factor applied to the delay after each failed attempt
+    pub multiplier: f64,
+    /// number of attempts before giving up, `None` retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// Whether another attempt is allowed after the given failed attempt (1-based).
+    pub fn retries_after(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt < max)
+    }
+
+    /// Delay to wait after the given failed attempt (1-based), capped at `max`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let secs = self.initial.as_secs_f64() * self.multiplier.powi(exponent);
+
+        if secs.is_finite() && secs < self.max.as_secs_f64() {
+            Duration::from_secs_f64(secs)
+        } else {
+            self.max
+        }
+    }
+}
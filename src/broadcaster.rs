@@ -0,0 +1,730 @@
+use crate::capture::Capture;
+use crate::event::Events;
+use crate::filter::SharedFilter;
+#[cfg(unix)]
+use crate::handover::{take_over, Adopting, Handover, Successor};
+#[cfg(unix)]
+use crate::net::UnixSocket;
+use crate::producer::{check_remotes, produce, Injected};
+use crate::tee::Tee;
+use crate::tls::local_acceptor;
+use crate::transform::SharedTransform;
+use crate::{
+    bind_listener, bind_udp, bind_with_backoff, log_stats, resolve, serve_admin, serve_metrics,
+    tx_to_datagrams, tx_to_streams, tx_to_websockets, BroadcastError, BroadcasterBuilder, Config,
+    Distribution, Event, Filter, Framing, Hub, ListenOptions, LocalProto, Metrics, NoClientsPolicy,
+    OutputFormat, Remote, ReverseDns, ReverseResolver, Transform,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::AsyncRead;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument};
+
+/// Broadcasts data from the remotes to multiple TCP consumers, or UDP targets.
+#[derive(Debug, Clone)]
+pub struct Broadcaster {
+    config: Config,
+    transform: SharedTransform,
+    filter: SharedFilter,
+    reverse_dns: ReverseDns,
+    /// what is read instead of the remotes, if anything
+    source: Option<Injected>,
+    events: Events,
+    metrics: Arc<Metrics>,
+    handover: Arc<Notify>,
+}
+
+impl Broadcaster {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            transform: SharedTransform::default(),
+            filter: SharedFilter::default(),
+            reverse_dns: ReverseDns::default(),
+            source: None,
+            events: Events::default(),
+            metrics: Arc::default(),
+            handover: Arc::default(),
+        }
+    }
+
+    /// Reads the data to broadcast from `source`, like a stream already connected or one in memory,
+    /// instead of from the remotes of `config`, which are left out.
+    ///
+    /// The source is read once, till it ends, as a file remote is, and the broadcaster then stops.
+    /// It is read by the first run only, of this broadcaster or any clone of it.
+    pub fn from_source<S: AsyncRead + Send + Unpin + 'static>(
+        source: S,
+        mut config: Config,
+    ) -> Self {
+        config.remotes.clear();
+
+        Self {
+            source: Some(Injected::new(source)),
+            ..Self::new(config)
+        }
+    }
+
+    /// Applies `transform` to every chunk before it is broadcast.
+    pub fn with_transform(mut self, transform: impl Transform + 'static) -> Self {
+        self.transform = SharedTransform::new(transform);
+        self
+    }
+
+    /// Broadcasts only the chunks `filter` keeps, before they get transformed.
+    pub fn with_filter(mut self, filter: impl Filter + 'static) -> Self {
+        self.filter = SharedFilter::new(filter);
+        self
+    }
+
+    /// Looks the names of consumers up with `resolver` instead of the system, when
+    /// [`Config::reverse_dns`] is set.
+    pub fn with_reverse_resolver(mut self, resolver: impl ReverseResolver + 'static) -> Self {
+        self.reverse_dns = ReverseDns::new(resolver);
+        self
+    }
+
+    pub(crate) fn with_shared(
+        mut self,
+        transform: SharedTransform,
+        filter: SharedFilter,
+        reverse_dns: ReverseDns,
+    ) -> Self {
+        self.transform = transform;
+        self.filter = filter;
+        self.reverse_dns = reverse_dns;
+        self
+    }
+
+    /// Receives what happens to the broadcast from now on: consumers and remotes coming and going,
+    /// and the chunks going out. Clones of the broadcaster report to the same receivers.
+    ///
+    /// Events never hold the broadcast up, a receiver falling more than a thousand events behind
+    /// misses the oldest ones.
+    pub fn events(&self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Counters and gauges of the broadcast while it runs, the same the metrics server renders.
+    /// Clones of the broadcaster share them.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Number of consumers currently connected, on any listener.
+    pub fn client_count(&self) -> usize {
+        self.metrics.clients_connected()
+    }
+
+    /// Bytes read from the remotes so far, before they are filtered or transformed.
+    pub fn bytes_received(&self) -> u64 {
+        self.metrics.bytes_received()
+    }
+
+    /// Bytes fully written to consumers so far.
+    pub fn bytes_sent(&self) -> u64 {
+        self.metrics.bytes_sent()
+    }
+
+    /// Whether a remote is being pulled from right now.
+    pub fn remote_connected(&self) -> bool {
+        self.metrics.remotes_connected() > 0
+    }
+
+    /// Hands the listener and the consumers over to the successor waiting at the handover socket,
+    /// then stops as if cancelled, leaving them connected. Only warns when no successor is waiting,
+    /// and does nothing without a handover socket. Clones of the broadcaster hand over the same.
+    ///
+    /// The remotes are let go of first, the consumers then get what they have pending, and the
+    /// successor takes over from there, pulling from the remotes again. New consumers wait in the
+    /// listener backlog meanwhile.
+    pub fn hand_over(&self) {
+        self.handover.notify_one();
+    }
+
+    pub fn builder() -> BroadcasterBuilder {
+        BroadcasterBuilder::default()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Checks the broadcast could be served as configured, without serving it: the certificates
+    /// load, every local address resolves and can be bound, the UDP targets resolve, and every
+    /// remote can be opened, with a single attempt each.
+    ///
+    /// Everything bound or opened is closed again before it returns. A Unix socket already in
+    /// place is left alone, it would be replaced when serving.
+    #[instrument(skip_all)]
+    pub async fn check(&self) -> Result<(), BroadcastError> {
+        let config = &self.config;
+
+        check_self_connect(config).await?;
+
+        config.local_tls.as_ref().map(local_acceptor).transpose()?;
+        for extra in &config.listeners {
+            extra.tls.as_ref().map(local_acceptor).transpose()?;
+        }
+
+        let options = ListenOptions::from(config);
+        // kept bound until the end, so the same address given twice fails too
+        let mut listeners = Vec::new();
+
+        match config.local_proto {
+            LocalProto::Tcp => {
+                if !check_elsewhere(config)? {
+                    listeners.push(bind_listener(&config.local, options).await?);
+                }
+            }
+            LocalProto::Udp => {
+                let _socket = bind_udp(&config.local).await?;
+                for target in &config.udp_targets {
+                    resolve(target).await?;
+                }
+            }
+        }
+
+        let addresses = config
+            .listeners
+            .iter()
+            .map(|extra| &extra.address)
+            .chain(&config.ws_addr)
+            .chain(&config.sse_addr)
+            .chain(&config.metrics_addr)
+            .chain(&config.admin_addr);
+        for address in addresses {
+            listeners.push(bind_listener(address, options).await?);
+        }
+        info!("{} local addresses can be bound", listeners.len());
+        drop(listeners);
+
+        check_remotes(config).await
+    }
+
+    /// Binds the local listener, or socket for UDP, and relays data from the remotes to every
+    /// connected consumer, until `cancel` is triggered or the remotes are done.
+    #[instrument(skip_all, fields(local = %self.config.local, remotes = ?self.config.remotes))]
+    pub async fn run(self, cancel: CancellationToken) -> Result<(), BroadcastError> {
+        let config = self.config;
+
+        // before anything is bound or connected to
+        check_self_connect(&config).await?;
+
+        // create the hub to share data between streams
+        let mut hub = Hub::new(config.broadcast_capacity, config.replay_bytes)
+            .with_transform(self.transform)
+            .with_filter(self.filter)
+            .with_events(self.events.clone())
+            .with_metrics(self.metrics.clone())
+            .with_pause_mode(config.pause_mode)
+            .with_min_clients(match config.no_clients_policy {
+                NoClientsPolicy::PauseRead => config.min_clients.max(1),
+                NoClientsPolicy::Discard | NoClientsPolicy::Buffer => config.min_clients,
+            });
+
+        if let Some(bytes_per_sec) = config.max_bandwidth {
+            hub = hub.with_rate_limit(bytes_per_sec);
+        }
+
+        if let Some(stagger) = config.replay_stagger {
+            hub = hub.with_replay_stagger(stagger);
+        }
+
+        if config.reverse_dns {
+            hub = hub.with_reverse_dns(self.reverse_dns);
+        }
+
+        if let Some(bytes) = config.max_bytes {
+            hub = hub.with_max_bytes(bytes);
+        }
+
+        if config.topics {
+            hub = hub.with_topics(config.framing);
+        }
+
+        if config.inject_delay.is_some() || config.inject_jitter.is_some() {
+            hub = hub.with_injected_delay(
+                config.inject_delay.unwrap_or_default(),
+                config.inject_jitter.unwrap_or_default(),
+            );
+        }
+
+        if let Some(window) = config.dedup_window {
+            hub = hub.with_dedup(window);
+        }
+
+        if config.framing != Framing::Raw {
+            hub = hub.with_whole_chunks();
+        }
+
+        if config.no_clients_policy == NoClientsPolicy::Buffer {
+            hub = hub.with_no_clients_buffer(config.no_clients_buffer);
+        }
+
+        if config.distribution == Distribution::RoundRobin {
+            hub = hub.with_round_robin(config.broadcast_capacity);
+        }
+
+        if config.bidirectional {
+            hub = hub.with_upstream();
+        }
+
+        if let Some(checksum) = config.checksum {
+            hub = hub.with_checksum(checksum);
+        }
+
+        if config.output_format == OutputFormat::Json {
+            hub = hub.with_json_records(config.continue_sequence);
+        }
+
+        if config.seq_header {
+            hub = hub.with_seq_header();
+        }
+
+        // the capture file is opened before anything gets published, so it has every chunk
+        let capture = match &config.capture_file {
+            Some(path) => {
+                let (capture, recorder) = Capture::open(path, config.capture_format).await?;
+                info!("recording the broadcast to {}", path.display());
+                hub = hub.with_capture(recorder);
+                Some(capture)
+            }
+            None => None,
+        };
+
+        // consumers are stopped separately, once the producer is, so they can drain whatever made
+        // it into the hub, including what the producer flushes on its way out
+        let shutdown = CancellationToken::new();
+        let _stop_on_drop = shutdown.clone().drop_guard();
+
+        // where successors wait, once consumers are accepted
+        let mut handover = None;
+
+        let mut consumers: Pin<Box<dyn Future<Output = ()> + Send + '_>> = match config.local_proto
+        {
+            LocalProto::Tcp => {
+                // TLS for consumers is set up before anyone can connect
+                let tls = config.local_tls.as_ref().map(local_acceptor).transpose()?;
+
+                match local_uds(&config)? {
+                    Some(listener) => {
+                        hub.metrics().listening();
+
+                        Box::pin(tx_to_streams(
+                            listener,
+                            hub.clone(),
+                            &config,
+                            tls,
+                            shutdown.clone(),
+                        ))
+                    }
+                    None => {
+                        // the listener and consumers of the previous broadcaster, if taking over
+                        #[cfg(unix)]
+                        let (taken, adopted) = match &config.handover_socket {
+                            Some(path) => {
+                                let handed_over = tokio::select! {
+                                    _ = cancel.cancelled() => return Ok(()),
+                                    handed_over = take_over(path) => handed_over?,
+                                };
+                                match handed_over {
+                                    Some(handed_over) => {
+                                        (Some(handed_over.listener), handed_over.clients)
+                                    }
+                                    None => (None, Vec::new()),
+                                }
+                            }
+                            None => (None, Vec::new()),
+                        };
+                        #[cfg(not(unix))]
+                        let taken = None;
+
+                        // setup local TCP listener, unless taken over or systemd passed one
+                        let listener = match taken {
+                            Some(listener) => listener,
+                            None => match systemd_listener(&config)? {
+                                Some(listener) => listener,
+                                None => match bind(&config.local, &config, &cancel).await? {
+                                    Some(listener) => listener,
+                                    None => return Ok(()),
+                                },
+                            },
+                        };
+                        let addr = listener.local_addr()?;
+                        info!("listening for consumers on {addr}");
+                        self.events.emit(Event::Listening { addr });
+                        hub.metrics().listening();
+
+                        // consumers are kept to hand them over in turn
+                        #[cfg(unix)]
+                        let listener =
+                            Adopting::new(listener, adopted, config.handover_socket.is_some());
+                        #[cfg(unix)]
+                        if let Some(path) = &config.handover_socket {
+                            handover = Some(Handover::bind(path, listener.listener_fd()?)?);
+                        }
+
+                        Box::pin(tx_to_streams(
+                            listener,
+                            hub.clone(),
+                            &config,
+                            tls,
+                            shutdown.clone(),
+                        ))
+                    }
+                }
+            }
+            LocalProto::Udp => {
+                let socket = bind_udp(&config.local).await?;
+                let addr = socket.local_addr()?;
+                info!("sending datagrams from {addr}");
+                self.events.emit(Event::Listening { addr });
+                hub.metrics().listening();
+
+                let mut targets = Vec::with_capacity(config.udp_targets.len());
+                for target in &config.udp_targets {
+                    targets.push(resolve(target).await?[0]);
+                }
+
+                Box::pin(tx_to_datagrams(
+                    socket,
+                    targets,
+                    hub.clone(),
+                    config.buffer_size,
+                    shutdown.clone(),
+                ))
+            }
+        };
+
+        // more TCP listeners share the hub, and with it the limit of consumers, each has its own
+        // TLS and authentication
+        for extra in &config.listeners {
+            let tls = extra.tls.as_ref().map(local_acceptor).transpose()?;
+            let Some(listener) = bind(&extra.address, &config, &cancel).await? else {
+                return Ok(());
+            };
+            let addr = listener.local_addr()?;
+            info!("listening for consumers on {addr}");
+            self.events.emit(Event::Listening { addr });
+
+            let listener_config = Config {
+                auth_token: extra.auth_token.clone(),
+                ..config.clone()
+            };
+            let hub = hub.clone();
+            let shutdown = shutdown.clone();
+            let streams = async move {
+                tx_to_streams(listener, hub, &listener_config, tls, shutdown).await;
+            };
+            consumers = Box::pin(async move {
+                tokio::join!(consumers, streams);
+            });
+        }
+
+        // WebSocket consumers are another sink, stopped and drained along with the others
+        if let Some(address) = &config.ws_addr {
+            let Some(listener) = bind(address, &config, &cancel).await? else {
+                return Ok(());
+            };
+            info!(
+                "listening for websocket consumers on {}",
+                listener.local_addr()?
+            );
+
+            let websockets = tx_to_websockets(listener, hub.clone(), &config, shutdown.clone());
+            consumers = Box::pin(async move {
+                tokio::join!(consumers, websockets);
+            });
+        }
+
+        // so are the Server-Sent Events
+        if let Some(address) = &config.sse_addr {
+            let Some(listener) = bind(address, &config, &cancel).await? else {
+                return Ok(());
+            };
+            info!("serving events on {}", listener.local_addr()?);
+
+            let events = crate::tx_to_sse(listener, hub.clone(), &config, shutdown.clone());
+            consumers = Box::pin(async move {
+                tokio::join!(consumers, events);
+            });
+        }
+
+        // the tee file gets everything the consumers get, and is written until they are done
+        let tee = match &config.tee_file {
+            Some(path) => {
+                let tee = Tee::open(path, &hub).await?;
+                info!("appending the broadcast to {}", path.display());
+                Some(tokio::spawn(tee.run(shutdown.clone())))
+            }
+            None => None,
+        };
+        let capture = capture.map(|capture| tokio::spawn(capture.run(shutdown.clone())));
+        let stdout = config
+            .stdout
+            .then(|| tokio::spawn(Tee::stdout(&hub).run(shutdown.clone())));
+
+        let producer = produce(&config, self.source.as_ref(), hub.clone(), &cancel);
+
+        // metrics are only served when an address is given
+        let metrics = async {
+            match &config.metrics_addr {
+                Some(address) => {
+                    let Some(listener) = bind(address, &config, &cancel).await? else {
+                        return Ok(());
+                    };
+                    info!("serving metrics on {}", listener.local_addr()?);
+                    serve_metrics(listener, hub.clone()).await;
+                    Ok(())
+                }
+                None => std::future::pending().await,
+            }
+        };
+
+        // same for the admin commands
+        let admin = async {
+            match &config.admin_addr {
+                Some(address) => {
+                    let Some(listener) = bind(address, &config, &cancel).await? else {
+                        return Ok(());
+                    };
+                    info!("serving admin commands on {}", listener.local_addr()?);
+                    serve_admin(listener, hub.clone()).await;
+                    Ok(())
+                }
+                None => std::future::pending().await,
+            }
+        };
+
+        // and the stats logged when an interval is given
+        let stats = async {
+            match config.stats_interval {
+                Some(interval) => log_stats(hub.metrics(), interval).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        // a successor taking over ends the broadcast here too
+        let successor = successor(handover, &self.handover, &hub);
+        let mut handing_over = None;
+
+        // wait for any of the tasks to complete
+        let mut consumers_done = false;
+        let result = tokio::select! {
+            _ = cancel.cancelled() => Ok(()),
+            successor = successor => {
+                handing_over = Some(successor);
+                Ok(())
+            }
+            result = producer => result,
+            result = metrics => result,
+            result = admin => result,
+            () = stats => Ok(()),
+            _ = &mut consumers => {
+                consumers_done = true;
+                Ok(())
+            }
+        };
+
+        shutdown.cancel();
+        if !consumers_done {
+            consumers.await;
+        }
+
+        // only once the consumers got what they had pending, so nothing gets written twice
+        if let Some(successor) = handing_over {
+            hand_over(successor).await?;
+        }
+
+        if let Some(tee) = tee {
+            let _ = tee.await;
+        }
+
+        if let Some(capture) = capture {
+            let _ = capture.await;
+        }
+
+        if let Some(stdout) = stdout {
+            let _ = stdout.await;
+        }
+
+        result
+    }
+}
+
+/// Binds a TCP listener, retrying as `config` says, `None` if cancelled meanwhile.
+async fn bind(
+    address: &str,
+    config: &Config,
+    cancel: &CancellationToken,
+) -> Result<Option<TcpListener>, BroadcastError> {
+    let options = ListenOptions::from(config);
+    bind_with_backoff(
+        address,
+        options,
+        &config.backoff,
+        config.bind_retries,
+        cancel,
+    )
+    .await
+}
+
+/// Waits for the successor to hand over to, never without a handover socket.
+#[cfg(unix)]
+async fn successor(handover: Option<Handover>, trigger: &Notify, hub: &Hub) -> Successor {
+    match handover {
+        Some(handover) => handover.successor(trigger, hub).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Hands the listener and the consumers over to `successor`.
+#[cfg(unix)]
+async fn hand_over(successor: Successor) -> Result<(), BroadcastError> {
+    Ok(successor.hand_over().await?)
+}
+
+/// Binds the Unix socket for consumers, when there is one.
+#[cfg(unix)]
+fn local_uds(config: &Config) -> Result<Option<UnixSocket>, BroadcastError> {
+    let Some(path) = &config.local_uds else {
+        return Ok(None);
+    };
+
+    let listener = UnixSocket::bind(path)?;
+    info!("listening for consumers on {}", listener.path().display());
+    Ok(Some(listener))
+}
+
+/// Fails if a TCP remote resolves to an address TCP consumers connect to, a listener bound to
+/// every interface taking any loopback address on its port.
+///
+/// Addresses that do not resolve are left for binding and connecting to report, and remotes
+/// reached through a proxy are not checked, the proxy resolves them.
+async fn check_self_connect(config: &Config) -> Result<(), BroadcastError> {
+    if config.local_proto != LocalProto::Tcp || config.remote_socks5.is_some() {
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    let local = config.local_uds.is_none().then_some(&config.local);
+    #[cfg(not(unix))]
+    let local = Some(&config.local);
+
+    let mut locals = Vec::new();
+    for address in local
+        .into_iter()
+        .chain(config.listeners.iter().map(|extra| &extra.address))
+    {
+        if let Ok(addrs) = resolve(address).await {
+            locals.extend(addrs.into_iter().map(|addr| (address, addr)));
+        }
+    }
+
+    for remote in &config.remotes {
+        // datagrams are received locally, files read
+        if matches!(remote, Remote::Udp(_) | Remote::File(_)) {
+            continue;
+        }
+        let Some(address) = remote.address() else {
+            continue;
+        };
+        let Ok(addrs) = resolve(address).await else {
+            continue;
+        };
+
+        for addr in addrs {
+            let same = locals.iter().find(|&&(_, local)| {
+                local == addr
+                    || local.ip().is_unspecified()
+                        && local.port() == addr.port()
+                        && (addr.ip().is_loopback() || addr.ip().is_unspecified())
+            });
+
+            if let Some((local, _)) = same {
+                return Err(BroadcastError::SelfConnect {
+                    local: local.to_string(),
+                    remote: address.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the Unix socket for consumers, when there is one instead of the local address, returns
+/// whether consumers are accepted elsewhere than on the local address, there or from systemd.
+#[cfg(unix)]
+fn check_elsewhere(config: &Config) -> Result<bool, BroadcastError> {
+    if let Some(path) = &config.local_uds {
+        match path.exists() {
+            true => info!("{} already exists, not binding it", path.display()),
+            false => drop(UnixSocket::bind(path)?),
+        }
+        return Ok(true);
+    }
+
+    if config.systemd_socket {
+        info!(
+            "not binding {}, systemd may pass the listener",
+            config.local
+        );
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Takes the listener for consumers passed by systemd, when asked to and there is one.
+#[cfg(unix)]
+fn systemd_listener(config: &Config) -> Result<Option<TcpListener>, BroadcastError> {
+    if !config.systemd_socket {
+        return Ok(None);
+    }
+
+    let listener = crate::net::systemd_listener()?;
+    match &listener {
+        Some(_) => info!("using the listener passed by systemd"),
+        None => info!("no listener passed by systemd, binding {}", config.local),
+    }
+    Ok(listener)
+}
+
+/// There is no socket activation elsewhere.
+#[cfg(not(unix))]
+fn systemd_listener(_: &Config) -> Result<Option<TcpListener>, BroadcastError> {
+    Ok(None)
+}
+
+/// Consumers are only accepted on the local address elsewhere.
+#[cfg(not(unix))]
+fn check_elsewhere(_: &Config) -> Result<bool, BroadcastError> {
+    Ok(false)
+}
+
+/// There are no successors to hand over to elsewhere.
+#[cfg(not(unix))]
+type Handover = std::convert::Infallible;
+
+#[cfg(not(unix))]
+async fn successor(_: Option<Handover>, _: &Notify, _: &Hub) -> Handover {
+    std::future::pending().await
+}
+
+#[cfg(not(unix))]
+async fn hand_over(successor: Handover) -> Result<(), BroadcastError> {
+    match successor {}
+}
+
+/// There are no Unix sockets to bind elsewhere.
+#[cfg(not(unix))]
+fn local_uds(_: &Config) -> Result<Option<TcpListener>, BroadcastError> {
+    Ok(None)
+}
@@ -0,0 +1,804 @@
+use crate::filter::SharedFilter;
+use crate::transform::SharedTransform;
+use crate::{
+    AcceptOverflow, Backoff, Broadcaster, CaptureFormat, Checksum, Coalesce, Compression, Config,
+    Distribution, DropPolicy, Filter, Framing, Keepalive, LagPolicy, LocalListener, LocalProto,
+    LocalTls, NoClientsPolicy, OutputFormat, PauseMode, Remote, RemoteMode, RemoteProto, RemoteTls,
+    ReverseDns, ReverseResolver, Socks5Proxy, SseEncoding, Transform, MIN_BUFFER_SIZE,
+};
+use ipnet::{AddrParseError, IpNet};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio_util::bytes::Bytes;
+
+/// Reasons a [`BroadcasterBuilder`] can refuse to build a [`Broadcaster`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// a required setting was never provided
+    Missing(&'static str),
+    /// an address is not in `host:port` form
+    InvalidAddress { address: String, reason: String },
+    /// the remote protocol is not supported
+    InvalidRemote(String),
+    /// the read buffer is smaller than [`MIN_BUFFER_SIZE`]
+    BufferTooSmall(usize),
+    /// the framing settings are not supported
+    InvalidFraming(String),
+    /// an access rule is not an IP network in CIDR notation
+    InvalidCidr { cidr: String, reason: String },
+    /// the consumers could not be handed over to a successor as configured
+    InvalidHandover(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Missing(setting) => write!(f, "missing {setting} address"),
+            BuildError::InvalidAddress { address, reason } => {
+                write!(f, "invalid address {address:?}: {reason}")
+            }
+            BuildError::InvalidRemote(reason) => write!(f, "invalid remote: {reason}"),
+            BuildError::InvalidFraming(reason) => write!(f, "invalid framing: {reason}"),
+            BuildError::InvalidHandover(reason) => write!(f, "invalid handover: {reason}"),
+            BuildError::InvalidCidr { cidr, reason } => {
+                write!(f, "invalid network {cidr:?}: {reason}")
+            }
+            BuildError::BufferTooSmall(size) => {
+                write!(
+                    f,
+                    "buffer size {size} is below the minimum of {MIN_BUFFER_SIZE}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Fluent builder for a [`Broadcaster`], defaults match the ones of the command line.
+///
+/// ```
+/// use udp_tcp_spmc_broadcast::Broadcaster;
+///
+/// # fn main() -> Result<(), udp_tcp_spmc_broadcast::BuildError> {
+/// let broadcaster = Broadcaster::builder()
+///     .local("0.0.0.0:8080")
+///     .remote("feed:9092")
+///     .buffer_size(4096)
+///     .build()?;
+///
+/// assert_eq!(broadcaster.config().buffer_size, 4096);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Remotes default to TCP, a `udp://` prefix binds a local UDP socket instead.
+///
+/// ```
+/// use udp_tcp_spmc_broadcast::{Broadcaster, Remote};
+///
+/// let broadcaster = Broadcaster::builder()
+///     .local("localhost:8080")
+///     .remote("udp://0.0.0.0:9092")
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(broadcaster.config().remotes, [Remote::Udp("0.0.0.0:9092".into())]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BroadcasterBuilder {
+    config: Config,
+    remotes: Vec<String>,
+    remote_proto: RemoteProto,
+    allow_cidrs: Vec<String>,
+    deny_cidrs: Vec<String>,
+    transform: SharedTransform,
+    filter: SharedFilter,
+    reverse_dns: ReverseDns,
+}
+
+impl BroadcasterBuilder {
+    /// Local `host:port` for consumers to connect to.
+    pub fn local(mut self, address: impl Into<String>) -> Self {
+        self.config.local = address.into();
+        self
+    }
+
+    /// Accepts TCP consumers on another listener too, can be called several times.
+    pub fn listener(mut self, listener: LocalListener) -> Self {
+        self.config.listeners.push(listener);
+        self
+    }
+
+    pub fn local_proto(mut self, proto: LocalProto) -> Self {
+        self.config.local_proto = proto;
+        self
+    }
+
+    /// Consumer `host:port` to send datagrams to with the UDP protocol, can be called several times.
+    pub fn udp_target(mut self, address: impl Into<String>) -> Self {
+        self.config.udp_targets.push(address.into());
+        self
+    }
+
+    /// Serves consumers over TLS.
+    pub fn local_tls(mut self, tls: LocalTls) -> Self {
+        self.config.local_tls = Some(tls);
+        self
+    }
+
+    /// Path of a Unix socket for consumers to connect to, instead of the local address.
+    #[cfg(unix)]
+    pub fn local_uds(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.local_uds = Some(path.into());
+        self
+    }
+
+    /// Takes the listener for consumers from systemd socket activation when there is one, the
+    /// local address is bound otherwise.
+    #[cfg(unix)]
+    pub fn systemd_socket(mut self, systemd_socket: bool) -> Self {
+        self.config.systemd_socket = systemd_socket;
+        self
+    }
+
+    /// Path of a Unix socket to take the listener and the TCP consumers over from the previous
+    /// broadcaster, when one waits there, and to then wait for the next one at, see
+    /// [`Broadcaster::hand_over`].
+    ///
+    /// Only plain consumers can be handed over, without TLS, token, compression or banner.
+    #[cfg(unix)]
+    pub fn handover_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.handover_socket = Some(path.into());
+        self
+    }
+
+    /// Remote to pull data from, either `host:port` or `protocol://host:port`.
+    ///
+    /// Can be called several times to pull from each of the remotes, see [`Self::remote_mode`].
+    pub fn remote(mut self, remote: impl Into<String>) -> Self {
+        self.remotes.push(remote.into());
+        self
+    }
+
+    /// Protocol of the remotes given without one, TCP by default.
+    pub fn remote_proto(mut self, proto: RemoteProto) -> Self {
+        self.remote_proto = proto;
+        self
+    }
+
+    pub fn remote_mode(mut self, mode: RemoteMode) -> Self {
+        self.config.remote_mode = mode;
+        self
+    }
+
+    /// Sends every chunk to every TCP consumer, or to one of them at a time, see [`Distribution`].
+    pub fn distribution(mut self, distribution: Distribution) -> Self {
+        self.config.distribution = distribution;
+        self
+    }
+
+    /// Wraps the connection to a TCP remote in TLS.
+    pub fn remote_tls(mut self, tls: RemoteTls) -> Self {
+        self.config.remote_tls = Some(tls);
+        self
+    }
+
+    /// Reaches the TCP remotes through a SOCKS5 proxy, which resolves their hosts too.
+    pub fn remote_socks5(mut self, proxy: Socks5Proxy) -> Self {
+        self.config.remote_socks5 = Some(proxy);
+        self
+    }
+
+    /// Local address the connections to the TCP remotes originate from, for multi-homed hosts.
+    pub fn remote_bind_addr(mut self, address: IpAddr) -> Self {
+        self.config.remote_bind_addr = Some(address);
+        self
+    }
+
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.config.buffer_size = buffer_size;
+        self
+    }
+
+    /// Reads the remotes with a buffer as large as their socket receive buffer, see
+    /// [`Config::auto_buffer_size`].
+    pub fn auto_buffer_size(mut self) -> Self {
+        self.config.auto_buffer_size = true;
+        self
+    }
+
+    /// Applies `transform` to every chunk before it is broadcast, see [`Transform`].
+    pub fn transform(mut self, transform: impl Transform + 'static) -> Self {
+        self.transform = SharedTransform::new(transform);
+        self
+    }
+
+    /// Broadcasts only the chunks `filter` keeps, see [`Filter`].
+    pub fn filter(mut self, filter: impl Filter + 'static) -> Self {
+        self.filter = SharedFilter::new(filter);
+        self
+    }
+
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.config.framing = framing;
+        self
+    }
+
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.config.max_message_size = size;
+        self
+    }
+
+    /// Leaves out the frames repeating any of the last `window` ones, needs framing.
+    pub fn dedup_window(mut self, window: usize) -> Self {
+        self.config.dedup_window = Some(window);
+        self
+    }
+
+    /// Batches the frames read for up to `max_delay`, or until there are `max_bytes` of them,
+    /// before they are broadcast as a single chunk.
+    pub fn coalesce(mut self, max_delay: Duration, max_bytes: usize) -> Self {
+        self.config.coalesce = Some(Coalesce {
+            max_delay,
+            max_bytes,
+        });
+        self
+    }
+
+    /// Frames every chunk with a checksum on the way out, see [`Checksum`] for the layout.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.config.checksum = Some(checksum);
+        self
+    }
+
+    /// How chunks are written to the consumers, see [`OutputFormat`] for the JSON records.
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.config.output_format = format;
+        self
+    }
+
+    /// Numbers the JSON records on across remote reconnects, instead of from 0 again.
+    pub fn continue_sequence(mut self, continue_sequence: bool) -> Self {
+        self.config.continue_sequence = continue_sequence;
+        self
+    }
+
+    /// Sends each chunk after an 8 bytes big endian sequence number, so consumers can tell when
+    /// chunks are missing, see [`Config::seq_header`] for the layout.
+    pub fn seq_header(mut self, seq_header: bool) -> Self {
+        self.config.seq_header = seq_header;
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.config.backoff = backoff;
+        self
+    }
+
+    /// Forwards what TCP consumers write to the TCP remote, instead of ignoring it.
+    pub fn bidirectional(mut self, bidirectional: bool) -> Self {
+        self.config.bidirectional = bidirectional;
+        self
+    }
+
+    pub fn max_bandwidth(mut self, bytes_per_sec: u64) -> Self {
+        self.config.max_bandwidth = Some(bytes_per_sec);
+        self
+    }
+
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.config.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Holds each chunk read from the remotes for `delay` before it is broadcast, for testing how
+    /// consumers cope with a laggy feed.
+    pub fn inject_delay(mut self, delay: Duration) -> Self {
+        self.config.inject_delay = Some(delay);
+        self
+    }
+
+    /// Holds each chunk up to `jitter` more, at random, on top of the injected delay, for testing.
+    pub fn inject_jitter(mut self, jitter: Duration) -> Self {
+        self.config.inject_jitter = Some(jitter);
+        self
+    }
+
+    /// Reads file remotes over and over, instead of stopping once they end.
+    pub fn file_loop(mut self, file_loop: bool) -> Self {
+        self.config.file_loop = file_loop;
+        self
+    }
+
+    /// Waits `pace` after each read from a file remote.
+    pub fn file_pace(mut self, pace: Duration) -> Self {
+        self.config.file_pace = pace;
+        self
+    }
+
+    pub fn reconnect(mut self, reconnect: bool) -> Self {
+        self.config.reconnect = reconnect;
+        self
+    }
+
+    /// Handles a TCP remote that sends nothing for `timeout` like one that closed, to catch
+    /// connections that went silent without closing.
+    /// Fails an attempt to connect to a TCP remote after `timeout`, so an unreachable host is
+    /// retried with the backoff soon.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    pub fn remote_read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.remote_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes and re-establishes the connection to a TCP remote every `max_lifetime`, for
+    /// upstreams that degrade over long-lived connections. Consumers stay connected meanwhile.
+    pub fn remote_max_lifetime(mut self, max_lifetime: Duration) -> Self {
+        self.config.remote_max_lifetime = Some(max_lifetime);
+        self
+    }
+
+    /// Sends TCP keepalive probes to a TCP remote, to detect a dead upstream sooner.
+    pub fn remote_keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.config.remote_keepalive = Some(keepalive);
+        self
+    }
+
+    pub fn broadcast_capacity(mut self, capacity: usize) -> Self {
+        self.config.broadcast_capacity = capacity;
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.config.nodelay = nodelay;
+        self
+    }
+
+    pub fn reuseaddr(mut self, reuseaddr: bool) -> Self {
+        self.config.reuseaddr = reuseaddr;
+        self
+    }
+
+    /// Sends TCP keepalive probes to the consumers, to drop the dead ones sooner.
+    pub fn keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.config.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Retries binding the listeners up to `retries` times, waiting as the backoff says.
+    pub fn bind_retries(mut self, retries: u32) -> Self {
+        self.config.bind_retries = retries;
+        self
+    }
+
+    /// Makes IPv6 listeners accept IPv4 peers too, like when bound to `[::]:port`.
+    pub fn dual_stack(mut self, dual_stack: bool) -> Self {
+        self.config.dual_stack = dual_stack;
+        self
+    }
+
+    pub fn write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.config.write_timeout = write_timeout;
+        self
+    }
+
+    pub fn client_queue_size(mut self, size: usize) -> Self {
+        self.config.client_queue_size = size;
+        self
+    }
+
+    pub fn drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.config.drop_policy = policy;
+        self
+    }
+
+    pub fn lag_policy(mut self, policy: LagPolicy) -> Self {
+        self.config.lag_policy = Some(policy);
+        self
+    }
+
+    pub fn client_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.config.client_idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Flags TCP consumers as slow once the bytes queued for them average more than `bytes`, as
+    /// logged and counted in the metrics.
+    pub fn slow_client_threshold(mut self, bytes: usize) -> Self {
+        self.config.slow_client_threshold = Some(bytes);
+        self
+    }
+
+    /// Drops the TCP consumers flagged as slow, instead of only reporting them.
+    pub fn slow_client_disconnect(mut self, disconnect: bool) -> Self {
+        self.config.slow_client_disconnect = disconnect;
+        self
+    }
+
+    /// Compresses the stream sent to each TCP consumer, see [`Compression`] for how.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.config.compression = Some(compression);
+        self
+    }
+
+    pub fn per_client_bandwidth(mut self, bytes_per_sec: u64) -> Self {
+        self.config.per_client_bandwidth = Some(bytes_per_sec);
+        self
+    }
+
+    pub fn per_client_smoothing(mut self, bytes_per_sec: u64) -> Self {
+        self.config.per_client_smoothing = Some(bytes_per_sec);
+        self
+    }
+
+    pub fn shutdown_grace(mut self, shutdown_grace: Duration) -> Self {
+        self.config.shutdown_grace = shutdown_grace;
+        self
+    }
+
+    /// Sends `payload` as a last message to each TCP consumer on shutdown, framed like the rest,
+    /// an empty payload makes a length-prefixed zero-length frame or an empty line.
+    pub fn shutdown_sentinel(mut self, payload: impl Into<Bytes>) -> Self {
+        self.config.shutdown_sentinel = Some(payload.into());
+        self
+    }
+
+    /// Writes `banner` to each TCP consumer as it connects, before anything else it gets, like
+    /// the version or capabilities some protocols expect first.
+    pub fn banner(mut self, banner: impl Into<Bytes>) -> Self {
+        self.config.banner = Some(banner.into());
+        self
+    }
+
+    /// Token TCP consumers have to send, followed by a newline, before they get any data.
+    pub fn auth_token(mut self, token: impl Into<String>) -> Self {
+        self.config.auth_token = Some(token.into());
+        self
+    }
+
+    pub fn auth_timeout(mut self, auth_timeout: Duration) -> Self {
+        self.config.auth_timeout = auth_timeout;
+        self
+    }
+
+    /// Has TCP consumers subscribe to a topic as they connect, sending it in a line, and only get
+    /// the frames whose message starts with it followed by a space.
+    pub fn topics(mut self, topics: bool) -> Self {
+        self.config.topics = topics;
+        self
+    }
+
+    /// Consumers connect through a load balancer that sends a PROXY protocol header first, their
+    /// address is taken from it.
+    pub fn proxy_protocol(mut self, proxy_protocol: bool) -> Self {
+        self.config.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    /// Logs accepted consumers with the name their address resolves back to.
+    pub fn reverse_dns(mut self, reverse_dns: bool) -> Self {
+        self.config.reverse_dns = reverse_dns;
+        self
+    }
+
+    /// Looks the names of consumers up with `resolver` instead of the system, for
+    /// [`reverse_dns`](Self::reverse_dns), see [`ReverseResolver`].
+    pub fn reverse_resolver(mut self, resolver: impl ReverseResolver + 'static) -> Self {
+        self.reverse_dns = ReverseDns::new(resolver);
+        self
+    }
+
+    /// Network allowed to connect as consumers, like `10.0.0.0/8`, can be called several times.
+    ///
+    /// Once any is given, only peers in one of them can connect.
+    pub fn allow_cidr(mut self, cidr: impl Into<String>) -> Self {
+        self.allow_cidrs.push(cidr.into());
+        self
+    }
+
+    /// Network never allowed to connect as consumers, can be called several times.
+    ///
+    /// Takes precedence over [`Self::allow_cidr`].
+    pub fn deny_cidr(mut self, cidr: impl Into<String>) -> Self {
+        self.deny_cidrs.push(cidr.into());
+        self
+    }
+
+    /// Admits at most `accepts_per_sec` new consumers per second on each listener, those over it
+    /// are delayed or closed as `overflow` says.
+    pub fn max_accepts_per_sec(mut self, accepts_per_sec: u64, overflow: AcceptOverflow) -> Self {
+        self.config.max_accepts_per_sec = Some(accepts_per_sec);
+        self.config.accept_overflow = overflow;
+        self
+    }
+
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.config.max_clients = max_clients;
+        self
+    }
+
+    /// Consumers that have to be connected for the remotes to be read, see
+    /// [`Config::min_clients`].
+    pub fn min_clients(mut self, min_clients: usize) -> Self {
+        self.config.min_clients = min_clients;
+        self
+    }
+
+    /// What happens to what the remotes send while no consumer is connected, see
+    /// [`NoClientsPolicy`].
+    pub fn no_clients_policy(mut self, policy: NoClientsPolicy) -> Self {
+        self.config.no_clients_policy = policy;
+        self
+    }
+
+    pub fn no_clients_buffer(mut self, bytes: usize) -> Self {
+        self.config.no_clients_buffer = bytes;
+        self
+    }
+
+    pub fn replay_bytes(mut self, replay_bytes: usize) -> Self {
+        self.config.replay_bytes = replay_bytes;
+        self
+    }
+
+    /// Spreads the replays of consumers connecting at once, each one `stagger` after the previous.
+    pub fn replay_stagger(mut self, stagger: Duration) -> Self {
+        self.config.replay_stagger = Some(stagger);
+        self
+    }
+
+    /// File to append everything broadcast to, for auditing or to capture it.
+    pub fn tee_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.tee_file = Some(path.into());
+        self
+    }
+
+    /// Writes everything broadcast to stdout too, to pipe it into other tools.
+    pub fn stdout(mut self, stdout: bool) -> Self {
+        self.config.stdout = stdout;
+        self
+    }
+
+    /// File to record every chunk broadcast to, with when it was, in `format`, to inspect offline.
+    pub fn capture_file(mut self, path: impl Into<PathBuf>, format: CaptureFormat) -> Self {
+        self.config.capture_file = Some(path.into());
+        self.config.capture_format = format;
+        self
+    }
+
+    /// Local `host:port` to accept WebSocket consumers on, each chunk is sent as a binary message.
+    pub fn ws_addr(mut self, address: impl Into<String>) -> Self {
+        self.config.ws_addr = Some(address.into());
+        self
+    }
+
+    /// Local `host:port` to serve Server-Sent Events on, one `data:` event per chunk.
+    pub fn sse_addr(mut self, address: impl Into<String>) -> Self {
+        self.config.sse_addr = Some(address.into());
+        self
+    }
+
+    /// How chunks are written in the Server-Sent Events, base64 by default.
+    pub fn sse_encoding(mut self, encoding: SseEncoding) -> Self {
+        self.config.sse_encoding = encoding;
+        self
+    }
+
+    /// Local `host:port` to serve Prometheus metrics on, and the health of the broadcast.
+    pub fn metrics_addr(mut self, address: impl Into<String>) -> Self {
+        self.config.metrics_addr = Some(address.into());
+        self
+    }
+
+    /// Logs a summary of the metrics every `interval`.
+    pub fn stats_interval(mut self, interval: Duration) -> Self {
+        self.config.stats_interval = Some(interval);
+        self
+    }
+
+    /// Local `host:port` to serve the admin commands on.
+    pub fn admin_addr(mut self, address: impl Into<String>) -> Self {
+        self.config.admin_addr = Some(address.into());
+        self
+    }
+
+    /// What pausing the broadcast does to the remotes, see [`Hub::pause`](crate::Hub::pause).
+    pub fn pause_mode(mut self, mode: PauseMode) -> Self {
+        self.config.pause_mode = mode;
+        self
+    }
+
+    /// Validates the settings and builds the broadcaster.
+    pub fn build(self) -> Result<Broadcaster, BuildError> {
+        let mut config = self.config;
+
+        // TCP consumers can connect over a Unix socket instead of the local address
+        #[cfg(unix)]
+        let over_uds = config.local_uds.is_some() && config.local_proto == LocalProto::Tcp;
+        #[cfg(not(unix))]
+        let over_uds = false;
+
+        if !over_uds {
+            if config.local.is_empty() {
+                return Err(BuildError::Missing("local"));
+            }
+            validate_address(&config.local)?;
+        }
+
+        for listener in &config.listeners {
+            if config.local_proto != LocalProto::Tcp {
+                return Err(BuildError::InvalidAddress {
+                    address: listener.address.clone(),
+                    reason: "more listeners are only for TCP consumers".to_string(),
+                });
+            }
+            validate_address(&listener.address)?;
+        }
+
+        if config.local_proto == LocalProto::Udp && config.udp_targets.is_empty() {
+            return Err(BuildError::Missing("udp target"));
+        }
+        for target in &config.udp_targets {
+            validate_address(target)?;
+        }
+
+        if self.remotes.is_empty() {
+            return Err(BuildError::Missing("remote"));
+        }
+
+        for remote in self.remotes {
+            let remote = Remote::parse_with(&remote, self.remote_proto)
+                .map_err(BuildError::InvalidRemote)?;
+            if let Some(address) = remote.address() {
+                validate_address(address)?;
+            }
+            config.remotes.push(remote);
+        }
+
+        if let Some(proxy) = &config.remote_socks5 {
+            validate_address(&proxy.address)?;
+        }
+
+        for address in config
+            .ws_addr
+            .iter()
+            .chain(&config.metrics_addr)
+            .chain(&config.admin_addr)
+        {
+            validate_address(address)?;
+        }
+
+        if let Some(address) = &config.sse_addr {
+            validate_address(address)?;
+        }
+
+        config.access.allow = parse_cidrs(&self.allow_cidrs)?;
+        config.access.deny = parse_cidrs(&self.deny_cidrs)?;
+
+        if config.buffer_size < MIN_BUFFER_SIZE {
+            return Err(BuildError::BufferTooSmall(config.buffer_size));
+        }
+
+        if let Framing::LengthPrefixed { width, .. } = config.framing {
+            if ![1, 2, 4, 8].contains(&width) {
+                return Err(BuildError::InvalidFraming(format!(
+                    "length field of {width} bytes, expected 1, 2, 4 or 8"
+                )));
+            }
+        }
+
+        if config.dedup_window.is_some() && config.framing == Framing::Raw {
+            return Err(BuildError::InvalidFraming(
+                "deduplication needs messages framed".to_string(),
+            ));
+        }
+
+        if let Some(sentinel) = &config.shutdown_sentinel {
+            match config.framing {
+                Framing::Raw => {
+                    return Err(BuildError::InvalidFraming(
+                        "a shutdown sentinel needs messages framed".to_string(),
+                    ))
+                }
+                Framing::LengthPrefixed { width, .. }
+                    if width < 8 && sentinel.len() >> (8 * width) != 0 =>
+                {
+                    return Err(BuildError::InvalidFraming(format!(
+                        "shutdown sentinel of {} bytes does not fit a length field of {width}",
+                        sentinel.len()
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        if config.topics {
+            let unsupported = if config.framing == Framing::Raw {
+                Some("topics need messages framed")
+            } else if config.coalesce.is_some() {
+                Some("batches of frames would mix topics")
+            } else if config.checksum.is_some()
+                || config.output_format != OutputFormat::Raw
+                || config.seq_header
+            {
+                Some("frames have to go out starting with their topic")
+            } else if config.distribution == Distribution::RoundRobin {
+                Some("frames dealt to consumers of other topics would be lost")
+            } else {
+                None
+            };
+
+            if let Some(reason) = unsupported {
+                return Err(BuildError::InvalidFraming(reason.to_string()));
+            }
+        }
+
+        #[cfg(unix)]
+        if config.handover_socket.is_some() {
+            let unsupported = if config.local_proto != LocalProto::Tcp || over_uds {
+                Some("only TCP consumers of the local address can be handed over")
+            } else if config.local_tls.is_some() {
+                Some("TLS sessions cannot be handed over")
+            } else if config.auth_token.is_some() {
+                Some("consumers would have to authenticate again")
+            } else if config.topics {
+                Some("consumers would have to subscribe again")
+            } else if config.compression.is_some() {
+                Some("compressed streams cannot be handed over")
+            } else if config.banner.is_some() {
+                Some("consumers would get the banner again")
+            } else if config.proxy_protocol {
+                Some("the PROXY protocol header of consumers taken over is already read")
+            } else {
+                None
+            };
+
+            if let Some(reason) = unsupported {
+                return Err(BuildError::InvalidHandover(reason.to_string()));
+            }
+        }
+
+        Ok(Broadcaster::new(config).with_shared(self.transform, self.filter, self.reverse_dns))
+    }
+}
+
+/// Parses networks in CIDR notation, like `192.168.0.0/16` or `fd00::/8`.
+fn parse_cidrs(cidrs: &[String]) -> Result<Vec<IpNet>, BuildError> {
+    cidrs
+        .iter()
+        .map(|cidr| {
+            cidr.parse()
+                .map_err(|e: AddrParseError| BuildError::InvalidCidr {
+                    cidr: cidr.clone(),
+                    reason: e.to_string(),
+                })
+        })
+        .collect()
+}
+
+/// Checks that `address` looks like `host:port`, the host is resolved later on.
+fn validate_address(address: &str) -> Result<(), BuildError> {
+    let invalid = |reason: &str| BuildError::InvalidAddress {
+        address: address.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or_else(|| invalid("missing port"))?;
+
+    if host.is_empty() {
+        return Err(invalid("empty host"));
+    }
+
+    port.parse::<u16>().map_err(|e| invalid(&e.to_string()))?;
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use crate::{BroadcastError, CaptureFormat};
+use base64::Engine;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tokio_util::bytes::Bytes;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, instrument, warn};
+
+/// How often what was appended to the capture file gets flushed to disk.
+const CAPTURE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Records waiting to be written, past that new ones are dropped.
+const CAPTURE_QUEUE: usize = 4096;
+
+/// Sending side of the capture, taken by the hub, cheap to clone.
+///
+/// Recording never waits: if the file falls more than a few thousand chunks behind, the new
+/// ones are left out of it.
+#[derive(Debug, Clone)]
+pub(crate) struct Recorder {
+    tx: Sender<(Duration, Bytes)>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Queues `data` for the capture file, timestamped now.
+    pub(crate) fn record(&self, data: &Bytes) {
+        let record = (self.started.elapsed(), data.clone());
+
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(record) {
+            warn!("capture file is behind, left out {} bytes", data.len());
+        }
+    }
+}
+
+/// Every chunk broadcast, along with when it was, appended to a file.
+#[derive(Debug)]
+pub(crate) struct Capture {
+    writer: BufWriter<File>,
+    rx: Receiver<(Duration, Bytes)>,
+    format: CaptureFormat,
+    /// whether the last write went through, so a failing disk is not reported over and over
+    healthy: bool,
+}
+
+impl Capture {
+    /// Opens the file at `path` for appending, returns it along with the recorder for the hub,
+    /// timestamps are counted from now.
+    pub(crate) async fn open(
+        path: &Path,
+        format: CaptureFormat,
+    ) -> Result<(Self, Recorder), BroadcastError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|source| BroadcastError::Open {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        let (tx, rx) = mpsc::channel(CAPTURE_QUEUE);
+
+        let capture = Self {
+            writer: BufWriter::new(file),
+            rx,
+            format,
+            healthy: true,
+        };
+        let recorder = Recorder {
+            tx,
+            started: Instant::now(),
+        };
+
+        Ok((capture, recorder))
+    }
+
+    /// Appends the recorded chunks to the file, until `cancel` is triggered and the records
+    /// pending in the queue have been written.
+    ///
+    /// Failing to write is logged and otherwise ignored, the live broadcast goes on regardless.
+    #[instrument(skip_all)]
+    pub(crate) async fn run(mut self, cancel: CancellationToken) {
+        let mut flush = tokio::time::interval(CAPTURE_FLUSH_INTERVAL);
+        let mut draining = false;
+
+        loop {
+            let (elapsed, data) = if draining {
+                match self.rx.try_recv() {
+                    Ok(record) => record,
+                    Err(_) => break,
+                }
+            } else {
+                tokio::select! {
+                    _ = cancel.cancelled() => {
+                        debug!("cancelled, writing pending records");
+                        draining = true;
+                        continue;
+                    }
+                    _ = flush.tick() => {
+                        let flushed = self.writer.flush().await;
+                        self.check(flushed);
+                        continue;
+                    }
+                    record = self.rx.recv() => match record {
+                        Some(record) => record,
+                        None => break,
+                    },
+                }
+            };
+
+            let line = self.format.record(elapsed, &data);
+            let written = self.writer.write_all(line.as_bytes()).await;
+            self.check(written);
+        }
+
+        let flushed = self.writer.flush().await;
+        self.check(flushed);
+    }
+
+    /// Logs the first of a run of failures.
+    fn check(&mut self, result: std::io::Result<()>) {
+        match result {
+            Ok(()) => self.healthy = true,
+            Err(e) if self.healthy => {
+                warn!("when writing to the capture file: {e}");
+                self.healthy = false;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+impl CaptureFormat {
+    /// The record for a chunk of `data` broadcast `elapsed` after the capture started.
+    fn record(&self, elapsed: Duration, data: &[u8]) -> String {
+        match self {
+            CaptureFormat::Jsonl => format!(
+                "{{\"ts_ns\":{},\"len\":{},\"data\":\"{}\"}}\n",
+                elapsed.as_nanos(),
+                data.len(),
+                base64::engine::general_purpose::STANDARD.encode(data)
+            ),
+        }
+    }
+}
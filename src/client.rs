@@ -0,0 +1,397 @@
+use crate::hub::Delivered;
+use crate::{
+    Compression, Config, DropPolicy, LagPolicy, DEFAULT_CLIENT_QUEUE_SIZE, DEFAULT_WRITE_TIMEOUT,
+};
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+use tokio_util::bytes::Bytes;
+
+/// How data is delivered to each single consumer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientOptions {
+    /// time a consumer has to accept a chunk before it gets dropped
+    pub write_timeout: Duration,
+    /// number of chunks queued for a consumer while it is busy writing
+    pub queue_size: usize,
+    /// what to do when the queue of a consumer is full
+    pub drop_policy: DropPolicy,
+    /// what to do when a consumer lags behind the channel, as the drop policy says if unset
+    pub lag_policy: Option<LagPolicy>,
+    /// time a consumer can stay behind without emptying its queue before it gets dropped, no
+    /// limit if unset
+    pub idle_timeout: Option<Duration>,
+    /// bytes per second a consumer can be sent at most, no limit if unset
+    pub rate_limit: Option<u64>,
+    /// bytes per second large chunks are spread over when written to a consumer, written at once
+    /// if unset
+    pub smooth_rate: Option<u64>,
+    /// compression of the stream sent to a consumer, none if unset
+    pub compression: Option<Compression>,
+    /// message written to a consumer on shutdown, once it got everything pending, none if unset
+    pub sentinel: Option<Bytes>,
+    /// bytes written to a consumer before anything else, none if unset
+    pub banner: Option<Bytes>,
+    /// average bytes queued for a consumer past which it is flagged as slow, no detection if unset
+    pub slow_threshold: Option<usize>,
+    /// whether consumers flagged as slow get dropped, instead of only being reported
+    pub slow_disconnect: bool,
+    /// topic of the chunks a consumer gets, as it subscribed to it, every chunk if unset, see
+    /// [`Config::topics`]
+    pub topic: Option<Bytes>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            queue_size: DEFAULT_CLIENT_QUEUE_SIZE,
+            drop_policy: DropPolicy::default(),
+            lag_policy: None,
+            idle_timeout: None,
+            rate_limit: None,
+            smooth_rate: None,
+            compression: None,
+            sentinel: None,
+            banner: None,
+            slow_threshold: None,
+            slow_disconnect: false,
+            topic: None,
+        }
+    }
+}
+
+impl From<&Config> for ClientOptions {
+    fn from(config: &Config) -> Self {
+        Self {
+            write_timeout: config.write_timeout,
+            queue_size: config.client_queue_size,
+            drop_policy: config.drop_policy,
+            lag_policy: config.lag_policy,
+            idle_timeout: config.client_idle_timeout,
+            rate_limit: config.per_client_bandwidth,
+            smooth_rate: config.per_client_smoothing,
+            compression: config.compression,
+            // sealed like the chunks the hub sends out
+            sentinel: config.shutdown_sentinel.as_ref().map(|payload| {
+                let frame = config.framing.frame(payload);
+                match config.checksum {
+                    Some(checksum) => checksum.seal(&frame),
+                    None => frame,
+                }
+            }),
+            banner: config.banner.clone(),
+            slow_threshold: config.slow_client_threshold,
+            slow_disconnect: config.slow_client_disconnect,
+            // each consumer subscribes as it connects
+            topic: None,
+        }
+    }
+}
+
+/// Why a consumer was removed, see [`ClientStats`](crate::ClientStats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisconnectReason {
+    /// writing to it failed, or it closed the connection
+    WriteError,
+    /// it took longer than the write timeout to accept a chunk
+    WriteTimeout,
+    /// it stayed behind for longer than the idle timeout
+    IdleTimeout,
+    /// its queue was full, with the disconnect drop policy
+    QueueFull,
+    /// it fell behind the broadcast channel, with the disconnect lag policy
+    Lagged,
+    /// its backlog stayed over the slow threshold, with slow consumers disconnected
+    Slow,
+    /// it was kicked out, through the admin server or the library
+    Kicked,
+    /// it was refused for there being as many consumers as allowed already
+    MaxClients,
+    /// the broadcast ended, after it got everything pending
+    Shutdown,
+    /// it was handed over to the next broadcaster
+    HandedOver,
+    /// it closed the connection cleanly, with a WebSocket close frame or the end of an SSE request
+    ClientClosed,
+}
+
+impl DisconnectReason {
+    /// Every reason, in the order metrics list them.
+    pub const ALL: [DisconnectReason; 11] = [
+        DisconnectReason::WriteError,
+        DisconnectReason::WriteTimeout,
+        DisconnectReason::IdleTimeout,
+        DisconnectReason::QueueFull,
+        DisconnectReason::Lagged,
+        DisconnectReason::Slow,
+        DisconnectReason::Kicked,
+        DisconnectReason::MaxClients,
+        DisconnectReason::Shutdown,
+        DisconnectReason::HandedOver,
+        DisconnectReason::ClientClosed,
+    ];
+
+    /// Reason of a consumer failing to be written to with `error`.
+    pub(crate) fn write_failed(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::TimedOut => DisconnectReason::WriteTimeout,
+            _ => DisconnectReason::WriteError,
+        }
+    }
+
+    /// Label of the reason in the metrics and the logs.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DisconnectReason::WriteError => "write_error",
+            DisconnectReason::WriteTimeout => "write_timeout",
+            DisconnectReason::IdleTimeout => "idle_timeout",
+            DisconnectReason::QueueFull => "queue_full",
+            DisconnectReason::Lagged => "lagged",
+            DisconnectReason::Slow => "slow",
+            DisconnectReason::Kicked => "kicked",
+            DisconnectReason::MaxClients => "max_clients",
+            DisconnectReason::Shutdown => "shutdown",
+            DisconnectReason::HandedOver => "handed_over",
+            DisconnectReason::ClientClosed => "client_closed",
+        }
+    }
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Bounded queue of the chunks waiting to be written to a consumer.
+#[derive(Debug)]
+pub(crate) struct ClientQueue {
+    chunks: VecDeque<Bytes>,
+    /// bytes in the queued chunks
+    bytes: usize,
+    size: usize,
+    policy: DropPolicy,
+    lag_policy: LagPolicy,
+    dropped: u64,
+}
+
+impl ClientQueue {
+    /// Creates a queue holding up to `size` chunks, at least one. Without a `lag_policy`, lagging
+    /// consumers are disconnected with the disconnect drop policy, and resync otherwise.
+    pub(crate) fn new(size: usize, policy: DropPolicy, lag_policy: Option<LagPolicy>) -> Self {
+        let lag_policy = lag_policy.unwrap_or(match policy {
+            DropPolicy::Disconnect => LagPolicy::Disconnect,
+            DropPolicy::Oldest | DropPolicy::Newest => LagPolicy::Resync,
+        });
+
+        Self {
+            chunks: VecDeque::new(),
+            bytes: 0,
+            size: size.max(1),
+            policy,
+            lag_policy,
+            dropped: 0,
+        }
+    }
+
+    /// Queues a chunk, making room for it as the policy says.
+    ///
+    /// Returns false if the queue is full and the consumer has to be disconnected.
+    pub(crate) fn push(&mut self, chunk: Bytes) -> bool {
+        if self.chunks.len() < self.size {
+            self.bytes += chunk.len();
+            self.chunks.push_back(chunk);
+            return true;
+        }
+
+        match self.policy {
+            DropPolicy::Oldest => {
+                self.pop();
+                self.bytes += chunk.len();
+                self.chunks.push_back(chunk);
+                self.dropped += 1;
+                true
+            }
+            DropPolicy::Newest => {
+                self.dropped += 1;
+                true
+            }
+            DropPolicy::Disconnect => false,
+        }
+    }
+
+    /// Counts chunks the consumer missed before they could be queued, returns the policy saying
+    /// what to do about it.
+    pub(crate) fn missed(&mut self, chunks: u64) -> LagPolicy {
+        self.dropped += chunks;
+        self.lag_policy
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Bytes> {
+        let chunk = self.chunks.pop_front()?;
+        self.bytes -= chunk.len();
+        Some(chunk)
+    }
+
+    /// Bytes waiting to be written.
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Chunks dropped so far, either because the queue was full or because they were missed.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// Weight of each new sample in the average backlog of a consumer.
+const BACKLOG_SMOOTHING: f64 = 0.25;
+
+/// Exponential moving average of the bytes queued for a consumer, telling when it falls behind
+/// for more than a burst.
+#[derive(Debug)]
+pub(crate) struct Backlog {
+    threshold: usize,
+    average: f64,
+    slow: bool,
+}
+
+/// What changed with a sample of the [`Backlog`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Pace {
+    /// the average went over the threshold
+    Slowed(f64),
+    /// the average is back under the threshold
+    Recovered,
+    Unchanged,
+}
+
+impl Backlog {
+    pub(crate) fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            average: 0.0,
+            slow: false,
+        }
+    }
+
+    /// Takes the `bytes` queued right now into the average.
+    pub(crate) fn sample(&mut self, bytes: usize) -> Pace {
+        self.average += BACKLOG_SMOOTHING * (bytes as f64 - self.average);
+        let slow = self.average > self.threshold as f64;
+
+        match (self.slow, slow) {
+            (false, true) => {
+                self.slow = true;
+                Pace::Slowed(self.average)
+            }
+            (true, false) => {
+                self.slow = false;
+                Pace::Recovered
+            }
+            _ => Pace::Unchanged,
+        }
+    }
+}
+
+/// Writer counting the bytes that go through it, as they go.
+#[derive(Debug)]
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    count: Arc<Delivered>,
+}
+
+impl<W> CountingWriter<W> {
+    pub(crate) fn new(inner: W, count: Arc<Delivered>) -> Self {
+        Self { inner, count }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(n)) = poll {
+            self.count.sent(n);
+        }
+
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Writer of a consumer, compressing what goes through it when asked to.
+#[derive(Debug)]
+pub(crate) enum Compressor<W> {
+    Plain(W),
+    Gzip(GzipEncoder<W>),
+    Zstd(ZstdEncoder<W>),
+}
+
+impl<W: AsyncWrite> Compressor<W> {
+    pub(crate) fn new(inner: W, compression: Option<Compression>) -> Self {
+        match compression {
+            None => Compressor::Plain(inner),
+            Some(Compression::Gzip) => Compressor::Gzip(GzipEncoder::new(inner)),
+            Some(Compression::Zstd) => Compressor::Zstd(ZstdEncoder::new(inner)),
+        }
+    }
+
+    pub(crate) fn is_compressed(&self) -> bool {
+        !matches!(self, Compressor::Plain(_))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Compressor<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Compressor::Plain(inner) => Pin::new(inner).poll_write(cx, buf),
+            Compressor::Gzip(inner) => Pin::new(inner).poll_write(cx, buf),
+            Compressor::Zstd(inner) => Pin::new(inner).poll_write(cx, buf),
+        }
+    }
+
+    /// Flushing also flushes the compressor, so everything written so far can be decompressed.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Compressor::Plain(inner) => Pin::new(inner).poll_flush(cx),
+            Compressor::Gzip(inner) => Pin::new(inner).poll_flush(cx),
+            Compressor::Zstd(inner) => Pin::new(inner).poll_flush(cx),
+        }
+    }
+
+    /// Shutting down ends the compressed stream before the writer itself.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Compressor::Plain(inner) => Pin::new(inner).poll_shutdown(cx),
+            Compressor::Gzip(inner) => Pin::new(inner).poll_shutdown(cx),
+            Compressor::Zstd(inner) => Pin::new(inner).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{publish, Coalesce, Hub};
+use tokio::time::Instant;
+use tokio_util::bytes::BytesMut;
+
+/// Batches the frames read from a remote into larger chunks before they get published, as
+/// [`Coalesce`] says, or publishes them right away without it.
+///
+/// Whatever is pending once it is dropped gets published, so nothing is lost when the remote
+/// closes, fails or the broadcast is shut down.
+pub(crate) struct Coalescer<'a> {
+    hub: &'a Hub,
+    settings: Option<Coalesce>,
+    pending: BytesMut,
+    /// when the pending bytes have to go out at the latest, none while there are none
+    deadline: Option<Instant>,
+}
+
+impl<'a> Coalescer<'a> {
+    pub(crate) fn new(hub: &'a Hub, settings: Option<Coalesce>) -> Self {
+        Self {
+            hub,
+            settings,
+            pending: BytesMut::new(),
+            deadline: None,
+        }
+    }
+
+    /// Adds a whole frame, publishing the batch once it is large enough.
+    pub(crate) fn push(&mut self, frame: BytesMut) {
+        let Some(settings) = self.settings else {
+            publish(self.hub, frame);
+            return;
+        };
+
+        if self.pending.is_empty() {
+            self.deadline = Some(Instant::now() + settings.max_delay);
+            self.pending = frame;
+        } else {
+            // frames of the same read are contiguous, and get joined without copying
+            self.pending.unsplit(frame);
+        }
+
+        if self.pending.len() >= settings.max_bytes {
+            self.flush();
+        }
+    }
+
+    /// Publishes the pending bytes, if any.
+    pub(crate) fn flush(&mut self) {
+        self.deadline = None;
+
+        if !self.pending.is_empty() {
+            publish(self.hub, self.pending.split());
+        }
+    }
+
+    /// Completes once the pending bytes have waited long enough, never while there are none.
+    pub(crate) async fn expired(&self) {
+        match self.deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+impl Drop for Coalescer<'_> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
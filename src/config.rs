@@ -0,0 +1,843 @@
+use crate::{
+    AccessList, Backoff, Framing, Keepalive, LocalTls, RemoteTls, Socks5Proxy,
+    DEFAULT_AUTH_TIMEOUT, DEFAULT_BROADCAST_CAPACITY, DEFAULT_BUFFER_SIZE,
+    DEFAULT_CLIENT_QUEUE_SIZE, DEFAULT_CONNECT_TIMEOUT, DEFAULT_MAX_CLIENTS,
+    DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_NO_CLIENTS_BUFFER, DEFAULT_SHUTDOWN_GRACE,
+    DEFAULT_WRITE_TIMEOUT,
+};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio_util::bytes::Bytes;
+
+/// Where the data to broadcast comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Remote {
+    /// remote `host:port` to connect to and pull data from
+    Tcp(String),
+    /// local `host:port` to bind and receive datagrams on
+    Udp(String),
+    /// local file to read data from, like a capture to replay
+    File(PathBuf),
+    /// remote `host:port/path` to `GET` over HTTP, Server-Sent Events or any other body
+    #[cfg(feature = "http-remote")]
+    HttpSse(String),
+}
+
+/// Protocol of a remote.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemoteProto {
+    #[default]
+    Tcp,
+    Udp,
+    File,
+    #[cfg(feature = "http-remote")]
+    HttpSse,
+}
+
+impl FromStr for RemoteProto {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(RemoteProto::Tcp),
+            "udp" => Ok(RemoteProto::Udp),
+            "file" => Ok(RemoteProto::File),
+            #[cfg(feature = "http-remote")]
+            "http-sse" => Ok(RemoteProto::HttpSse),
+            #[cfg(feature = "http-remote")]
+            _ => Err(format!(
+                "unsupported protocol: {s}, expected tcp, udp, file or http-sse"
+            )),
+            #[cfg(not(feature = "http-remote"))]
+            _ => Err(format!(
+                "unsupported protocol: {s}, expected tcp, udp or file"
+            )),
+        }
+    }
+}
+
+impl Remote {
+    /// Parses either a `protocol://host:port` or a plain `host:port` string, the later using
+    /// the `default` protocol. Files are given by their path instead of `host:port`.
+    pub fn parse_with(s: &str, default: RemoteProto) -> Result<Self, String> {
+        if s.contains("://") {
+            return s.parse();
+        }
+
+        Ok(match default {
+            RemoteProto::Tcp => Remote::Tcp(s.to_string()),
+            RemoteProto::Udp => Remote::Udp(s.to_string()),
+            RemoteProto::File => Remote::File(s.into()),
+            #[cfg(feature = "http-remote")]
+            RemoteProto::HttpSse => Remote::HttpSse(s.to_string()),
+        })
+    }
+
+    /// The `host:port` part of the remote, none for files.
+    pub fn address(&self) -> Option<&str> {
+        match self {
+            Remote::Tcp(address) | Remote::Udp(address) => Some(address),
+            Remote::File(_) => None,
+            #[cfg(feature = "http-remote")]
+            Remote::HttpSse(url) => Some(url.split_once('/').map_or(url, |(address, _)| address)),
+        }
+    }
+
+    /// The path to `GET` from an HTTP remote, `/` if it has none.
+    #[cfg(feature = "http-remote")]
+    pub(crate) fn path(&self) -> &str {
+        match self {
+            Remote::HttpSse(url) => url.find('/').map_or("/", |at| &url[at..]),
+            _ => "/",
+        }
+    }
+}
+
+impl FromStr for Remote {
+    type Err = String;
+
+    /// Parses a `protocol://host:port` string, eg. `tcp://feed:9092` or `udp://0.0.0.0:9092`, or
+    /// a `file://path` one, or `http-sse://host:port/path` for an HTTP remote.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (protocol, address) = s
+            .split_once("://")
+            .ok_or_else(|| format!("missing protocol in {s}, expected proto://<domain>:<port>"))?;
+
+        match protocol {
+            "tcp" => Ok(Remote::Tcp(address.to_string())),
+            "udp" => Ok(Remote::Udp(address.to_string())),
+            "file" => Ok(Remote::File(address.into())),
+            #[cfg(feature = "http-remote")]
+            "http-sse" => Ok(Remote::HttpSse(address.to_string())),
+            _ => Err(format!("unsupported protocol: {protocol}")),
+        }
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Remote::Tcp(address) => write!(f, "tcp://{address}"),
+            Remote::Udp(address) => write!(f, "udp://{address}"),
+            Remote::File(path) => write!(f, "file://{}", path.display()),
+            #[cfg(feature = "http-remote")]
+            Remote::HttpSse(url) => write!(f, "http-sse://{url}"),
+        }
+    }
+}
+
+/// How consumers get the data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LocalProto {
+    /// consumers connect to the local listener
+    #[default]
+    Tcp,
+    /// datagrams are sent from the local socket to a fixed list of targets
+    Udp,
+}
+
+impl FromStr for LocalProto {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(LocalProto::Tcp),
+            "udp" => Ok(LocalProto::Udp),
+            _ => Err(format!(
+                "unsupported local protocol: {s}, expected tcp or udp"
+            )),
+        }
+    }
+}
+
+/// How a broadcaster with several remotes pulls data from them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemoteMode {
+    /// pull from all of them at once, interleaving their data
+    #[default]
+    Merge,
+    /// pull only from the first available one, in order, switching to the next on failure
+    Failover,
+}
+
+impl FromStr for RemoteMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(RemoteMode::Merge),
+            "failover" => Ok(RemoteMode::Failover),
+            _ => Err(format!(
+                "unsupported remote mode: {s}, expected merge or failover"
+            )),
+        }
+    }
+}
+
+/// What pausing the broadcast, through the admin commands, does to the remotes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PauseMode {
+    /// they are still read, and what they send meanwhile is left out
+    #[default]
+    Drop,
+    /// they are no longer read, what they send waits for the broadcast to resume, as long as
+    /// their buffers hold it
+    Hold,
+}
+
+impl FromStr for PauseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(PauseMode::Drop),
+            "hold" => Ok(PauseMode::Hold),
+            _ => Err(format!(
+                "unsupported pause mode: {s}, expected drop or hold"
+            )),
+        }
+    }
+}
+
+/// What happens to what the remotes send while no consumer is connected to get it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoClientsPolicy {
+    /// they are still read, and what they send is lost
+    #[default]
+    Discard,
+    /// they are still read, and the last bytes they send are kept for the next consumer, up to
+    /// `no_clients_buffer` of them, who gets them after the replay history, or as its first turn
+    /// with round-robin distribution; a tee file or UDP targets get everything as it comes, and
+    /// do not count as consumers
+    Buffer,
+    /// they are no longer read, as with a `min_clients` of 1
+    PauseRead,
+}
+
+impl FromStr for NoClientsPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "discard" => Ok(NoClientsPolicy::Discard),
+            "buffer" => Ok(NoClientsPolicy::Buffer),
+            "pause-read" => Ok(NoClientsPolicy::PauseRead),
+            _ => Err(format!(
+                "unsupported no clients policy: {s}, expected discard, buffer or pause-read"
+            )),
+        }
+    }
+}
+
+/// How the chunks are distributed among the TCP consumers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Distribution {
+    /// every consumer gets every chunk
+    #[default]
+    Broadcast,
+    /// each chunk goes to a single consumer, taking turns, skipping those gone or with a full
+    /// queue; there is no replay history, and the other kinds of consumers still get every chunk
+    RoundRobin,
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "broadcast" => Ok(Distribution::Broadcast),
+            "round-robin" => Ok(Distribution::RoundRobin),
+            _ => Err(format!(
+                "unsupported distribution: {s}, expected broadcast or round-robin"
+            )),
+        }
+    }
+}
+
+/// What to do with a consumer whose queue is full when another chunk arrives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// drop the oldest chunk in the queue to make room for the new one
+    Oldest,
+    /// drop the new chunk, keeping the queue as is
+    Newest,
+    /// disconnect the consumer
+    #[default]
+    Disconnect,
+}
+
+impl FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oldest" => Ok(DropPolicy::Oldest),
+            "newest" => Ok(DropPolicy::Newest),
+            "disconnect" => Ok(DropPolicy::Disconnect),
+            _ => Err(format!(
+                "unsupported drop policy: {s}, expected oldest, newest or disconnect"
+            )),
+        }
+    }
+}
+
+/// What happens to a consumer that falls so far behind the broadcast channel that chunks are gone
+/// before it could take them. The chunks are missed either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// skip ahead to the oldest chunk left and go on, logging the gap
+    Resync,
+    /// disconnect the consumer
+    Disconnect,
+    /// skip ahead like resync, without logging it, the metrics count it anyway
+    CountOnly,
+}
+
+impl FromStr for LagPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resync" => Ok(LagPolicy::Resync),
+            "disconnect" => Ok(LagPolicy::Disconnect),
+            "count-only" => Ok(LagPolicy::CountOnly),
+            _ => Err(format!(
+                "unsupported lag policy: {s}, expected resync, disconnect or count-only"
+            )),
+        }
+    }
+}
+
+/// How chunks are written in the `data:` field of Server-Sent Events.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SseEncoding {
+    /// base64 of the chunk, safe for any data
+    #[default]
+    Base64,
+    /// the chunk as UTF-8 text, with invalid sequences replaced and one `data:` line per line
+    Text,
+}
+
+impl FromStr for SseEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base64" => Ok(SseEncoding::Base64),
+            "text" => Ok(SseEncoding::Text),
+            _ => Err(format!(
+                "unsupported SSE encoding: {s}, expected base64 or text"
+            )),
+        }
+    }
+}
+
+/// What to do with connections accepted faster than the configured rate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AcceptOverflow {
+    /// hold off accepting until the rate allows it, connections wait in the listen backlog
+    #[default]
+    Delay,
+    /// close the connection right away
+    Close,
+}
+
+impl FromStr for AcceptOverflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delay" => Ok(AcceptOverflow::Delay),
+            "close" => Ok(AcceptOverflow::Close),
+            _ => Err(format!(
+                "unsupported accept overflow: {s}, expected delay or close"
+            )),
+        }
+    }
+}
+
+/// Compression of the stream sent to each TCP consumer.
+///
+/// Every consumer gets its own compressed stream, starting when it connects, with the history
+/// if any: a single gzip member or zstd frame that only ends when the consumer is disconnected.
+/// The compressor is flushed after each chunk, so what arrives can be decompressed right away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!(
+                "unsupported compression: {s}, expected gzip or zstd"
+            )),
+        }
+    }
+}
+
+/// Checksum each chunk is broadcast with, so consumers can detect corruption.
+///
+/// Each chunk, framing included, goes out as a frame of its own: the length of the chunk, as a
+/// 4 bytes big endian integer, the chunk itself, and its checksum, big endian too. That is the
+/// CRC-32 (IEEE) in 4 bytes, or the 64 bits xxHash (XXH64, seed 0) in 8 bytes. Consumers can read
+/// the length, then as many bytes plus the checksum, and check the frame with
+/// [`Checksum::verify`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    Crc32,
+    Xxhash,
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "crc32" => Ok(Checksum::Crc32),
+            "xxhash" => Ok(Checksum::Xxhash),
+            _ => Err(format!(
+                "unsupported checksum: {s}, expected crc32 or xxhash"
+            )),
+        }
+    }
+}
+
+/// How chunks are written to the consumers.
+///
+/// With json each chunk is a line of JSON, like
+/// `{"seq":0,"ts_ns":1718000000000000000,"len":5,"data":"aGVsbG8="}`, with the number of the
+/// chunk, counted from 0 again each time a remote connects unless told otherwise, the nanoseconds
+/// since the Unix epoch when it was received, its length and the chunk itself in base64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Raw,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(OutputFormat::Raw),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "unsupported output format: {s}, expected raw or json"
+            )),
+        }
+    }
+}
+
+/// Format of the records in the capture file.
+///
+/// With jsonl each chunk broadcast is a line of JSON, like
+/// `{"ts_ns":1500,"len":5,"data":"aGVsbG8="}`, with the nanoseconds since the capture started on
+/// a monotonic clock, the length of the chunk and the chunk itself in base64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaptureFormat {
+    #[default]
+    Jsonl,
+}
+
+impl FromStr for CaptureFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(CaptureFormat::Jsonl),
+            _ => Err(format!("unsupported capture format: {s}, expected jsonl")),
+        }
+    }
+}
+
+/// How the frames read from a remote are batched into fewer, larger chunks.
+///
+/// Frames are held until there are `max_bytes` of them, or the first one has waited for
+/// `max_delay`, and then go out together as a single chunk, so consumers get fewer writes for a
+/// little latency. Frames are never split, with framing every chunk is still made of whole ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coalesce {
+    pub max_delay: Duration,
+    pub max_bytes: usize,
+}
+
+/// Another listener for TCP consumers, with TLS and authentication settings of its own.
+///
+/// Written as `host:port` followed by comma separated settings, like
+/// `0.0.0.0:9443,cert_file=cert.pem,key_file=key.pem,auth_token=secret`, the address can also be
+/// given as `address=host:port`. Without settings it is plain TCP, and anyone gets data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalListener {
+    /// local `host:port` to accept consumers on
+    pub address: String,
+    /// TLS settings for the consumers of this listener, plain TCP if unset
+    pub tls: Option<LocalTls>,
+    /// token the consumers of this listener have to send, anyone gets data if unset
+    pub auth_token: Option<String>,
+}
+
+impl LocalListener {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            tls: None,
+            auth_token: None,
+        }
+    }
+}
+
+impl FromStr for LocalListener {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut address = None;
+        let mut cert_file = None;
+        let mut key_file = None;
+        let mut auth_token = None;
+
+        for part in s.split(',') {
+            match part.split_once('=') {
+                None if address.is_none() => address = Some(part.to_string()),
+                Some(("address", value)) => address = Some(value.to_string()),
+                Some(("cert_file", value)) => cert_file = Some(PathBuf::from(value)),
+                Some(("key_file", value)) => key_file = Some(PathBuf::from(value)),
+                Some(("auth_token", value)) => auth_token = Some(value.to_string()),
+                _ => {
+                    return Err(format!(
+                        "unsupported listener setting: {part}, expected address, cert_file, key_file or auth_token"
+                    ))
+                }
+            }
+        }
+
+        let tls = match (cert_file, key_file) {
+            (Some(cert_file), Some(key_file)) => Some(LocalTls {
+                cert_file,
+                key_file,
+            }),
+            (None, None) => None,
+            _ => return Err("listener needs both cert_file and key_file for TLS".to_string()),
+        };
+
+        Ok(Self {
+            address: address.ok_or("listener without an address")?,
+            tls,
+            auth_token,
+        })
+    }
+}
+
+/// Configuration of a [`Broadcaster`](crate::Broadcaster).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// local `host:port` for consumers to connect and get data pushed, or to send datagrams from
+    pub local: String,
+    /// whether consumers connect over TCP or get datagrams sent to them
+    pub local_proto: LocalProto,
+    /// `host:port` of the consumers to send datagrams to, with the UDP protocol
+    pub udp_targets: Vec<String>,
+    /// whether every TCP consumer gets every chunk, or they take turns
+    pub distribution: Distribution,
+    /// TLS settings for consumers, plain TCP if unset
+    pub local_tls: Option<LocalTls>,
+    /// more listeners for TCP consumers, alongside `local`, all getting the same broadcast
+    pub listeners: Vec<LocalListener>,
+    /// path of a Unix socket for consumers to connect to instead of `local`, none if unset
+    #[cfg(unix)]
+    pub local_uds: Option<PathBuf>,
+    /// whether to take the listener for consumers from systemd socket activation when there is
+    /// one, instead of binding `local`
+    #[cfg(unix)]
+    pub systemd_socket: bool,
+    /// path of a Unix socket to take the TCP consumers over from the previous broadcaster, and to
+    /// hand them over to the next one at, none if unset
+    #[cfg(unix)]
+    pub handover_socket: Option<PathBuf>,
+    /// where to pull data from, at least one
+    pub remotes: Vec<Remote>,
+    /// how data from several remotes is combined
+    pub remote_mode: RemoteMode,
+    /// size of the buffer used to read from the remote
+    pub buffer_size: usize,
+    /// whether the buffer used to read from a TCP or UDP remote is as large as the receive buffer
+    /// of its socket instead, `buffer_size` being only for those whose size cannot be told
+    pub auto_buffer_size: bool,
+    /// how the data from the remote is split into messages
+    pub framing: Framing,
+    /// bytes a message can have at most with framing, a larger one fails the connection to the
+    /// remote, which is reconnected as any other failure
+    pub max_message_size: usize,
+    /// checksum each chunk is framed with on the way out, chunks as they are if unset
+    pub checksum: Option<Checksum>,
+    /// how chunks are written to the consumers, after the checksum
+    pub output_format: OutputFormat,
+    /// whether the JSON records are numbered on across remote reconnects, instead of from 0 again
+    pub continue_sequence: bool,
+    /// whether each chunk goes out after a sequence header, the number of the chunk as an 8 bytes
+    /// big endian integer, counted from 0 since the broadcast started and across remote
+    /// reconnects, before the chunk as it would go out otherwise, checksum or JSON record included
+    pub seq_header: bool,
+    /// number of last frames a frame is compared with, and left out if it repeats one of them,
+    /// no deduplication if unset
+    pub dedup_window: Option<usize>,
+    /// how frames are batched before they are broadcast, one chunk per frame if unset
+    pub coalesce: Option<Coalesce>,
+    /// TLS settings for a TCP remote, plain TCP if unset
+    pub remote_tls: Option<RemoteTls>,
+    /// SOCKS5 proxy to reach the TCP remotes through, direct connections if unset
+    pub remote_socks5: Option<Socks5Proxy>,
+    /// local address the connections to the TCP remotes, or to their proxy, originate from, on a
+    /// port the system picks, as the routing says if unset
+    pub remote_bind_addr: Option<IpAddr>,
+    /// backoff used when (re)connecting to a TCP remote
+    pub backoff: Backoff,
+    /// whether what TCP consumers write is forwarded to the TCP remote
+    pub bidirectional: bool,
+    /// bytes per second read from the remotes at most, all of them together, no limit if unset
+    pub max_bandwidth: Option<u64>,
+    /// bytes read from the remotes at most, all of them together, after which the broadcaster
+    /// shuts down once the consumers got them, no limit if unset
+    pub max_bytes: Option<u64>,
+    /// for testing only, time each chunk read from the remotes is held before it is broadcast, to
+    /// see how consumers cope with a laggy feed, none if unset
+    pub inject_delay: Option<Duration>,
+    /// for testing only, random time up to this long held on top of the injected delay, none if
+    /// unset
+    pub inject_jitter: Option<Duration>,
+    /// whether to read file remotes over and over, instead of stopping once they end
+    pub file_loop: bool,
+    /// time to wait after each read from a file remote, to approximate the timing of a capture
+    pub file_pace: Duration,
+    /// whether to reconnect when a TCP remote closes the connection, or it gets reset, times out
+    /// or sends what cannot be decoded, or just return; other read errors are returned either way
+    pub reconnect: bool,
+    /// time a TCP remote can go without sending anything before it is considered stalled and
+    /// handled like a closed one, no limit if unset
+    pub remote_read_timeout: Option<Duration>,
+    /// time connecting to a TCP remote can take before the attempt fails, and is retried
+    pub connect_timeout: Duration,
+    /// time after which the connection to a TCP remote is closed and re-established on purpose,
+    /// even without reconnection, no limit if unset
+    pub remote_max_lifetime: Option<Duration>,
+    /// TCP keepalive probes sent to a TCP remote, the system defaults if unset
+    pub remote_keepalive: Option<Keepalive>,
+    /// number of chunks retained for consumers that fall behind before they get dropped
+    pub broadcast_capacity: usize,
+    /// whether to disable Nagle's algorithm on the TCP remotes and consumers
+    pub nodelay: bool,
+    /// whether to set `SO_REUSEADDR` on the listeners, so a restart can bind again right away
+    pub reuseaddr: bool,
+    /// TCP keepalive probes sent to the consumers, the system defaults if unset
+    pub keepalive: Option<Keepalive>,
+    /// times binding a listener is retried with `backoff` while the address is in use
+    pub bind_retries: u32,
+    /// whether IPv6 listeners also accept IPv4 peers, as IPv4-mapped addresses
+    pub dual_stack: bool,
+    /// time a consumer has to accept a chunk before it gets dropped
+    pub write_timeout: Duration,
+    /// number of chunks queued for each consumer while it is busy writing
+    pub client_queue_size: usize,
+    /// what to do when the queue of a consumer is full
+    pub drop_policy: DropPolicy,
+    /// what to do with a consumer lagging behind the broadcast channel, if unset it is
+    /// disconnected with the disconnect drop policy and resyncs with the others
+    pub lag_policy: Option<LagPolicy>,
+    /// time a consumer can stay behind without emptying its queue before it gets dropped, no
+    /// limit if unset
+    pub client_idle_timeout: Option<Duration>,
+    /// average bytes queued for a TCP consumer past which it is flagged as slow, no detection if
+    /// unset
+    pub slow_client_threshold: Option<usize>,
+    /// whether TCP consumers flagged as slow get dropped, instead of only being reported
+    pub slow_client_disconnect: bool,
+    /// compression of the stream sent to each TCP consumer, none if unset
+    pub compression: Option<Compression>,
+    /// bytes per second each TCP consumer can be sent at most, no limit if unset
+    pub per_client_bandwidth: Option<u64>,
+    /// bytes per second the chunks written to each TCP consumer are spread at, a slice at a time,
+    /// so a large one, or the replay history, does not arrive in a single burst, written as they
+    /// come if unset
+    pub per_client_smoothing: Option<u64>,
+    /// time consumers get to receive their pending data on shutdown before they are closed
+    pub shutdown_grace: Duration,
+    /// payload of a last message sent to each TCP consumer on shutdown, once it got everything
+    /// pending, framed like the rest, so it can tell a clean end from a crash, none if unset
+    pub shutdown_sentinel: Option<Bytes>,
+    /// bytes written to each TCP consumer as it connects, before the replay history and the
+    /// broadcast, as they are, none if unset
+    pub banner: Option<Bytes>,
+    /// token TCP consumers have to send, followed by a newline, before they get any data, none if
+    /// unset
+    pub auth_token: Option<String>,
+    /// time a TCP consumer has to send the token, and the topic with topics
+    pub auth_timeout: Duration,
+    /// whether TCP consumers subscribe to a topic, sending it in a line as they connect, after
+    /// the token if any, and only get the frames tagged with it, see [`Config::framing`]; a frame
+    /// is tagged with what its message starts with, up to the first space, and an empty line
+    /// subscribes to every frame
+    pub topics: bool,
+    /// which peers can connect as TCP consumers
+    pub access: AccessList,
+    /// whether TCP consumers connect through a load balancer sending a PROXY protocol header,
+    /// version 1 or 2, before anything else, with the address of the actual consumer
+    pub proxy_protocol: bool,
+    /// whether accepted TCP consumers are logged with the name their address resolves back to, not
+    /// only the address
+    pub reverse_dns: bool,
+    /// consumers admitted per second at most on each listener, no limit if unset
+    pub max_accepts_per_sec: Option<u64>,
+    /// what to do with consumers connecting faster than `max_accepts_per_sec`
+    pub accept_overflow: AcceptOverflow,
+    /// maximum number of simultaneous consumers, 0 for unlimited
+    pub max_clients: usize,
+    /// consumers that have to be getting the broadcast for the remotes to be read, what the remotes send
+    /// waits in their buffers meanwhile instead of being lost, 0 to always read them
+    pub min_clients: usize,
+    /// what happens to what the remotes send while no consumer is connected
+    pub no_clients_policy: NoClientsPolicy,
+    /// bytes kept for the next consumer while none is connected, with the buffer policy, as the
+    /// whole messages fitting in them with framing
+    pub no_clients_buffer: usize,
+    /// number of most recent bytes replayed to consumers when they connect, as the whole messages
+    /// fitting in them with framing, 0 disables it
+    pub replay_bytes: usize,
+    /// time between the replays of consumers connecting all at once, like after an outage, each
+    /// one waiting its turn for the history, every one at once if unset
+    pub replay_stagger: Option<Duration>,
+    /// file to append everything broadcast to, none if unset
+    pub tee_file: Option<PathBuf>,
+    /// whether everything broadcast is written to stdout too
+    pub stdout: bool,
+    /// file to record every chunk broadcast to, with its timestamp, none if unset
+    pub capture_file: Option<PathBuf>,
+    /// how the chunks are recorded in the capture file
+    pub capture_format: CaptureFormat,
+    /// local `host:port` to accept WebSocket consumers on, alongside the TCP ones, none if unset
+    pub ws_addr: Option<String>,
+    /// local `host:port` to serve Server-Sent Events on, alongside the TCP consumers, none if
+    /// unset
+    pub sse_addr: Option<String>,
+    /// how chunks are written in the Server-Sent Events
+    pub sse_encoding: SseEncoding,
+    /// local `host:port` to serve Prometheus metrics on, none if unset
+    pub metrics_addr: Option<String>,
+    /// how often a summary of the metrics gets logged, never if unset
+    pub stats_interval: Option<Duration>,
+    /// local `host:port` to serve the admin commands on, none if unset
+    pub admin_addr: Option<String>,
+    /// what pausing the broadcast through the admin commands does to the remotes
+    pub pause_mode: PauseMode,
+}
+
+impl Config {
+    /// Configuration with defaults for everything but the addresses, with a single remote.
+    pub fn new(local: impl Into<String>, remote: Remote) -> Self {
+        Self {
+            local: local.into(),
+            remotes: vec![remote],
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for Config {
+    /// Defaults match the ones of the command line, addresses are left empty.
+    fn default() -> Self {
+        Self {
+            local: String::new(),
+            local_proto: LocalProto::default(),
+            udp_targets: Vec::new(),
+            local_tls: None,
+            listeners: Vec::new(),
+            #[cfg(unix)]
+            local_uds: None,
+            #[cfg(unix)]
+            systemd_socket: false,
+            #[cfg(unix)]
+            handover_socket: None,
+            remotes: Vec::new(),
+            remote_mode: RemoteMode::default(),
+            distribution: Distribution::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            auto_buffer_size: false,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            framing: Framing::default(),
+            checksum: None,
+            output_format: OutputFormat::default(),
+            continue_sequence: false,
+            seq_header: false,
+            dedup_window: None,
+            coalesce: None,
+            remote_tls: None,
+            remote_socks5: None,
+            remote_bind_addr: None,
+            backoff: Backoff::default(),
+            bidirectional: false,
+            max_bandwidth: None,
+            max_bytes: None,
+            inject_delay: None,
+            inject_jitter: None,
+            file_loop: false,
+            file_pace: Duration::ZERO,
+            reconnect: true,
+            remote_read_timeout: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            remote_max_lifetime: None,
+            remote_keepalive: None,
+            broadcast_capacity: DEFAULT_BROADCAST_CAPACITY,
+            nodelay: true,
+            reuseaddr: true,
+            keepalive: None,
+            bind_retries: 0,
+            dual_stack: false,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            client_queue_size: DEFAULT_CLIENT_QUEUE_SIZE,
+            drop_policy: DropPolicy::default(),
+            lag_policy: None,
+            client_idle_timeout: None,
+            slow_client_threshold: None,
+            slow_client_disconnect: false,
+            compression: None,
+            per_client_bandwidth: None,
+            per_client_smoothing: None,
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            shutdown_sentinel: None,
+            banner: None,
+            auth_token: None,
+            auth_timeout: DEFAULT_AUTH_TIMEOUT,
+            topics: false,
+            access: AccessList::default(),
+            proxy_protocol: false,
+            reverse_dns: false,
+            max_accepts_per_sec: None,
+            accept_overflow: AcceptOverflow::default(),
+            max_clients: DEFAULT_MAX_CLIENTS,
+            min_clients: 0,
+            no_clients_policy: NoClientsPolicy::default(),
+            no_clients_buffer: DEFAULT_NO_CLIENTS_BUFFER,
+            replay_bytes: 0,
+            replay_stagger: None,
+            tee_file: None,
+            stdout: false,
+            capture_file: None,
+            capture_format: CaptureFormat::default(),
+            ws_addr: None,
+            sse_addr: None,
+            sse_encoding: SseEncoding::default(),
+            metrics_addr: None,
+            stats_interval: None,
+            admin_addr: None,
+            pause_mode: PauseMode::default(),
+        }
+    }
+}
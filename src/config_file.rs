@@ -0,0 +1,329 @@
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::{fmt, io};
+
+/// Reasons a config file can not be layered under the command line.
+#[derive(Debug)]
+pub enum ConfigFileError {
+    /// the file could not be read
+    Read { path: PathBuf, source: io::Error },
+    /// the file is not valid TOML
+    Parse { path: PathBuf, reason: String },
+    /// a key in the file is not an argument of the command
+    UnknownKey(String),
+    /// the value of a key can not be given as an argument
+    InvalidValue { key: String, reason: &'static str },
+}
+
+impl fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFileError::Read { path, source } => {
+                write!(f, "failed to read {}: {source}", path.display())
+            }
+            ConfigFileError::Parse { path, reason } => {
+                write!(f, "invalid config file {}: {reason}", path.display())
+            }
+            ConfigFileError::UnknownKey(key) => write!(f, "unknown key in config file: {key}"),
+            ConfigFileError::InvalidValue { key, reason } => {
+                write!(f, "invalid value for {key} in config file: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigFileError::Read { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Command line with the values of a config file layered under it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layered {
+    /// the command line arguments, followed by those from the file
+    pub args: Vec<OsString>,
+    /// ids of the arguments that got their value from the file
+    pub from_file: HashSet<String>,
+}
+
+/// Where the effective value of an argument came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    CommandLine,
+    ConfigFile,
+    Environment,
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Source::CommandLine => "command line",
+            Source::ConfigFile => "config file",
+            Source::Environment => "environment",
+            Source::Default => "default",
+        })
+    }
+}
+
+/// How the effective value of every argument is logged at startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConfigLog {
+    /// a line per argument
+    Text,
+    /// a single line of JSON, see [`effective_json`]
+    #[default]
+    Json,
+    /// not at all
+    Off,
+}
+
+impl std::str::FromStr for ConfigLog {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ConfigLog::Text),
+            "json" => Ok(ConfigLog::Json),
+            "off" => Ok(ConfigLog::Off),
+            _ => Err(format!(
+                "unsupported config log: {s}, expected text, json or off"
+            )),
+        }
+    }
+}
+
+/// Placeholder for the values of the arguments hiding their environment values, like tokens.
+const REDACTED: &str = "<redacted>";
+
+/// Adds the values of the TOML file given with `--<config_arg>`, if any, to the command line
+/// `args` of `command`, so they get parsed and validated like the rest.
+///
+/// Keys in the file are the ids of the arguments, like `max_clients` for `--max-clients`, with
+/// arrays for arguments that can be repeated and booleans for flags. Tables are given as their
+/// comma separated `key=value` pairs, so `[[listener]]` tables become `--listener` arguments. Arguments given on the
+/// command line take precedence over the file, which takes precedence over environment variables
+/// and defaults.
+pub fn layer_config_file(
+    command: &Command,
+    config_arg: &str,
+    args: Vec<OsString>,
+) -> Result<Layered, ConfigFileError> {
+    // errors are left for the final parse, like required arguments only the file has
+    let given = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+        .ok();
+
+    let path = given
+        .as_ref()
+        .and_then(|matches| matches.get_raw(config_arg))
+        .and_then(|mut values| values.next())
+        .map(PathBuf::from);
+
+    let Some(path) = path else {
+        return Ok(Layered {
+            args,
+            from_file: HashSet::new(),
+        });
+    };
+
+    let text = std::fs::read_to_string(&path).map_err(|source| ConfigFileError::Read {
+        path: path.clone(),
+        source,
+    })?;
+    let table: toml::Table = text
+        .parse()
+        .map_err(|e: toml::de::Error| ConfigFileError::Parse {
+            path: path.clone(),
+            reason: e.to_string(),
+        })?;
+
+    let mut layered = Layered {
+        args,
+        from_file: HashSet::new(),
+    };
+
+    for (key, value) in table {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str() && arg.get_long().is_some())
+            .filter(|_| key != config_arg)
+            .ok_or_else(|| ConfigFileError::UnknownKey(key.clone()))?;
+
+        let on_command_line = given
+            .as_ref()
+            .and_then(|matches| value_source(matches, &key))
+            == Some(ValueSource::CommandLine);
+        if on_command_line {
+            continue;
+        }
+
+        let long = format!("--{}", arg.get_long().unwrap_or_default());
+        let flag = matches!(arg.get_action(), ArgAction::SetTrue);
+
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+
+        for value in values {
+            match (flag, value) {
+                (true, toml::Value::Boolean(true)) => layered.args.push(long.clone().into()),
+                (true, toml::Value::Boolean(false)) => {}
+                (true, _) => {
+                    return Err(ConfigFileError::InvalidValue {
+                        key,
+                        reason: "expected a boolean",
+                    })
+                }
+                (false, value) => {
+                    let value = scalar(value).ok_or_else(|| ConfigFileError::InvalidValue {
+                        key: key.clone(),
+                        reason: "expected a string, number, boolean, table or an array of them",
+                    })?;
+                    layered.args.push(long.clone().into());
+                    layered.args.push(value.into());
+                }
+            }
+        }
+
+        layered.from_file.insert(key);
+    }
+
+    Ok(layered)
+}
+
+/// Where the effective value of each argument set in `matches` came from, with the raw values as
+/// given, redacted for those hiding their environment values.
+pub fn value_sources(
+    command: &Command,
+    matches: &ArgMatches,
+    layered: &Layered,
+) -> Vec<(String, Vec<String>, Source)> {
+    let mut sources = Vec::new();
+
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+
+        let source = match value_source(matches, id) {
+            Some(ValueSource::CommandLine) if layered.from_file.contains(id) => Source::ConfigFile,
+            Some(ValueSource::CommandLine) => Source::CommandLine,
+            Some(ValueSource::EnvVariable) => Source::Environment,
+            Some(ValueSource::DefaultValue) => Source::Default,
+            _ => continue,
+        };
+
+        let values = if arg.is_hide_env_values_set() {
+            vec![REDACTED.to_string()]
+        } else {
+            matches
+                .get_raw(id)
+                .map(|values| {
+                    values
+                        .map(|value| value.to_string_lossy().into_owned())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        sources.push((id.to_string(), values, source));
+    }
+
+    sources
+}
+
+/// The effective value of each argument set in `matches` as a single JSON object, keyed by the
+/// argument ids, with the values as given and where they came from, like
+/// `{"max_clients":{"value":"10","source":"config file"},"allow":{"value":["10.0.0.0/8"],"source":"command line"}}`.
+///
+/// Arguments that can be repeated have an array of values. Those hiding their environment values
+/// are redacted, see [`value_sources`].
+pub fn effective_json(command: &Command, matches: &ArgMatches, layered: &Layered) -> String {
+    let fields: Vec<String> = value_sources(command, matches, layered)
+        .into_iter()
+        .map(|(id, values, source)| {
+            let repeated = command.get_arguments().any(|arg| {
+                arg.get_id() == id.as_str() && matches!(arg.get_action(), ArgAction::Append)
+            });
+
+            let value = match (repeated, values.as_slice()) {
+                (false, [value]) => json_string(value),
+                _ => {
+                    let values: Vec<String> = values.iter().map(|v| json_string(v)).collect();
+                    format!("[{}]", values.join(","))
+                }
+            };
+
+            format!(
+                "{}:{{\"value\":{value},\"source\":{}}}",
+                json_string(&id),
+                json_string(&source.to_string())
+            )
+        })
+        .collect();
+
+    format!("{{{}}}", fields.join(","))
+}
+
+/// `s` as a JSON string, quoted and escaped.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// Where the value of `id` came from, none if it is not an argument of `matches` at all.
+fn value_source(matches: &ArgMatches, id: &str) -> Option<ValueSource> {
+    matches
+        .try_contains_id(id)
+        .ok()
+        .and_then(|_| matches.value_source(id))
+}
+
+/// The value as a command line argument, none for nested arrays and tables.
+fn scalar(value: toml::Value) -> Option<String> {
+    let toml::Value::Table(table) = value else {
+        return plain(value);
+    };
+
+    let pairs = table
+        .into_iter()
+        .map(|(key, value)| plain(value).map(|value| format!("{key}={value}")))
+        .collect::<Option<Vec<_>>>()?;
+    Some(pairs.join(","))
+}
+
+/// The value as a command line argument, none for arrays and tables.
+fn plain(value: toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Datetime(value) => Some(value.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => None,
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::{HashSet, VecDeque};
+use xxhash_rust::xxh64::xxh64;
+
+/// Hashes of the last frames broadcast, to tell when one is sent again.
+///
+/// Only the hashes are kept, 64 bits each, so memory stays constant whatever the size of the
+/// frames. Two different frames with the same hash are taken as the same, which is unlikely enough
+/// to not matter.
+#[derive(Debug)]
+pub(crate) struct Dedup {
+    window: usize,
+    order: VecDeque<u64>,
+    seen: HashSet<u64>,
+}
+
+impl Dedup {
+    /// Remembers the last `window` frames, at least one.
+    pub(crate) fn new(window: usize) -> Self {
+        let window = window.max(1);
+
+        Self {
+            window,
+            order: VecDeque::with_capacity(window),
+            seen: HashSet::with_capacity(window),
+        }
+    }
+
+    /// Whether `frame` is among the last ones, if not it is remembered in place of the oldest.
+    pub(crate) fn repeated(&mut self, frame: &[u8]) -> bool {
+        let hash = xxh64(frame, 0);
+
+        if self.seen.contains(&hash) {
+            return true;
+        }
+
+        if self.order.len() == self.window {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.order.push_back(hash);
+        self.seen.insert(hash);
+        false
+    }
+}
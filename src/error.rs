@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use std::{fmt, io};
+
+/// Reasons a [`Broadcaster`](crate::Broadcaster) can stop with.
+#[derive(Debug)]
+pub enum BroadcastError {
+    /// an address could not be resolved
+    Resolve { address: String, source: io::Error },
+    /// the remote could not be connected to
+    Connect { address: String, source: io::Error },
+    /// a local socket could not be bound
+    Bind { address: String, source: io::Error },
+    /// the TLS session with the remote could not be established
+    Tls { address: String, source: io::Error },
+    /// a certificate or key file could not be loaded
+    Certificate { path: PathBuf, source: io::Error },
+    /// a file to read data from could not be opened
+    Open { path: PathBuf, source: io::Error },
+    /// a remote is the address consumers connect to, the broadcaster would connect to itself
+    SelfConnect { local: String, remote: String },
+    /// any other I/O failure while broadcasting
+    Io(io::Error),
+}
+
+impl fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BroadcastError::Resolve { address, source } => {
+                write!(f, "failed to resolve {address}: {source}")
+            }
+            BroadcastError::Connect { address, source } => {
+                write!(f, "failed to connect to {address}: {source}")
+            }
+            BroadcastError::Bind { address, source } => {
+                write!(f, "failed to bind {address}: {source}")
+            }
+            BroadcastError::Tls { address, source } => {
+                write!(f, "TLS handshake with {address} failed: {source}")
+            }
+            BroadcastError::Certificate { path, source } => {
+                write!(f, "failed to load {}: {source}", path.display())
+            }
+            BroadcastError::Open { path, source } => {
+                write!(f, "failed to open {}: {source}", path.display())
+            }
+            BroadcastError::SelfConnect { local, remote } => write!(
+                f,
+                "remote {remote} is the local address {local} consumers connect to, the \
+                 broadcaster would connect to itself"
+            ),
+            BroadcastError::Io(source) => write!(f, "{source}"),
+        }
+    }
+}
+
+impl std::error::Error for BroadcastError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BroadcastError::Resolve { source, .. }
+            | BroadcastError::Connect { source, .. }
+            | BroadcastError::Bind { source, .. }
+            | BroadcastError::Tls { source, .. }
+            | BroadcastError::Certificate { source, .. }
+            | BroadcastError::Open { source, .. }
+            | BroadcastError::Io(source) => Some(source),
+            BroadcastError::SelfConnect { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for BroadcastError {
+    fn from(error: io::Error) -> Self {
+        BroadcastError::Io(error)
+    }
+}
@@ -0,0 +1,47 @@
+use crate::Remote;
+use std::net::SocketAddr;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+/// Events kept for receivers lagging behind, past that the oldest ones are lost to them.
+const CAPACITY: usize = 1024;
+
+/// Something that happened to the broadcast, see [`Broadcaster::events`](crate::Broadcaster::events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// the TCP listener for consumers, or the UDP socket sending to them, is bound to `addr`,
+    /// which tells the port the system picked for port 0
+    Listening { addr: SocketAddr },
+    /// a consumer got a slot, consumers over a Unix socket have an unspecified address
+    ClientConnected { addr: SocketAddr },
+    /// a consumer gave its slot back, after that many bytes were written to it
+    ClientDisconnected { addr: SocketAddr, bytes_sent: u64 },
+    /// a remote is being pulled from
+    RemoteConnected { remote: Remote },
+    /// a remote closed, failed or was read to the end
+    RemoteDisconnected { remote: Remote },
+    /// a chunk of `len` bytes went to that many consumers
+    ChunkBroadcast { len: usize, clients: usize },
+}
+
+/// Sending side of the events, cheap to clone.
+///
+/// Sending never waits: without receivers events are discarded, and receivers that fall behind
+/// lose the oldest ones.
+#[derive(Debug, Clone)]
+pub(crate) struct Events(Sender<Event>);
+
+impl Default for Events {
+    fn default() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl Events {
+    pub(crate) fn subscribe(&self) -> Receiver<Event> {
+        self.0.subscribe()
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        let _ = self.0.send(event);
+    }
+}
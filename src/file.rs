@@ -0,0 +1,90 @@
+use crate::BroadcastError;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::time::Sleep;
+use tracing::debug;
+
+/// File read as a remote, optionally starting over once it ends and pausing after each read.
+#[derive(Debug)]
+pub(crate) struct FileSource {
+    file: File,
+    looping: bool,
+    pace: Duration,
+    delay: Option<Pin<Box<Sleep>>>,
+    rewinding: bool,
+    /// whether anything was read since the last rewind, so an empty file does not loop forever
+    read_any: bool,
+}
+
+impl FileSource {
+    /// Opens the file at `path`, read over and over with `looping`, waiting `pace` after each read.
+    pub(crate) async fn open(
+        path: &Path,
+        looping: bool,
+        pace: Duration,
+    ) -> Result<Self, BroadcastError> {
+        let file = File::open(path)
+            .await
+            .map_err(|source| BroadcastError::Open {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        Ok(Self {
+            file,
+            looping,
+            pace,
+            delay: None,
+            rewinding: false,
+            read_any: false,
+        })
+    }
+}
+
+impl AsyncRead for FileSource {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if let Some(delay) = &mut this.delay {
+            ready!(delay.as_mut().poll(cx));
+            this.delay = None;
+        }
+
+        loop {
+            if this.rewinding {
+                ready!(Pin::new(&mut this.file).poll_complete(cx))?;
+                this.rewinding = false;
+            }
+
+            let filled = buf.filled().len();
+            ready!(Pin::new(&mut this.file).poll_read(cx, buf))?;
+
+            if buf.filled().len() > filled {
+                this.read_any = true;
+                if !this.pace.is_zero() {
+                    this.delay = Some(Box::pin(tokio::time::sleep(this.pace)));
+                }
+                return Poll::Ready(Ok(()));
+            }
+
+            if !this.looping || !this.read_any {
+                return Poll::Ready(Ok(()));
+            }
+
+            debug!("end of file, starting over");
+            Pin::new(&mut this.file).start_seek(SeekFrom::Start(0))?;
+            this.rewinding = true;
+            this.read_any = false;
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+use std::sync::Arc;
+use tokio_util::bytes::Bytes;
+
+/// Decides which chunks get broadcast, eg. to leave out heartbeats.
+///
+/// Closures taking a [`Bytes`] reference and returning whether to keep it are filters too.
+pub trait Filter: Send + Sync {
+    /// Whether `chunk` should be broadcast.
+    fn keep(&self, chunk: &Bytes) -> bool;
+}
+
+impl<F> Filter for F
+where
+    F: Fn(&Bytes) -> bool + Send + Sync,
+{
+    fn keep(&self, chunk: &Bytes) -> bool {
+        self(chunk)
+    }
+}
+
+/// Filter keeping every chunk, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeepAll;
+
+impl Filter for KeepAll {
+    fn keep(&self, _: &Bytes) -> bool {
+        true
+    }
+}
+
+/// Filter leaving out the chunks starting with a given prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropPrefix(pub Bytes);
+
+impl Filter for DropPrefix {
+    fn keep(&self, chunk: &Bytes) -> bool {
+        !chunk.starts_with(&self.0)
+    }
+}
+
+/// A filter that can be cloned and printed along the rest of the settings.
+#[derive(Clone)]
+pub(crate) struct SharedFilter(Arc<dyn Filter>);
+
+impl SharedFilter {
+    pub(crate) fn new(filter: impl Filter + 'static) -> Self {
+        Self(Arc::new(filter))
+    }
+
+    pub(crate) fn keep(&self, chunk: &Bytes) -> bool {
+        self.0.keep(chunk)
+    }
+}
+
+impl Default for SharedFilter {
+    fn default() -> Self {
+        Self::new(KeepAll)
+    }
+}
+
+impl fmt::Debug for SharedFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Filter")
+    }
+}
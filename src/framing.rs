@@ -0,0 +1,185 @@
+use std::io;
+use std::str::FromStr;
+use tokio_util::bytes::{BufMut, Bytes, BytesMut};
+use tokio_util::codec::length_delimited::LengthDelimitedCodecError;
+use tokio_util::codec::{Decoder, LengthDelimitedCodec};
+
+/// Byte order of a length prefix.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+impl FromStr for Endian {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "big" => Ok(Endian::Big),
+            "little" => Ok(Endian::Little),
+            _ => Err(format!(
+                "unsupported byte order: {s}, expected big or little"
+            )),
+        }
+    }
+}
+
+/// How the bytes from the remote are split into the chunks handed to consumers.
+///
+/// Each chunk is a whole message and is forwarded as it arrived, framing included, so consumers
+/// never get a partial message, not even when they join late or fall behind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// no message boundaries, whatever a read returns is a chunk
+    #[default]
+    Raw,
+    /// each message is preceded by its length, in a field of `width` bytes (1, 2, 4 or 8)
+    LengthPrefixed { width: usize, endian: Endian },
+    /// each message ends with the `delimiter` byte, a trailing partial message is forwarded on EOF
+    /// only with `flush_partial`
+    Line { delimiter: u8, flush_partial: bool },
+}
+
+impl Framing {
+    /// Wraps `payload` into a whole message: after its length, or before the delimiter, and as is
+    /// without framing.
+    pub fn frame(&self, payload: &[u8]) -> Bytes {
+        match *self {
+            Framing::Raw => Bytes::copy_from_slice(payload),
+            Framing::LengthPrefixed { width, endian } => {
+                let mut frame = BytesMut::with_capacity(width + payload.len());
+                match endian {
+                    Endian::Big => frame.put_uint(payload.len() as u64, width),
+                    Endian::Little => frame.put_uint_le(payload.len() as u64, width),
+                }
+                frame.put_slice(payload);
+                frame.freeze()
+            }
+            Framing::Line { delimiter, .. } => {
+                let mut frame = BytesMut::with_capacity(payload.len() + 1);
+                frame.put_slice(payload);
+                frame.put_u8(delimiter);
+                frame.freeze()
+            }
+        }
+    }
+
+    /// Decoder failing on messages over `max_message_size` bytes, without the prefix or the
+    /// delimiter, before anything gets buffered for them.
+    pub(crate) fn decoder(&self, max_message_size: usize) -> FrameDecoder {
+        match *self {
+            Framing::Raw => FrameDecoder::Raw,
+            Framing::LengthPrefixed { width, endian } => {
+                let mut builder = LengthDelimitedCodec::builder();
+                // keep the prefix in the frame, the length counts only what follows it
+                builder
+                    .length_field_length(width)
+                    .length_adjustment(width as isize)
+                    .num_skip(0)
+                    .max_frame_length(max_message_size);
+
+                if endian == Endian::Little {
+                    builder.little_endian();
+                }
+
+                FrameDecoder::LengthPrefixed {
+                    codec: builder.new_codec(),
+                    max_length: max_message_size,
+                }
+            }
+            Framing::Line {
+                delimiter,
+                flush_partial,
+            } => FrameDecoder::Line {
+                delimiter,
+                flush_partial,
+                max_length: max_message_size,
+                scanned: 0,
+            },
+        }
+    }
+}
+
+/// Splits the read buffer into frames, according to a [`Framing`].
+#[derive(Debug)]
+pub(crate) enum FrameDecoder {
+    Raw,
+    LengthPrefixed {
+        codec: LengthDelimitedCodec,
+        max_length: usize,
+    },
+    Line {
+        delimiter: u8,
+        flush_partial: bool,
+        /// longest line buffered while waiting for its delimiter
+        max_length: usize,
+        /// bytes already known not to contain the delimiter
+        scanned: usize,
+    },
+}
+
+impl Decoder for FrameDecoder {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, io::Error> {
+        match self {
+            FrameDecoder::Raw if src.is_empty() => Ok(None),
+            FrameDecoder::Raw => Ok(Some(src.split())),
+            FrameDecoder::LengthPrefixed { codec, max_length } => codec.decode(src).map_err(|e| {
+                match e
+                    .get_ref()
+                    .is_some_and(|e| e.is::<LengthDelimitedCodecError>())
+                {
+                    true => too_large(*max_length),
+                    false => e,
+                }
+            }),
+            FrameDecoder::Line {
+                delimiter,
+                max_length,
+                scanned,
+                ..
+            } => match src[*scanned..].iter().position(|b| b == delimiter) {
+                Some(position) => {
+                    let end = *scanned + position + 1;
+                    *scanned = 0;
+                    Ok(Some(src.split_to(end)))
+                }
+                None if src.len() > *max_length => Err(too_large(*max_length)),
+                None => {
+                    *scanned = src.len();
+                    Ok(None)
+                }
+            },
+        }
+    }
+
+    /// Whatever is left once the reader is done is an incomplete frame, only lines can be flushed.
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, io::Error> {
+        if let Some(frame) = self.decode(src)? {
+            return Ok(Some(frame));
+        }
+
+        match self {
+            FrameDecoder::Line {
+                flush_partial: true,
+                scanned,
+                ..
+            } if !src.is_empty() => {
+                *scanned = 0;
+                Ok(Some(src.split()))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn too_large(max_length: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("message over the maximum size of {max_length} bytes"),
+    )
+}
@@ -0,0 +1,358 @@
+use crate::net::{Accept, UnixSocket};
+use crate::{BroadcastError, Config, Hub};
+use std::io::{self, Error, ErrorKind};
+use std::mem::size_of;
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::sync::Mutex;
+use tokio::io::Interest;
+use tokio::net::{TcpListener, TcpStream, UnixStream};
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+/// Most descriptors passed in a single message, well below what the kernel takes.
+const FDS_PER_MESSAGE: usize = 64;
+
+/// Byte sent along with each batch of descriptors, telling whether more batches follow.
+const MORE: u8 = 1;
+const LAST: u8 = 0;
+
+/// What a broadcaster gets from the one it takes over from: the listener for consumers and the
+/// consumers connected to it.
+#[derive(Debug)]
+pub(crate) struct HandedOver {
+    pub(crate) listener: TcpListener,
+    pub(crate) clients: Vec<TcpStream>,
+}
+
+/// Takes the listener and the consumers over from the broadcaster waiting for a successor at
+/// `path`, once it gets asked to hand them over.
+///
+/// Returns `None` right away if there is no broadcaster there, or once it goes away without
+/// handing anything over.
+pub(crate) async fn take_over(path: &Path) -> Result<Option<HandedOver>, BroadcastError> {
+    let failed = |source| BroadcastError::Connect {
+        address: path.display().to_string(),
+        source,
+    };
+
+    let stream = match UnixStream::connect(path).await {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            debug!(
+                "no broadcaster to take over from at {}: {e}",
+                path.display()
+            );
+            return Ok(None);
+        }
+        Err(e) => return Err(failed(e)),
+    };
+    info!(
+        "waiting for the broadcaster at {} to hand over",
+        path.display()
+    );
+
+    let mut fds = Vec::new();
+    loop {
+        let (flag, batch) = stream
+            .async_io(Interest::READABLE, || recv_fds(stream.as_raw_fd()))
+            .await
+            .map_err(failed)?;
+        fds.extend(batch);
+
+        match flag {
+            Some(MORE) => continue,
+            Some(_) => break,
+            None if fds.is_empty() => {
+                info!("{} went away without handing over", path.display());
+                return Ok(None);
+            }
+            None => {
+                return Err(failed(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "handover cut short",
+                )))
+            }
+        }
+    }
+
+    let mut fds = fds.into_iter();
+    let Some(listener) = fds.next() else {
+        return Err(failed(Error::new(
+            ErrorKind::InvalidData,
+            "no listener handed over",
+        )));
+    };
+
+    let listener = std::net::TcpListener::from(listener);
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+
+    let mut clients = Vec::with_capacity(fds.len());
+    for fd in fds {
+        let stream = std::net::TcpStream::from(fd);
+        stream.set_nonblocking(true)?;
+        clients.push(TcpStream::from_std(stream)?);
+    }
+
+    info!("took {} consumers over", clients.len());
+    Ok(Some(HandedOver { listener, clients }))
+}
+
+/// Waits at a Unix socket for a successor to connect, and for the broadcaster to be asked to hand
+/// over to it.
+#[derive(Debug)]
+pub(crate) struct Handover {
+    socket: UnixSocket,
+    listener: OwnedFd,
+}
+
+impl Handover {
+    /// Binds the socket at `path` successors connect to, replacing the one of a previous run,
+    /// `listener` is what they get to accept consumers on.
+    pub(crate) fn bind(path: &Path, listener: OwnedFd) -> Result<Self, BroadcastError> {
+        let socket = UnixSocket::bind(path)?;
+        info!("waiting for successors on {}", socket.path().display());
+        Ok(Self { socket, listener })
+    }
+
+    /// Completes with the successor to hand over to, once `trigger` is notified and one is
+    /// connected. Being asked while none is only gets a warning.
+    ///
+    /// The consumers of `hub` are taken for the successor then, nothing more gets written to them
+    /// than what they have pending. The socket is removed before it returns, so the successor can
+    /// bind it for its own.
+    pub(crate) async fn successor(self, trigger: &Notify, hub: &Hub) -> Successor {
+        let mut successor = None;
+
+        loop {
+            tokio::select! {
+                accepted = self.socket.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        info!("a successor is waiting for the handover");
+                        successor = Some(stream);
+                    }
+                    Err(e) => warn!("when accepting successors: {e}"),
+                },
+                _ = trigger.notified() => match successor.take() {
+                    Some(stream) => {
+                        return Successor {
+                            stream,
+                            listener: self.listener,
+                            clients: hub.hand_over(),
+                        }
+                    }
+                    None => warn!(
+                        "asked to hand over, but no successor is waiting on {}",
+                        self.socket.path().display()
+                    ),
+                },
+            }
+        }
+    }
+}
+
+/// The broadcaster taking over, along with what it gets.
+#[derive(Debug)]
+pub(crate) struct Successor {
+    stream: UnixStream,
+    listener: OwnedFd,
+    clients: Vec<OwnedFd>,
+}
+
+impl Successor {
+    /// Sends the listener then the consumers, see [`take_over`] for the other end.
+    pub(crate) async fn hand_over(self) -> io::Result<()> {
+        let fds: Vec<RawFd> = std::iter::once(&self.listener)
+            .chain(&self.clients)
+            .map(AsRawFd::as_raw_fd)
+            .collect();
+        let batches: Vec<&[RawFd]> = fds.chunks(FDS_PER_MESSAGE).collect();
+
+        for (i, batch) in batches.iter().enumerate() {
+            let flag = if i + 1 == batches.len() { LAST } else { MORE };
+            self.stream
+                .async_io(Interest::WRITABLE, || {
+                    send_fds(self.stream.as_raw_fd(), batch, flag)
+                })
+                .await?;
+        }
+
+        info!("handed {} consumers over", self.clients.len());
+        Ok(())
+    }
+}
+
+/// Sends `flag` along with the descriptors in `fds`, at most [`FDS_PER_MESSAGE`] of them.
+fn send_fds(socket: RawFd, fds: &[RawFd], flag: u8) -> io::Result<()> {
+    let flag = [flag];
+    let mut iov = libc::iovec {
+        iov_base: flag.as_ptr() as *mut libc::c_void,
+        iov_len: flag.len(),
+    };
+
+    let len = std::mem::size_of_val(fds) as u32;
+    // SAFETY: only computes a size
+    let space = unsafe { libc::CMSG_SPACE(len) } as usize;
+    // u64s, so the control message headers are aligned
+    let mut control = vec![0u64; space.div_ceil(size_of::<u64>())];
+
+    // SAFETY: all zeroes is a valid, empty, message header
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    if !fds.is_empty() {
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = space as _;
+
+        // SAFETY: the control buffer has room for a header followed by every descriptor
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(len) as _;
+
+            let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+            for (i, fd) in fds.iter().enumerate() {
+                data.add(i).write_unaligned(*fd);
+            }
+        }
+    }
+
+    // SAFETY: the message points to buffers alive until the call returns
+    match unsafe { libc::sendmsg(socket, &msg, 0) } {
+        -1 => Err(Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Receives a flag along with the descriptors sent with it, no flag once the sender is gone.
+fn recv_fds(socket: RawFd) -> io::Result<(Option<u8>, Vec<OwnedFd>)> {
+    let mut flag = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: flag.as_mut_ptr().cast(),
+        iov_len: flag.len(),
+    };
+
+    // SAFETY: only computes a size
+    let space = unsafe { libc::CMSG_SPACE((FDS_PER_MESSAGE * size_of::<RawFd>()) as u32) };
+    let mut control = vec![0u64; (space as usize).div_ceil(size_of::<u64>())];
+
+    // SAFETY: all zeroes is a valid, empty, message header
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = space as _;
+
+    // the descriptors are not meant for the programs this one may run
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let flags = libc::MSG_CMSG_CLOEXEC;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let flags = 0;
+
+    // SAFETY: the message points to buffers alive until the call returns
+    let received = unsafe { libc::recvmsg(socket, &mut msg, flags) };
+    if received == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut fds = Vec::new();
+    // SAFETY: the headers are the ones the kernel filled the control buffer with
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+
+                for i in 0..len / size_of::<RawFd>() {
+                    // the descriptors passed are this process' own now
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "more descriptors than expected in a message",
+        ));
+    }
+
+    match received {
+        0 => Ok((None, fds)),
+        _ => Ok((Some(flag[0]), fds)),
+    }
+}
+
+/// Listener for consumers that first yields the ones taken over from the previous broadcaster.
+///
+/// With `keep`, every consumer accepted on it is kept for the next handover, see
+/// [`Accept::hand_over`].
+#[derive(Debug)]
+pub(crate) struct Adopting {
+    listener: TcpListener,
+    adopted: Mutex<Vec<TcpStream>>,
+    keep: bool,
+}
+
+impl Adopting {
+    pub(crate) fn new(listener: TcpListener, adopted: Vec<TcpStream>, keep: bool) -> Self {
+        Self {
+            listener,
+            adopted: Mutex::new(adopted),
+            keep,
+        }
+    }
+
+    /// Copy of the listener descriptor, to hand it over along with the consumers.
+    pub(crate) fn listener_fd(&self) -> io::Result<OwnedFd> {
+        socket2::SockRef::from(&self.listener)
+            .try_clone()
+            .map(OwnedFd::from)
+    }
+}
+
+impl Accept for Adopting {
+    type Stream = TcpStream;
+
+    async fn accept(&self) -> io::Result<(TcpStream, Option<SocketAddr>)> {
+        loop {
+            let adopted = self.adopted.lock().expect("adopted lock poisoned").pop();
+            let Some(stream) = adopted else {
+                return Accept::accept(&self.listener).await;
+            };
+
+            match stream.peer_addr() {
+                Ok(addr) => {
+                    debug!("taking over {addr}");
+                    return Ok((stream, Some(addr)));
+                }
+                Err(e) => debug!("consumer gone before it was taken over: {e}"),
+            }
+        }
+    }
+
+    fn configure(stream: &TcpStream, config: &Config) {
+        TcpListener::configure(stream, config);
+    }
+
+    fn hand_over(&self, stream: &TcpStream) -> Option<OwnedFd> {
+        if !self.keep {
+            return None;
+        }
+
+        match socket2::SockRef::from(stream).try_clone() {
+            Ok(socket) => Some(OwnedFd::from(socket)),
+            Err(e) => {
+                warn!("keeping the consumer for a handover: {e}");
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::io::{self, Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio_util::bytes::{Buf, BytesMut};
+
+/// Longest response head taken from the remote, status line and headers.
+const MAX_HEAD: usize = 16 * 1024;
+
+/// Most headers taken in the response head.
+const MAX_HEADERS: usize = 64;
+
+/// Most bytes read from the remote at a time.
+const READ_SIZE: usize = 8 * 1024;
+
+/// Body of the response to a `GET` of an HTTP remote, as it is for most, or only the data of each
+/// event for Server-Sent Events, each line of it followed by a newline, so line framing gets one
+/// per frame for events of a single line.
+///
+/// Bodies with a length end there, others once the remote closes, chunked ones are taken out of
+/// their chunks first.
+pub(crate) struct HttpBody<S> {
+    stream: S,
+    /// read from the stream, still to be decoded
+    raw: BytesMut,
+    body: Body,
+    /// decoded body of an event stream, still to be split into events
+    lines: BytesMut,
+    /// data of the event being received, an event stream only
+    event: Option<BytesMut>,
+    /// decoded, ready to be read
+    out: BytesMut,
+    done: bool,
+}
+
+/// How the end of the body is told.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Body {
+    /// bytes left
+    Length(u64),
+    /// chunks, at the given part of one
+    Chunked(Chunk),
+    UntilClose,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chunk {
+    Size,
+    /// bytes left of the data
+    Data(u64),
+    /// the line ending after the data
+    End,
+    /// the last one, only trailers left
+    Last,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> HttpBody<S> {
+    /// Sends a `GET` of `path` to `host`, the `host:port` the stream is connected to, and reads
+    /// the head of the response, failing unless it is a success.
+    pub(crate) async fn get(mut stream: S, host: &str, path: &str) -> io::Result<Self> {
+        let request = format!(
+            "GET {path} HTTP/1.1\r\nHost: {host}\r\nAccept: text/event-stream, */*\r\n\
+             Cache-Control: no-cache\r\nConnection: close\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).await?;
+        stream.flush().await?;
+
+        let mut raw = BytesMut::with_capacity(4096);
+        let (end, body, event_stream) = loop {
+            if let Some(head) = parse_head(&raw)? {
+                break head;
+            }
+            if raw.len() > MAX_HEAD {
+                return Err(invalid("response head too long"));
+            }
+            if stream.read_buf(&mut raw).await? == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "closed before the response head",
+                ));
+            }
+        };
+        raw.advance(end);
+
+        Ok(Self {
+            stream,
+            raw,
+            body,
+            lines: BytesMut::new(),
+            event: event_stream.then(BytesMut::new),
+            out: BytesMut::new(),
+            done: false,
+        })
+    }
+}
+
+/// Parses the response head at the start of `raw` once it is all there: its length, how the body
+/// ends and whether it is an event stream. Fails on any response but a success.
+fn parse_head(raw: &[u8]) -> io::Result<Option<(usize, Body, bool)>> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut response = httparse::Response::new(&mut headers);
+
+    let end = match response.parse(raw) {
+        Ok(httparse::Status::Complete(end)) => end,
+        Ok(httparse::Status::Partial) => return Ok(None),
+        Err(e) => return Err(invalid(&format!("malformed response head: {e}"))),
+    };
+
+    let code = response.code.unwrap_or_default();
+    if code != 200 {
+        let reason = response.reason.unwrap_or_default();
+        return Err(invalid(&format!("unexpected response: {code} {reason}")));
+    }
+
+    let mut body = Body::UntilClose;
+    let mut event_stream = false;
+    for header in response.headers.iter() {
+        let value = std::str::from_utf8(header.value)
+            .map_err(|_| invalid("header value not UTF-8"))?
+            .trim();
+
+        match header.name.to_ascii_lowercase().as_str() {
+            "transfer-encoding" if value.eq_ignore_ascii_case("chunked") => {
+                body = Body::Chunked(Chunk::Size)
+            }
+            "content-length" if body == Body::UntilClose => {
+                body = Body::Length(value.parse().map_err(|_| invalid("bad content length"))?)
+            }
+            "content-type" => {
+                event_stream = value
+                    .split(';')
+                    .next()
+                    .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("text/event-stream"))
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Some((end, body, event_stream)))
+}
+
+impl<S> HttpBody<S> {
+    /// Decodes what was read so far, returns whether the body is over.
+    fn decode(&mut self) -> io::Result<bool> {
+        let over = self.dechunk()?;
+
+        if let Some(event) = &mut self.event {
+            while let Some(at) = self.lines.iter().position(|&b| b == b'\n') {
+                let mut line = self.lines.split_to(at + 1);
+                line.truncate(at);
+                if line.last() == Some(&b'\r') {
+                    line.truncate(at - 1);
+                }
+
+                // a blank line ends the event, only data fields are kept, a line each
+                if line.is_empty() {
+                    if !event.is_empty() {
+                        self.out.extend_from_slice(&event.split());
+                    }
+                } else if let Some(data) = line.strip_prefix(b"data:") {
+                    event.extend_from_slice(data.strip_prefix(b" ").unwrap_or(data));
+                    event.extend_from_slice(b"\n");
+                }
+            }
+        }
+
+        Ok(over)
+    }
+
+    /// Moves the bytes of the body out of the raw ones, returns whether it is over.
+    fn dechunk(&mut self) -> io::Result<bool> {
+        let body = match self.event {
+            Some(_) => &mut self.lines,
+            None => &mut self.out,
+        };
+
+        loop {
+            match &mut self.body {
+                Body::UntilClose => {
+                    body.extend_from_slice(&self.raw.split());
+                    return Ok(false);
+                }
+                Body::Length(left) => {
+                    let n = self.raw.len().min(*left as usize);
+                    body.extend_from_slice(&self.raw.split_to(n));
+                    *left -= n as u64;
+                    return Ok(*left == 0);
+                }
+                Body::Chunked(chunk) => match chunk {
+                    Chunk::Size => {
+                        let Some(at) = self.raw.windows(2).position(|w| w == b"\r\n") else {
+                            return Ok(false);
+                        };
+                        let line = self.raw.split_to(at + 2);
+                        let size = std::str::from_utf8(&line[..at])
+                            .ok()
+                            .and_then(|line| line.split(';').next())
+                            .and_then(|size| u64::from_str_radix(size.trim(), 16).ok())
+                            .ok_or_else(|| invalid("bad chunk size"))?;
+
+                        *chunk = match size {
+                            0 => Chunk::Last,
+                            size => Chunk::Data(size),
+                        };
+                    }
+                    Chunk::Data(left) => {
+                        if self.raw.is_empty() {
+                            return Ok(false);
+                        }
+                        let n = self.raw.len().min(*left as usize);
+                        body.extend_from_slice(&self.raw.split_to(n));
+                        *left -= n as u64;
+
+                        if *left == 0 {
+                            *chunk = Chunk::End;
+                        }
+                    }
+                    Chunk::End => {
+                        if self.raw.len() < 2 {
+                            return Ok(false);
+                        }
+                        if &self.raw[..2] != b"\r\n" {
+                            return Err(invalid("chunk not followed by a line ending"));
+                        }
+                        self.raw.advance(2);
+                        *chunk = Chunk::Size;
+                    }
+                    Chunk::Last => return Ok(true),
+                },
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for HttpBody<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if !this.out.is_empty() {
+                let n = this.out.len().min(buf.remaining());
+                buf.put_slice(&this.out.split_to(n));
+                return Poll::Ready(Ok(()));
+            }
+            if this.done {
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.decode()? {
+                this.done = true;
+                continue;
+            }
+            if !this.out.is_empty() {
+                continue;
+            }
+
+            // nothing decoded yet, like a chunk size or an event split across reads
+            let mut read = [0u8; READ_SIZE];
+            let mut read = ReadBuf::new(&mut read);
+            ready!(Pin::new(&mut this.stream).poll_read(cx, &mut read))?;
+
+            // a body that should have gone on is cut short, as far as it got anyway
+            if read.filled().is_empty() {
+                this.done = true;
+            }
+            this.raw.extend_from_slice(read.filled());
+        }
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, reason)
+}
@@ -0,0 +1,864 @@
+use crate::capture::Recorder;
+use crate::dedup::Dedup;
+use crate::event::Events;
+use crate::filter::SharedFilter;
+use crate::output::{Records, Sequence};
+use crate::share::{Feed, Shares};
+use crate::topic::topic_of;
+use crate::transform::SharedTransform;
+use crate::upstream::Upstream;
+use crate::{Checksum, Event, Framing, Metrics, PauseMode, Remote, ReverseDns, TokenBucket};
+use rand::Rng;
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::io::OwnedFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::SendError, Receiver, Sender};
+use tokio::sync::watch;
+use tokio_util::bytes::Bytes;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+/// Shared state between the producer and the consumers, cheap to clone.
+///
+/// Wraps the broadcast channel together with the replay history, so that new consumers get the
+/// history and the live data without gaps or duplicates. Also keeps track of who is connected.
+///
+/// Publishing never waits on consumers: each one has a task of its own taking chunks from the
+/// channel into its own queue, so a slow or blocked consumer only holds itself up, and the
+/// remotes are read as fast as they send, or as the rate limit allows. The time from a read to the
+/// chunk being available to every consumer is that of a lock and a channel send, whatever their
+/// number or speed.
+#[derive(Debug, Clone)]
+pub struct Hub {
+    tx: Sender<Bytes>,
+    replay: Arc<Mutex<Replay>>,
+    /// what came while no consumer was connected, for the next one, locked after the replay
+    pending: Option<Arc<Mutex<Replay>>>,
+    metrics: Arc<Metrics>,
+    transform: SharedTransform,
+    filter: SharedFilter,
+    rate_limit: Option<Arc<Mutex<TokenBucket>>>,
+    max_bytes: Option<Arc<Budget>>,
+    /// time each chunk is held before it is published, and the most held at random on top
+    injected_delay: Option<(Duration, Duration)>,
+    registry: Arc<Mutex<Registry>>,
+    upstream: Option<Upstream>,
+    events: Events,
+    checksum: Option<Checksum>,
+    records: Option<Arc<Records>>,
+    sequence: Option<Arc<Sequence>>,
+    capture: Option<Recorder>,
+    dedup: Option<Arc<Mutex<Dedup>>>,
+    /// how the chunks are framed, to tell their topic
+    topic_framing: Framing,
+    /// time between replays, and when the next one can start
+    replay_stagger: Option<(Duration, Arc<Mutex<tokio::time::Instant>>)>,
+    reverse_dns: Option<ReverseDns>,
+    shares: Option<Arc<Mutex<Shares>>>,
+    paused: Arc<watch::Sender<bool>>,
+    pause_mode: PauseMode,
+    /// remote to pull from instead, once one is set through the admin server
+    swap: Arc<watch::Sender<Option<Remote>>>,
+    /// number of consumers getting the broadcast, to wait for enough of them
+    present: Arc<watch::Sender<usize>>,
+    min_clients: usize,
+}
+
+impl Hub {
+    /// Creates a hub retaining up to `capacity` chunks for lagging consumers and the last
+    /// `replay_bytes` bytes for consumers joining late.
+    pub fn new(capacity: usize, replay_bytes: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+
+        Self {
+            tx,
+            replay: Arc::new(Mutex::new(Replay::new(replay_bytes))),
+            pending: None,
+            metrics: Arc::new(Metrics::default()),
+            transform: SharedTransform::default(),
+            filter: SharedFilter::default(),
+            rate_limit: None,
+            max_bytes: None,
+            injected_delay: None,
+            registry: Arc::default(),
+            upstream: None,
+            events: Events::default(),
+            checksum: None,
+            records: None,
+            sequence: None,
+            capture: None,
+            dedup: None,
+            topic_framing: Framing::Raw,
+            replay_stagger: None,
+            reverse_dns: None,
+            shares: None,
+            paused: Arc::new(watch::Sender::new(false)),
+            pause_mode: PauseMode::default(),
+            swap: Arc::new(watch::Sender::new(None)),
+            present: Arc::new(watch::Sender::new(0)),
+            min_clients: 0,
+        }
+    }
+
+    /// Accounts for everything in `metrics` from now on, instead of in metrics of its own.
+    pub(crate) fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Leaves out the chunks `filter` does not keep from now on.
+    pub(crate) fn with_filter(mut self, filter: SharedFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Leaves out the chunks repeating any of the last `window` ones from now on, after the filter.
+    pub(crate) fn with_dedup(mut self, window: usize) -> Self {
+        self.dedup = Some(Arc::new(Mutex::new(Dedup::new(window))));
+        self
+    }
+
+    /// Keeps the last `bytes` bytes published while no consumer is connected from now on, for the
+    /// next one to join, which gets them after the history.
+    pub(crate) fn with_no_clients_buffer(mut self, bytes: usize) -> Self {
+        let whole = self.replay.lock().expect("replay lock poisoned").whole;
+        self.pending = Some(Arc::new(Mutex::new(Replay {
+            whole,
+            ..Replay::new(bytes)
+        })));
+        self
+    }
+
+    /// Evicts whole chunks from the replay history and the no-clients buffer from now on instead of
+    /// cutting the oldest one, for chunks that are framed messages.
+    pub(crate) fn with_whole_chunks(self) -> Self {
+        self.replay.lock().expect("replay lock poisoned").whole = true;
+        if let Some(pending) = &self.pending {
+            pending.lock().expect("pending lock poisoned").whole = true;
+        }
+        self
+    }
+
+    /// Tells the topic of the chunks as framed by `framing` from now on, see [`Hub::subscribed`].
+    pub(crate) fn with_topics(mut self, framing: Framing) -> Self {
+        self.topic_framing = framing;
+        self
+    }
+
+    /// Whether a consumer subscribed to `topic` gets `chunk`, any consumer does without one.
+    pub(crate) fn subscribed(&self, chunk: &[u8], topic: Option<&[u8]>) -> bool {
+        match topic {
+            Some(topic) => topic_of(chunk, self.topic_framing) == Some(topic),
+            None => true,
+        }
+    }
+
+    /// Has consumers getting the history take turns from now on, `stagger` apart.
+    pub(crate) fn with_replay_stagger(mut self, stagger: Duration) -> Self {
+        let next = tokio::time::Instant::now();
+        self.replay_stagger = Some((stagger, Arc::new(Mutex::new(next))));
+        self
+    }
+
+    /// Waits for the turn of a consumer to get the history, right away without a stagger or when
+    /// no other one got it lately.
+    pub(crate) async fn replay_turn(&self) {
+        let Some((stagger, next)) = &self.replay_stagger else {
+            return;
+        };
+
+        let now = tokio::time::Instant::now();
+        let turn = {
+            let mut next = next.lock().expect("replay stagger lock poisoned");
+            let turn = (*next).max(now);
+            *next = turn + *stagger;
+            turn
+        };
+
+        if turn > now {
+            debug!("waiting {:?} for the turn to replay", turn - now);
+            tokio::time::sleep_until(turn).await;
+        }
+    }
+
+    /// Looks the names of consumers up with `reverse_dns` to log them, from now on.
+    pub(crate) fn with_reverse_dns(mut self, reverse_dns: ReverseDns) -> Self {
+        self.reverse_dns = Some(reverse_dns);
+        self
+    }
+
+    pub(crate) fn reverse_dns(&self) -> Option<&ReverseDns> {
+        self.reverse_dns.as_ref()
+    }
+
+    /// Applies `transform` to every chunk published from now on.
+    pub(crate) fn with_transform(mut self, transform: SharedTransform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Paces the data coming into the hub to `bytes_per_sec`, shared by every remote.
+    pub(crate) fn with_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.rate_limit = Some(Arc::new(Mutex::new(TokenBucket::new(bytes_per_sec))));
+        self
+    }
+
+    /// Waits as long as needed for `n` more bytes to come in without exceeding the rate limit.
+    pub(crate) async fn throttle(&self, n: usize) {
+        let Some(rate_limit) = &self.rate_limit else {
+            return;
+        };
+
+        let wait = rate_limit
+            .lock()
+            .expect("rate limit lock poisoned")
+            .take(n, tokio::time::Instant::now());
+
+        if !wait.is_zero() {
+            debug!("rate limited, waiting {wait:?}");
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Holds each chunk read for `delay`, and up to `jitter` more at random, before it is published
+    /// from now on, to test consumers against a laggy feed.
+    pub(crate) fn with_injected_delay(mut self, delay: Duration, jitter: Duration) -> Self {
+        self.injected_delay = Some((delay, jitter));
+        self
+    }
+
+    /// Waits as long as the injected delay says before a chunk read gets published, right away
+    /// without one.
+    pub(crate) async fn injected_delay(&self) {
+        let Some((delay, jitter)) = self.injected_delay else {
+            return;
+        };
+
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=jitter);
+        tokio::time::sleep(delay + jitter).await;
+    }
+
+    /// Reads no more than `bytes` bytes from the remotes from now on, shared by every remote.
+    pub(crate) fn with_max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = Some(Arc::new(Budget {
+            left: AtomicU64::new(bytes),
+            spent: CancellationToken::new(),
+        }));
+        self
+    }
+
+    /// Bytes that can still be read from the remotes, none without a limit.
+    pub(crate) fn bytes_left(&self) -> Option<u64> {
+        let budget = self.max_bytes.as_ref()?;
+        Some(budget.left.load(Ordering::Relaxed))
+    }
+
+    /// Takes `n` bytes just read out of what can still be, returns how many of them fit, the rest
+    /// is over the limit.
+    pub(crate) fn take_bytes(&self, n: usize) -> usize {
+        let Some(budget) = &self.max_bytes else {
+            return n;
+        };
+
+        // never fails, the closure always gives a value
+        let left = budget
+            .left
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                Some(left.saturating_sub(n as u64))
+            })
+            .unwrap_or_else(|left| left);
+        left.min(n as u64) as usize
+    }
+
+    /// Tells the remotes every byte they could be read for was published, so they stop.
+    pub(crate) fn all_bytes_read(&self) {
+        if let Some(budget) = &self.max_bytes {
+            budget.spent.cancel();
+        }
+    }
+
+    /// Completes once every byte the remotes could be read for was published, never without a
+    /// limit.
+    pub(crate) async fn bytes_spent(&self) {
+        match &self.max_bytes {
+            Some(budget) => budget.spent.cancelled().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Frames every chunk published from now on with its `checksum`, after the transform.
+    pub(crate) fn with_checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// Wraps every chunk published from now on into a numbered JSON record, after the checksum,
+    /// see [`OutputFormat`](crate::OutputFormat). With `continued` the numbering goes on when a
+    /// remote connects again.
+    pub(crate) fn with_json_records(mut self, continued: bool) -> Self {
+        self.records = Some(Arc::new(Records::new(continued)));
+        self
+    }
+
+    /// Puts a sequence header before every chunk published from now on, last, see
+    /// [`Config::seq_header`](crate::Config::seq_header).
+    pub(crate) fn with_seq_header(mut self) -> Self {
+        self.sequence = Some(Arc::default());
+        self
+    }
+
+    /// Starts the numbering of the JSON records over, as a remote just connected.
+    pub(crate) fn restart_sequence(&self) {
+        if let Some(records) = &self.records {
+            records.restart();
+        }
+    }
+
+    /// Wraps `data` into the next JSON record, or after the next sequence header, if any.
+    fn wrap(&self, data: Bytes) -> Bytes {
+        let data = match &self.records {
+            Some(records) => records.wrap(&data),
+            None => data,
+        };
+
+        match &self.sequence {
+            Some(sequence) => sequence.prefix(&data),
+            None => data,
+        }
+    }
+
+    /// Deals each chunk published from now on to a single TCP consumer in turn, instead of to all
+    /// of them, each with a queue of `capacity` chunks.
+    pub(crate) fn with_round_robin(mut self, capacity: usize) -> Self {
+        self.shares = Some(Arc::new(Mutex::new(Shares::new(capacity))));
+        self
+    }
+
+    /// Records every chunk broadcast from now on with `capture`, as it goes out.
+    pub(crate) fn with_capture(mut self, capture: Recorder) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Reports what happens to `events` from now on.
+    pub(crate) fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub(crate) fn events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Carries what consumers write back to the remote from now on.
+    pub(crate) fn with_upstream(mut self) -> Self {
+        self.upstream = Some(Upstream::new());
+        self
+    }
+
+    /// Channel for what consumers write back to the remote, only in bidirectional mode.
+    pub(crate) fn upstream(&self) -> Option<&Upstream> {
+        self.upstream.as_ref()
+    }
+
+    /// What pausing does to the remotes from now on.
+    pub(crate) fn with_pause_mode(mut self, mode: PauseMode) -> Self {
+        self.pause_mode = mode;
+        self
+    }
+
+    /// Stops relaying to the consumers until resumed, they stay connected meanwhile. The remotes
+    /// are read on and what they send is left out, or they are held, as the [`PauseMode`] says.
+    ///
+    /// Returns false if it already was.
+    pub fn pause(&self) -> bool {
+        self.paused
+            .send_if_modified(|paused| !std::mem::replace(paused, true))
+    }
+
+    /// Relays to the consumers again, returns false if it was not paused.
+    pub fn resume(&self) -> bool {
+        self.paused
+            .send_if_modified(|paused| std::mem::replace(paused, false))
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Whether the remotes are to be left unread for now.
+    pub(crate) fn held(&self) -> bool {
+        self.pause_mode == PauseMode::Hold && self.is_paused()
+    }
+
+    /// Completes once the broadcast is not paused.
+    pub(crate) async fn resumed(&self) {
+        let mut paused = self.paused.subscribe();
+        let _ = paused.wait_for(|paused| !paused).await;
+    }
+
+    /// Has the remotes replaced by `remote`, the producer switches to it.
+    pub(crate) fn swap_remote(&self, remote: Remote) {
+        self.swap.send_replace(Some(remote));
+    }
+
+    /// Tells every remote set with [`Hub::swap_remote`] from now on.
+    pub(crate) fn remote_swaps(&self) -> watch::Receiver<Option<Remote>> {
+        self.swap.subscribe()
+    }
+
+    /// Leaves the remotes unread while fewer than `min_clients` consumers are connected, from now
+    /// on.
+    pub(crate) fn with_min_clients(mut self, min_clients: usize) -> Self {
+        self.min_clients = min_clients;
+        self
+    }
+
+    /// Whether there are too few consumers for the remotes to be read.
+    pub(crate) fn short_of_clients(&self) -> bool {
+        *self.present.borrow() < self.min_clients
+    }
+
+    /// Completes once there are enough consumers for the remotes to be read.
+    pub(crate) async fn enough_clients(&self) {
+        let mut present = self.present.subscribe();
+        let _ = present.wait_for(|&n| n >= self.min_clients).await;
+    }
+
+    /// Completes once there are too few consumers for the remotes to be read, never without a
+    /// minimum.
+    pub(crate) async fn too_few_clients(&self) {
+        if self.min_clients == 0 {
+            return std::future::pending().await;
+        }
+
+        let mut present = self.present.subscribe();
+        let _ = present.wait_for(|&n| n < self.min_clients).await;
+    }
+
+    /// Counts a consumer towards the minimum for as long as the guard is alive, taken once it gets
+    /// the broadcast, so nothing is read before it can get it.
+    fn present(&self) -> Present {
+        self.present.send_modify(|n| *n += 1);
+        Present(self.present.clone())
+    }
+
+    /// Number of consumers currently holding a [`ClientSlot`].
+    pub fn clients(&self) -> usize {
+        self.metrics.clients_connected()
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Takes a slot for a new consumer at `addr`, unless there are already `max_clients` of them.
+    ///
+    /// A `max_clients` of 0 means unlimited. The slot is given back when dropped.
+    pub fn try_join(&self, max_clients: usize, addr: SocketAddr) -> Option<ClientSlot> {
+        self.metrics
+            .clients()
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (max_clients == 0 || n < max_clients).then_some(n + 1)
+            })
+            .ok()?;
+
+        let connected_at = Instant::now();
+        let entry = Entry {
+            addr,
+            connected_at,
+            delivered: Arc::new(Delivered::new(connected_at, self.metrics.clone())),
+            kick: CancellationToken::new(),
+        };
+
+        let mut registry = self.registry.lock().expect("registry lock poisoned");
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.clients.insert(id, entry.clone());
+        drop(registry);
+
+        self.events.emit(Event::ClientConnected { addr });
+
+        Some(ClientSlot {
+            id,
+            entry,
+            metrics: self.metrics.clone(),
+            registry: self.registry.clone(),
+            events: self.events.clone(),
+        })
+    }
+
+    /// The consumers currently holding a [`ClientSlot`], in the order they connected.
+    pub fn connected(&self) -> Vec<ClientInfo> {
+        let registry = self.registry.lock().expect("registry lock poisoned");
+        registry.clients.values().map(Entry::info).collect()
+    }
+
+    /// Keeps `fd` to hand the consumer holding `slot` over to a successor, for as long as it does.
+    #[cfg(unix)]
+    pub(crate) fn keep_for_handover(&self, slot: &ClientSlot, fd: OwnedFd) {
+        let mut registry = self.registry.lock().expect("registry lock poisoned");
+        registry.sockets.insert(slot.id, fd);
+    }
+
+    /// Takes the consumers kept for a handover, from now on they get no more than what they
+    /// have pending, and no shutdown sentinel.
+    #[cfg(unix)]
+    pub(crate) fn hand_over(&self) -> Vec<OwnedFd> {
+        let mut registry = self.registry.lock().expect("registry lock poisoned");
+        registry.handing_over = true;
+        std::mem::take(&mut registry.sockets)
+            .into_values()
+            .collect()
+    }
+
+    /// Whether the consumers are being handed over to a successor.
+    pub(crate) fn handing_over(&self) -> bool {
+        let registry = self.registry.lock().expect("registry lock poisoned");
+        registry.handing_over
+    }
+
+    /// Disconnects the consumers at `addr`, returns how many there were.
+    pub fn kick(&self, addr: SocketAddr) -> usize {
+        let registry = self.registry.lock().expect("registry lock poisoned");
+        let kicked = registry.clients.values().filter(|entry| entry.addr == addr);
+
+        kicked.map(|entry| entry.kick.cancel()).count()
+    }
+
+    /// Filters, transforms, seals with the checksum and wraps into a JSON record if asked to, and
+    /// sends a chunk to every subscribed consumer, returns how many there were, or 0 if the chunk
+    /// got filtered out, repeats a recent one or the broadcast is paused. With round-robin
+    /// distribution the chunk goes to the next TCP consumer able to take it instead, and to every
+    /// subscriber that is not one.
+    pub fn publish(&self, data: Bytes) -> Result<usize, SendError<Bytes>> {
+        self.metrics.received(data.len());
+
+        if self.pause_mode == PauseMode::Drop && self.is_paused() {
+            debug!("paused, left out {} bytes", data.len());
+            return Ok(0);
+        }
+
+        if !self.filter.keep(&data) {
+            debug!("filtered out {} bytes", data.len());
+            self.metrics.filtered();
+            return Ok(0);
+        }
+
+        if let Some(dedup) = &self.dedup {
+            if dedup.lock().expect("dedup lock poisoned").repeated(&data) {
+                debug!("left out {} bytes repeating a recent chunk", data.len());
+                self.metrics.deduplicated();
+                return Ok(0);
+            }
+        }
+
+        let data = self.transform.apply(data);
+        let data = match self.checksum {
+            Some(checksum) => checksum.seal(&data),
+            None => data,
+        };
+
+        if let Some(shares) = &self.shares {
+            return self.deal(shares, data);
+        }
+
+        let mut replay = self.replay.lock().expect("replay lock poisoned");
+        // numbered under the lock, so the records are in the order consumers get them
+        let data = self.wrap(data);
+        let len = data.len();
+        // recorded under the lock, so the records are in the order consumers get the chunks
+        if let Some(capture) = &self.capture {
+            capture.record(&data);
+        }
+
+        // kept out of the history until a consumer gets it, see join, the tee file and the like
+        // being subscribed or not
+        if let Some(pending) = &self.pending {
+            if *self.present.borrow() == 0 {
+                debug!("no consumer connected, keeping {len} bytes for the next one");
+                pending.lock().expect("pending lock poisoned").push(data);
+                return Ok(0);
+            }
+        }
+
+        replay.push(data.clone());
+        let clients = self.tx.send(data)?;
+        drop(replay);
+
+        self.events.emit(Event::ChunkBroadcast { len, clients });
+        Ok(clients)
+    }
+
+    /// Hands a chunk to the consumer whose turn it is, there is no history of chunks meant for a
+    /// single consumer.
+    fn deal(&self, shares: &Mutex<Shares>, data: Bytes) -> Result<usize, SendError<Bytes>> {
+        let mut shares = shares.lock().expect("shares lock poisoned");
+        let data = self.wrap(data);
+        let len = data.len();
+        if let Some(capture) = &self.capture {
+            capture.record(&data);
+        }
+        // the tee file and the other kinds of consumers still get everything
+        let _ = self.tx.send(data.clone());
+
+        // kept for the next consumer to take its turn, see feed
+        if let Some(pending) = &self.pending {
+            if *self.present.borrow() == 0 {
+                debug!("no consumer connected, keeping {len} bytes for the next one");
+                pending.lock().expect("pending lock poisoned").push(data);
+                return Ok(0);
+            }
+        }
+
+        shares.deal(data).map_err(SendError)?;
+        drop(shares);
+
+        self.events.emit(Event::ChunkBroadcast { len, clients: 1 });
+        Ok(1)
+    }
+
+    /// Takes the chunks for a new TCP consumer, its own turn with round-robin distribution, or
+    /// the broadcast and its history otherwise, see [`Hub::join`].
+    pub(crate) fn feed(&self) -> (Feed, Vec<Bytes>, Present) {
+        let Some(shares) = &self.shares else {
+            let (rx, history, present) = self.join();
+            return (Feed::Broadcast(rx), history, present);
+        };
+
+        // under the lock chunks are dealt with, so none is kept for a consumer already there
+        let mut shares = shares.lock().expect("shares lock poisoned");
+        let history = match &self.pending {
+            Some(pending) => pending.lock().expect("pending lock poisoned").take(),
+            None => Vec::new(),
+        };
+        (Feed::Share(shares.join()), history, self.present())
+    }
+
+    /// Subscribes a new consumer and counts it as connected, returns the live receiver, the
+    /// history to write before it and the guard counting it.
+    ///
+    /// What was kept while no consumer was connected goes to this one, after the history, and
+    /// into the history for the ones after it. It is counted under the same lock chunks are
+    /// kept under, so none is kept once it has subscribed.
+    pub(crate) fn join(&self) -> (Receiver<Bytes>, Vec<Bytes>, Present) {
+        let mut replay = self.replay.lock().expect("replay lock poisoned");
+        let mut history = replay.history();
+
+        if let Some(pending) = &self.pending {
+            let mut pending = pending.lock().expect("pending lock poisoned");
+            for data in pending.take() {
+                replay.push(data.clone());
+                history.push(data);
+            }
+        }
+
+        (self.tx.subscribe(), history, self.present())
+    }
+
+    /// Subscribes to the broadcast, returns the live receiver and the history to write before
+    /// it.
+    ///
+    /// Unlike consumers joining, subscribers like the tee file are not counted as connected, and
+    /// do not get what is kept while none is.
+    pub fn subscribe(&self) -> (Receiver<Bytes>, Vec<Bytes>) {
+        let replay = self.replay.lock().expect("replay lock poisoned");
+        (self.tx.subscribe(), replay.history())
+    }
+}
+
+/// Bytes the remotes can still be read for, and whether they all were published.
+#[derive(Debug)]
+struct Budget {
+    left: AtomicU64,
+    spent: CancellationToken,
+}
+
+/// A consumer getting the broadcast, see [`Hub::present`].
+#[derive(Debug)]
+pub(crate) struct Present(Arc<watch::Sender<usize>>);
+
+impl Drop for Present {
+    fn drop(&mut self) {
+        self.0.send_modify(|n| *n -= 1);
+    }
+}
+
+/// Accounts for a connected consumer for as long as it is alive.
+#[derive(Debug)]
+pub struct ClientSlot {
+    id: u64,
+    entry: Entry,
+    metrics: Arc<Metrics>,
+    registry: Arc<Mutex<Registry>>,
+    events: Events,
+}
+
+impl ClientSlot {
+    /// What was written to the consumer, shown while it is connected.
+    pub(crate) fn delivered(&self) -> Arc<Delivered> {
+        self.entry.delivered.clone()
+    }
+
+    /// Triggered once the consumer gets kicked out.
+    pub(crate) fn kicked(&self) -> CancellationToken {
+        self.entry.kick.clone()
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        let mut registry = self.registry.lock().expect("registry lock poisoned");
+        registry.clients.remove(&self.id);
+        #[cfg(unix)]
+        registry.sockets.remove(&self.id);
+        self.metrics.clients().fetch_sub(1, Ordering::Relaxed);
+        drop(registry);
+
+        self.events.emit(Event::ClientDisconnected {
+            addr: self.entry.addr,
+            bytes_sent: self.entry.delivered.bytes_sent(),
+        });
+    }
+}
+
+/// A connected consumer, as seen from the outside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub addr: SocketAddr,
+    /// bytes written to the consumer so far
+    pub bytes_sent: u64,
+    /// how long the consumer has been connected
+    pub connected_for: Duration,
+}
+
+/// Consumers holding a slot, by the order they got it.
+#[derive(Debug, Default)]
+struct Registry {
+    next_id: u64,
+    clients: BTreeMap<u64, Entry>,
+    /// copies of the consumer sockets to hand over to a successor, only with a handover socket
+    #[cfg(unix)]
+    sockets: BTreeMap<u64, OwnedFd>,
+    handing_over: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    addr: SocketAddr,
+    connected_at: Instant,
+    delivered: Arc<Delivered>,
+    kick: CancellationToken,
+}
+
+impl Entry {
+    fn info(&self) -> ClientInfo {
+        ClientInfo {
+            addr: self.addr,
+            bytes_sent: self.delivered.bytes_sent(),
+            connected_for: self.connected_at.elapsed(),
+        }
+    }
+}
+
+/// Bytes written to a consumer so far, counted by the task writing to it as they go. How long the
+/// first of them took to get there since the consumer was accepted goes to the metrics.
+#[derive(Debug)]
+pub(crate) struct Delivered {
+    accepted_at: Instant,
+    bytes_sent: AtomicU64,
+    first_byte: OnceLock<Duration>,
+    metrics: Arc<Metrics>,
+}
+
+impl Delivered {
+    fn new(accepted_at: Instant, metrics: Arc<Metrics>) -> Self {
+        Self {
+            accepted_at,
+            bytes_sent: AtomicU64::new(0),
+            first_byte: OnceLock::new(),
+            metrics,
+        }
+    }
+
+    /// Counts `n` more bytes written, the first ones give the first byte latency.
+    pub(crate) fn sent(&self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        self.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+
+        if self.first_byte.get().is_none() {
+            let latency = self.accepted_at.elapsed();
+            if self.first_byte.set(latency).is_ok() {
+                debug!("first byte written {latency:?} after accepting the client");
+                self.metrics.first_byte(latency);
+            }
+        }
+    }
+
+    pub(crate) fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+}
+
+/// Ring buffer of the most recent bytes, stored as the chunks they arrived in.
+///
+/// The oldest chunk is cut to keep exactly the last bytes, unless chunks are `whole` messages:
+/// those are evicted whole so a consumer getting the history does not start partway through one,
+/// and the history may hold a bit less than its capacity.
+#[derive(Debug)]
+struct Replay {
+    capacity: usize,
+    chunks: VecDeque<Bytes>,
+    len: usize,
+    whole: bool,
+}
+
+impl Replay {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            chunks: VecDeque::new(),
+            len: 0,
+            whole: false,
+        }
+    }
+
+    fn push(&mut self, data: Bytes) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.len += data.len();
+        self.chunks.push_back(data);
+
+        // drop the oldest bytes until the history fits again
+        while self.len > self.capacity {
+            let excess = self.len - self.capacity;
+            let oldest = self.chunks.front_mut().expect("len is positive");
+
+            if oldest.len() <= excess || self.whole {
+                self.len -= oldest.len();
+                self.chunks.pop_front();
+            } else {
+                let _ = oldest.split_to(excess);
+                self.len -= excess;
+            }
+        }
+    }
+
+    fn history(&self) -> Vec<Bytes> {
+        self.chunks.iter().cloned().collect()
+    }
+
+    /// Empties the ring buffer, returns what it had.
+    fn take(&mut self) -> Vec<Bytes> {
+        self.len = 0;
+        self.chunks.drain(..).collect()
+    }
+}
@@ -0,0 +1,86 @@
+use crate::Checksum;
+use std::fmt;
+use tokio_util::bytes::{BufMut, Bytes, BytesMut};
+
+/// Size of the length field before the payload.
+const LENGTH_SIZE: usize = 4;
+
+impl Checksum {
+    /// Size of the checksum after the payload.
+    pub fn size(&self) -> usize {
+        match self {
+            Checksum::Crc32 => 4,
+            Checksum::Xxhash => 8,
+        }
+    }
+
+    fn compute(&self, payload: &[u8]) -> u64 {
+        match self {
+            Checksum::Crc32 => crc32fast::hash(payload).into(),
+            Checksum::Xxhash => xxhash_rust::xxh64::xxh64(payload, 0),
+        }
+    }
+
+    /// Wraps `payload` into a frame carrying its checksum, see [`Checksum`] for the layout.
+    pub fn seal(&self, payload: &[u8]) -> Bytes {
+        let mut frame = BytesMut::with_capacity(LENGTH_SIZE + payload.len() + self.size());
+        frame.put_u32(payload.len() as u32);
+        frame.put_slice(payload);
+        frame.put_uint(self.compute(payload), self.size());
+        frame.freeze()
+    }
+
+    /// Checks a whole frame as sealed by [`Checksum::seal`], returns the payload in it if intact.
+    ///
+    /// ```
+    /// use udp_tcp_spmc_broadcast::{Checksum, IntegrityError};
+    ///
+    /// let mut frame = Checksum::Crc32.seal(b"hello").to_vec();
+    /// assert_eq!(Checksum::Crc32.verify(&frame), Ok(&b"hello"[..]));
+    ///
+    /// frame[4] ^= 1;
+    /// assert!(matches!(Checksum::Crc32.verify(&frame), Err(IntegrityError::Mismatch { .. })));
+    /// ```
+    pub fn verify<'a>(&self, frame: &'a [u8]) -> Result<&'a [u8], IntegrityError> {
+        let Some((length, rest)) = frame.split_first_chunk::<LENGTH_SIZE>() else {
+            return Err(IntegrityError::Truncated);
+        };
+        let length = u32::from_be_bytes(*length) as usize;
+
+        if rest.len() != length + self.size() {
+            return Err(IntegrityError::Truncated);
+        }
+
+        let (payload, checksum) = rest.split_at(length);
+        let expected = checksum.iter().fold(0, |acc, &b| (acc << 8) | u64::from(b));
+        let actual = self.compute(payload);
+
+        if expected != actual {
+            return Err(IntegrityError::Mismatch { expected, actual });
+        }
+
+        Ok(payload)
+    }
+}
+
+/// Reasons a frame fails [`Checksum::verify`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityError {
+    /// the frame is not as long as its length field says
+    Truncated,
+    /// the payload does not have the checksum the frame carries
+    Mismatch { expected: u64, actual: u64 },
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityError::Truncated => write!(f, "frame does not match its length"),
+            IntegrityError::Mismatch { expected, actual } => {
+                write!(f, "checksum mismatch, expected {expected:x} got {actual:x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for IntegrityError {}
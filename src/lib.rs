@@ -1,72 +1,1074 @@
-use tokio::io::AsyncRead;
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::Result;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpListener;
-use tokio::net::TcpStream;
-use tokio::net::UdpSocket;
-use tokio::sync::broadcast::{self, Sender};
-use tokio::try_join;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::task::JoinSet;
+use tokio_rustls::{server, TlsAcceptor};
 use tokio_util::bytes::Bytes;
-use tokio_util::bytes::BytesMut;
-use tracing::instrument;
-use tracing::{debug, warn};
+use tokio_util::bytes::{BufMut, BytesMut};
+use tokio_util::codec::Decoder;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, info_span, warn};
+use tracing::{instrument, Instrument};
 
-// In udp, if a message is larger than the buffer remaining bytes will be discarded
-// https://docs.rs/tokio/latest/tokio/net/struct.UdpSocket.html#method.recv_buf
-const BUFFER_SIZE: usize = 8 * 1024;
+mod access;
+mod admin;
+mod auth;
+mod backoff;
+mod broadcaster;
+mod builder;
+mod capture;
+mod client;
+mod coalesce;
+mod config;
+mod config_file;
+mod dedup;
+mod error;
+mod event;
+mod file;
+mod filter;
+mod framing;
+#[cfg(unix)]
+mod handover;
+#[cfg(feature = "http-remote")]
+mod http;
+mod hub;
+mod integrity;
+mod metrics;
+mod net;
+mod output;
+mod producer;
+mod proxy;
+mod rate;
+mod rdns;
+mod share;
+mod signal;
+mod socks;
+mod sse;
+mod tee;
+mod tls;
+mod topic;
+mod transform;
+mod upstream;
+mod ws;
 
-/// Continuously reads data from an async reader and sends it to a channel of bytes.
+pub use access::AccessList;
+pub(crate) use admin::serve_admin;
+use auth::Auth;
+pub use backoff::Backoff;
+pub use broadcaster::Broadcaster;
+pub use builder::{BroadcasterBuilder, BuildError};
+use client::{Backlog, ClientQueue, Compressor, CountingWriter, Pace};
+pub use client::{ClientOptions, DisconnectReason};
+use coalesce::Coalescer;
+pub use config::{
+    AcceptOverflow, CaptureFormat, Checksum, Coalesce, Compression, Config, Distribution,
+    DropPolicy, LagPolicy, LocalListener, LocalProto, NoClientsPolicy, OutputFormat, PauseMode,
+    Remote, RemoteMode, RemoteProto, SseEncoding,
+};
+pub use config_file::{
+    effective_json, layer_config_file, value_sources, ConfigFileError, ConfigLog, Layered, Source,
+};
+pub use error::BroadcastError;
+pub use event::Event;
+pub use filter::{DropPrefix, Filter, KeepAll};
+pub use framing::{Endian, Framing};
+use hub::Delivered;
+pub use hub::{ClientInfo, ClientSlot, Hub};
+pub use integrity::IntegrityError;
+pub use metrics::Metrics;
+pub(crate) use metrics::{log_stats, serve_metrics};
+pub use net::{
+    bind_listener, bind_udp, connect, resolve, Keepalive, ListenOptions, DEFAULT_KEEPALIVE_RETRIES,
+};
+use net::{timed_out, Accept};
+use rate::Smoother;
+pub use rate::TokenBucket;
+use rdns::ReverseDns;
+pub use rdns::{ReverseResolver, SystemResolver};
+#[cfg(unix)]
+pub use signal::handover_signal;
+pub use signal::shutdown_signal;
+use socks::connect_through;
+pub use socks::Socks5Proxy;
+pub(crate) use sse::tx_to_sse;
+pub use tls::{LocalTls, RemoteTls};
+use topic::read_topic;
+pub use transform::{Identity, Transform};
+pub(crate) use ws::tx_to_websockets;
+
+/// Default size of the buffer used to read from the remote.
+///
+/// In udp, if a message is larger than the buffer remaining bytes will be discarded
+/// https://docs.rs/tokio/latest/tokio/net/struct.UdpSocket.html#method.recv_buf
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Smallest accepted size of the buffer used to read from the remote.
+pub const MIN_BUFFER_SIZE: usize = 64;
+
+/// Default size of the largest message taken from the remote with framing.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Continuously reads data from an async reader and publishes it to the hub, one chunk per frame.
+///
+/// Each read is at most `buffer_size` bytes and always has that much room available, so no datagram
+/// that fits in it gets truncated. Frames can span any number of reads, and be larger than the
+/// buffer: the bytes of an incomplete one are kept for the next read, only whole frames get
+/// published. With a rate limit on the hub, reads are paced to stay under it. Returns once the
+/// reader reaches EOF, dropping any incomplete frame left, or with the error that interrupted the
+/// reading. With a limit on the bytes read on the hub, it also returns once that many were, as if
+/// at EOF.
+pub async fn reader_to_tx<R: AsyncReadExt + Unpin>(
+    reader: R,
+    hub: Hub,
+    buffer_size: usize,
+    framing: Framing,
+) -> Result<()> {
+    let options = ReadOptions {
+        buffer_size,
+        framing,
+        max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        read_timeout: None,
+        coalesce: None,
+    };
+    reader_to_tx_with(reader, hub, options).await
+}
+
+/// How [`reader_to_tx_with`] reads and publishes.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ReadOptions {
+    pub(crate) buffer_size: usize,
+    pub(crate) framing: Framing,
+    /// bytes a frame can have at most, larger ones fail the reading before they are buffered
+    pub(crate) max_message_size: usize,
+    /// time a read can take before it fails with [`ErrorKind::TimedOut`], no limit if unset
+    pub(crate) read_timeout: Option<Duration>,
+    /// how frames are batched before they are published, one chunk per frame if unset
+    pub(crate) coalesce: Option<Coalesce>,
+}
+
+/// Like [`reader_to_tx`], with a timeout on each read and the frames batched as `options` say.
 #[instrument(skip_all)]
-pub async fn reader_to_tx<R: AsyncReadExt + Unpin>(mut reader: R, tx: Sender<Bytes>) {
-    let mut buffer = BytesMut::with_capacity(BUFFER_SIZE);
+pub(crate) async fn reader_to_tx_with<R: AsyncReadExt + Unpin>(
+    mut reader: R,
+    hub: Hub,
+    options: ReadOptions,
+) -> Result<()> {
+    let ReadOptions {
+        buffer_size,
+        framing,
+        max_message_size,
+        read_timeout,
+        coalesce,
+    } = options;
+    let mut buffer = BytesMut::with_capacity(buffer_size);
+    let mut decoder = framing.decoder(max_message_size);
+    let mut coalescer = Coalescer::new(&hub, coalesce);
+    let mut swaps = hub.remote_swaps();
+
+    loop {
+        // held while paused, what the remote sends waits in its buffers meanwhile
+        if hub.held() {
+            coalescer.flush();
+            debug!("paused, no longer reading");
+            hub.resumed().await;
+            debug!("resumed, reading again");
+        }
 
-    while let Ok(n) = reader.read_buf(&mut buffer).await {
-        if n == 0 {
-            debug!("no bytes read from the reader");
+        // and while too few consumers are connected to get what is read
+        if hub.short_of_clients() {
+            coalescer.flush();
+            debug!("too few consumers, no longer reading");
+            hub.enough_clients().await;
+            debug!("enough consumers, reading again");
             continue;
         }
 
-        let data = buffer.split_to(n).freeze();
+        // chunks handed to the channel may still hold the previous allocation, so make room
+        buffer.reserve(buffer_size);
+
+        // no more than is left to read, nothing at all once it is spent
+        let room = match hub.bytes_left() {
+            Some(left) => buffer_size.min(usize::try_from(left).unwrap_or(usize::MAX)),
+            None => buffer_size,
+        };
+        let mut limited = (&mut buffer).limit(room);
+        let read = async {
+            match read_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, reader.read_buf(&mut limited))
+                    .await
+                    .map_err(|_| timed_out("read", timeout))?,
+                None => reader.read_buf(&mut limited).await,
+            }
+        };
+
+        // reading is cancel safe, a batch waiting too long goes out in between, and consumers
+        // leaving stop it, as does a switch to another remote once the batch went out, the reader
+        // then waits to be dropped
+        let n = tokio::select! {
+            n = read => n?,
+            _ = coalescer.expired() => {
+                coalescer.flush();
+                continue;
+            }
+            _ = hub.too_few_clients() => continue,
+            Ok(()) = swaps.changed() => {
+                coalescer.flush();
+                debug!("switching remotes, no longer reading");
+                return std::future::pending().await;
+            }
+        };
+
+        hub.throttle(n).await;
+        if n > 0 {
+            hub.injected_delay().await;
+        }
+
+        // another remote may have taken some of what was left meanwhile
+        let taken = hub.take_bytes(n);
+        buffer.truncate(buffer.len() - (n - taken));
+        let spent = hub.bytes_left() == Some(0);
+
+        if n == 0 || spent {
+            while let Some(frame) = decoder.decode_eof(&mut buffer)? {
+                coalescer.push(frame);
+            }
+            coalescer.flush();
+
+            if !buffer.is_empty() {
+                warn!(
+                    "reader reached EOF, dropping {} bytes of an incomplete frame",
+                    buffer.len()
+                );
+            }
+            if spent {
+                info!("read as many bytes as allowed, no longer reading");
+                hub.all_bytes_read();
+            } else {
+                debug!("reader reached EOF");
+            }
+            return Ok(());
+        }
 
-        match tx.send(data) {
-            Ok(r) => debug!("send {n} bytes to {r} receivers"),
-            Err(_) => warn!("no listeners subscribed when sending, lost {n} bytes of data"),
-            // alternatively we could try put the Bytes back, eg. Err(SendError(b)) => buffer.put(b)
+        while let Some(frame) = decoder.decode(&mut buffer)? {
+            coalescer.push(frame);
         }
     }
 }
 
-/// Handles the transmission of data from a channel to an async writer.
+fn publish(hub: &Hub, frame: BytesMut) {
+    let n = frame.len();
+
+    match hub.publish(frame.freeze()) {
+        Ok(r) => debug!("send {n} bytes to {r} receivers"),
+        Err(_) => warn!("no listeners subscribed when sending, lost {n} bytes of data"),
+        // alternatively we could try put the Bytes back, eg. Err(SendError(b)) => buffer.put(b)
+    }
+}
+
+/// Handles the transmission of data from the hub to an async writer, until `cancel` is triggered
+/// and the data pending in the channel has been written.
+///
+/// The `options.banner`, if any, is written first, then the replay history, if any. The writer is
+/// flushed after each chunk, so buffered writers deliver data promptly. Writers that take longer
+/// than `options.write_timeout` to accept a chunk are considered stuck and get dropped. While a
+/// chunk is being written the next ones wait in a queue of `options.queue_size` chunks, once it is
+/// full `options.drop_policy` decides what goes. With `options.rate_limit`, writes are paced to
+/// stay under that many bytes per second. With `options.smooth_rate`, the history and the chunks
+/// are written a slice at a time at that many bytes per second on average, instead of all at once.
+/// With a replay stagger on the hub, the history waits for the turn of the writer. With
+/// `options.idle_timeout`, writers that stay behind for that long without ever catching up with the
+/// queue get dropped too. With `options.slow_threshold`, writers whose queue holds more than that
+/// many bytes on average are flagged as slow, and dropped with `options.slow_disconnect`. With
+/// `options.sentinel`, it is written last once cancelled and everything pending got through, a
+/// writer failing to take it is only warned about. Returns what the writer got once it is done.
 #[instrument(skip_all)]
 pub async fn tx_to_writer<W: AsyncWriteExt + Unpin + std::fmt::Debug>(
     mut writer: W,
-    tx: Sender<Bytes>,
-) {
-    let mut rx = tx.subscribe();
+    hub: Hub,
+    options: ClientOptions,
+    cancel: CancellationToken,
+) -> ClientStats {
+    let connected_at = Instant::now();
+    let write_timeout = options.write_timeout;
+    let mut bytes_sent = 0;
+    let mut queue = ClientQueue::new(options.queue_size, options.drop_policy, options.lag_policy);
+    let mut pace = options.rate_limit.map(TokenBucket::new);
+    let mut smooth = options.smooth_rate.map(Smoother::new);
+    let idle_timeout = options.idle_timeout;
+    let mut backlog = options.slow_threshold.map(Backlog::new);
+
+    let (mut rx, history, _present) = hub.feed();
+
+    if let Some(banner) = options.banner.clone() {
+        let n = banner.len();
+
+        if let Err(e) = write_chunk(&mut writer, banner, write_timeout).await {
+            warn!("when writing the banner to the stream: {e}, dropping receiver");
+            let reason = DisconnectReason::write_failed(&e);
+            hub.metrics().dropped(reason);
+            return ClientStats::new(bytes_sent, queue.dropped(), connected_at, reason);
+        }
+
+        hub.metrics().sent(n);
+        bytes_sent += n as u64;
+    }
 
-    while let Ok(mut data) = rx.recv().await {
-        debug!("received {} bytes from the channel", data.len());
+    // consumers joining at once take turns, so not every replay goes out at the same time
+    if !history.is_empty() {
+        tokio::select! {
+            () = hub.replay_turn() => {}
+            _ = cancel.cancelled() => {}
+        }
+    }
+
+    let topic = options.topic.as_deref();
+    for data in history {
+        if !hub.subscribed(&data, topic) {
+            continue;
+        }
+        let n = data.len();
+
+        if let Err(e) = write_smoothly(&mut writer, data, write_timeout, smooth.as_mut()).await {
+            warn!("when replaying history to the stream: {e}, dropping receiver");
+            let reason = DisconnectReason::write_failed(&e);
+            hub.metrics().dropped(reason);
+            return ClientStats::new(bytes_sent, queue.dropped(), connected_at, reason);
+        }
 
-        match writer.write_all_buf(&mut data).await {
-            Ok(_) => debug!("success writing all buffer bytes"),
+        hub.metrics().sent(n);
+        bytes_sent += n as u64;
+    }
+
+    // once cancelled, only what is already waiting in the channel gets written
+    let mut draining = false;
+    // since when the writer has had chunks waiting for it, none while it is caught up
+    let mut behind_since = None;
+
+    let reason = 'deliver: loop {
+        if draining {
+            loop {
+                let received = match rx.try_recv() {
+                    Ok(data) => Ok(data),
+                    Err(TryRecvError::Lagged(n)) => Err(RecvError::Lagged(n)),
+                    Err(_) => break,
+                };
+
+                if let Err(reason) = enqueue(&mut queue, received, &hub, topic) {
+                    hub.metrics().dropped(reason);
+                    break 'deliver reason;
+                }
+            }
+        }
+
+        let Some(data) = queue.pop() else {
+            // consumers handed over to a successor are not done with the broadcast
+            if draining && hub.handing_over() {
+                hub.metrics().disconnected(DisconnectReason::HandedOver);
+                break DisconnectReason::HandedOver;
+            }
+
+            if draining {
+                if let Some(sentinel) = options.sentinel {
+                    let n = sentinel.len();
+                    match write_chunk(&mut writer, sentinel, write_timeout).await {
+                        Ok(()) => {
+                            hub.metrics().sent(n);
+                            bytes_sent += n as u64;
+                        }
+                        Err(e) => warn!("when writing the shutdown sentinel: {e}"),
+                    }
+                }
+                hub.metrics().disconnected(DisconnectReason::Shutdown);
+                break DisconnectReason::Shutdown;
+            }
+
+            tokio::select! {
+                _ = cancel.cancelled() => {
+                    debug!("cancelled, draining pending data");
+                    draining = true;
+                }
+                received = rx.recv() => {
+                    if let Err(reason) = enqueue(&mut queue, received, &hub, topic) {
+                        hub.metrics().dropped(reason);
+                        break reason;
+                    }
+                }
+            }
+            continue;
+        };
+
+        let n = data.len();
+        let write = async {
+            if let Some(pace) = &mut pace {
+                pace.acquire(n).await;
+            }
+            write_smoothly(&mut writer, data, write_timeout, smooth.as_mut()).await
+        };
+        tokio::pin!(write);
+
+        let since = *behind_since.get_or_insert_with(tokio::time::Instant::now);
+        let idle = async move {
+            match idle_timeout {
+                Some(idle_timeout) => {
+                    tokio::time::sleep_until(since + idle_timeout).await;
+                    idle_timeout
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(idle);
+
+        // keep taking chunks from the channel while writing, a slow writer fills its own queue
+        let written = loop {
+            tokio::select! {
+                biased;
+                written = &mut write => break written,
+                idle_timeout = &mut idle => {
+                    warn!("behind for {idle_timeout:?} without catching up, dropping receiver");
+                    hub.metrics().dropped(DisconnectReason::IdleTimeout);
+                    break 'deliver DisconnectReason::IdleTimeout;
+                }
+                _ = cancel.cancelled(), if !draining => {
+                    debug!("cancelled, draining pending data");
+                    draining = true;
+                }
+                received = rx.recv(), if !draining => {
+                    let kept = enqueue(&mut queue, received, &hub, topic).and_then(|()| {
+                        keep_pace(&mut backlog, &queue, &hub, options.slow_disconnect)
+                    });
+                    if let Err(reason) = kept {
+                        hub.metrics().dropped(reason);
+                        break 'deliver reason;
+                    }
+                }
+            }
+        };
+
+        match written {
+            Ok(_) => {
+                debug!("success writing all buffer bytes");
+                hub.metrics().sent(n);
+                bytes_sent += n as u64;
+
+                if queue.is_empty() {
+                    behind_since = None;
+                }
+
+                if let Err(reason) = keep_pace(&mut backlog, &queue, &hub, options.slow_disconnect)
+                {
+                    hub.metrics().dropped(reason);
+                    break reason;
+                }
+            }
             Err(e) => {
                 warn!("when writing buffer to the stream: {e}, dropping receiver");
-                break;
+                let reason = DisconnectReason::write_failed(&e);
+                hub.metrics().dropped(reason);
+                break reason;
+            }
+        }
+    };
+
+    ClientStats::new(bytes_sent, queue.dropped(), connected_at, reason)
+}
+
+/// Queues what came from the channel, of the `topic` only if any, fails with the reason to drop the
+/// receiver for if it has to be.
+fn enqueue(
+    queue: &mut ClientQueue,
+    received: std::result::Result<Bytes, RecvError>,
+    hub: &Hub,
+    topic: Option<&[u8]>,
+) -> std::result::Result<(), DisconnectReason> {
+    match received {
+        Ok(data) if !hub.subscribed(&data, topic) => {
+            debug!("left out {} bytes of another topic", data.len());
+            Ok(())
+        }
+        Ok(data) => {
+            debug!("received {} bytes from the channel", data.len());
+
+            if !queue.push(data) {
+                warn!("queue is full, dropping receiver");
+                return Err(DisconnectReason::QueueFull);
+            }
+            Ok(())
+        }
+        Err(RecvError::Lagged(n)) => {
+            hub.metrics().lagged(n);
+
+            match queue.missed(n) {
+                LagPolicy::Resync => {
+                    warn!("lagged {n} chunks behind, skipping them");
+                    Ok(())
+                }
+                LagPolicy::CountOnly => Ok(()),
+                LagPolicy::Disconnect => {
+                    warn!("lagged {n} chunks behind, dropping receiver");
+                    Err(DisconnectReason::Lagged)
+                }
+            }
+        }
+        // the channel closes along with the broadcast
+        Err(e) => {
+            warn!("when receiving from the channel: {e}, dropping receiver");
+            Err(DisconnectReason::Shutdown)
+        }
+    }
+}
+
+/// Takes what is queued into the average backlog, if tracked, fails if the receiver has to be
+/// dropped for being slow.
+fn keep_pace(
+    backlog: &mut Option<Backlog>,
+    queue: &ClientQueue,
+    hub: &Hub,
+    disconnect: bool,
+) -> std::result::Result<(), DisconnectReason> {
+    let Some(backlog) = backlog else {
+        return Ok(());
+    };
+
+    match backlog.sample(queue.bytes()) {
+        Pace::Slowed(average) => {
+            hub.metrics().slow();
+            if disconnect {
+                warn!("backlog averaging {average:.0} bytes, slow, dropping receiver");
+                return Err(DisconnectReason::Slow);
             }
+            warn!("backlog averaging {average:.0} bytes, slow");
         }
+        Pace::Recovered => info!("backlog back under the slow threshold"),
+        Pace::Unchanged => {}
     }
+
+    Ok(())
+}
+
+/// What a single consumer got before it was removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientStats {
+    /// bytes fully written to the consumer
+    pub bytes_sent: u64,
+    /// chunks the consumer did not get, because its queue was full or it fell behind
+    pub chunks_dropped: u64,
+    /// how long the consumer stayed connected
+    pub duration: Duration,
+    /// why the consumer was removed
+    pub reason: DisconnectReason,
+}
+
+impl ClientStats {
+    fn new(
+        bytes_sent: u64,
+        chunks_dropped: u64,
+        connected_at: Instant,
+        reason: DisconnectReason,
+    ) -> Self {
+        Self {
+            bytes_sent,
+            chunks_dropped,
+            duration: connected_at.elapsed(),
+            reason,
+        }
+    }
+}
+
+/// Writes and flushes a whole chunk, failing if it takes longer than `write_timeout`.
+async fn write_chunk<W: AsyncWriteExt + Unpin>(
+    writer: &mut W,
+    mut data: Bytes,
+    write_timeout: Duration,
+) -> Result<()> {
+    let write = async {
+        writer.write_all_buf(&mut data).await?;
+        writer.flush().await
+    };
+
+    tokio::time::timeout(write_timeout, write)
+        .await
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::TimedOut,
+                format!("write timed out after {write_timeout:?}"),
+            )
+        })?
 }
 
-/// Handles the transmission of data from a channel to multiple TCP streams asynchronously.
+/// Writes a whole chunk like [`write_chunk`], with `smooth` a slice at a time, each one in its
+/// turn, so a large chunk is spread over the time it takes at the rate.
+async fn write_smoothly<W: AsyncWriteExt + Unpin>(
+    writer: &mut W,
+    mut data: Bytes,
+    write_timeout: Duration,
+    smooth: Option<&mut Smoother>,
+) -> Result<()> {
+    let Some(smooth) = smooth else {
+        return write_chunk(writer, data, write_timeout).await;
+    };
+
+    while !data.is_empty() {
+        let slice = data.split_to(data.len().min(smooth.slice()));
+        smooth.wait(slice.len()).await;
+        write_chunk(writer, slice, write_timeout).await?;
+    }
+
+    Ok(())
+}
+
+/// Handles the transmission of data from the hub to multiple TCP streams asynchronously.
+///
+/// With `tls` each stream goes through the handshake first, and with `config.auth_token` clients
+/// then have to send the token followed by a newline within `config.auth_timeout`, clients failing
+/// either are dropped before they get any data. With `config.topics` they then send the topic they
+/// subscribe to likewise, and only get the chunks of it. See [`accept_consumers`] for who gets in
+/// and how they are stopped.
 #[instrument(skip_all)]
-async fn tx_to_streams(listener: TcpListener, tx: Sender<Bytes>) {
-    while let Ok((stream, _)) = listener.accept().await {
-        tokio::spawn(tx_to_writer(stream, tx.clone()));
+pub(crate) async fn tx_to_streams<L: Accept>(
+    listener: L,
+    hub: Hub,
+    config: &Config,
+    tls: Option<TlsAcceptor>,
+    cancel: CancellationToken,
+) {
+    let options = ClientOptions::from(config);
+    let buffer_size = config.buffer_size;
+    let handshake = Handshake {
+        tls,
+        auth: config
+            .auth_token
+            .as_deref()
+            .map(|token| Auth::new(token, config.auth_timeout)),
+        topics: config.topics.then_some(config.auth_timeout),
+    };
+
+    accept_consumers(
+        listener,
+        &hub,
+        config,
+        &cancel,
+        |stream, addr, delivered| {
+            let hub = hub.clone();
+            let handshake = handshake.clone();
+            let options = options.clone();
+            let cancel = cancel.clone();
+            async move {
+                let stats = serve_client(
+                    stream,
+                    handshake,
+                    hub,
+                    options,
+                    buffer_size,
+                    delivered,
+                    cancel,
+                )
+                .await;
+
+                if let Some(stats) = stats {
+                    info!(
+                        "client {addr} disconnected after {:?}, {} bytes sent, {} chunks dropped, \
+                         {}",
+                        stats.duration, stats.bytes_sent, stats.chunks_dropped, stats.reason
+                    );
+                }
+            }
+        },
+    )
+    .await
+}
+
+/// Accepts consumers on `listener`, serving each one in its own task with `serve`, which gets the
+/// stream, the peer address and the counter of bytes sent to it. Consumers without an address,
+/// over a Unix socket, get [`UNIX_PEER`].
+///
+/// With `config.proxy_protocol`, each connection has to send a PROXY protocol header first, read
+/// alongside the others, and the address in it is the one of the consumer from then on, for the
+/// access rules and the logs alike. Connections failing to send one are closed.
+///
+/// With `config.max_accepts_per_sec`, connections coming in faster than that are held off or
+/// closed, as `config.accept_overflow` says. Connections from peers `config.access` does not
+/// permit, and connections beyond `config.max_clients` (0 for unlimited), are closed right away,
+/// and consumers kicked out through the hub are closed too. Once `cancel` is triggered no more
+/// connections are accepted, and the connected consumers get up to `config.shutdown_grace` to
+/// receive their pending data before they are closed.
+pub(crate) async fn accept_consumers<L, F, Fut>(
+    listener: L,
+    hub: &Hub,
+    config: &Config,
+    cancel: &CancellationToken,
+    serve: F,
+) where
+    L: Accept,
+    F: Fn(L::Stream, SocketAddr, Arc<Delivered>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let max_clients = config.max_clients;
+    let mut clients = JoinSet::new();
+    let mut accepts = config.max_accepts_per_sec.map(TokenBucket::new);
+    // when a connection was last held off or closed, to log only when throttling starts and stops
+    let mut last_throttled = None;
+    // connections whose PROXY protocol header is being read
+    let mut headers: JoinSet<std::result::Result<_, (Option<SocketAddr>, Error)>> = JoinSet::new();
+
+    loop {
+        let (stream, addr) = tokio::select! {
+            _ = cancel.cancelled() => break,
+            Some(_) = clients.join_next(), if !clients.is_empty() => continue,
+            Some(read) = headers.join_next(), if !headers.is_empty() => match read {
+                Ok(Ok(read)) => read,
+                Ok(Err((addr, e))) => {
+                    warn!("dropping connection from {}: {e}", addr.unwrap_or(UNIX_PEER));
+                    continue;
+                }
+                Err(_) => continue,
+            },
+            accepted = listener.accept() => match accepted {
+                Ok((mut stream, addr)) if config.proxy_protocol => {
+                    headers.spawn(async move {
+                        match proxy::read_header(&mut stream).await {
+                            Ok(real) => Ok((stream, real.or(addr))),
+                            Err(e) => Err((addr, e)),
+                        }
+                    });
+                    continue;
+                }
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("when accepting connections: {e}");
+                    break;
+                }
+            },
+        };
+
+        if let Some(accepts) = &mut accepts {
+            let now = tokio::time::Instant::now();
+
+            // how long to hold the connection off, none if it has to be closed
+            let wait = match config.accept_overflow {
+                AcceptOverflow::Delay => Some(accepts.take(1, now)),
+                AcceptOverflow::Close => accepts.try_take(1, now).then_some(Duration::ZERO),
+            };
+
+            if wait != Some(Duration::ZERO) {
+                if last_throttled.is_none() {
+                    warn!("accepting connections too fast, throttling them");
+                }
+                last_throttled = Some(now);
+            } else if last_throttled.is_some_and(|at| now - at >= THROTTLE_QUIET) {
+                info!("no longer throttling connections");
+                last_throttled = None;
+            }
+
+            match wait {
+                None => continue,
+                Some(wait) if !wait.is_zero() => tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = tokio::time::sleep(wait) => {}
+                },
+                Some(_) => {}
+            }
+        }
+
+        // the access rules are for the network, file permissions restrict Unix sockets
+        let addr = match addr {
+            Some(addr) if !config.access.permits(addr.ip()) => {
+                warn!("refusing connection from {addr}, not allowed by the access rules");
+                continue;
+            }
+            Some(addr) => addr,
+            None => UNIX_PEER,
+        };
+
+        let Some(slot) = hub.try_join(max_clients, addr) else {
+            warn!("refusing connection from {addr}, already serving {max_clients} clients");
+            hub.metrics().disconnected(DisconnectReason::MaxClients);
+            continue;
+        };
+
+        L::configure(&stream, config);
+
+        // the lookup can take a while, the name is logged once there, after the address alone
+        match hub.reverse_dns() {
+            Some(reverse_dns) if addr != UNIX_PEER => {
+                let reverse_dns = reverse_dns.clone();
+                tokio::spawn(async move {
+                    match reverse_dns.name(addr.ip()).await {
+                        Some(name) => info!("accepted client {addr} ({name})"),
+                        None => info!("accepted client {addr}"),
+                    }
+                });
+            }
+            _ => info!("accepted client {addr}"),
+        }
+
+        #[cfg(unix)]
+        if let Some(fd) = listener.hand_over(&stream) {
+            hub.keep_for_handover(&slot, fd);
+        }
+
+        let kicked = slot.kicked();
+        let serving = serve(stream, addr, slot.delivered());
+        let span = info_span!("client", peer = %addr);
+        let hub = hub.clone();
+
+        clients.spawn(
+            async move {
+                tokio::select! {
+                    () = serving => {}
+                    _ = kicked.cancelled() => {
+                        info!("client {addr} kicked out, {}", DisconnectReason::Kicked);
+                        hub.metrics().disconnected(DisconnectReason::Kicked);
+                    }
+                }
+
+                drop(slot);
+            }
+            .instrument(span),
+        );
+    }
+
+    drop(listener);
+
+    let drain = async { while clients.join_next().await.is_some() {} };
+
+    if tokio::time::timeout(config.shutdown_grace, drain)
+        .await
+        .is_err()
+    {
+        warn!(
+            "{} clients still busy after {:?}, closing them",
+            clients.len(),
+            config.shutdown_grace
+        );
+    }
+
+    // dropping the set aborts whatever is left
+}
+
+/// What TCP consumers go through before they get any data.
+#[derive(Clone)]
+struct Handshake {
+    tls: Option<TlsAcceptor>,
+    auth: Option<Auth>,
+    /// time consumers have to send the topic they subscribe to, when they have to
+    topics: Option<Duration>,
+}
+
+impl Handshake {
+    /// Checks the token sent by the consumer when one is expected, returns false if it failed.
+    async fn authenticate<S: AsyncRead + Unpin>(&self, stream: &mut S) -> bool {
+        let Some(auth) = &self.auth else {
+            return true;
+        };
+
+        match auth.check(stream).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("authentication failed: {e}, dropping client");
+                false
+            }
+        }
+    }
+
+    /// Reads the topic the consumer subscribes to into `options` when it has to send one, returns
+    /// false if it failed.
+    async fn subscribe<S: AsyncRead + Unpin>(
+        &self,
+        stream: &mut S,
+        options: &mut ClientOptions,
+    ) -> bool {
+        let Some(timeout) = self.topics else {
+            return true;
+        };
+
+        match read_topic(stream, timeout).await {
+            Ok(topic) => {
+                match &topic {
+                    Some(topic) => info!("subscribed to {}", String::from_utf8_lossy(topic)),
+                    None => info!("subscribed to every topic"),
+                }
+                options.topic = topic;
+                true
+            }
+            Err(e) => {
+                warn!("subscription failed: {e}, dropping client");
+                false
+            }
+        }
+    }
+}
+
+/// Delivers the data from the hub to a single TCP consumer, going through the handshake first.
+/// Returns `None` if the handshake failed.
+async fn serve_client<S: AsyncRead + AsyncWrite + Unpin + std::fmt::Debug>(
+    mut stream: S,
+    handshake: Handshake,
+    hub: Hub,
+    mut options: ClientOptions,
+    buffer_size: usize,
+    delivered: Arc<Delivered>,
+    cancel: CancellationToken,
+) -> Option<ClientStats> {
+    let Some(acceptor) = &handshake.tls else {
+        if !handshake.authenticate(&mut stream).await
+            || !handshake.subscribe(&mut stream, &mut options).await
+        {
+            return None;
+        }
+
+        info!("client connected");
+        return Some(deliver(stream, hub, options, buffer_size, delivered, cancel).await);
+    };
+
+    match accept_tls(acceptor, stream, options.write_timeout).await {
+        Ok(mut stream) => {
+            if !handshake.authenticate(&mut stream).await
+                || !handshake.subscribe(&mut stream, &mut options).await
+            {
+                return None;
+            }
+
+            info!("client connected over TLS");
+            Some(deliver(stream, hub, options, buffer_size, delivered, cancel).await)
+        }
+        Err(e) => {
+            warn!("TLS handshake failed: {e}, dropping client");
+            None
+        }
+    }
+}
+
+/// Writes the data from the hub to a consumer stream, in bidirectional mode also relays what the
+/// consumer writes to the remote, `buffer_size` bytes at most at a time.
+///
+/// Consumers are only ever read from to relay what they write, so one shutting down its write side
+/// still gets the broadcast, it is done only once it cannot be written to.
+async fn deliver<S: AsyncRead + AsyncWrite + Unpin + std::fmt::Debug>(
+    stream: S,
+    hub: Hub,
+    options: ClientOptions,
+    buffer_size: usize,
+    delivered: Arc<Delivered>,
+    cancel: CancellationToken,
+) -> ClientStats {
+    let write_timeout = options.write_timeout;
+
+    let Some(upstream) = hub.upstream().cloned() else {
+        let writer = Compressor::new(CountingWriter::new(stream, delivered), options.compression);
+        return finish(writer, hub, options, cancel, write_timeout).await;
+    };
+
+    let (reader, writer) = tokio::io::split(stream);
+    let writer = Compressor::new(CountingWriter::new(writer, delivered), options.compression);
+
+    // the consumer is done once it cannot be written to, whether it still sends or not
+    let relay = async {
+        if let Err(e) = upstream.relay_from(reader, buffer_size).await {
+            warn!("when reading from the client: {e}, no longer relaying it");
+        }
+        std::future::pending().await
+    };
+
+    tokio::select! {
+        stats = finish(writer, hub, options, cancel, write_timeout) => stats,
+        never = relay => never,
+    }
+}
+
+/// Writes the data from the hub until done, then ends the compressed stream, if any, so the
+/// consumer can tell it is complete.
+async fn finish<W: AsyncWrite + Unpin + std::fmt::Debug>(
+    mut writer: Compressor<W>,
+    hub: Hub,
+    options: ClientOptions,
+    cancel: CancellationToken,
+    write_timeout: Duration,
+) -> ClientStats {
+    let stats = tx_to_writer(&mut writer, hub, options, cancel).await;
+
+    if writer.is_compressed() {
+        match tokio::time::timeout(write_timeout, writer.shutdown()).await {
+            Ok(Ok(())) => debug!("compressed stream ended"),
+            Ok(Err(e)) => debug!("when ending the compressed stream: {e}"),
+            Err(_) => debug!("ending the compressed stream timed out"),
+        }
     }
+
+    stats
+}
+
+/// Sends the data from the hub as datagrams to every one of the `targets`, until `cancel` is
+/// triggered and the data pending in the channel has been sent.
+///
+/// Chunks larger than `max_datagram` bytes are split, so they do not get fragmented unexpectedly.
+/// Failing to send to a target is logged and otherwise ignored, as UDP makes no promises anyway.
+#[instrument(skip_all)]
+pub(crate) async fn tx_to_datagrams(
+    socket: UdpSocket,
+    targets: Vec<SocketAddr>,
+    hub: Hub,
+    max_datagram: usize,
+    cancel: CancellationToken,
+) {
+    let (mut rx, _) = hub.subscribe();
+    let mut draining = false;
+
+    loop {
+        let data = if draining {
+            match rx.try_recv() {
+                Ok(data) => data,
+                Err(_) => break,
+            }
+        } else {
+            tokio::select! {
+                _ = cancel.cancelled() => {
+                    draining = true;
+                    continue;
+                }
+                result = rx.recv() => match result {
+                    Ok(data) => data,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("fell behind, skipped {n} chunks");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+            }
+        };
+
+        for datagram in data.chunks(max_datagram) {
+            for target in &targets {
+                match socket.send_to(datagram, target).await {
+                    Ok(n) => hub.metrics().sent(n),
+                    Err(e) => warn!("when sending a datagram to {target}: {e}"),
+                }
+            }
+        }
+    }
+}
+
+/// Runs the server side of the TLS handshake, bounded like any other write to the client.
+async fn accept_tls<S: AsyncRead + AsyncWrite + Unpin>(
+    acceptor: &TlsAcceptor,
+    stream: S,
+    timeout: Duration,
+) -> Result<server::TlsStream<S>> {
+    tokio::time::timeout(timeout, acceptor.accept(stream))
+        .await
+        .map_err(|_| Error::new(ErrorKind::TimedOut, format!("timed out after {timeout:?}")))?
 }
 
 /// Thin wrapper around Tokio's UdpSocket to implement `AsyncRead` trait, and by extension `ASyncReadExt`
-struct AsyncUdpSocket(UdpSocket);
+///
+/// Empty datagrams are skipped, otherwise they would be mistaken for EOF.
+pub(crate) struct AsyncUdpSocket(UdpSocket);
 
 impl From<UdpSocket> for AsyncUdpSocket {
     fn from(socket: UdpSocket) -> Self {
@@ -80,43 +1082,157 @@ impl AsyncRead for AsyncUdpSocket {
         cx: &mut std::task::Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> std::task::Poll<std::io::Result<()>> {
-        self.0.poll_recv(cx, buf)
+        loop {
+            let filled = buf.filled().len();
+
+            match self.0.poll_recv(cx, buf) {
+                std::task::Poll::Ready(Ok(())) if buf.filled().len() == filled => continue,
+                poll => return poll,
+            }
+        }
     }
 }
 
-/// Lagging is ignored, https://docs.rs/tokio/1.35.1/tokio/sync/broadcast/#lagging
-const MAX_CHANNEL_MESSAGES: usize = 1024;
+/// Default time a consumer has to accept a chunk before it is considered stuck and dropped.
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
 
-/// Broadcasts data from a single UDP socket producer to multiple TCP stream consumers.
-#[instrument(skip_all)]
-pub async fn udp_broadcaster(socket: UdpSocket, listener: TcpListener) -> Result<()> {
-    // create the channel to share data between streams
-    let (tx, _) = broadcast::channel::<Bytes>(MAX_CHANNEL_MESSAGES);
+/// Address consumers over a Unix socket are registered with, as they have none, so they are listed
+/// and kicked out all together.
+pub const UNIX_PEER: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED), 0);
 
-    // spawn the tasks to handle the data transmission
-    let socket_to_tx = tokio::spawn(reader_to_tx::<AsyncUdpSocket>(socket.into(), tx.clone()));
-    let tx_to_stream = tokio::spawn(tx_to_streams(listener, tx.clone()));
+/// Time without connections throttled before throttling is considered over.
+const THROTTLE_QUIET: Duration = Duration::from_secs(1);
 
-    // wait for the tasks to complete
-    try_join!(socket_to_tx, tx_to_stream)?;
+/// Default time connecting to a TCP remote can take before the attempt fails, instead of the
+/// system timeout that can take minutes for an unreachable host.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
-    Ok(())
+/// Default time a consumer has to send the token, when one is expected.
+pub const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default time consumers get to receive their pending data on shutdown.
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Default maximum number of simultaneous consumers.
+pub const DEFAULT_MAX_CLIENTS: usize = 1024;
+
+/// Default number of chunks queued for each consumer while it is busy writing.
+pub const DEFAULT_CLIENT_QUEUE_SIZE: usize = 1024;
+
+/// Default time frames are held for when coalescing, if only a size is given.
+pub const DEFAULT_COALESCE_DELAY: Duration = Duration::from_millis(10);
+
+/// Default size of the batches of frames when coalescing, if only a time is given.
+pub const DEFAULT_COALESCE_BYTES: usize = 64 * 1024;
+
+/// Default number of bytes kept for the next consumer while none is connected, with the buffer
+/// policy.
+pub const DEFAULT_NO_CLIENTS_BUFFER: usize = 1024 * 1024;
+
+/// Default number of chunks the broadcast channel retains for consumers that fall behind.
+///
+/// Consumers lagging further than this are dropped, see
+/// https://docs.rs/tokio/1.35.1/tokio/sync/broadcast/#lagging
+pub const DEFAULT_BROADCAST_CAPACITY: usize = 1024;
+
+/// Connects to a remote TCP host by name or IP, retrying with exponential backoff until it
+/// succeeds.
+///
+/// Returns `None` if `cancel` is triggered before a connection could be established, or the error
+/// of the last attempt once the backoff runs out of attempts.
+pub async fn connect_with_backoff(
+    addr: &str,
+    backoff: &Backoff,
+    cancel: &CancellationToken,
+) -> std::result::Result<Option<TcpStream>, BroadcastError> {
+    connect_with_backoff_through(addr, None, None, DEFAULT_CONNECT_TIMEOUT, backoff, cancel).await
 }
 
-/// Broadcasts data from a single TCP stream producer to multiple TCP stream consumers.
-#[instrument(skip_all)]
-pub async fn tcp_broadcaster(stream: TcpStream, listener: TcpListener) -> Result<()> {
-    // create the channel to share data between streams
-    let (tx, _) = broadcast::channel::<Bytes>(MAX_CHANNEL_MESSAGES);
+/// Like [`connect_with_backoff`], through `proxy` if there is one, each attempt connecting to it
+/// anew, and from the local address `bind` if there is one.
+#[instrument(skip(proxy, backoff, cancel))]
+pub(crate) async fn connect_with_backoff_through(
+    addr: &str,
+    proxy: Option<&Socks5Proxy>,
+    bind: Option<std::net::IpAddr>,
+    connect_timeout: Duration,
+    backoff: &Backoff,
+    cancel: &CancellationToken,
+) -> std::result::Result<Option<TcpStream>, BroadcastError> {
+    let mut attempt = 1;
 
-    // spawn the tasks to handle the data transmission
-    let stream_to_tx = tokio::spawn(reader_to_tx(stream, tx.clone()));
-    let tx_to_stream = tokio::spawn(tx_to_streams(listener, tx.clone()));
+    loop {
+        let result = tokio::select! {
+            _ = cancel.cancelled() => return Ok(None),
+            result = connect_through(addr, proxy, bind, connect_timeout) => result,
+        };
 
-    // wait for the tasks to complete
-    try_join!(stream_to_tx, tx_to_stream)?;
+        match result {
+            Ok(stream) => {
+                info!("connected to {addr} on attempt {attempt}");
+                return Ok(Some(stream));
+            }
+            Err(e) if !backoff.retries_after(attempt) => {
+                warn!("attempt {attempt} to connect to {addr} failed: {e}, giving up");
+                return Err(e);
+            }
+            Err(e) => {
+                let delay = backoff.delay(attempt);
+                warn!("attempt {attempt} to connect to {addr} failed: {e}, retrying in {delay:?}");
 
-    Ok(())
+                tokio::select! {
+                    _ = cancel.cancelled() => return Ok(None),
+                    _ = tokio::time::sleep(delay) => {}
+                }
+            }
+        }
+
+        attempt += 1;
+    }
+}
+
+/// Binds a TCP listener, retrying up to `retries` times with `backoff` while it cannot be bound,
+/// like when the previous process still holds the port during a restart.
+///
+/// Returns `None` if `cancel` is triggered before the listener could be bound. Errors other than
+/// binding, like resolving the address, are not retried.
+#[instrument(skip(options, backoff, cancel))]
+pub async fn bind_with_backoff(
+    addr: &str,
+    options: ListenOptions,
+    backoff: &Backoff,
+    retries: u32,
+    cancel: &CancellationToken,
+) -> std::result::Result<Option<TcpListener>, BroadcastError> {
+    let mut attempt = 1;
+
+    loop {
+        let result = tokio::select! {
+            _ = cancel.cancelled() => return Ok(None),
+            result = bind_listener(addr, options) => result,
+        };
+
+        match result {
+            Ok(listener) => return Ok(Some(listener)),
+            Err(e @ BroadcastError::Bind { .. }) if attempt <= retries => {
+                let delay = backoff.delay(attempt);
+                warn!("attempt {attempt} to bind {addr} failed: {e}, retrying in {delay:?}");
+
+                tokio::select! {
+                    _ = cancel.cancelled() => return Ok(None),
+                    _ = tokio::time::sleep(delay) => {}
+                }
+            }
+            Err(e @ BroadcastError::Bind { .. }) if retries > 0 => {
+                warn!("attempt {attempt} to bind {addr} failed: {e}, giving up");
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        }
+
+        attempt += 1;
+    }
 }
 
 #[cfg(test)]
@@ -1,90 +1,976 @@
-use clap::Parser;
-use tokio::io::Result;
-use tokio::net::{TcpListener, UdpSocket};
-use tokio::task::JoinHandle;
-use tokio::{net::TcpStream, try_join};
-use tracing::{info, Level};
-use tracing_subscriber::FmtSubscriber;
-use udp_tcp_spmc_broadcast::{tcp_broadcaster, udp_broadcaster};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use ipnet::IpNet;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio_util::bytes::Bytes;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use tracing_subscriber::{EnvFilter, FmtSubscriber};
+#[cfg(unix)]
+use udp_tcp_spmc_broadcast::handover_signal;
+use udp_tcp_spmc_broadcast::{effective_json, layer_config_file, value_sources, ConfigLog};
+use udp_tcp_spmc_broadcast::{
+    shutdown_signal, AcceptOverflow, AccessList, Backoff, Broadcaster, CaptureFormat, Checksum,
+    Coalesce, Compression, Config, Distribution, DropPolicy, DropPrefix, Endian, Framing,
+    Keepalive, LagPolicy, LocalListener, LocalProto, LocalTls, NoClientsPolicy, OutputFormat,
+    PauseMode, Remote, RemoteMode, RemoteProto, RemoteTls, Socks5Proxy, SseEncoding,
+    DEFAULT_AUTH_TIMEOUT, DEFAULT_BROADCAST_CAPACITY, DEFAULT_BUFFER_SIZE,
+    DEFAULT_CLIENT_QUEUE_SIZE, DEFAULT_COALESCE_BYTES, DEFAULT_COALESCE_DELAY,
+    DEFAULT_CONNECT_TIMEOUT, DEFAULT_KEEPALIVE_RETRIES, DEFAULT_MAX_CLIENTS,
+    DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_NO_CLIENTS_BUFFER, DEFAULT_SHUTDOWN_GRACE,
+    DEFAULT_WRITE_TIMEOUT, MIN_BUFFER_SIZE,
+};
 
 /// Simple TCP broadcaster, connects to a remote TCP host and broadcast to a local TCP socket
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// host:port for consumers to connect and get data pushed
-    #[arg(short = 'c', long)]
-    consumer: String,
-
-    /// protocol://host:port for producer to pull(TCP) or listen(UDP) data from
-    #[arg(short = 'p', long)]
-    producer: String,
-}
-
-/// Utility function to use with `try_join!` in `main`
-async fn flatten<T>(handle: JoinHandle<Result<T>>) -> Result<T> {
-    match handle.await {
-        Ok(Ok(result)) => Ok(result),
-        Ok(Err(err)) => Err(err),
-        Err(err) => panic!("Error joining task: {:?}", err),
-    }
-}
-
-async fn setup(consumer: &str, producer: &str) -> JoinHandle<Result<()>> {
-    // setup local TCP listener
-    let listener = TcpListener::bind(&producer)
-        .await
-        .expect("Failed to bind TCP listener");
-
-    // get protocol from producer
-    let protocol = *producer
-        .split("://")
-        .collect::<Vec<_>>()
-        .first()
-        .expect("Invalid producer parameter, missing protocol: proto://<domain>:<port>");
-
-    match protocol {
-        "tcp" => {
-            // setup remote TCP stream
-            let stream = TcpStream::connect(consumer)
-                .await
-                .expect("Failed to connect to remote host");
-
-            //  setup tasks
-            tokio::spawn(tcp_broadcaster(stream, listener))
+    /// TOML file with defaults for any of these arguments, keyed by their name in snake_case,
+    /// like `max_clients = 10`, flags given here override it
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// how the effective configuration is logged at startup: json, as a single line with every
+    /// argument, its value and where it came from; text, a line per argument; or off, with
+    /// secrets like tokens redacted either way
+    #[arg(long, default_value = "json")]
+    log_config: ConfigLog,
+
+    /// check the configuration and exit, without serving: addresses resolve, the local ones can
+    /// be bound, certificates load and every producer can be opened; exits with an error if not
+    #[arg(long)]
+    check: bool,
+
+    /// host:port for consumers to connect and get data pushed, or to send datagrams from, can be
+    /// repeated to accept TCP consumers on every one of them, all with the same TLS and token
+    #[cfg_attr(unix, arg(short = 'c', long, required_unless_present = "local_uds"))]
+    #[cfg_attr(not(unix), arg(short = 'c', long, required = true))]
+    consumer: Vec<String>,
+
+    /// another host:port to accept TCP consumers on with TLS and token of its own, followed by
+    /// comma separated settings, like
+    /// 0.0.0.0:9443,cert_file=cert.pem,key_file=key.pem,auth_token=secret, plain and open to anyone
+    /// without them; can be repeated, and given as tables in the config file
+    #[arg(long, hide_env_values = true)]
+    listener: Vec<LocalListener>,
+
+    /// path of a Unix socket for consumers to connect to instead, removed on shutdown
+    #[cfg(unix)]
+    #[arg(long, conflicts_with = "consumer")]
+    local_uds: Option<PathBuf>,
+
+    /// take the listener for consumers from systemd socket activation (LISTEN_FDS) when there is
+    /// one, --consumer is bound otherwise
+    #[cfg(unix)]
+    #[arg(long, conflicts_with = "local_uds")]
+    systemd_socket: bool,
+
+    /// path of a Unix socket to take the listener and the consumers over from the running
+    /// broadcaster, once it gets SIGUSR2, and to then hand them over at in turn; only for plain TCP
+    /// consumers
+    #[cfg(unix)]
+    #[arg(long, conflicts_with_all = [
+        "local_uds", "local_cert_file", "auth_token", "compression", "banner", "banner_file",
+        "proxy_protocol",
+    ])]
+    handover_socket: Option<PathBuf>,
+
+    /// how consumers get the data, either tcp (they connect) or udp (datagrams sent to targets)
+    #[arg(long, default_value = "tcp")]
+    local_proto: LocalProto,
+
+    /// host:port to send datagrams to with the udp local protocol, can be repeated
+    #[arg(long, required_if_eq("local_proto", "udp"))]
+    udp_target: Vec<String>,
+
+    /// serve consumers over TLS
+    #[arg(long, requires_all = ["local_cert_file", "local_key_file"])]
+    local_tls: bool,
+
+    /// PEM file with the certificate chain presented to consumers
+    #[arg(long, requires = "local_tls")]
+    local_cert_file: Option<PathBuf>,
+
+    /// PEM file with the private key of the consumers certificate
+    #[arg(long, requires = "local_tls")]
+    local_key_file: Option<PathBuf>,
+
+    /// [protocol://]host:port for producer to pull(TCP) or listen(UDP) data from, or the path of
+    /// a file to read, or host:port/path to GET over HTTP, can be repeated
+    #[arg(
+        short = 'p',
+        long,
+        visible_alias = "remote",
+        required = true,
+        value_parser = parse_remote
+    )]
+    producer: Vec<String>,
+
+    /// protocol of the producers given without one, either tcp, udp, file (a path to read) or
+    /// http-sse (host:port/path to GET, relaying the data of Server-Sent Events, a line each, or
+    /// any other body as it is)
+    #[arg(long, default_value = "tcp")]
+    remote_proto: RemoteProto,
+
+    /// how to pull from several producers, either merge (all at once) or failover (one at a time)
+    #[arg(long, default_value = "merge")]
+    remote_mode: RemoteMode,
+
+    /// how chunks go to the consumers, either broadcast (every consumer gets every chunk) or
+    /// round-robin (each goes to the next consumer able to take it, without replay history)
+    #[arg(long, default_value = "broadcast")]
+    distribution: Distribution,
+
+    /// connect to a TCP producer over TLS
+    #[arg(long)]
+    remote_tls: bool,
+
+    /// PEM file with the CAs to trust for the producer instead of the system ones
+    #[arg(long, requires = "remote_tls")]
+    remote_ca_file: Option<PathBuf>,
+
+    /// name to verify the producer certificate against, defaults to its host
+    #[arg(long, requires = "remote_tls")]
+    remote_sni: Option<String>,
+
+    /// host:port of a SOCKS5 proxy to connect to TCP producers through
+    #[arg(long)]
+    remote_socks5: Option<String>,
+
+    /// local IP address the connections to TCP producers, or to their proxy, originate from
+    #[arg(long)]
+    remote_bind_addr: Option<IpAddr>,
+
+    /// username to authenticate to the SOCKS5 proxy with
+    #[arg(long, requires_all = ["remote_socks5", "socks5_pass"])]
+    socks5_user: Option<String>,
+
+    /// password to authenticate to the SOCKS5 proxy with
+    #[arg(long, env = "SOCKS5_PASS", hide_env_values = true)]
+    socks5_pass: Option<String>,
+
+    /// size in bytes of the buffer used to read from the producer, or auto to make it as large as
+    /// the receive buffer of its socket, or the default if that cannot be told
+    #[arg(
+        long,
+        env = "BUFFER_SIZE",
+        default_value_t = BufferSize::Fixed(DEFAULT_BUFFER_SIZE),
+        value_parser = parse_buffer_size
+    )]
+    buffer_size: BufferSize,
+
+    /// how the producer data is split into messages, either raw (no boundaries), length-prefixed
+    /// or line
+    #[arg(long, default_value = "raw")]
+    framing: FramingMode,
+
+    /// byte ending each message with line framing, either a single character or 0x-prefixed hex
+    #[arg(long, default_value = "0x0a", value_parser = parse_delimiter)]
+    delimiter: u8,
+
+    /// forward a trailing line without delimiter when the producer closes, instead of dropping it
+    #[arg(long)]
+    flush_partial_line: bool,
+
+    /// width in bytes of the length field with length-prefixed framing
+    #[arg(long, default_value_t = 4, value_parser = parse_length_width)]
+    length_width: usize,
+
+    /// byte order of the length field with length-prefixed framing, either big or little
+    #[arg(long, default_value = "big")]
+    length_endian: Endian,
+
+    /// size in bytes of the largest message taken from the producer with framing, a larger one
+    /// gets the producer reconnected
+    #[arg(long, default_value_t = DEFAULT_MAX_MESSAGE_SIZE, value_parser = parse_max_message_size)]
+    max_message_size: usize,
+
+    /// frame each chunk going out with a checksum so consumers can detect corruption, either
+    /// crc32 or xxhash, as the length of the chunk in 4 bytes, the chunk and the checksum, all
+    /// big endian
+    #[arg(long)]
+    checksum: Option<Checksum>,
+
+    /// how chunks are written to the consumers, either raw or json, a line per chunk with its
+    /// sequence number, receive time in nanoseconds since the epoch, length and base64 data
+    #[arg(long, default_value = "raw")]
+    output_format: OutputFormat,
+
+    /// keep numbering the json records across remote reconnects, instead of from 0 again
+    #[arg(long)]
+    continue_sequence: bool,
+
+    /// send each chunk after its sequence number, counted from 0 across remote reconnects, as an 8
+    /// bytes big endian integer, so consumers can tell chunks went missing; the chunk follows as it
+    /// would go out otherwise, checksum included
+    #[arg(long)]
+    seq_header: bool,
+
+    /// batch the messages from the producer for up to this many milliseconds before they are
+    /// broadcast, for fewer writes to the consumers, 10 if only --coalesce-bytes is given
+    #[arg(long)]
+    coalesce_ms: Option<u64>,
+
+    /// batch the messages from the producer until there are this many bytes of them, 65536 if only
+    /// --coalesce-ms is given
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    coalesce_bytes: Option<u64>,
+
+    /// forward what consumers write to the producer, turning this into a shared proxy
+    #[arg(long)]
+    bidirectional: bool,
+
+    /// bytes per second read from the producers at most, all of them together, no limit if unset
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_bandwidth_bps: Option<u64>,
+
+    /// bytes read from the producers at most, all of them together, then shut down once the
+    /// consumers got them, no limit if unset
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_bytes: Option<u64>,
+
+    /// for testing only: hold each chunk read from the producers this many milliseconds before
+    /// broadcasting it, to see how consumers cope with a laggy feed
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    inject_delay_ms: Option<u64>,
+
+    /// for testing only: hold each chunk up to this many more milliseconds, at random, on top of
+    /// the injected delay
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    inject_jitter_ms: Option<u64>,
+
+    /// read file producers over and over, instead of stopping once they end
+    #[arg(long = "loop")]
+    file_loop: bool,
+
+    /// time in milliseconds to wait after each read from a file producer, to replay at a pace
+    #[arg(long, default_value_t = 0)]
+    pace_ms: u64,
+
+    /// delay in milliseconds before retrying a failed connection to the producer
+    #[arg(long, default_value_t = 500)]
+    reconnect_initial_ms: u64,
+
+    /// upper bound in milliseconds for the delay between connection attempts
+    #[arg(long, default_value_t = 30_000)]
+    reconnect_max_ms: u64,
+
+    /// growth factor applied to the delay after each failed connection attempt
+    #[arg(long, default_value_t = 2.0)]
+    reconnect_multiplier: f64,
+
+    /// give up after this many failed connection attempts, retries forever if unset
+    #[arg(long)]
+    reconnect_max_attempts: Option<u32>,
+
+    /// exit when the producer closes the connection, or it gets reset or times out, instead of
+    /// reconnecting
+    #[arg(long)]
+    no_reconnect: bool,
+
+    /// reconnect to a producer that sends nothing for this long, to catch connections that went
+    /// silent without closing, no limit if unset
+    #[arg(long)]
+    remote_read_timeout_ms: Option<u64>,
+
+    /// time in milliseconds connecting to a producer can take before the attempt fails and is
+    /// retried, instead of the system timeout for unreachable hosts
+    #[arg(
+        long,
+        default_value_t = DEFAULT_CONNECT_TIMEOUT.as_millis() as u64,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    connect_timeout_ms: u64,
+
+    /// close and re-establish the connection to a producer once it is this old, for upstreams
+    /// that degrade over long-lived connections, no limit if unset
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    remote_max_lifetime_sec: Option<u64>,
+
+    /// time in milliseconds without data before TCP keepalive probes are sent to a producer, so
+    /// a dead one is detected without waiting for the read timeout, the system defaults if unset
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    remote_keepalive_ms: Option<u64>,
+
+    /// time in milliseconds between unanswered keepalive probes to a producer, defaults to
+    /// --remote-keepalive-ms
+    #[arg(
+        long,
+        requires = "remote_keepalive_ms",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    remote_keepalive_interval_ms: Option<u64>,
+
+    /// unanswered keepalive probes before the connection to a producer is dropped
+    #[arg(long, requires = "remote_keepalive_ms", default_value_t = DEFAULT_KEEPALIVE_RETRIES)]
+    remote_keepalive_retries: u32,
+
+    /// number of chunks retained for consumers that fall behind before they get dropped
+    #[arg(long, default_value_t = DEFAULT_BROADCAST_CAPACITY)]
+    broadcast_capacity: usize,
+
+    /// disable Nagle's algorithm on the TCP producers and consumers, on by default
+    #[arg(long, overrides_with = "no_nodelay")]
+    nodelay: bool,
+
+    /// keep Nagle's algorithm on, trading latency for fewer packets
+    #[arg(long)]
+    no_nodelay: bool,
+
+    /// do not set SO_REUSEADDR on the listeners, binding fails while old connections linger
+    #[arg(long)]
+    no_reuseaddr: bool,
+
+    /// time in milliseconds without data before TCP keepalive probes are sent to a consumer, so it
+    /// gets dropped soon if it is gone, the system defaults if unset
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    heartbeat_ms: Option<u64>,
+
+    /// time in milliseconds between unanswered keepalive probes, defaults to --heartbeat-ms
+    #[arg(long, requires = "heartbeat_ms", value_parser = clap::value_parser!(u64).range(1..))]
+    keepalive_interval_ms: Option<u64>,
+
+    /// unanswered keepalive probes before a consumer is dropped
+    #[arg(long, requires = "heartbeat_ms", default_value_t = DEFAULT_KEEPALIVE_RETRIES)]
+    keepalive_retries: u32,
+
+    /// times binding a listener is retried while its address is in use, waiting as for reconnects
+    #[arg(long, default_value_t = 0)]
+    bind_retries: u32,
+
+    /// accept IPv4 consumers on IPv6 listeners too, as IPv4-mapped addresses, IPv6 only otherwise
+    #[arg(long)]
+    dual_stack: bool,
+
+    /// time in milliseconds a consumer has to accept a chunk before it gets dropped
+    #[arg(long, default_value_t = DEFAULT_WRITE_TIMEOUT.as_millis() as u64)]
+    write_timeout_ms: u64,
+
+    /// number of chunks queued for each consumer while it is busy writing
+    #[arg(long, default_value_t = DEFAULT_CLIENT_QUEUE_SIZE, value_parser = parse_queue_size)]
+    client_queue_size: usize,
+
+    /// what to do when the queue of a consumer is full, either oldest or newest (drop that chunk)
+    /// or disconnect (drop the consumer)
+    #[arg(long, default_value = "disconnect")]
+    drop_policy: DropPolicy,
+
+    /// what to do when a consumer falls behind the broadcast channel, either resync (skip ahead
+    /// and log the gap), disconnect (drop the consumer) or count-only (skip ahead without logging),
+    /// as the drop policy says if unset
+    #[arg(long)]
+    lag_policy: Option<LagPolicy>,
+
+    /// time in milliseconds a consumer can stay behind without emptying its queue before it gets
+    /// dropped, no limit if unset
+    #[arg(long)]
+    client_idle_timeout_ms: Option<u64>,
+
+    /// flag a consumer as slow, in the logs and metrics, once the bytes queued for it average
+    /// more than this, well before its queue fills up or its writes time out; no detection if
+    /// unset
+    #[arg(long)]
+    slow_client_threshold: Option<usize>,
+
+    /// drop the consumers flagged as slow, instead of only reporting them
+    #[arg(long, requires = "slow_client_threshold")]
+    slow_client_disconnect: bool,
+
+    /// compresses the stream sent to each consumer, either gzip or zstd, none if unset; every
+    /// consumer gets its own stream from when it connects, flushed after each chunk
+    #[arg(long = "compress")]
+    compression: Option<Compression>,
+
+    /// bytes per second each consumer can be sent at most, no limit if unset
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    per_client_bps: Option<u64>,
+
+    /// bytes per second the data written to each consumer is spread at, so large chunks and the
+    /// replay history do not arrive in a single burst, written as it comes if unset
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    smooth_bps: Option<u64>,
+
+    /// time in milliseconds consumers get to receive their pending data on shutdown
+    #[arg(long, default_value_t = DEFAULT_SHUTDOWN_GRACE.as_millis() as u64)]
+    shutdown_grace_ms: u64,
+
+    /// send consumers a last message on shutdown, after their pending data, with this payload,
+    /// either text or 0x-prefixed hex, and framed like the rest, a zero-length one if empty
+    #[arg(long, num_args = 0..=1, default_missing_value = "", value_parser = parse_prefix)]
+    shutdown_sentinel: Option<Bytes>,
+
+    /// send consumers these bytes as they connect, before the replay history and the broadcast,
+    /// either text or 0x-prefixed hex, like a banner some protocols expect first
+    #[arg(long, value_parser = parse_prefix)]
+    banner: Option<Bytes>,
+
+    /// same as --banner, with the bytes of this file
+    #[arg(long, conflicts_with = "banner", value_parser = read_banner)]
+    banner_file: Option<Bytes>,
+
+    /// token consumers have to send, followed by a newline, before they get any data, anyone gets
+    /// data if unset
+    #[arg(long, env = "AUTH_TOKEN", hide_env_values = true)]
+    auth_token: Option<String>,
+
+    /// time in milliseconds a consumer has to send the token, and the topic with --topics
+    #[arg(long, default_value_t = DEFAULT_AUTH_TIMEOUT.as_millis() as u64)]
+    auth_timeout_ms: u64,
+
+    /// consumers subscribe to a topic by sending it in a line as they connect, after the token if
+    /// any, and only get the frames tagged with it, those whose message starts with the topic
+    /// followed by a space, like `prices 101.5`; an empty line subscribes to every frame
+    #[arg(long)]
+    topics: bool,
+
+    /// consumers connect through a load balancer sending a PROXY protocol header, version 1 or 2,
+    /// first; their address is taken from it, for --allow-cidr and --deny-cidr too
+    #[arg(long)]
+    proxy_protocol: bool,
+
+    /// log accepted consumers with the name their address resolves back to, looked up in the
+    /// background, the address alone if it has none
+    #[arg(long)]
+    reverse_dns: bool,
+
+    /// network allowed to connect as consumer in CIDR notation, can be repeated, all if unset
+    #[arg(long)]
+    allow_cidr: Vec<IpNet>,
+
+    /// network not allowed to connect as consumer in CIDR notation, can be repeated, takes
+    /// precedence over --allow-cidr
+    #[arg(long)]
+    deny_cidr: Vec<IpNet>,
+
+    /// new consumers admitted per second at most on each listener, no limit if unset
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_accepts_per_sec: Option<u64>,
+
+    /// what to do with consumers connecting faster than --max-accepts-per-sec, either delay (they
+    /// wait to be accepted) or close (they are disconnected right away)
+    #[arg(long, requires = "max_accepts_per_sec", default_value = "delay")]
+    accept_overflow: AcceptOverflow,
+
+    /// maximum number of simultaneous consumers, 0 for unlimited
+    #[arg(long, default_value_t = DEFAULT_MAX_CLIENTS)]
+    max_clients: usize,
+
+    /// no longer read the producers while fewer than this many consumers are connected, so
+    /// nothing is read for no one, 0 to always read them
+    #[arg(long, default_value_t = 0)]
+    min_clients: usize,
+
+    /// what happens to what the producers send while no consumer is connected, either discard
+    /// (it is lost), buffer (the last of it is kept for the next consumer) or pause-read (the
+    /// producers are no longer read, like with a minimum of 1 client)
+    #[arg(long, default_value = "discard")]
+    no_clients_policy: NoClientsPolicy,
+
+    /// number of bytes kept for the next consumer with the buffer no clients policy, as the whole
+    /// messages fitting in them with framing
+    #[arg(long, default_value_t = DEFAULT_NO_CLIENTS_BUFFER)]
+    no_clients_buffer: usize,
+
+    /// number of most recent bytes replayed to consumers when they connect, as the whole messages
+    /// fitting in them with framing, 0 disables it
+    #[arg(long, default_value_t = 0)]
+    replay_bytes: usize,
+
+    /// time in milliseconds between the replays of consumers connecting all at once, so the burst
+    /// is spread out
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    replay_stagger_ms: Option<u64>,
+
+    /// leave out the chunks starting with this prefix, either text or 0x-prefixed hex, best used
+    /// along with framing so each chunk is a whole message
+    #[arg(long, value_parser = parse_prefix)]
+    filter_prefix: Option<Bytes>,
+
+    /// leave out the messages repeating any of the last this many, as told by their hash, with
+    /// framing; counted in the metrics
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    dedup_window: Option<u64>,
+
+    /// file to append everything broadcast to, disabled if unset
+    #[arg(long)]
+    tee_file: Option<PathBuf>,
+
+    /// write everything broadcast to stdout too, to pipe it into other tools, the logs go to
+    /// stderr then; a closed pipe only stops the copy
+    #[arg(long)]
+    stdout: bool,
+
+    /// file to record every chunk broadcast to along with its timestamp, keeping the chunk
+    /// boundaries and timing the tee file loses, disabled if unset
+    #[arg(long)]
+    capture_file: Option<PathBuf>,
+
+    /// format of the records in the capture file, jsonl for a line of JSON per chunk with the
+    /// nanoseconds since the capture started and the chunk in base64
+    #[arg(long, default_value = "jsonl")]
+    capture_format: CaptureFormat,
+
+    /// host:port to accept WebSocket consumers on, each chunk sent as a binary message, disabled if
+    /// unset
+    #[arg(long)]
+    ws_addr: Option<String>,
+
+    /// host:port to serve Server-Sent Events on (text/event-stream), one data event per chunk,
+    /// disabled if unset
+    #[arg(long)]
+    sse_addr: Option<String>,
+
+    /// how chunks are written in the Server-Sent Events, either base64 or text
+    #[arg(long, default_value = "base64")]
+    sse_encoding: SseEncoding,
+
+    /// host:port to serve Prometheus metrics on, at /metrics, and readiness at /healthz, 200 while
+    /// a producer is connected and 503 otherwise, disabled if unset
+    #[arg(long)]
+    metrics_addr: Option<String>,
+
+    /// log a line every this many seconds with the consumers connected, the bytes per second read
+    /// and written since the last one and the reconnects so far, never if unset
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    stats_interval_sec: Option<u64>,
+
+    /// host:port to serve the admin commands on (list, kick <addr>, stats, pause, resume and
+    /// remote set <host:port>), disabled if unset
+    #[arg(long)]
+    admin_addr: Option<String>,
+
+    /// what pausing through the admin commands does to the remotes, either drop (still read, what
+    /// they send is left out) or hold (no longer read until resumed)
+    #[arg(long, default_value = "drop")]
+    pause_mode: PauseMode,
+}
+
+/// Checks a producer parses, the protocol is applied later on as it depends on `--remote-proto`
+fn parse_remote(s: &str) -> Result<String, String> {
+    Remote::parse_with(s, RemoteProto::default())?;
+    Ok(s.to_string())
+}
+
+/// Parses a prefix, hex if it starts with `0x`, otherwise the bytes of the text itself
+fn parse_prefix(s: &str) -> Result<Bytes, String> {
+    let Some(hex) = s.strip_prefix("0x") else {
+        return Ok(Bytes::copy_from_slice(s.as_bytes()));
+    };
+
+    if hex.is_empty() || hex.len() % 2 != 0 {
+        return Err("hex prefix must have an even, non-zero number of digits".to_string());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| format!("{e}")))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Bytes::from)
+}
+
+/// Whether the logs go to stderr, to leave stdout to the broadcast with `--stdout`.
+static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+fn log_writer() -> Box<dyn std::io::Write> {
+    match LOG_TO_STDERR.load(Ordering::Relaxed) {
+        true => Box::new(std::io::stderr()),
+        false => Box::new(std::io::stdout()),
+    }
+}
+
+/// Reads the banner from a file, as it is
+fn read_banner(path: &str) -> Result<Bytes, String> {
+    std::fs::read(path)
+        .map(Bytes::from)
+        .map_err(|e| format!("failed to read {path}: {e}"))
+}
+
+/// Parses a delimiter byte, like `;` or `0x1e`
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).map_err(|e| format!("{e}")),
+        None if s.len() == 1 => Ok(s.as_bytes()[0]),
+        None => Err("must be a single byte".to_string()),
+    }
+}
+
+/// Parses the width of a length field, one of the sizes of an unsigned integer
+fn parse_length_width(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(width @ (1 | 2 | 4 | 8)) => Ok(width),
+        _ => Err("must be 1, 2, 4 or 8".to_string()),
+    }
+}
+
+/// Parses a queue size, which has to fit at least one chunk
+fn parse_queue_size(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        size => size.map_err(|e| format!("{e}")),
+    }
+}
+
+/// How the producer data is split into messages, the settings of each are given apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FramingMode {
+    Raw,
+    LengthPrefixed,
+    Line,
+}
+
+impl FromStr for FramingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(FramingMode::Raw),
+            "length-prefixed" => Ok(FramingMode::LengthPrefixed),
+            "line" => Ok(FramingMode::Line),
+            _ => Err(format!(
+                "unsupported framing: {s}, expected raw, length-prefixed or line"
+            )),
+        }
+    }
+}
+
+impl Args {
+    /// Fails unless the messages are framed when an argument working on whole messages is given.
+    fn check_framing(&self) -> Result<(), clap::Error> {
+        if self.framing != FramingMode::Raw {
+            return Ok(());
         }
 
-        "udp" => {
-            // setup UDP listener
-            let socket = UdpSocket::bind(consumer)
-                .await
-                .expect("Failed to connect to remote host");
+        let needing = [
+            ("--shutdown-sentinel", self.shutdown_sentinel.is_some()),
+            ("--topics", self.topics),
+            ("--dedup-window", self.dedup_window.is_some()),
+        ];
+        match needing.into_iter().find(|(_, given)| *given) {
+            Some((arg, _)) => Err(Args::command().error(
+                ErrorKind::MissingRequiredArgument,
+                format!("{arg} needs --framing length-prefixed or line"),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Size of the buffer used to read from the producer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BufferSize {
+    /// as large as the receive buffer of the producer socket
+    Auto,
+    Fixed(usize),
+}
 
-            //  setup tasks
-            tokio::spawn(udp_broadcaster(socket, listener))
+impl fmt::Display for BufferSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferSize::Auto => write!(f, "auto"),
+            BufferSize::Fixed(size) => write!(f, "{size}"),
+        }
+    }
+}
+
+/// Parses a buffer size, either auto or a size not below `MIN_BUFFER_SIZE`
+fn parse_buffer_size(s: &str) -> Result<BufferSize, String> {
+    if s == "auto" {
+        return Ok(BufferSize::Auto);
+    }
+
+    let size: usize = s.parse().map_err(|e| format!("{e}"))?;
+
+    if size < MIN_BUFFER_SIZE {
+        return Err(format!("must be at least {MIN_BUFFER_SIZE} bytes, or auto"));
+    }
+
+    Ok(BufferSize::Fixed(size))
+}
+
+/// Parses the size of the largest message, which cannot be empty
+fn parse_max_message_size(s: &str) -> Result<usize, String> {
+    let size: usize = s.parse().map_err(|e| format!("{e}"))?;
+
+    if size == 0 {
+        return Err("must be at least 1 byte".to_string());
+    }
+
+    Ok(size)
+}
+
+impl From<Args> for Config {
+    fn from(args: Args) -> Self {
+        let local_tls = args
+            .local_cert_file
+            .zip(args.local_key_file)
+            .filter(|_| args.local_tls)
+            .map(|(cert_file, key_file)| LocalTls {
+                cert_file,
+                key_file,
+            });
+
+        // the first address is the main one, the others are listeners just like it
+        let mut consumers = args.consumer.into_iter();
+        let local = consumers.next().unwrap_or_default();
+        let listeners = consumers
+            .map(|address| LocalListener {
+                address,
+                tls: local_tls.clone(),
+                auth_token: args.auth_token.clone(),
+            })
+            .chain(args.listener)
+            .collect();
+
+        Config {
+            local,
+            #[cfg(unix)]
+            local_uds: args.local_uds,
+            #[cfg(unix)]
+            systemd_socket: args.systemd_socket,
+            #[cfg(unix)]
+            handover_socket: args.handover_socket,
+            local_proto: args.local_proto,
+            udp_targets: args.udp_target,
+            local_tls,
+            listeners,
+            remotes: args
+                .producer
+                .iter()
+                .map(|remote| {
+                    Remote::parse_with(remote, args.remote_proto).expect("checked by parse_remote")
+                })
+                .collect(),
+            remote_mode: args.remote_mode,
+            distribution: args.distribution,
+            remote_tls: args.remote_tls.then_some(RemoteTls {
+                ca_file: args.remote_ca_file,
+                sni: args.remote_sni,
+            }),
+            remote_socks5: args.remote_socks5.map(|address| Socks5Proxy {
+                address,
+                credentials: args.socks5_user.zip(args.socks5_pass),
+            }),
+            remote_bind_addr: args.remote_bind_addr,
+            buffer_size: match args.buffer_size {
+                BufferSize::Fixed(size) => size,
+                BufferSize::Auto => DEFAULT_BUFFER_SIZE,
+            },
+            auto_buffer_size: args.buffer_size == BufferSize::Auto,
+            framing: match args.framing {
+                FramingMode::Raw => Framing::Raw,
+                FramingMode::LengthPrefixed => Framing::LengthPrefixed {
+                    width: args.length_width,
+                    endian: args.length_endian,
+                },
+                FramingMode::Line => Framing::Line {
+                    delimiter: args.delimiter,
+                    flush_partial: args.flush_partial_line,
+                },
+            },
+            max_message_size: args.max_message_size,
+            checksum: args.checksum,
+            output_format: args.output_format,
+            continue_sequence: args.continue_sequence,
+            seq_header: args.seq_header,
+            dedup_window: args.dedup_window.map(|window| window as usize),
+            coalesce: (args.coalesce_ms.is_some() || args.coalesce_bytes.is_some()).then(|| {
+                Coalesce {
+                    max_delay: args
+                        .coalesce_ms
+                        .map_or(DEFAULT_COALESCE_DELAY, Duration::from_millis),
+                    max_bytes: args
+                        .coalesce_bytes
+                        .map_or(DEFAULT_COALESCE_BYTES, |bytes| bytes as usize),
+                }
+            }),
+            backoff: Backoff {
+                initial: Duration::from_millis(args.reconnect_initial_ms),
+                max: Duration::from_millis(args.reconnect_max_ms),
+                multiplier: args.reconnect_multiplier,
+                max_attempts: args.reconnect_max_attempts,
+            },
+            bidirectional: args.bidirectional,
+            max_bandwidth: args.max_bandwidth_bps,
+            max_bytes: args.max_bytes,
+            inject_delay: args.inject_delay_ms.map(Duration::from_millis),
+            inject_jitter: args.inject_jitter_ms.map(Duration::from_millis),
+            file_loop: args.file_loop,
+            file_pace: Duration::from_millis(args.pace_ms),
+            reconnect: !args.no_reconnect,
+            remote_read_timeout: args.remote_read_timeout_ms.map(Duration::from_millis),
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            remote_max_lifetime: args.remote_max_lifetime_sec.map(Duration::from_secs),
+            remote_keepalive: args.remote_keepalive_ms.map(|idle_ms| {
+                let idle = Duration::from_millis(idle_ms);
+                Keepalive {
+                    idle,
+                    interval: args
+                        .remote_keepalive_interval_ms
+                        .map_or(idle, Duration::from_millis),
+                    retries: args.remote_keepalive_retries,
+                }
+            }),
+            broadcast_capacity: args.broadcast_capacity,
+            nodelay: !args.no_nodelay,
+            reuseaddr: !args.no_reuseaddr,
+            keepalive: args.heartbeat_ms.map(|idle_ms| {
+                let idle = Duration::from_millis(idle_ms);
+                Keepalive {
+                    idle,
+                    interval: args
+                        .keepalive_interval_ms
+                        .map_or(idle, Duration::from_millis),
+                    retries: args.keepalive_retries,
+                }
+            }),
+            bind_retries: args.bind_retries,
+            dual_stack: args.dual_stack,
+            write_timeout: Duration::from_millis(args.write_timeout_ms),
+            client_queue_size: args.client_queue_size,
+            drop_policy: args.drop_policy,
+            lag_policy: args.lag_policy,
+            client_idle_timeout: args.client_idle_timeout_ms.map(Duration::from_millis),
+            slow_client_threshold: args.slow_client_threshold,
+            slow_client_disconnect: args.slow_client_disconnect,
+            compression: args.compression,
+            per_client_bandwidth: args.per_client_bps,
+            per_client_smoothing: args.smooth_bps,
+            shutdown_grace: Duration::from_millis(args.shutdown_grace_ms),
+            shutdown_sentinel: args.shutdown_sentinel,
+            banner: args.banner.or(args.banner_file),
+            auth_token: args.auth_token,
+            auth_timeout: Duration::from_millis(args.auth_timeout_ms),
+            topics: args.topics,
+            access: AccessList {
+                allow: args.allow_cidr,
+                deny: args.deny_cidr,
+            },
+            proxy_protocol: args.proxy_protocol,
+            reverse_dns: args.reverse_dns,
+            max_accepts_per_sec: args.max_accepts_per_sec,
+            accept_overflow: args.accept_overflow,
+            max_clients: args.max_clients,
+            min_clients: args.min_clients,
+            no_clients_policy: args.no_clients_policy,
+            no_clients_buffer: args.no_clients_buffer,
+            replay_bytes: args.replay_bytes,
+            replay_stagger: args.replay_stagger_ms.map(Duration::from_millis),
+            tee_file: args.tee_file,
+            stdout: args.stdout,
+            capture_file: args.capture_file,
+            capture_format: args.capture_format,
+            ws_addr: args.ws_addr,
+            sse_addr: args.sse_addr,
+            sse_encoding: args.sse_encoding,
+            metrics_addr: args.metrics_addr,
+            stats_interval: args.stats_interval_sec.map(Duration::from_secs),
+            admin_addr: args.admin_addr,
+            pause_mode: args.pause_mode,
         }
-        _ => panic!("Unsupported protocol: {}", protocol),
     }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    // setup tracing
+async fn main() -> ExitCode {
+    // setup tracing, verbosity can be tuned with RUST_LOG
     let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::TRACE)
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(log_writer)
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    // parse arguments
-    let Args { consumer, producer } = Args::parse();
+    // parse arguments, with the ones in the config file under them
+    let layered = match layer_config_file(&Args::command(), "config", std::env::args_os().collect())
+    {
+        Ok(layered) => layered,
+        Err(e) => {
+            error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let matches = Args::command().get_matches_from(layered.args.clone());
+    let args = match Args::from_arg_matches(&matches) {
+        Ok(args) => args,
+        Err(e) => e.exit(),
+    };
+    if let Err(e) = args.check_framing() {
+        e.exit()
+    }
+    LOG_TO_STDERR.store(args.stdout, Ordering::Relaxed);
 
-    info!("Running with passed args {:?}", Args::parse());
+    match args.log_config {
+        ConfigLog::Json => info!("{}", effective_json(&Args::command(), &matches, &layered)),
+        ConfigLog::Text => {
+            for (id, values, source) in value_sources(&Args::command(), &matches, &layered) {
+                info!("{id} = {} ({source})", values.join(", "));
+            }
+        }
+        ConfigLog::Off => {}
+    }
 
-    // setup tasks
-    let broadcast = setup(&consumer, &producer).await;
-    let signal = tokio::spawn(tokio::signal::ctrl_c());
+    // cancel everything on ctrl-c or SIGTERM
+    let cancel = CancellationToken::new();
 
-    // wait for any of the tasks to complete
-    try_join!(flatten(signal), flatten(broadcast))?;
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            match shutdown_signal().await {
+                Ok(()) => {
+                    info!("Shutting down");
+                    cancel.cancel();
+                }
+                Err(e) => error!("listening for shutdown signals: {e}"),
+            }
+        }
+    });
+
+    let mut broadcaster = Broadcaster::new(args.clone().into());
+
+    if let Some(prefix) = args.filter_prefix {
+        broadcaster = broadcaster.with_filter(DropPrefix(prefix));
+    }
+
+    if args.check {
+        return match broadcaster.check().await {
+            Ok(()) => {
+                info!("configuration is valid");
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                error!("configuration check failed: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // hand the consumers over on SIGUSR2, to whoever waits at the handover socket
+    #[cfg(unix)]
+    if args.handover_socket.is_some() {
+        let broadcaster = broadcaster.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = handover_signal().await {
+                    error!("listening for handover signals: {e}");
+                    break;
+                }
+                broadcaster.hand_over();
+            }
+        });
+    }
 
-    Ok(())
+    match broadcaster.run(cancel).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,384 @@
+use crate::{DisconnectReason, Hub};
+use std::fmt::Write as _;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, instrument, warn};
+
+/// Longest line taken in the head of a request, the request line or a header.
+const MAX_LINE: u64 = 8 * 1024;
+
+/// Time a peer has to send the head of its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Upper bounds, in seconds, of the buckets of the first byte latency histogram.
+const FIRST_BYTE_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Counters and gauges describing the broadcast, shared by every task through the [`Hub`].
+#[derive(Debug, Default)]
+pub struct Metrics {
+    clients_connected: AtomicUsize,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    clients_dropped: AtomicU64,
+    clients_slow: AtomicU64,
+    remote_reconnects: AtomicU64,
+    chunks_filtered: AtomicU64,
+    chunks_deduplicated: AtomicU64,
+    chunks_lagged: AtomicU64,
+    /// consumers removed for each reason, in the order of [`DisconnectReason::ALL`]
+    disconnects: [AtomicU64; DisconnectReason::ALL.len()],
+    remotes_connected: AtomicUsize,
+    listening: AtomicBool,
+    /// consumers whose first byte took up to each of the bucket bounds, and beyond the last one
+    first_byte_buckets: [AtomicU64; FIRST_BYTE_BUCKETS.len() + 1],
+    first_byte_count: AtomicU64,
+    first_byte_micros: AtomicU64,
+}
+
+impl Metrics {
+    pub fn clients_connected(&self) -> usize {
+        self.clients_connected.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn clients_dropped(&self) -> u64 {
+        self.clients_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn clients_slow(&self) -> u64 {
+        self.clients_slow.load(Ordering::Relaxed)
+    }
+
+    pub fn remote_reconnects(&self) -> u64 {
+        self.remote_reconnects.load(Ordering::Relaxed)
+    }
+
+    pub fn chunks_filtered(&self) -> u64 {
+        self.chunks_filtered.load(Ordering::Relaxed)
+    }
+
+    pub fn chunks_deduplicated(&self) -> u64 {
+        self.chunks_deduplicated.load(Ordering::Relaxed)
+    }
+
+    pub fn chunks_lagged(&self) -> u64 {
+        self.chunks_lagged.load(Ordering::Relaxed)
+    }
+
+    /// Consumers removed so far for `reason`.
+    pub fn disconnects(&self, reason: DisconnectReason) -> u64 {
+        self.disconnects[reason as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn remotes_connected(&self) -> usize {
+        self.remotes_connected.load(Ordering::Relaxed)
+    }
+
+    /// Number of consumers that got their first byte.
+    pub fn first_byte_count(&self) -> u64 {
+        self.first_byte_count.load(Ordering::Relaxed)
+    }
+
+    /// Time from accepting each consumer to writing the first byte to it, added up.
+    pub fn first_byte_sum(&self) -> Duration {
+        Duration::from_micros(self.first_byte_micros.load(Ordering::Relaxed))
+    }
+
+    /// Whether consumers can connect and there is a remote to get data from for them.
+    pub fn healthy(&self) -> bool {
+        self.listening.load(Ordering::Relaxed) && self.remotes_connected() > 0
+    }
+
+    pub(crate) fn clients(&self) -> &AtomicUsize {
+        &self.clients_connected
+    }
+
+    pub(crate) fn received(&self, n: usize) {
+        self.bytes_received.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sent(&self, n: usize) {
+        self.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a consumer dropped for failing or falling behind, for `reason`.
+    pub(crate) fn dropped(&self, reason: DisconnectReason) {
+        self.clients_dropped.fetch_add(1, Ordering::Relaxed);
+        self.disconnected(reason);
+    }
+
+    /// Counts a consumer removed for `reason`, whatever it is.
+    pub(crate) fn disconnected(&self, reason: DisconnectReason) {
+        self.disconnects[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn slow(&self) {
+        self.clients_slow.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reconnected(&self) {
+        self.remote_reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn filtered(&self) {
+        self.chunks_filtered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn deduplicated(&self) {
+        self.chunks_deduplicated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn lagged(&self, chunks: u64) {
+        self.chunks_lagged.fetch_add(chunks, Ordering::Relaxed);
+    }
+
+    pub(crate) fn remote_connected(&self) {
+        self.remotes_connected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn remote_disconnected(&self) {
+        self.remotes_connected.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Records the time a consumer took from being accepted to getting its first byte.
+    pub(crate) fn first_byte(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let bucket = FIRST_BYTE_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(FIRST_BYTE_BUCKETS.len());
+
+        self.first_byte_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.first_byte_count.fetch_add(1, Ordering::Relaxed);
+        self.first_byte_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Marks the listener, or socket, for consumers as bound.
+    pub(crate) fn listening(&self) {
+        self.listening.store(true, Ordering::Relaxed);
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let metrics = [
+            (
+                "tcp_broadcast_clients_connected",
+                "gauge",
+                "Number of consumers currently connected.",
+                self.clients_connected() as u64,
+            ),
+            (
+                "tcp_broadcast_bytes_received_total",
+                "counter",
+                "Bytes received from the remote.",
+                self.bytes_received(),
+            ),
+            (
+                "tcp_broadcast_bytes_sent_total",
+                "counter",
+                "Bytes written to consumers.",
+                self.bytes_sent(),
+            ),
+            (
+                "tcp_broadcast_clients_dropped_total",
+                "counter",
+                "Consumers dropped because they failed or fell behind.",
+                self.clients_dropped(),
+            ),
+            (
+                "tcp_broadcast_clients_slow_total",
+                "counter",
+                "Times a consumer was flagged as slow, its average backlog over the threshold.",
+                self.clients_slow(),
+            ),
+            (
+                "tcp_broadcast_remote_reconnects_total",
+                "counter",
+                "Times the connection to the remote was re-established.",
+                self.remote_reconnects(),
+            ),
+            (
+                "tcp_broadcast_chunks_filtered_total",
+                "counter",
+                "Chunks left out by the filter.",
+                self.chunks_filtered(),
+            ),
+            (
+                "tcp_broadcast_chunks_deduplicated_total",
+                "counter",
+                "Chunks left out for repeating one of the last ones.",
+                self.chunks_deduplicated(),
+            ),
+            (
+                "tcp_broadcast_chunks_lagged_total",
+                "counter",
+                "Chunks consumers missed for falling behind the broadcast channel.",
+                self.chunks_lagged(),
+            ),
+            (
+                "tcp_broadcast_remotes_connected",
+                "gauge",
+                "Number of remotes currently being pulled from.",
+                self.remotes_connected() as u64,
+            ),
+        ];
+
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name} {value}");
+        }
+
+        let name = "tcp_broadcast_client_disconnects_total";
+        let _ = writeln!(out, "# HELP {name} Consumers removed, by reason.");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for reason in DisconnectReason::ALL {
+            let value = self.disconnects(reason);
+            let _ = writeln!(out, "{name}{{reason=\"{reason}\"}} {value}");
+        }
+
+        let name = "tcp_broadcast_first_byte_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Time from accepting a consumer to writing the first byte to it."
+        );
+        let _ = writeln!(out, "# TYPE {name} histogram");
+
+        // buckets are cumulative, each one counts the consumers of the ones before it too
+        let mut count = 0;
+        for (i, bucket) in self.first_byte_buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            match FIRST_BYTE_BUCKETS.get(i) {
+                Some(bound) => {
+                    let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}");
+                }
+                None => {
+                    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+                }
+            }
+        }
+        let _ = writeln!(out, "{name}_sum {}", self.first_byte_sum().as_secs_f64());
+        let _ = writeln!(out, "{name}_count {count}");
+
+        out
+    }
+}
+
+/// Logs a summary line every `interval`: consumers connected, throughput in and out since the
+/// last one, and reconnects so far. Never completes.
+pub(crate) async fn log_stats(metrics: &Metrics, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    // the first tick completes right away
+    ticks.tick().await;
+
+    let mut last = (metrics.bytes_received(), metrics.bytes_sent());
+    let mut last_at = tokio::time::Instant::now();
+
+    loop {
+        ticks.tick().await;
+
+        let now = tokio::time::Instant::now();
+        let totals = (metrics.bytes_received(), metrics.bytes_sent());
+        let elapsed = (now - last_at).as_secs_f64();
+        let rate = |total: u64, before: u64| ((total - before) as f64 / elapsed) as u64;
+
+        info!(
+            "stats: {} clients, {} bytes/s in, {} bytes/s out, {} reconnects",
+            metrics.clients_connected(),
+            rate(totals.0, last.0),
+            rate(totals.1, last.1),
+            metrics.remote_reconnects()
+        );
+
+        last = totals;
+        last_at = now;
+    }
+}
+
+/// Serves the hub metrics over HTTP on `/metrics`, and its health on `/healthz`: 200 while
+/// [`Metrics::healthy`], 503 otherwise, like while reconnecting to the remote. Any other path gets
+/// a 404.
+#[instrument(skip_all)]
+pub(crate) async fn serve_metrics(listener: TcpListener, hub: Hub) {
+    while let Ok((stream, addr)) = listener.accept().await {
+        let hub = hub.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(stream, &hub).await {
+                warn!("serving metrics to {addr}: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_request(stream: TcpStream, hub: &Hub) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+
+    let request_line = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request head not sent in time"))??;
+
+    debug!("metrics request {:?}", request_line.trim_end());
+
+    let path = request_line.split_whitespace().nth(1);
+    let (status, body) = match path {
+        Some("/metrics") => ("200 OK", hub.metrics().render()),
+        Some("/healthz") if hub.metrics().healthy() => ("200 OK", "ok\n".to_string()),
+        Some("/healthz") => ("503 Service Unavailable", "unavailable\n".to_string()),
+        _ => ("404 Not Found", String::new()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    let stream = stream.get_mut();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Reads the head of a request, returns its request line: the headers are skipped, nothing in
+/// them matters.
+async fn read_head<S: AsyncBufRead + Unpin>(stream: &mut S) -> io::Result<String> {
+    let request_line = read_line(stream).await?;
+
+    loop {
+        match read_line(stream).await?.as_str() {
+            "\r\n" | "\n" | "" => return Ok(request_line),
+            _ => {}
+        }
+    }
+}
+
+/// Reads a line of the request head, failing on one longer than [`MAX_LINE`], empty at EOF.
+async fn read_line<S: AsyncBufRead + Unpin>(stream: &mut S) -> io::Result<String> {
+    let mut line = String::new();
+    let read = stream.take(MAX_LINE).read_line(&mut line).await?;
+
+    if read as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("request line longer than {MAX_LINE} bytes"),
+        ));
+    }
+
+    Ok(line)
+}
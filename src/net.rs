@@ -0,0 +1,372 @@
+use crate::{BroadcastError, Config};
+use socket2::{SockRef, TcpKeepalive};
+use std::fmt::Debug;
+use std::future::Future;
+use std::io::{self, Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{lookup_host, TcpListener, TcpSocket, TcpStream, UdpSocket};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tracing::debug;
+
+/// Resolves a `host:port` address, host can be either a name or a literal IP.
+///
+/// Fails if the resolution yields no addresses at all.
+pub async fn resolve(addr: &str) -> Result<Vec<SocketAddr>, BroadcastError> {
+    let resolve_error = |source| BroadcastError::Resolve {
+        address: addr.to_string(),
+        source,
+    };
+
+    let addrs: Vec<_> = lookup_host(addr).await.map_err(resolve_error)?.collect();
+
+    if addrs.is_empty() {
+        return Err(resolve_error(Error::new(
+            ErrorKind::NotFound,
+            "did not resolve to any address",
+        )));
+    }
+
+    debug!("{addr} resolved to {addrs:?}");
+
+    Ok(addrs)
+}
+
+/// Tries `f` on each of the addresses in order, returns the first success or the last error.
+async fn first_ok<T, F, Fut>(addrs: Vec<SocketAddr>, f: F) -> Result<T, Error>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: std::future::Future<Output = Result<T, Error>>,
+{
+    let mut last_error = None;
+
+    for socket_addr in addrs {
+        match f(socket_addr).await {
+            Ok(result) => return Ok(result),
+            Err(e) => {
+                debug!("{socket_addr} failed: {e}");
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| Error::new(ErrorKind::NotFound, "no address to try")))
+}
+
+/// Connects to a remote TCP host, trying each of the resolved addresses in order.
+pub async fn connect(addr: &str) -> Result<TcpStream, BroadcastError> {
+    connect_from(addr, None).await
+}
+
+/// Like [`connect`], from the local address `bind` if there is one, on a port the system picks.
+pub(crate) async fn connect_from(
+    addr: &str,
+    bind: Option<IpAddr>,
+) -> Result<TcpStream, BroadcastError> {
+    let addrs = resolve(addr).await?;
+
+    let attempt = |target: SocketAddr| async move {
+        let Some(bind) = bind else {
+            return TcpStream::connect(target).await;
+        };
+
+        let socket = match target {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        socket.bind(SocketAddr::new(bind, 0))?;
+        socket.connect(target).await
+    };
+
+    first_ok(addrs, attempt)
+        .await
+        .map_err(|source| BroadcastError::Connect {
+            address: addr.to_string(),
+            source,
+        })
+}
+
+/// Backlog of pending connections for the listeners, the one `TcpListener::bind` uses.
+const LISTEN_BACKLOG: u32 = 1024;
+
+/// Socket options of the listeners.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenOptions {
+    /// whether the address can be bound again right away after a restart, even while connections
+    /// from the previous run linger in `TIME_WAIT`
+    pub reuseaddr: bool,
+    /// whether IPv6 listeners also accept IPv4 peers, as IPv4-mapped addresses
+    pub dual_stack: bool,
+}
+
+impl Default for ListenOptions {
+    fn default() -> Self {
+        Self {
+            reuseaddr: true,
+            dual_stack: false,
+        }
+    }
+}
+
+impl From<&Config> for ListenOptions {
+    fn from(config: &Config) -> Self {
+        Self {
+            reuseaddr: config.reuseaddr,
+            dual_stack: config.dual_stack,
+        }
+    }
+}
+
+/// Binds a TCP listener on the first resolved address that can be bound.
+///
+/// IPv6 listeners only accept IPv6 peers, whatever the system default, unless
+/// `options.dual_stack` is set.
+pub async fn bind_listener(
+    addr: &str,
+    options: ListenOptions,
+) -> Result<TcpListener, BroadcastError> {
+    let addrs = resolve(addr).await?;
+
+    first_ok(addrs, |socket_addr| async move {
+        let socket = match socket_addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => {
+                let socket = TcpSocket::new_v6()?;
+                SockRef::from(&socket).set_only_v6(!options.dual_stack)?;
+                socket
+            }
+        };
+        socket.set_reuseaddr(options.reuseaddr)?;
+        socket.bind(socket_addr)?;
+        socket.listen(LISTEN_BACKLOG)
+    })
+    .await
+    .map_err(|source| BroadcastError::Bind {
+        address: addr.to_string(),
+        source,
+    })
+}
+
+/// Binds a UDP socket on the first resolved address that can be bound.
+pub async fn bind_udp(addr: &str) -> Result<UdpSocket, BroadcastError> {
+    let addrs = resolve(addr).await?;
+
+    first_ok(addrs, UdpSocket::bind)
+        .await
+        .map_err(|source| BroadcastError::Bind {
+            address: addr.to_string(),
+            source,
+        })
+}
+
+/// Listener consumers connect to.
+pub(crate) trait Accept {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static;
+
+    /// Accepts a consumer, with its address if it has one, not over a Unix socket.
+    fn accept(&self)
+        -> impl Future<Output = io::Result<(Self::Stream, Option<SocketAddr>)>> + Send;
+
+    /// Applies the socket options in `config` to an accepted stream.
+    fn configure(stream: &Self::Stream, config: &Config);
+
+    /// Copy of the descriptor of an accepted stream, kept to hand the consumer over to a
+    /// successor, none for consumers that are not.
+    #[cfg(unix)]
+    fn hand_over(&self, _: &Self::Stream) -> Option<std::os::unix::io::OwnedFd> {
+        None
+    }
+}
+
+impl Accept for TcpListener {
+    type Stream = TcpStream;
+
+    async fn accept(&self) -> io::Result<(TcpStream, Option<SocketAddr>)> {
+        let (stream, addr) = TcpListener::accept(self).await?;
+        Ok((stream, Some(addr)))
+    }
+
+    fn configure(stream: &TcpStream, config: &Config) {
+        set_nodelay(stream, config.nodelay);
+
+        if let Some(keepalive) = &config.keepalive {
+            set_keepalive(stream, keepalive);
+        }
+    }
+}
+
+/// Unix socket listener, the socket file is removed once it is dropped.
+#[cfg(unix)]
+#[derive(Debug)]
+pub(crate) struct UnixSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    /// Binds a Unix socket at `path`, replacing the socket file left behind by a previous run.
+    pub(crate) fn bind(path: &Path) -> Result<Self, BroadcastError> {
+        use std::os::unix::fs::FileTypeExt;
+
+        let bind_error = |source| BroadcastError::Bind {
+            address: path.display().to_string(),
+            source,
+        };
+
+        // only sockets are replaced, anything else in the way is an error binding
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                debug!("removing stale socket {}", path.display());
+                std::fs::remove_file(path).map_err(bind_error)?;
+            }
+        }
+
+        let listener = UnixListener::bind(path).map_err(bind_error)?;
+
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl Accept for UnixSocket {
+    type Stream = UnixStream;
+
+    async fn accept(&self) -> io::Result<(UnixStream, Option<SocketAddr>)> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok((stream, None))
+    }
+
+    fn configure(_: &UnixStream, _: &Config) {}
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            debug!("removing socket {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// First file descriptor passed by systemd socket activation.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: std::os::unix::io::RawFd = 3;
+
+/// Takes the listener passed by systemd socket activation, if there is one for this process.
+#[cfg(unix)]
+pub(crate) fn systemd_listener() -> io::Result<Option<TcpListener>> {
+    let listen_pid = std::env::var("LISTEN_PID").ok();
+    let listen_fds = std::env::var("LISTEN_FDS").ok();
+
+    inherited_listener(
+        listen_pid.as_deref(),
+        listen_fds.as_deref(),
+        SD_LISTEN_FDS_START,
+    )
+}
+
+/// Takes the listener at `fd` when `listen_fds` says at least one was passed, and `listen_pid`, if
+/// given, says it was passed to this process. Further sockets are left alone.
+#[cfg(unix)]
+pub(crate) fn inherited_listener(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    fd: std::os::unix::io::RawFd,
+) -> io::Result<Option<TcpListener>> {
+    use std::os::unix::io::FromRawFd;
+
+    if let Some(pid) = listen_pid {
+        if pid.parse() != Ok(std::process::id()) {
+            debug!("sockets passed to process {pid}, not this one");
+            return Ok(None);
+        }
+    }
+
+    let Some(fds) = listen_fds else {
+        return Ok(None);
+    };
+
+    let count: u32 = fds.parse().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid LISTEN_FDS: {fds}"),
+        )
+    })?;
+
+    match count {
+        0 => return Ok(None),
+        1 => {}
+        _ => debug!("{count} sockets passed, using the first one"),
+    }
+
+    // SAFETY: the descriptor is handed over to this process, nothing else in it owns it
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener).map(Some)
+}
+
+/// Enables or disables Nagle's algorithm on `stream`, failures are only logged.
+pub(crate) fn set_nodelay(stream: &TcpStream, nodelay: bool) {
+    if let Err(e) = stream.set_nodelay(nodelay) {
+        debug!("setting TCP_NODELAY to {nodelay}: {e}");
+    }
+}
+
+/// Default number of unanswered keepalive probes before a consumer is dropped.
+pub const DEFAULT_KEEPALIVE_RETRIES: u32 = 3;
+
+/// TCP keepalive probes sent to the consumers, so dead peers are noticed and dropped within
+/// seconds rather than the minutes TCP takes on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// time without data before the first probe
+    pub idle: Duration,
+    /// time between unanswered probes
+    pub interval: Duration,
+    /// unanswered probes before the connection is dropped
+    pub retries: u32,
+}
+
+impl Keepalive {
+    /// Probes after `idle` without data, then every `idle` up to
+    /// [`DEFAULT_KEEPALIVE_RETRIES`] times.
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            idle,
+            interval: idle,
+            retries: DEFAULT_KEEPALIVE_RETRIES,
+        }
+    }
+}
+
+/// Enables `SO_KEEPALIVE` on `stream` with the timings of `keepalive`, failures are only logged.
+pub(crate) fn set_keepalive(stream: &TcpStream, keepalive: &Keepalive) {
+    let params = TcpKeepalive::new()
+        .with_time(keepalive.idle)
+        .with_interval(keepalive.interval)
+        .with_retries(keepalive.retries);
+
+    if let Err(e) = SockRef::from(stream).set_tcp_keepalive(&params) {
+        debug!("setting SO_KEEPALIVE: {e}");
+    }
+}
+
+/// Error for a step with a consumer, like `write`, not done within `timeout`.
+pub(crate) fn timed_out(what: &str, timeout: Duration) -> Error {
+    Error::new(
+        ErrorKind::TimedOut,
+        format!("{what} timed out after {timeout:?}"),
+    )
+}
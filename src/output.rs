@@ -0,0 +1,66 @@
+use base64::Engine;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_util::bytes::{BufMut, Bytes, BytesMut};
+
+/// Size of the sequence header before each chunk.
+const SEQUENCE_HEADER_SIZE: usize = 8;
+
+/// Numbers the chunks going out with a header of their own, see
+/// [`Config::seq_header`](crate::Config::seq_header) for the layout.
+#[derive(Debug, Default)]
+pub(crate) struct Sequence {
+    next: AtomicU64,
+}
+
+impl Sequence {
+    /// `data` after the next sequence number.
+    pub(crate) fn prefix(&self, data: &[u8]) -> Bytes {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+
+        let mut chunk = BytesMut::with_capacity(SEQUENCE_HEADER_SIZE + data.len());
+        chunk.put_u64(seq);
+        chunk.put_slice(data);
+        chunk.freeze()
+    }
+}
+
+/// Wraps the chunks going out into JSON records, numbered in the order consumers get them.
+#[derive(Debug)]
+pub(crate) struct Records {
+    next: AtomicU64,
+    /// whether the numbering goes on across remote reconnects, instead of starting over
+    continued: bool,
+}
+
+impl Records {
+    pub(crate) fn new(continued: bool) -> Self {
+        Self {
+            next: AtomicU64::new(0),
+            continued,
+        }
+    }
+
+    /// The record for `data`, received now, with the next sequence number.
+    pub(crate) fn wrap(&self, data: &[u8]) -> Bytes {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let received = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        format!(
+            "{{\"seq\":{seq},\"ts_ns\":{},\"len\":{},\"data\":\"{}\"}}\n",
+            received.as_nanos(),
+            data.len(),
+            base64::engine::general_purpose::STANDARD.encode(data)
+        )
+        .into()
+    }
+
+    /// Starts the numbering over, unless it is continued.
+    pub(crate) fn restart(&self) {
+        if !self.continued {
+            self.next.store(0, Ordering::Relaxed);
+        }
+    }
+}
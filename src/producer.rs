@@ -0,0 +1,599 @@
+use crate::file::FileSource;
+use crate::net::{set_keepalive, set_nodelay};
+use crate::tls::RemoteConnector;
+use crate::{
+    bind_udp, connect_through, connect_with_backoff_through, reader_to_tx_with, AsyncUdpSocket,
+    BroadcastError, Config, Event, Hub, ReadOptions, Remote, RemoteMode, MIN_BUFFER_SIZE,
+};
+use socket2::SockRef;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, instrument, warn};
+
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A remote ready to be pulled from, along with the size of the buffer to read it with.
+type Opened = (Reader, Option<Writer>, usize);
+
+/// Splits a stream to read from it and write to it at once.
+fn split<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S) -> (Reader, Option<Writer>) {
+    let (reader, writer) = tokio::io::split(stream);
+    (Box::new(reader), Some(Box::new(writer)))
+}
+
+/// Reads from the remote into the hub, in bidirectional mode also writes to it what consumers
+/// send. Either failing ends it, with the error, as does the remote sending nothing for
+/// `read_timeout`.
+async fn pull(
+    (reader, writer, buffer_size): Opened,
+    hub: &Hub,
+    config: &Config,
+    read_timeout: Option<Duration>,
+) -> std::io::Result<()> {
+    let options = ReadOptions {
+        buffer_size,
+        framing: config.framing,
+        max_message_size: config.max_message_size,
+        read_timeout,
+        coalesce: config.coalesce,
+    };
+    let read = reader_to_tx_with(reader, hub.clone(), options);
+
+    match (writer, hub.upstream()) {
+        (Some(writer), Some(upstream)) => tokio::select! {
+            result = read => result,
+            result = upstream.forward_to(writer) => result,
+        },
+        _ => read.await,
+    }
+}
+
+/// Why pulling from a remote stopped, when it did not fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pulled {
+    /// the remote closed, or got to its end
+    Closed,
+    /// the connection lived as long as it could, and is to be re-established right away
+    Recycled,
+}
+
+/// Reports a remote connected for as long as it is alive, and disconnected once dropped, even
+/// when pulling from it is cancelled halfway.
+struct Connected<'a> {
+    remote: &'a Remote,
+    hub: &'a Hub,
+}
+
+impl<'a> Connected<'a> {
+    fn new(remote: &'a Remote, hub: &'a Hub) -> Self {
+        hub.events().emit(Event::RemoteConnected {
+            remote: remote.clone(),
+        });
+        hub.metrics().remote_connected();
+        hub.restart_sequence();
+
+        Self { remote, hub }
+    }
+}
+
+impl Drop for Connected<'_> {
+    fn drop(&mut self) {
+        self.hub.metrics().remote_disconnected();
+        self.hub.events().emit(Event::RemoteDisconnected {
+            remote: self.remote.clone(),
+        });
+    }
+}
+
+/// Pulls from `remote` like [`pull`], reporting it connected meanwhile, for no longer than
+/// `config.remote_max_lifetime` for a TCP remote.
+async fn pulled(
+    remote: &Remote,
+    opened: Opened,
+    hub: &Hub,
+    config: &Config,
+) -> std::io::Result<Pulled> {
+    let _connected = Connected::new(remote, hub);
+
+    // only a TCP remote has a connection that can go silent, or stale
+    let (read_timeout, max_lifetime) = match remote {
+        Remote::Tcp(_) => (config.remote_read_timeout, config.remote_max_lifetime),
+        #[cfg(feature = "http-remote")]
+        Remote::HttpSse(_) => (config.remote_read_timeout, config.remote_max_lifetime),
+        Remote::Udp(_) | Remote::File(_) => (None, None),
+    };
+    let expired = async {
+        match max_lifetime {
+            Some(max_lifetime) => tokio::time::sleep(max_lifetime).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        result = pull(opened, hub, config, read_timeout) => result.map(|()| Pulled::Closed),
+        _ = expired => {
+            info!("recycling the connection to {remote} after {max_lifetime:?}");
+            Ok(Pulled::Recycled)
+        }
+    }
+}
+
+/// Whether a read from a remote failed in a way connecting again gets over, like the remote being
+/// restarted or briefly out of reach, or sending frames that cannot be decoded, which a new
+/// connection starts over from. Anything else, like not being allowed to read at all, would fail
+/// the same way again.
+pub(crate) fn transient(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::BrokenPipe
+            | ErrorKind::NotConnected
+            | ErrorKind::HostUnreachable
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::NetworkDown
+            | ErrorKind::TimedOut
+            // TLS connections cut without a close_notify
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::InvalidData
+    )
+}
+
+/// Pulls data from every configured remote into the hub, as dictated by the remote mode.
+pub(crate) async fn remotes_to_tx(
+    config: &Config,
+    hub: Hub,
+    cancel: &CancellationToken,
+) -> Result<(), BroadcastError> {
+    let tls = config
+        .remote_tls
+        .as_ref()
+        .map(RemoteConnector::new)
+        .transpose()?;
+
+    // the other remotes stop too once one of them read the last bytes allowed
+    let spent = hub.clone();
+    let pulling = async {
+        match config.remote_mode {
+            RemoteMode::Merge => merge(config, tls.as_ref(), hub, cancel).await,
+            RemoteMode::Failover => failover(config, tls.as_ref(), hub, cancel).await,
+        }
+    };
+
+    tokio::select! {
+        result = pulling => result,
+        () = spent.bytes_spent() => Ok(()),
+    }
+}
+
+/// A source handed to the broadcaster to read from in place of the remotes, cheap to clone, only
+/// the first run gets to read it.
+#[derive(Clone)]
+pub(crate) struct Injected(Arc<Mutex<Option<Reader>>>);
+
+impl Injected {
+    pub(crate) fn new<S: AsyncRead + Send + Unpin + 'static>(source: S) -> Self {
+        Self(Arc::new(Mutex::new(Some(Box::new(source)))))
+    }
+}
+
+impl fmt::Debug for Injected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Injected")
+    }
+}
+
+/// Pulls data from a source handed to the broadcaster into the hub, until it ends or fails. There
+/// is no reconnecting to it, a run after the one that read it fails right away.
+pub(crate) async fn source_to_tx(
+    source: &Injected,
+    config: &Config,
+    hub: Hub,
+) -> Result<(), BroadcastError> {
+    let taken = source.0.lock().expect("source lock poisoned").take();
+    let Some(reader) = taken else {
+        return Err(Error::other("the source was already read by a previous run").into());
+    };
+
+    // a source that went quiet is for its owner to give up on
+    pull((reader, None, config.buffer_size), &hub, config, None).await?;
+    info!("done reading the source");
+    Ok(())
+}
+
+/// Pulls from the source handed in, if any, or from the remotes, like [`source_to_tx`] and
+/// [`remotes_to_tx`], switching to the remote set through the admin server whenever one is.
+///
+/// Switching publishes what was read and batched so far, closes what is pulled from and connects to
+/// the new remote with the backoff, as when reconnecting, the consumers stay connected meanwhile.
+pub(crate) async fn produce(
+    config: &Config,
+    source: Option<&Injected>,
+    hub: Hub,
+    cancel: &CancellationToken,
+) -> Result<(), BroadcastError> {
+    let mut swaps = hub.remote_swaps();
+    let mut config = config.clone();
+    let mut source = source;
+
+    loop {
+        let pulling = async {
+            match source {
+                Some(source) => source_to_tx(source, &config, hub.clone()).await,
+                None => remotes_to_tx(&config, hub.clone(), cancel).await,
+            }
+        };
+
+        // the pulling first, so what it read and batched gets published before it is let go of
+        tokio::select! {
+            biased;
+            result = pulling => return result,
+            Ok(()) = swaps.changed() => {}
+        }
+
+        let Some(remote) = swaps.borrow_and_update().clone() else {
+            continue;
+        };
+        info!("pulling from {remote} from now on");
+        hub.metrics().reconnected();
+
+        config.remotes = vec![remote];
+        source = None;
+    }
+}
+
+/// Opens every remote once and closes it right away, failing with the first that can not be
+/// opened.
+pub(crate) async fn check_remotes(config: &Config) -> Result<(), BroadcastError> {
+    let tls = config
+        .remote_tls
+        .as_ref()
+        .map(RemoteConnector::new)
+        .transpose()?;
+
+    for remote in &config.remotes {
+        open(remote, config, tls.as_ref()).await?;
+        info!("remote {remote} can be opened");
+    }
+
+    Ok(())
+}
+
+/// Pulls from all the remotes at once, each one reconnecting on its own.
+///
+/// Returns once every remote is done, with the first error any of them stopped with.
+async fn merge(
+    config: &Config,
+    tls: Option<&RemoteConnector>,
+    hub: Hub,
+    cancel: &CancellationToken,
+) -> Result<(), BroadcastError> {
+    let mut pulls = JoinSet::new();
+
+    for remote in &config.remotes {
+        let remote = remote.clone();
+        let config = config.clone();
+        let tls = tls.cloned();
+        let hub = hub.clone();
+        let cancel = cancel.clone();
+
+        pulls
+            .spawn(async move { remote_to_tx(&remote, &config, tls.as_ref(), hub, &cancel).await });
+    }
+
+    let mut first_error = None;
+
+    while let Some(joined) = pulls.join_next().await {
+        let result = joined.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+
+        if let Err(e) = result {
+            warn!("remote stopped: {e}");
+            first_error.get_or_insert(e);
+        }
+    }
+
+    first_error.map_or(Ok(()), Err)
+}
+
+/// Pulls data from a single remote into the hub.
+///
+/// When a TCP remote closes the connection it is re-established using the configured backoff,
+/// unless reconnection is disabled, in which case it returns. Connected consumers are kept meanwhile.
+/// With TLS configured, a failed handshake is not retried, as it is most likely a misconfiguration.
+/// In bidirectional mode, failing to write to the remote is handled like failing to read from it.
+/// A file remote is read once, or over and over with `config.file_loop`, and then it returns.
+/// A TCP remote is also reconnected on purpose once `config.remote_max_lifetime` is over.
+/// Once the remotes were read for `config.max_bytes` it returns too.
+/// An HTTP remote is requested over such a connection, and requested again once the body is over.
+#[instrument(skip_all, fields(remote = %remote))]
+async fn remote_to_tx(
+    remote: &Remote,
+    config: &Config,
+    tls: Option<&RemoteConnector>,
+    hub: Hub,
+    cancel: &CancellationToken,
+) -> Result<(), BroadcastError> {
+    loop {
+        let opened = match remote {
+            Remote::Udp(address) => udp_reader(address, config).await?,
+            Remote::File(path) => file_reader(path, config).await?,
+            _ => {
+                let address = remote
+                    .address()
+                    .expect("TCP and HTTP remotes have an address");
+                let proxy = config.remote_socks5.as_ref();
+                let connecting = connect_with_backoff_through(
+                    address,
+                    proxy,
+                    config.remote_bind_addr,
+                    config.connect_timeout,
+                    &config.backoff,
+                    cancel,
+                );
+                let Some(stream) = connecting.await? else {
+                    return Ok(());
+                };
+
+                match stream_reader(remote, stream, config, tls).await {
+                    Ok(opened) => opened,
+                    // a failed request is retried like a failed read
+                    Err(e) if config.reconnect && !matches!(remote, Remote::Tcp(_)) => {
+                        warn!("requesting {remote}: {e}, reconnecting");
+                        hub.metrics().reconnected();
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+
+        match pulled(remote, opened, &hub, config).await {
+            Ok(Pulled::Closed) if hub.bytes_left() == Some(0) => return Ok(()),
+            Ok(Pulled::Recycled) => {}
+            Ok(Pulled::Closed) if matches!(remote, Remote::File(_)) => {
+                info!("done reading {remote}");
+                return Ok(());
+            }
+            Ok(Pulled::Closed) if config.reconnect => {
+                warn!("remote {remote} closed the connection, reconnecting")
+            }
+            Err(e) if config.reconnect && transient(&e) => warn!(
+                "reading from remote {remote} failed ({:?}): {e}, reconnecting",
+                e.kind()
+            ),
+            Err(e) => {
+                warn!(
+                    "reading from remote {remote} failed ({:?}): {e}, giving up",
+                    e.kind()
+                );
+                return Err(e.into());
+            }
+            Ok(_) => return Ok(()),
+        }
+
+        hub.metrics().reconnected();
+    }
+}
+
+/// Pulls from one remote at a time, in order, switching to the next one when it fails.
+///
+/// Each remote keeps its own backoff: a remote that failed is not tried again before its delay
+/// is over, and once it has used up its attempts it is skipped altogether. Returns when no remote
+/// is left, or when the active one closes and reconnection is disabled.
+async fn failover(
+    config: &Config,
+    tls: Option<&RemoteConnector>,
+    hub: Hub,
+    cancel: &CancellationToken,
+) -> Result<(), BroadcastError> {
+    let remotes = &config.remotes;
+    let backoff = &config.backoff;
+
+    // failed attempts in a row and when to try again, for each of the remotes
+    let mut attempts = vec![(0u32, Instant::now()); remotes.len()];
+    let mut index = 0;
+
+    loop {
+        let remote = &remotes[index];
+        let (failed, retry_at) = &mut attempts[index];
+
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = tokio::time::sleep_until(*retry_at) => {}
+        }
+
+        let opened = tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            opened = open(remote, config, tls) => opened,
+        };
+
+        match opened {
+            Ok(opened) => {
+                info!("pulling from {remote}");
+                *failed = 0;
+
+                match pulled(remote, opened, &hub, config).await {
+                    Ok(Pulled::Closed) if hub.bytes_left() == Some(0) => return Ok(()),
+                    // back to the same remote, it did not fail
+                    Ok(Pulled::Recycled) => {
+                        hub.metrics().reconnected();
+                        continue;
+                    }
+                    Ok(Pulled::Closed) if matches!(remote, Remote::File(_)) => {
+                        info!("done reading {remote}");
+                        return Ok(());
+                    }
+                    Ok(Pulled::Closed) if config.reconnect => {
+                        warn!("remote {remote} closed the connection")
+                    }
+                    Err(e) if config.reconnect && transient(&e) => {
+                        warn!("reading from remote {remote} failed ({:?}): {e}", e.kind())
+                    }
+                    Err(e) => {
+                        warn!(
+                            "reading from remote {remote} failed ({:?}): {e}, giving up",
+                            e.kind()
+                        );
+                        return Err(e.into());
+                    }
+                    Ok(_) => return Ok(()),
+                }
+            }
+            Err(e) => {
+                *failed += 1;
+
+                if backoff.retries_after(*failed) {
+                    *retry_at = Instant::now() + backoff.delay(*failed);
+                }
+
+                warn!("attempt {failed} on remote {remote} failed: {e}");
+
+                if attempts
+                    .iter()
+                    .all(|(failed, _)| !backoff.retries_after(*failed))
+                {
+                    warn!("no remote left to fail over to, giving up");
+                    return Err(e);
+                }
+            }
+        }
+
+        // the next remote with attempts left
+        loop {
+            index = (index + 1) % remotes.len();
+
+            if backoff.retries_after(attempts[index].0) {
+                break;
+            }
+        }
+
+        info!("failing over to {}", remotes[index]);
+        hub.metrics().reconnected();
+    }
+}
+
+/// Opens a remote for reading, and writing if it is TCP, with a single attempt.
+async fn open(
+    remote: &Remote,
+    config: &Config,
+    tls: Option<&RemoteConnector>,
+) -> Result<Opened, BroadcastError> {
+    Ok(match remote {
+        Remote::Udp(address) => udp_reader(address, config).await?,
+        Remote::File(path) => file_reader(path, config).await?,
+        _ => {
+            let address = remote
+                .address()
+                .expect("TCP and HTTP remotes have an address");
+            let proxy = config.remote_socks5.as_ref();
+            let bind = config.remote_bind_addr;
+            let stream = connect_through(address, proxy, bind, config.connect_timeout).await?;
+            stream_reader(remote, stream, config, tls).await?
+        }
+    })
+}
+
+/// Sets up the connection to a TCP or HTTP remote once made: its socket options, the TLS session
+/// if any, then the request if it is an HTTP remote.
+async fn stream_reader(
+    remote: &Remote,
+    stream: TcpStream,
+    config: &Config,
+    tls: Option<&RemoteConnector>,
+) -> Result<Opened, BroadcastError> {
+    configure_remote(&stream, config);
+    let buffer_size = read_buffer_size(config, Some(SockRef::from(&stream)));
+    let address = remote
+        .address()
+        .expect("TCP and HTTP remotes have an address");
+
+    let (reader, writer) = match remote {
+        #[cfg(feature = "http-remote")]
+        Remote::HttpSse(_) => match tls {
+            Some(tls) => http_reader(remote, tls.connect(address, stream).await?).await?,
+            None => http_reader(remote, stream).await?,
+        },
+        _ => match tls {
+            Some(tls) => split(tls.connect(address, stream).await?),
+            None => split(stream),
+        },
+    };
+    Ok((reader, writer, buffer_size))
+}
+
+/// Requests the body of an HTTP remote over `stream`, which can only be read from.
+#[cfg(feature = "http-remote")]
+async fn http_reader<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    remote: &Remote,
+    stream: S,
+) -> Result<(Reader, Option<Writer>), BroadcastError> {
+    let address = remote.address().expect("HTTP remotes have an address");
+
+    let body = crate::http::HttpBody::get(stream, address, remote.path())
+        .await
+        .map_err(|source| BroadcastError::Connect {
+            address: remote.to_string(),
+            source,
+        })?;
+    Ok((Box::new(body), None))
+}
+
+/// Applies the socket options of `config` for a TCP remote to its connection.
+pub(crate) fn configure_remote(stream: &TcpStream, config: &Config) {
+    set_nodelay(stream, config.nodelay);
+
+    if let Some(keepalive) = &config.remote_keepalive {
+        set_keepalive(stream, keepalive);
+    }
+}
+
+/// Opens a file remote, which can only be read from.
+async fn file_reader(path: &Path, config: &Config) -> Result<Opened, BroadcastError> {
+    let file = FileSource::open(path, config.file_loop, config.file_pace).await?;
+    Ok((Box::new(file), None, read_buffer_size(config, None)))
+}
+
+/// Binds a UDP remote, which can only be read from.
+async fn udp_reader(address: &str, config: &Config) -> Result<Opened, BroadcastError> {
+    let socket = bind_udp(address).await?;
+    let buffer_size = read_buffer_size(config, Some(SockRef::from(&socket)));
+    Ok((Box::new(AsyncUdpSocket::from(socket)), None, buffer_size))
+}
+
+/// Bytes read from a remote at a time: `config.buffer_size`, or with `config.auto_buffer_size`
+/// the size of the receive buffer of its `socket`, so a read can take whatever the kernel has
+/// for it at once. It stays `config.buffer_size` for remotes without a socket, or when the size
+/// of its buffer cannot be told.
+pub(crate) fn read_buffer_size(config: &Config, socket: Option<SockRef<'_>>) -> usize {
+    if !config.auto_buffer_size {
+        return config.buffer_size;
+    }
+
+    let Some(socket) = socket else {
+        return config.buffer_size;
+    };
+
+    match socket.recv_buffer_size() {
+        Ok(size) => {
+            debug!("reading {size} bytes at a time, as much as the receive buffer holds");
+            size.max(MIN_BUFFER_SIZE)
+        }
+        Err(e) => {
+            warn!(
+                "telling the receive buffer size: {e}, reading {} bytes at a time",
+                config.buffer_size
+            );
+            config.buffer_size
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::timed_out;
+use std::io::{self, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Time a connection has to send its PROXY protocol header, load balancers send it right away.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest version 1 header, line ending included, as the specification says.
+const V1_MAX: usize = 107;
+
+/// What version 2 headers start with.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Reads the PROXY protocol header, version 1 or 2, a load balancer in front sends first, and
+/// returns the address of the peer that connected to it.
+///
+/// Nothing past the header is read, whatever follows is left for the consumer. Headers without an
+/// address, like health checks of the load balancer itself, give none, and the connection is
+/// taken as coming from the load balancer.
+pub(crate) async fn read_header<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> io::Result<Option<SocketAddr>> {
+    tokio::time::timeout(HEADER_TIMEOUT, async {
+        match stream.read_u8().await? {
+            b'P' => read_v1(stream).await,
+            b'\r' => read_v2(stream).await,
+            _ => Err(invalid("not a PROXY protocol header")),
+        }
+    })
+    .await
+    .map_err(|_| timed_out("PROXY protocol header", HEADER_TIMEOUT))?
+}
+
+/// Reads the rest of a text header, like `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`, after
+/// its first byte.
+async fn read_v1<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    let mut line = vec![b'P'];
+
+    // a byte at a time, so nothing after the line ending is taken
+    loop {
+        match stream.read_u8().await? {
+            b'\n' => break,
+            _ if line.len() == V1_MAX => return Err(invalid("PROXY protocol header too long")),
+            byte => line.push(byte),
+        }
+    }
+
+    let line = std::str::from_utf8(&line)
+        .ok()
+        .and_then(|line| line.strip_suffix('\r'))
+        .ok_or_else(|| invalid("malformed PROXY protocol header"))?;
+    let mut fields = line.split(' ');
+
+    if fields.next() != Some("PROXY") {
+        return Err(invalid("malformed PROXY protocol header"));
+    }
+
+    match fields.next() {
+        Some("TCP4" | "TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid("unsupported PROXY protocol family")),
+    }
+
+    let fields: Vec<&str> = fields.collect();
+    let [source, _, port, _] = fields[..] else {
+        return Err(invalid("malformed PROXY protocol header"));
+    };
+
+    let ip: IpAddr = source
+        .parse()
+        .map_err(|_| invalid("malformed PROXY protocol source address"))?;
+    let port: u16 = port
+        .parse()
+        .map_err(|_| invalid("malformed PROXY protocol source port"))?;
+
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+/// Reads the rest of a binary header after its first byte, the addresses are followed by
+/// extensions, which are skipped.
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    let mut header = [0u8; 16];
+    header[0] = b'\r';
+    stream.read_exact(&mut header[1..]).await?;
+
+    if header[..12] != V2_SIGNATURE {
+        return Err(invalid("not a PROXY protocol header"));
+    }
+    if header[12] >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    let command = header[12] & 0x0f;
+    let family = header[13];
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+
+    let mut addresses = vec![0u8; len];
+    stream.read_exact(&mut addresses).await?;
+
+    // LOCAL, sent by the load balancer on its own behalf
+    if command == 0 {
+        return Ok(None);
+    }
+    if command != 1 {
+        return Err(invalid("unsupported PROXY protocol command"));
+    }
+
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+
+    match family {
+        // TCP over IPv4: source and destination addresses, then ports
+        0x11 if len >= 12 => {
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[..4]).expect("4 bytes"));
+            Ok(Some(SocketAddr::new(ip.into(), port(8))))
+        }
+        // TCP over IPv6, likewise
+        0x21 if len >= 36 => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[..16]).expect("16 bytes"));
+            Ok(Some(SocketAddr::new(ip.into(), port(32))))
+        }
+        0x11 | 0x21 => Err(invalid("PROXY protocol addresses cut short")),
+        // unspecified, or not over IP, nothing to tell the peer by
+        _ => Ok(None),
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, reason)
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Token bucket pacing a stream of bytes to a given rate.
+///
+/// Tokens are bytes, they refill continuously at `rate` per second and up to a tenth of a second
+/// worth of them can be saved for a burst. Taking more tokens than available is allowed, the
+/// debt is then paid by waiting, so chunks of any size get through at the configured pace.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket refilling at `bytes_per_sec`, at least one.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        let burst = (rate / 10.0).max(1.0);
+
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Tokens available as of the last update, negative while in debt.
+    pub fn tokens(&self) -> f64 {
+        self.tokens
+    }
+
+    /// Adds the tokens accumulated since the last update, up to the burst size.
+    pub fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+    }
+
+    /// Takes `n` tokens, returns how long to wait before using them so the rate is respected.
+    pub fn take(&mut self, n: usize, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= n as f64;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    /// Takes `n` tokens only if they are all available, without going into debt.
+    pub fn try_take(&mut self, n: usize, now: Instant) -> bool {
+        self.refill(now);
+
+        let available = self.tokens >= n as f64;
+        if available {
+            self.tokens -= n as f64;
+        }
+        available
+    }
+
+    /// Takes `n` tokens, waiting as long as needed for them.
+    pub async fn acquire(&mut self, n: usize) {
+        let wait = self.take(n, Instant::now());
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Slices written per second when smoothing, so a large chunk goes out a little at a time.
+const SMOOTH_SLICES_PER_SEC: u64 = 50;
+
+/// Schedule spreading the bytes written to a consumer evenly at a given rate.
+///
+/// Unlike a [`TokenBucket`] nothing is saved up for a burst: each slice of a chunk is written
+/// once the previous ones had the time they take at the rate, however long ago the last chunk
+/// was.
+#[derive(Debug, Clone)]
+pub(crate) struct Smoother {
+    rate: f64,
+    slice: usize,
+    /// when the bytes written so far are all due, the next slice waits for it
+    next: Instant,
+}
+
+impl Smoother {
+    /// Spreads writes at `bytes_per_sec`, at least one.
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1);
+
+        Self {
+            rate: bytes_per_sec as f64,
+            slice: (bytes_per_sec / SMOOTH_SLICES_PER_SEC).max(1) as usize,
+            next: Instant::now(),
+        }
+    }
+
+    /// Most bytes written at once.
+    pub(crate) fn slice(&self) -> usize {
+        self.slice
+    }
+
+    /// Waits for the turn of a slice of `n` bytes.
+    pub(crate) async fn wait(&mut self, n: usize) {
+        let now = Instant::now();
+        let start = self.next.max(now);
+        self.next = start + Duration::from_secs_f64(n as f64 / self.rate);
+
+        if start > now {
+            tokio::time::sleep_until(start).await;
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Time a name, or the lack of one, is remembered for an address, so consumers reconnecting over
+/// and over do not get it looked up each time.
+const NAME_TTL: Duration = Duration::from_secs(60);
+
+/// Tells the name of the host at an address, the other way around from resolving one.
+///
+/// Lookups may block, they are done away from the tasks serving the broadcast. Closures taking
+/// an [`IpAddr`] and returning its name are resolvers too.
+pub trait ReverseResolver: Send + Sync {
+    /// Name of the host at `ip`, failing if it has none.
+    fn lookup(&self, ip: IpAddr) -> io::Result<String>;
+}
+
+impl<F> ReverseResolver for F
+where
+    F: Fn(IpAddr) -> io::Result<String> + Send + Sync,
+{
+    fn lookup(&self, ip: IpAddr) -> io::Result<String> {
+        self(ip)
+    }
+}
+
+/// Resolver asking the system, as `getnameinfo` does, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+#[cfg(unix)]
+impl ReverseResolver for SystemResolver {
+    fn lookup(&self, ip: IpAddr) -> io::Result<String> {
+        // longest host name getnameinfo gives, terminating nul included
+        const MAX_HOST: usize = 1025;
+
+        let addr = socket2::SockAddr::from(std::net::SocketAddr::new(ip, 0));
+        let mut host = [0 as libc::c_char; MAX_HOST];
+
+        // SAFETY: the address and the buffer are alive and as long as told until the call returns
+        let failed = unsafe {
+            libc::getnameinfo(
+                addr.as_ptr().cast(),
+                addr.len(),
+                host.as_mut_ptr(),
+                host.len() as _,
+                std::ptr::null_mut(),
+                0,
+                libc::NI_NAMEREQD,
+            )
+        };
+
+        if failed != 0 {
+            // SAFETY: gai_strerror gives a static nul terminated string for any code
+            let reason = unsafe { std::ffi::CStr::from_ptr(libc::gai_strerror(failed)) };
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                reason.to_string_lossy().into_owned(),
+            ));
+        }
+
+        // SAFETY: getnameinfo nul terminates the name it succeeds with
+        let name = unsafe { std::ffi::CStr::from_ptr(host.as_ptr()) };
+        Ok(name.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(not(unix))]
+impl ReverseResolver for SystemResolver {
+    fn lookup(&self, _: IpAddr) -> io::Result<String> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "reverse lookups are not supported on this platform",
+        ))
+    }
+}
+
+/// Name of an address, none if it has none, and until when it is remembered.
+type Cached = (Option<String>, Instant);
+
+/// Looks the names of consumers up, remembering them for a while, cheap to clone.
+#[derive(Clone)]
+pub(crate) struct ReverseDns {
+    resolver: Arc<dyn ReverseResolver>,
+    /// every address looked up
+    names: Arc<Mutex<HashMap<IpAddr, Cached>>>,
+}
+
+impl ReverseDns {
+    pub(crate) fn new(resolver: impl ReverseResolver + 'static) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+            names: Arc::default(),
+        }
+    }
+
+    /// Name of the host at `ip`, none if the lookup failed, in which case the address is all there
+    /// is to tell it by.
+    pub(crate) async fn name(&self, ip: IpAddr) -> Option<String> {
+        let now = Instant::now();
+
+        {
+            let mut names = self.names.lock().expect("names lock poisoned");
+            if let Some((name, until)) = names.get(&ip) {
+                if *until > now {
+                    return name.clone();
+                }
+            }
+            names.retain(|_, (_, until)| *until > now);
+        }
+
+        let resolver = self.resolver.clone();
+        let looked_up = tokio::task::spawn_blocking(move || resolver.lookup(ip))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)));
+        let name = match looked_up {
+            Ok(name) => Some(name),
+            Err(e) => {
+                debug!("looking the name of {ip} up: {e}");
+                None
+            }
+        };
+
+        self.names
+            .lock()
+            .expect("names lock poisoned")
+            .insert(ip, (name.clone(), Instant::now() + NAME_TTL));
+        name
+    }
+}
+
+impl Default for ReverseDns {
+    fn default() -> Self {
+        Self::new(SystemResolver)
+    }
+}
+
+impl fmt::Debug for ReverseDns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ReverseDns")
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_util::bytes::Bytes;
+use tracing::debug;
+
+/// Consumers taking turns at the chunks, for round-robin distribution.
+///
+/// Each one has a queue of its own, a chunk goes to the next consumer after the last one that got
+/// one. Consumers that are gone, or whose queue is full, are skipped for the one after them.
+#[derive(Debug)]
+pub(crate) struct Shares {
+    capacity: usize,
+    next_id: u64,
+    sinks: BTreeMap<u64, mpsc::Sender<Bytes>>,
+    /// the consumer that got the last chunk, none before the first one
+    last: Option<u64>,
+}
+
+impl Shares {
+    /// Creates the turns, with queues of `capacity` chunks for each consumer.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            next_id: 0,
+            sinks: BTreeMap::new(),
+            last: None,
+        }
+    }
+
+    /// Adds a consumer at the end of the turns, it leaves them once the receiver is dropped.
+    pub(crate) fn join(&mut self) -> mpsc::Receiver<Bytes> {
+        let (tx, rx) = mpsc::channel(self.capacity);
+
+        self.sinks.insert(self.next_id, tx);
+        self.next_id += 1;
+        rx
+    }
+
+    /// Hands `data` to the next consumer able to take it, returns it back if none could.
+    pub(crate) fn deal(&mut self, mut data: Bytes) -> Result<(), Bytes> {
+        let after = self.last.map_or(0, |last| last + 1);
+        let turns: Vec<u64> = self
+            .sinks
+            .range(after..)
+            .chain(self.sinks.range(..after))
+            .map(|(&id, _)| id)
+            .collect();
+
+        for id in turns {
+            match self.sinks[&id].try_send(data) {
+                Ok(()) => {
+                    self.last = Some(id);
+                    return Ok(());
+                }
+                Err(TrySendError::Full(back)) => {
+                    debug!("consumer {id} is full, skipping it");
+                    data = back;
+                }
+                Err(TrySendError::Closed(back)) => {
+                    debug!("consumer {id} is gone, skipping it");
+                    self.sinks.remove(&id);
+                    data = back;
+                }
+            }
+        }
+
+        Err(data)
+    }
+}
+
+/// Where a consumer takes its chunks from: the broadcast everyone gets, or its own queue when
+/// taking turns.
+#[derive(Debug)]
+pub(crate) enum Feed {
+    Broadcast(Receiver<Bytes>),
+    Share(mpsc::Receiver<Bytes>),
+}
+
+impl Feed {
+    pub(crate) async fn recv(&mut self) -> Result<Bytes, RecvError> {
+        match self {
+            Feed::Broadcast(rx) => rx.recv().await,
+            Feed::Share(rx) => rx.recv().await.ok_or(RecvError::Closed),
+        }
+    }
+
+    pub(crate) fn try_recv(&mut self) -> Result<Bytes, TryRecvError> {
+        match self {
+            Feed::Broadcast(rx) => rx.try_recv(),
+            Feed::Share(rx) => rx.try_recv().map_err(|e| match e {
+                mpsc::error::TryRecvError::Empty => TryRecvError::Empty,
+                mpsc::error::TryRecvError::Disconnected => TryRecvError::Closed,
+            }),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::io;
+use tracing::info;
+
+/// Waits until the process is asked to shut down, either by ctrl-c or, on Unix, by SIGTERM.
+///
+/// Fails if the signal handlers could not be installed.
+#[cfg(unix)]
+pub async fn shutdown_signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            info!("received ctrl-c");
+        }
+        _ = terminate.recv() => info!("received SIGTERM"),
+    }
+
+    Ok(())
+}
+
+/// Waits until the process is asked to hand its consumers over to a successor, by SIGUSR2.
+///
+/// Fails if the signal handler could not be installed.
+#[cfg(unix)]
+pub async fn handover_signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    signal(SignalKind::user_defined2())?.recv().await;
+    info!("received SIGUSR2");
+
+    Ok(())
+}
+
+/// Waits until the process is asked to shut down by ctrl-c.
+///
+/// Fails if the signal handler could not be installed.
+#[cfg(not(unix))]
+pub async fn shutdown_signal() -> io::Result<()> {
+    tokio::signal::ctrl_c().await?;
+    info!("received ctrl-c");
+
+    Ok(())
+}
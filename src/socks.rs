@@ -0,0 +1,75 @@
+use crate::net::connect_from;
+use crate::{timed_out, BroadcastError};
+use std::io::Error;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+use tracing::debug;
+
+/// SOCKS5 proxy the TCP remotes are reached through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    /// `host:port` of the proxy
+    pub address: String,
+    /// username and password to authenticate with, no authentication if unset
+    pub credentials: Option<(String, String)>,
+}
+
+impl Socks5Proxy {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            credentials: None,
+        }
+    }
+
+    /// Connects to the proxy, from `bind` if there is one, and has it connect to `target`, a
+    /// `host:port` it resolves itself.
+    pub(crate) async fn connect(
+        &self,
+        target: &str,
+        bind: Option<IpAddr>,
+    ) -> Result<TcpStream, BroadcastError> {
+        let stream = connect_from(&self.address, bind).await?;
+        debug!("connected to proxy {}", self.address);
+
+        let proxied = match &self.credentials {
+            Some((username, password)) => {
+                Socks5Stream::connect_with_password_and_socket(stream, target, username, password)
+                    .await
+            }
+            None => Socks5Stream::connect_with_socket(stream, target).await,
+        };
+
+        proxied
+            .map(Socks5Stream::into_inner)
+            .map_err(|e| BroadcastError::Connect {
+                address: format!("{target} through {}", self.address),
+                source: Error::other(e),
+            })
+    }
+}
+
+/// Connects to `addr`, through `proxy` if there is one, from the local address `bind` if there is
+/// one, failing if that takes longer than `timeout`, proxy handshake included.
+pub(crate) async fn connect_through(
+    addr: &str,
+    proxy: Option<&Socks5Proxy>,
+    bind: Option<IpAddr>,
+    timeout: Duration,
+) -> Result<TcpStream, BroadcastError> {
+    let connect = async {
+        match proxy {
+            Some(proxy) => proxy.connect(addr, bind).await,
+            None => connect_from(addr, bind).await,
+        }
+    };
+
+    tokio::time::timeout(timeout, connect)
+        .await
+        .map_err(|_| BroadcastError::Connect {
+            address: addr.to_string(),
+            source: timed_out("connect", timeout),
+        })?
+}
@@ -0,0 +1,185 @@
+use crate::hub::Delivered;
+use crate::{accept_consumers, timed_out, Config, DisconnectReason, Hub, SseEncoding};
+use base64::Engine;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, instrument, warn};
+
+const RESPONSE_HEAD: &str = "HTTP/1.1 200 OK\r\n\
+    Content-Type: text/event-stream\r\n\
+    Cache-Control: no-cache\r\n\
+    Connection: close\r\n\r\n";
+
+/// Handles the transmission of data from the hub to HTTP consumers as Server-Sent Events, one
+/// `data:` event per chunk, whatever the path requested.
+///
+/// Event consumers follow the same rules as TCP ones, see [`accept_consumers`]. The request has
+/// `config.write_timeout` to arrive, consumers that take longer are dropped.
+#[instrument(skip_all)]
+pub(crate) async fn tx_to_sse(
+    listener: TcpListener,
+    hub: Hub,
+    config: &Config,
+    cancel: CancellationToken,
+) {
+    let write_timeout = config.write_timeout;
+    let encoding = config.sse_encoding;
+
+    accept_consumers(
+        listener,
+        &hub,
+        config,
+        &cancel,
+        |stream, addr, delivered| {
+            let hub = hub.clone();
+            let cancel = cancel.clone();
+            async move {
+                let serving = serve_events(stream, hub, encoding, write_timeout, delivered, cancel);
+                match serving.await {
+                    Ok(()) => info!("event client {addr} disconnected"),
+                    Err(e) => warn!("event client {addr}: {e}, dropping it"),
+                }
+            }
+        },
+    )
+    .await
+}
+
+/// Reads the request and writes the history and then the live chunks as events, until the
+/// consumer leaves or `cancel` is triggered and the data pending in the channel has been sent.
+async fn serve_events(
+    mut stream: TcpStream,
+    hub: Hub,
+    encoding: SseEncoding,
+    write_timeout: Duration,
+    delivered: Arc<Delivered>,
+    cancel: CancellationToken,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+
+    tokio::time::timeout(write_timeout, read_request(&mut reader))
+        .await
+        .map_err(|_| timed_out("request", write_timeout))??;
+
+    info!("event client connected");
+
+    let (mut rx, history, _present) = hub.join();
+
+    let events = Writer {
+        hub: &hub,
+        write_timeout,
+        delivered: &delivered,
+    };
+
+    events.send(&mut writer, RESPONSE_HEAD).await?;
+
+    for data in history {
+        if let Err(e) = events.send(&mut writer, &event(&data, encoding)).await {
+            hub.metrics().dropped(DisconnectReason::write_failed(&e));
+            return Err(e);
+        }
+    }
+
+    let mut ignored = [0u8; 64];
+    loop {
+        let data = tokio::select! {
+            _ = cancel.cancelled() => break,
+            received = rx.recv() => match received {
+                Ok(data) => data,
+                Err(e) => {
+                    hub.metrics().dropped(match e {
+                        RecvError::Lagged(_) => DisconnectReason::Lagged,
+                        RecvError::Closed => DisconnectReason::Shutdown,
+                    });
+                    return Err(Error::other(format!("when receiving from the channel: {e}")));
+                }
+            },
+            // nothing is expected after the request, the consumer is gone once its side closes
+            read = reader.read(&mut ignored) => match read {
+                Ok(0) => {
+                    hub.metrics().disconnected(DisconnectReason::ClientClosed);
+                    return Ok(());
+                }
+                Ok(_) => continue,
+                Err(e) => return Err(e),
+            },
+        };
+
+        if let Err(e) = events.send(&mut writer, &event(&data, encoding)).await {
+            hub.metrics().dropped(DisconnectReason::write_failed(&e));
+            return Err(e);
+        }
+    }
+
+    debug!("cancelled, draining pending data");
+
+    while let Ok(data) = rx.try_recv() {
+        events.send(&mut writer, &event(&data, encoding)).await?;
+    }
+    hub.metrics().disconnected(DisconnectReason::Shutdown);
+
+    writer.shutdown().await
+}
+
+/// What every event sent needs, to time it out and account for it.
+struct Writer<'a> {
+    hub: &'a Hub,
+    write_timeout: Duration,
+    delivered: &'a Delivered,
+}
+
+impl Writer<'_> {
+    async fn send(&self, writer: &mut (impl AsyncWrite + Unpin), event: &str) -> io::Result<()> {
+        tokio::time::timeout(self.write_timeout, writer.write_all(event.as_bytes()))
+            .await
+            .map_err(|_| timed_out("write", self.write_timeout))??;
+
+        self.hub.metrics().sent(event.len());
+        self.delivered.sent(event.len());
+        Ok(())
+    }
+}
+
+/// Reads the request line and headers, none of them matter but they must be complete.
+async fn read_request(reader: &mut (impl AsyncBufReadExt + Unpin)) -> io::Result<()> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    debug!("events request {:?}", line.trim_end());
+
+    loop {
+        line.clear();
+        match reader.read_line(&mut line).await? {
+            0 => return Err(ErrorKind::UnexpectedEof.into()),
+            n if n <= 2 => return Ok(()),
+            _ => continue,
+        }
+    }
+}
+
+/// Formats a chunk as an event, a `data:` event can not hold a line break so text spanning
+/// several lines gets a `data:` field for each.
+fn event(data: &[u8], encoding: SseEncoding) -> String {
+    match encoding {
+        SseEncoding::Base64 => {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(data);
+            format!("data: {encoded}\n\n")
+        }
+        SseEncoding::Text => {
+            let text = String::from_utf8_lossy(data).replace("\r\n", "\n");
+            let mut event = String::with_capacity(text.len() + 8);
+            for line in text.split(['\r', '\n']) {
+                event.push_str("data: ");
+                event.push_str(line);
+                event.push('\n');
+            }
+            event.push('\n');
+            event
+        }
+    }
+}
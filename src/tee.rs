@@ -0,0 +1,143 @@
+use crate::{BroadcastError, Hub};
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter, Stdout};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::broadcast::Receiver;
+use tokio_util::bytes::Bytes;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, instrument, warn};
+
+/// How often what was appended to the tee file gets flushed to disk.
+const TEE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Copy of everything broadcast, appended to a file or written to stdout.
+#[derive(Debug)]
+pub(crate) struct Tee<W: AsyncWrite = File> {
+    writer: BufWriter<W>,
+    rx: Receiver<Bytes>,
+    /// what is written to, for the logs
+    name: &'static str,
+    /// whether to flush as soon as there is nothing else to write, instead of every
+    /// [`TEE_FLUSH_INTERVAL`], for readers down a pipeline
+    eager: bool,
+    /// whether the last write went through, so a failing disk is not reported over and over
+    healthy: bool,
+}
+
+impl Tee<Stdout> {
+    /// Writes to stdout, flushing as soon as it caught up, and subscribes to the hub right away.
+    pub(crate) fn stdout(hub: &Hub) -> Self {
+        Self::new(tokio::io::stdout(), "stdout", hub)
+    }
+}
+
+impl Tee {
+    /// Opens the file at `path` for appending, and subscribes to the hub right away to not miss
+    /// anything broadcast from then on.
+    pub(crate) async fn open(path: &Path, hub: &Hub) -> Result<Self, BroadcastError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|source| BroadcastError::Open {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        let (rx, _) = hub.subscribe();
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+            rx,
+            name: "tee file",
+            eager: false,
+            healthy: true,
+        })
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Tee<W> {
+    /// Writes to `writer`, flushing as soon as it caught up, and subscribes to the hub right away.
+    pub(crate) fn new(writer: W, name: &'static str, hub: &Hub) -> Self {
+        let (rx, _) = hub.subscribe();
+
+        Self {
+            writer: BufWriter::new(writer),
+            rx,
+            name,
+            eager: true,
+            healthy: true,
+        }
+    }
+
+    /// Writes the chunks from the hub out, until `cancel` is triggered and the data
+    /// pending in the channel has been written.
+    ///
+    /// Failing to write, like a closed pipe, is logged and otherwise ignored, the live broadcast
+    /// goes on regardless.
+    #[instrument(skip_all)]
+    pub(crate) async fn run(mut self, cancel: CancellationToken) {
+        let mut flush = tokio::time::interval(TEE_FLUSH_INTERVAL);
+        let mut draining = false;
+
+        loop {
+            let data = if draining {
+                match self.rx.try_recv() {
+                    Ok(data) => data,
+                    Err(TryRecvError::Lagged(n)) => {
+                        warn!("{} missed {n} chunks", self.name);
+                        continue;
+                    }
+                    Err(_) => break,
+                }
+            } else {
+                tokio::select! {
+                    _ = cancel.cancelled() => {
+                        debug!("cancelled, writing pending data");
+                        draining = true;
+                        continue;
+                    }
+                    _ = flush.tick() => {
+                        let flushed = self.writer.flush().await;
+                        self.check(flushed);
+                        continue;
+                    }
+                    result = self.rx.recv() => match result {
+                        Ok(data) => data,
+                        Err(RecvError::Lagged(n)) => {
+                            warn!("{} missed {n} chunks", self.name);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                }
+            };
+
+            let written = self.writer.write_all(&data).await;
+            self.check(written);
+
+            if self.eager && self.rx.is_empty() {
+                let flushed = self.writer.flush().await;
+                self.check(flushed);
+            }
+        }
+
+        let flushed = self.writer.flush().await;
+        self.check(flushed);
+    }
+
+    /// Logs the first of a run of failures.
+    fn check(&mut self, result: std::io::Result<()>) {
+        match result {
+            Ok(()) => self.healthy = true,
+            Err(e) if self.healthy => {
+                warn!("when writing to the {}: {e}", self.name);
+                self.healthy = false;
+            }
+            Err(_) => {}
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use super::*;
+use net::set_nodelay;
 use once_cell::sync::Lazy;
 use rand::Rng;
 use std::{
@@ -11,6 +12,7 @@ use std::{
     time::Duration,
 };
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
 
 #[derive(Clone)]
 struct WaitForTest<const N: usize>(Arc<AtomicUsize>);
@@ -57,9 +59,14 @@ async fn test_reader_to_tx() {
     static COUNT_ASSERTS: Lazy<WaitForTest<1>> = Lazy::new(WaitForTest::new);
 
     // setup function under test
-    let (tx, _) = tokio::sync::broadcast::channel(1);
-    let (reader, mut writer) = tokio::io::duplex(BUFFER_SIZE);
-    tokio::spawn(reader_to_tx(reader, tx.clone())); // <- function under test
+    let hub = Hub::new(1, 0);
+    let (reader, mut writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+    tokio::spawn(reader_to_tx(
+        reader,
+        hub.clone(),
+        DEFAULT_BUFFER_SIZE,
+        Framing::Raw,
+    )); // <- function under test
 
     // give `reader_to_tx` a break to wire everything up
     sleep(Duration::from_secs(1));
@@ -69,7 +76,7 @@ async fn test_reader_to_tx() {
     });
 
     tokio::spawn({
-        let mut rx = tx.subscribe();
+        let (mut rx, _) = hub.subscribe();
 
         async move {
             let received = rx.recv().await.unwrap();
@@ -95,9 +102,14 @@ async fn test_tx_to_writer() {
     static COUNT_ASSERTS: Lazy<WaitForTest<1>> = Lazy::new(WaitForTest::new);
 
     // setup function under test
-    let (tx, _) = tokio::sync::broadcast::channel(1);
-    let (mut reader, writer) = tokio::io::duplex(BUFFER_SIZE);
-    tokio::spawn(tx_to_writer(writer, tx.clone())); // <- function under test
+    let hub = Hub::new(1, 0);
+    let (mut reader, writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+    tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions::default(),
+        CancellationToken::new(),
+    )); // <- function under test
 
     // give `tx_to_writer` a break to wire everything up
     sleep(Duration::from_secs(1));
@@ -112,7 +124,7 @@ async fn test_tx_to_writer() {
 
     tokio::spawn(async move {
         let data_bytes = Bytes::from(data[..].to_vec());
-        tx.send(data_bytes).unwrap();
+        hub.publish(data_bytes).unwrap();
     });
 
     COUNT_ASSERTS.clone().await;
@@ -157,7 +169,6 @@ async fn tcp_broadcast_test() {
     let stream_addr = "127.0.0.1:9091"; // <-- test data will be send here
 
     let stream = TcpListener::bind(&stream_addr).await.unwrap();
-    let listener = TcpListener::bind(&listener_addr).await.unwrap();
 
     tokio::task::spawn(async move {
         let mut remote_stream = stream.accept().await.unwrap().0;
@@ -167,11 +178,10 @@ async fn tcp_broadcast_test() {
     // give `remote_stream` a break to setup
     sleep(Duration::from_secs(1));
 
-    let stream = TcpStream::connect(&stream_addr).await.unwrap();
+    let config = Config::new(listener_addr, Remote::Tcp(stream_addr.to_string()));
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
 
-    tokio::spawn(tcp_broadcaster(stream, listener)); // <- function under test
-
-    // give `tcp_broadcaster` a break to wire things up
+    // give `Broadcaster` a break to wire things up
     sleep(Duration::from_secs(1));
     tokio::spawn(TcpStream::connect(listener_addr));
 
@@ -222,15 +232,14 @@ async fn udp_broadcast_test() {
     let read_addr = "127.0.0.1:8091"; // <-- UDP socket address, used to listen to messages that will be distributed to TCP connections at previous address
     let write_addr = "127.0.0.1:8092"; // <-- used to send test data to `read_addr`
 
-    let listener = TcpListener::bind(&listener_addr).await.unwrap();
-    let read_socket: UdpSocket = UdpSocket::bind(read_addr).await.unwrap();
     let write_socket: UdpSocket = UdpSocket::bind(write_addr).await.unwrap();
 
     write_socket.connect(read_addr).await.unwrap();
 
-    tokio::spawn(udp_broadcaster(read_socket, listener));
+    let config = Config::new(listener_addr, Remote::Udp(read_addr.to_string()));
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
 
-    // give `udp_broadcaster` a break to wire things up
+    // give `Broadcaster` a break to wire things up
     sleep(Duration::from_secs(1));
 
     // launch mock clients
@@ -246,3 +255,5333 @@ async fn udp_broadcast_test() {
     COUNT_ASSERTS.clone().await;
     assert!(COUNT_ASSERTS.check());
 }
+
+#[test]
+fn backoff_delay_grows_and_caps() {
+    let backoff = Backoff {
+        initial: Duration::from_millis(500),
+        max: Duration::from_secs(3),
+        multiplier: 2.0,
+        max_attempts: None,
+    };
+
+    assert_eq!(backoff.delay(1), Duration::from_millis(500));
+    assert_eq!(backoff.delay(2), Duration::from_secs(1));
+    assert_eq!(backoff.delay(3), Duration::from_secs(2));
+    assert_eq!(backoff.delay(4), Duration::from_secs(3));
+    assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(3));
+}
+
+#[test_log::test(tokio::test)]
+async fn connect_with_backoff_retries_until_remote_is_up() {
+    let remote_addr = "127.0.0.1:9191";
+
+    let backoff = Backoff {
+        initial: Duration::from_millis(100),
+        max: Duration::from_millis(200),
+        multiplier: 2.0,
+        max_attempts: None,
+    };
+    let cancel = CancellationToken::new();
+
+    // bring the remote up only after a few failed attempts
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let listener = TcpListener::bind(remote_addr).await.unwrap();
+        listener.accept().await.unwrap();
+    });
+
+    let stream = connect_with_backoff(remote_addr, &backoff, &cancel).await; // <- function under test
+
+    assert!(stream.unwrap().is_some());
+}
+
+#[test_log::test(tokio::test)]
+async fn connect_times_out_on_an_unresponsive_host() {
+    // a listener with a full backlog drops the handshakes, like an unreachable host would
+    let socket = tokio::net::TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.1:9218".parse().unwrap()).unwrap();
+    let _listener = socket.listen(0).unwrap();
+    let _queued = TcpStream::connect("127.0.0.1:9218").await.unwrap();
+
+    let timeout = Duration::from_millis(200);
+    let started = std::time::Instant::now();
+    let result = connect_through("127.0.0.1:9218", None, None, timeout).await; // <- function under test
+
+    assert!(started.elapsed() < Duration::from_secs(1));
+    match result {
+        Err(BroadcastError::Connect { source, .. }) => {
+            assert_eq!(source.kind(), ErrorKind::TimedOut);
+        }
+        other => panic!("expected a connect timeout, got {other:?}"),
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn connect_with_backoff_aborts_on_cancel() {
+    let backoff = Backoff {
+        initial: Duration::from_secs(60),
+        ..Backoff::default()
+    };
+    let cancel = CancellationToken::new();
+
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            cancel.cancel();
+        }
+    });
+
+    // nothing listens on this port, so the first attempt fails and we get stuck in the backoff
+    let stream = tokio::time::timeout(
+        Duration::from_secs(5),
+        connect_with_backoff("127.0.0.1:9192", &backoff, &cancel), // <- function under test
+    )
+    .await
+    .expect("backoff was not aborted by the cancellation");
+
+    assert!(stream.unwrap().is_none());
+}
+
+#[test_log::test(tokio::test)]
+async fn broadcaster_reconnects_keeping_consumers() {
+    let listener_addr = "127.0.0.1:9082"; // <-- tests TCP clients will connect here
+    let remote_addr = "127.0.0.1:9092"; // <-- mock remote, closes after each message
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.backoff.initial = Duration::from_millis(50);
+
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    // the first connection from the broadcaster is held until the client is subscribed
+    let (mut first, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    first.write_all(b"first").await.unwrap();
+    drop(first);
+
+    // the broadcaster should come back for more
+    let (mut second, _) = remote.accept().await.unwrap();
+    second.write_all(b"second").await.unwrap();
+
+    let mut received = [0u8; 11];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&received, b"firstsecond");
+}
+
+#[test_log::test(tokio::test)]
+async fn broadcaster_without_reconnect_exits_on_eof() {
+    let remote_addr = "127.0.0.1:9093";
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new("127.0.0.1:9083", Remote::Tcp(remote_addr.to_string()));
+    config.reconnect = false;
+
+    let broadcaster = tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    // close the connection right away
+    drop(remote.accept().await.unwrap());
+
+    let result = tokio::time::timeout(Duration::from_secs(5), broadcaster)
+        .await
+        .expect("broadcaster did not exit on EOF");
+
+    assert!(result.unwrap().is_ok());
+}
+
+#[test_log::test(tokio::test)]
+async fn hostnames_resolve_for_bind_and_connect() {
+    let listener = bind_listener("localhost:9084", ListenOptions::default())
+        .await
+        .unwrap(); // <- function under test
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+    });
+
+    let mut stream = connect("localhost:9084").await.unwrap(); // <- function under test
+
+    let mut received = [0u8; 5];
+    stream.read_exact(&mut received).await.unwrap();
+
+    assert_eq!(&received, b"hello");
+}
+
+#[test_log::test(tokio::test)]
+async fn unresolvable_hostname_is_an_error() {
+    let result = resolve("does-not-exist.invalid:9085").await; // <- function under test
+
+    assert!(matches!(result, Err(BroadcastError::Resolve { .. })));
+}
+
+#[test_log::test(tokio::test)]
+async fn every_consumer_receives_the_same_payload() {
+    const NUM_CLIENTS: usize = 3;
+
+    let mut data = [0_u8; 512];
+    rand::thread_rng().fill(&mut data[..]);
+
+    let listener_addr = "127.0.0.1:9086";
+    let remote_addr = "127.0.0.1:9096";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut clients = Vec::new();
+    for _ in 0..NUM_CLIENTS {
+        clients.push(TcpStream::connect(listener_addr).await.unwrap());
+    }
+
+    // give the broadcaster a break to subscribe all clients
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(&data).await.unwrap();
+
+    for mut client in clients {
+        let mut received = [0u8; 512];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(received, data);
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn tx_to_writer_flushes_buffered_writers() {
+    let hub = Hub::new(1, 0);
+    let (mut reader, writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+
+    // a small payload would sit in the `BufWriter` forever if it was never flushed
+    let writer = tokio::io::BufWriter::new(writer);
+    tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions::default(),
+        CancellationToken::new(),
+    )); // <- function under test
+
+    // give `tx_to_writer` a break to subscribe
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    hub.publish(Bytes::from_static(b"ping")).unwrap();
+
+    let mut received = [0u8; 4];
+    tokio::time::timeout(Duration::from_millis(500), reader.read_exact(&mut received))
+        .await
+        .expect("payload was not flushed")
+        .unwrap();
+
+    assert_eq!(&received, b"ping");
+}
+
+#[test_log::test(tokio::test)]
+async fn failed_consumers_do_not_affect_survivors() {
+    let listener_addr = "127.0.0.1:9087";
+    let remote_addr = "127.0.0.1:9097";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut clients = Vec::new();
+    for _ in 0..4 {
+        clients.push(TcpStream::connect(listener_addr).await.unwrap());
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // make two non-adjacent clients fail
+    let mut survivors: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, client)| (i % 2 == 1).then_some(client))
+        .collect();
+
+    // keep sending so the failed writes are detected while survivors keep receiving
+    for round in 0..5u8 {
+        remote_stream.write_all(&[round; 16]).await.unwrap();
+
+        for survivor in survivors.iter_mut() {
+            let mut received = [0u8; 16];
+            tokio::time::timeout(Duration::from_secs(5), survivor.read_exact(&mut received))
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(received, [round; 16]);
+        }
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn slow_consumer_does_not_block_fast_consumer() {
+    const CHUNKS: usize = 16;
+
+    let hub = Hub::new(CHUNKS, 0);
+
+    // the slow reader never reads, so its writer blocks as soon as the duplex buffer is full
+    let (_slow_reader, slow_writer) = tokio::io::duplex(64);
+    let (mut fast_reader, fast_writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+
+    tokio::spawn(tx_to_writer(
+        slow_writer,
+        hub.clone(),
+        ClientOptions::default(),
+        CancellationToken::new(),
+    )); // <- function under test
+    tokio::spawn(tx_to_writer(
+        fast_writer,
+        hub.clone(),
+        ClientOptions::default(),
+        CancellationToken::new(),
+    )); // <- function under test
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for i in 0..CHUNKS {
+        hub.publish(Bytes::from(vec![i as u8; 32])).unwrap();
+    }
+
+    let mut received = vec![0u8; CHUNKS * 32];
+    tokio::time::timeout(
+        Duration::from_secs(1),
+        fast_reader.read_exact(&mut received),
+    )
+    .await
+    .expect("fast consumer was blocked by the slow one")
+    .unwrap();
+
+    for (i, chunk) in received.chunks(32).enumerate() {
+        assert_eq!(chunk, [i as u8; 32]);
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn tx_to_writer_stops_on_cancel() {
+    let hub = Hub::new(1, 0);
+    let (mut reader, writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+    let cancel = CancellationToken::new();
+
+    let handle = tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions::default(),
+        cancel.clone(),
+    )); // <- function under test
+
+    cancel.cancel();
+
+    tokio::time::timeout(Duration::from_secs(1), handle)
+        .await
+        .expect("tx_to_writer ignored the cancellation")
+        .unwrap();
+
+    // the writer was dropped, so the reader sees EOF
+    assert_eq!(reader.read(&mut [0u8; 1]).await.unwrap(), 0);
+}
+
+#[test_log::test(tokio::test)]
+async fn stuck_consumer_is_dropped_after_write_timeout() {
+    let hub = Hub::new(16, 0);
+    let write_timeout = Duration::from_millis(200);
+
+    // the stuck reader never reads, the healthy one does
+    let (_stuck_reader, stuck_writer) = tokio::io::duplex(64);
+    let (mut healthy_reader, healthy_writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+
+    let stuck = tokio::spawn(tx_to_writer(
+        stuck_writer,
+        hub.clone(),
+        ClientOptions {
+            write_timeout,
+            ..ClientOptions::default()
+        },
+        CancellationToken::new(),
+    )); // <- function under test
+    let healthy = tokio::spawn(tx_to_writer(
+        healthy_writer,
+        hub.clone(),
+        ClientOptions {
+            write_timeout,
+            ..ClientOptions::default()
+        },
+        CancellationToken::new(),
+    )); // <- function under test
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    hub.publish(Bytes::from(vec![1u8; 128])).unwrap();
+
+    tokio::time::timeout(Duration::from_secs(2), stuck)
+        .await
+        .expect("stuck consumer was not dropped")
+        .unwrap();
+
+    // the healthy consumer is still around and receiving
+    hub.publish(Bytes::from(vec![2u8; 128])).unwrap();
+
+    let mut received = [0u8; 256];
+    healthy_reader.read_exact(&mut received).await.unwrap();
+
+    assert_eq!(received[..128], [1u8; 128]);
+    assert_eq!(received[128..], [2u8; 128]);
+    assert!(!healthy.is_finished());
+}
+
+#[test]
+fn builder_applies_settings_and_defaults() {
+    let broadcaster = Broadcaster::builder()
+        .local("0.0.0.0:8080")
+        .remote("feed:9092")
+        .buffer_size(4096)
+        .build()
+        .unwrap(); // <- function under test
+
+    let config = broadcaster.config();
+
+    assert_eq!(config.local, "0.0.0.0:8080");
+    assert_eq!(config.remotes, [Remote::Tcp("feed:9092".to_string())]);
+    assert_eq!(config.buffer_size, 4096);
+    assert_eq!(config.broadcast_capacity, DEFAULT_BROADCAST_CAPACITY);
+    assert_eq!(config.write_timeout, DEFAULT_WRITE_TIMEOUT);
+    assert!(config.reconnect);
+}
+
+#[test]
+fn builder_rejects_invalid_addresses() {
+    let empty_host = Broadcaster::builder()
+        .local("0.0.0.0:8080")
+        .remote(":9092")
+        .build(); // <- function under test
+
+    assert!(matches!(
+        empty_host,
+        Err(BuildError::InvalidAddress { ref address, .. }) if address == ":9092"
+    ));
+
+    let missing_remote = Broadcaster::builder().local("0.0.0.0:8080").build(); // <- function under test
+
+    assert!(matches!(missing_remote, Err(BuildError::Missing("remote"))));
+
+    let bad_protocol = Broadcaster::builder()
+        .local("0.0.0.0:8080")
+        .remote("sctp://feed:9092")
+        .build(); // <- function under test
+
+    assert!(matches!(bad_protocol, Err(BuildError::InvalidRemote(_))));
+}
+
+#[test_log::test(tokio::test)]
+async fn reader_to_tx_reads_at_most_buffer_size() {
+    let hub = Hub::new(16, 0);
+    let (mut rx, _) = hub.subscribe();
+    let (reader, mut writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+
+    tokio::spawn(reader_to_tx(reader, hub, MIN_BUFFER_SIZE, Framing::Raw)); // <- function under test
+
+    writer.write_all(&[7u8; 3 * MIN_BUFFER_SIZE]).await.unwrap();
+
+    let mut total = 0;
+    while total < 3 * MIN_BUFFER_SIZE {
+        let chunk = rx.recv().await.unwrap();
+        assert!(chunk.len() <= MIN_BUFFER_SIZE);
+        total += chunk.len();
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn datagrams_are_not_truncated_by_previous_reads() {
+    const DATAGRAM_SIZE: usize = 1000;
+
+    let hub = Hub::new(64, 0);
+    let (mut rx, _) = hub.subscribe();
+
+    let read_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let write_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    write_socket
+        .connect(read_socket.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    tokio::spawn(reader_to_tx(
+        AsyncUdpSocket::from(read_socket),
+        hub,
+        DATAGRAM_SIZE,
+        Framing::Raw,
+    )); // <- function under test
+
+    // without making room before each read, later datagrams would land in the leftover capacity
+    for i in 0..20u8 {
+        write_socket.send(&[i; DATAGRAM_SIZE]).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap()[..], [i; DATAGRAM_SIZE]);
+    }
+}
+
+#[test]
+fn builder_rejects_small_buffers() {
+    let result = Broadcaster::builder()
+        .local("0.0.0.0:8080")
+        .remote("feed:9092")
+        .buffer_size(MIN_BUFFER_SIZE - 1)
+        .build(); // <- function under test
+
+    assert!(matches!(result, Err(BuildError::BufferTooSmall(_))));
+}
+
+#[test_log::test(tokio::test)]
+async fn closed_remote_port_is_a_connect_error() {
+    // nothing listens on the remote port
+    let mut config = Config::new("127.0.0.1:9088", Remote::Tcp("127.0.0.1:9098".to_string()));
+    config.backoff.initial = Duration::from_millis(10);
+    config.backoff.max_attempts = Some(3);
+
+    let result = Broadcaster::new(config).run(CancellationToken::new()).await; // <- function under test
+
+    assert!(matches!(result, Err(BroadcastError::Connect { .. })));
+}
+
+#[test_log::test(tokio::test)]
+async fn busy_local_port_is_a_bind_error() {
+    let _taken = TcpListener::bind("127.0.0.1:9089").await.unwrap();
+
+    let config = Config::new("127.0.0.1:9089", Remote::Tcp("127.0.0.1:9099".to_string()));
+
+    let result = Broadcaster::new(config).run(CancellationToken::new()).await; // <- function under test
+
+    assert!(matches!(result, Err(BroadcastError::Bind { .. })));
+}
+
+#[test]
+fn hub_replays_the_last_bytes() {
+    let hub = Hub::new(16, 8);
+
+    hub.publish(Bytes::from_static(b"abcd")).unwrap_err(); // <- no consumers yet
+    hub.publish(Bytes::from_static(b"efghij")).unwrap_err();
+
+    let (_, history) = hub.subscribe(); // <- function under test
+
+    assert_eq!(history.concat(), b"cdefghij");
+}
+
+#[test]
+fn hub_replays_the_last_bytes_of_a_chunk_larger_than_the_history() {
+    let hub = Hub::new(16, 4);
+
+    hub.publish(Bytes::from_static(b"abcdefghij")).unwrap_err(); // <- no consumers yet
+
+    let (_, history) = hub.subscribe(); // <- function under test
+
+    assert_eq!(history.concat(), b"ghij");
+}
+
+#[test]
+fn framed_hub_replays_whole_chunks() {
+    let hub = Hub::new(16, 8).with_whole_chunks();
+
+    hub.publish(Bytes::from_static(b"ab")).unwrap_err(); // <- no consumers yet
+    hub.publish(Bytes::from_static(b"cd")).unwrap_err();
+    hub.publish(Bytes::from_static(b"efghij")).unwrap_err();
+
+    let (_, history) = hub.subscribe(); // <- function under test
+
+    // the oldest chunk goes whole, rather than being cut
+    assert_eq!(history, [&b"cd"[..], &b"efghij"[..]]);
+}
+
+#[test]
+fn hub_without_replay_has_no_history() {
+    let hub = Hub::new(16, 0);
+
+    hub.publish(Bytes::from_static(b"abcd")).unwrap_err();
+
+    let (_, history) = hub.subscribe(); // <- function under test
+
+    assert!(history.is_empty());
+}
+
+#[test_log::test(tokio::test)]
+async fn late_consumer_receives_replay_then_live_data() {
+    let listener_addr = "127.0.0.1:9101";
+    let remote_addr = "127.0.0.1:9111";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.replay_bytes = 6;
+
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    // sent before anyone is connected
+    remote_stream.write_all(b"history").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"live").await.unwrap();
+
+    let mut received = [0u8; 10];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&received, b"istorylive");
+}
+
+#[test_log::test(tokio::test)]
+async fn clients_over_the_limit_are_refused() {
+    let listener_addr = "127.0.0.1:9102";
+    let remote_addr = "127.0.0.1:9112";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.max_clients = 2;
+
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut first = TcpStream::connect(listener_addr).await.unwrap();
+    let mut second = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // the third one is closed right away
+    let mut third = TcpStream::connect(listener_addr).await.unwrap();
+    let mut buf = [0u8; 1];
+    let n = tokio::time::timeout(Duration::from_secs(5), third.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(n, 0);
+
+    remote_stream.write_all(b"data").await.unwrap();
+
+    for client in [&mut first, &mut second] {
+        let mut received = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&received, b"data");
+    }
+}
+
+#[test]
+fn hub_slots_are_given_back_on_drop() {
+    let hub = Hub::new(16, 0);
+
+    let addr = "127.0.0.1:5000".parse().unwrap();
+
+    let first = hub.try_join(1, addr); // <- function under test
+    assert!(first.is_some());
+    assert!(hub.try_join(1, addr).is_none());
+
+    drop(first);
+    assert_eq!(hub.clients(), 0);
+    assert!(hub.connected().is_empty());
+    assert!(hub.try_join(1, addr).is_some());
+}
+
+#[test_log::test(tokio::test)]
+async fn metrics_are_served_over_http() {
+    let listener_addr = "127.0.0.1:9103";
+    let remote_addr = "127.0.0.1:9113";
+    let metrics_addr = "127.0.0.1:9121";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.metrics_addr = Some(metrics_addr.to_string());
+
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"data").await.unwrap();
+
+    let mut received = [0u8; 4];
+    client.read_exact(&mut received).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut http = TcpStream::connect(metrics_addr).await.unwrap();
+    http.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+
+    let mut response = String::new();
+    http.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("tcp_broadcast_clients_connected 1\n"));
+    assert!(response.contains("tcp_broadcast_bytes_received_total 4\n"));
+    assert!(response.contains("tcp_broadcast_bytes_sent_total 4\n"));
+    assert!(response.contains("tcp_broadcast_clients_dropped_total 0\n"));
+}
+
+#[test_log::test(tokio::test)]
+async fn metrics_requests_with_overlong_lines_get_no_response() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let metrics_addr = listener.local_addr().unwrap();
+    tokio::spawn(serve_metrics(listener, Hub::new(1, 0))); // <- function under test
+
+    let mut http = TcpStream::connect(metrics_addr).await.unwrap();
+    let endless = vec![b'a'; 64 * 1024];
+    let _ = http
+        .write_all(b"GET /metrics HTTP/1.1\r\nX-Padding: ")
+        .await;
+    let _ = http.write_all(&endless).await;
+
+    let mut response = Vec::new();
+    let _ = tokio::time::timeout(Duration::from_secs(5), http.read_to_end(&mut response))
+        .await
+        .expect("connection was not closed");
+
+    assert!(
+        response.is_empty(),
+        "{:?}",
+        String::from_utf8_lossy(&response)
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn client_stats_count_the_bytes_written_before_dropping() {
+    let hub = Hub::new(16, 0);
+    let (mut reader, writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+
+    let handle = tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions::default(),
+        CancellationToken::new(),
+    )); // <- function under test
+
+    // give `tx_to_writer` a break to subscribe
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    hub.publish(Bytes::from_static(b"hello")).unwrap();
+    hub.publish(Bytes::from_static(b"world")).unwrap();
+
+    let mut received = [0u8; 10];
+    reader.read_exact(&mut received).await.unwrap();
+
+    // the next write fails, so the client is removed
+    drop(reader);
+    hub.publish(Bytes::from_static(b"lost")).unwrap();
+
+    let stats = tokio::time::timeout(Duration::from_secs(1), handle)
+        .await
+        .expect("tx_to_writer did not notice the closed reader")
+        .unwrap();
+
+    assert_eq!(stats.bytes_sent, received.len() as u64);
+    assert!(stats.duration >= Duration::from_millis(100));
+}
+
+#[cfg(unix)]
+#[test_log::test(tokio::test)]
+async fn sigterm_stops_the_broadcaster() {
+    let listener_addr = "127.0.0.1:9104";
+    let remote_addr = "127.0.0.1:9114";
+
+    let _remote = TcpListener::bind(remote_addr).await.unwrap();
+    let cancel = CancellationToken::new();
+
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            shutdown_signal().await.unwrap(); // <- function under test
+            cancel.cancel();
+        }
+    });
+
+    let config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    let handle = tokio::spawn(Broadcaster::new(config).run(cancel));
+
+    // give the handler a break to be installed
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let status = std::process::Command::new("kill")
+        .args(["-TERM", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .expect("broadcaster ignored SIGTERM")
+        .unwrap()
+        .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn cancelled_writer_drains_pending_data() {
+    let hub = Hub::new(64, 0);
+    // a tiny pipe keeps most of the chunks waiting in the channel
+    let (mut reader, writer) = tokio::io::duplex(4);
+    let cancel = CancellationToken::new();
+
+    tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions::default(),
+        cancel.clone(),
+    )); // <- function under test
+
+    // give `tx_to_writer` a break to subscribe
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut sent = Vec::new();
+    for i in 0..32u8 {
+        let chunk = vec![i; 4];
+        sent.extend_from_slice(&chunk);
+        hub.publish(chunk.into()).unwrap();
+    }
+
+    cancel.cancel();
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(1), reader.read_to_end(&mut received))
+        .await
+        .expect("writer was not closed after draining")
+        .unwrap();
+
+    assert_eq!(received, sent);
+}
+
+#[test_log::test(tokio::test)]
+async fn cancelled_broadcaster_delivers_pending_bytes_before_closing() {
+    let listener_addr = "127.0.0.1:9105";
+    let remote_addr = "127.0.0.1:9115";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+    let cancel = CancellationToken::new();
+
+    let config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    let handle = tokio::spawn(Broadcaster::new(config).run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"last words").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    cancel.cancel();
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+        .await
+        .expect("client was not closed")
+        .unwrap();
+
+    assert_eq!(received, b"last words");
+    handle.await.unwrap().unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn stuck_clients_are_closed_after_the_grace_period() {
+    let listener_addr = "127.0.0.1:9106";
+    let remote_addr = "127.0.0.1:9116";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+    let cancel = CancellationToken::new();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.write_timeout = Duration::from_secs(60);
+    config.shutdown_grace = Duration::from_millis(200);
+    let handle = tokio::spawn(Broadcaster::new(config).run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    // never reads, so its writer ends up blocked
+    let _stuck = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let chunk = vec![0u8; 64 * 1024];
+    for _ in 0..128 {
+        remote_stream.write_all(&chunk).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    cancel.cancel();
+
+    tokio::time::timeout(Duration::from_secs(2), handle)
+        .await
+        .expect("broadcaster waited past the grace period")
+        .unwrap()
+        .unwrap();
+}
+
+/// Writes `contents` to a file in the temp dir, unique to this test run.
+fn temp_file(name: &str, contents: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("tcp-broadcast-{}-{name}", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// TLS acceptor presenting a fresh self-signed certificate for `localhost`, and the certificate.
+fn self_signed_acceptor() -> (tokio_rustls::TlsAcceptor, rcgen::CertifiedKey) {
+    use tokio_rustls::rustls::{crypto::ring, pki_types::PrivateKeyDer, ServerConfig};
+
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![certified.cert.der().clone()],
+            PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into()),
+        )
+        .unwrap();
+
+    (tokio_rustls::TlsAcceptor::from(Arc::new(config)), certified)
+}
+
+#[test_log::test(tokio::test)]
+async fn tls_remote_is_trusted_with_a_custom_ca() {
+    let listener_addr = "127.0.0.1:9107";
+    let remote_addr = "127.0.0.1:9117";
+
+    let (acceptor, certified) = self_signed_acceptor();
+    let ca_file = temp_file("remote-ca.pem", &certified.cert.pem());
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .remote_tls(RemoteTls {
+            ca_file: Some(ca_file),
+            sni: Some("localhost".to_string()),
+        })
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (remote_stream, _) = remote.accept().await.unwrap();
+    let mut remote_stream = acceptor.accept(remote_stream).await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"secret").await.unwrap();
+    remote_stream.flush().await.unwrap();
+
+    let mut received = [0u8; 6];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&received, b"secret");
+}
+
+#[test_log::test(tokio::test)]
+async fn untrusted_tls_remote_is_a_tls_error() {
+    let listener_addr = "127.0.0.1:9108";
+    let remote_addr = "127.0.0.1:9118";
+
+    let (acceptor, _) = self_signed_acceptor();
+    // a CA that did not sign the remote certificate
+    let (_, other) = self_signed_acceptor();
+    let ca_file = temp_file("other-ca.pem", &other.cert.pem());
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = remote.accept().await.unwrap();
+        let _ = acceptor.accept(stream).await;
+    });
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.remote_tls = Some(RemoteTls {
+        ca_file: Some(ca_file),
+        sni: Some("localhost".to_string()),
+    });
+
+    let result = Broadcaster::new(config).run(CancellationToken::new()).await; // <- function under test
+
+    assert!(matches!(result, Err(BroadcastError::Tls { .. })));
+}
+
+#[test_log::test(tokio::test)]
+async fn tls_consumers_receive_the_broadcast() {
+    use tokio_rustls::rustls::{crypto::ring, pki_types::ServerName, ClientConfig, RootCertStore};
+
+    let listener_addr = "127.0.0.1:9109";
+    let remote_addr = "127.0.0.1:9119";
+
+    let (_, certified) = self_signed_acceptor();
+    let cert_file = temp_file("local-cert.pem", &certified.cert.pem());
+    let key_file = temp_file("local-key.pem", &certified.key_pair.serialize_pem());
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .local_tls(LocalTls {
+            cert_file,
+            key_file,
+        })
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    // a plain TCP client fails the handshake and gets dropped
+    let mut plain = TcpStream::connect(listener_addr).await.unwrap();
+    plain.write_all(b"not a client hello").await.unwrap();
+    let mut rest = Vec::new();
+    let _ = tokio::time::timeout(Duration::from_secs(5), plain.read_to_end(&mut rest))
+        .await
+        .expect("plain client was not dropped");
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+
+    let stream = TcpStream::connect(listener_addr).await.unwrap();
+    let mut client = connector
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"encrypted").await.unwrap();
+
+    let mut received = [0u8; 9];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&received, b"encrypted");
+}
+
+#[test_log::test(tokio::test)]
+async fn merge_mode_interleaves_all_remotes() {
+    let listener_addr = "127.0.0.1:9122";
+    let first_addr = "127.0.0.1:9123";
+    let second_addr = "127.0.0.1:9124";
+
+    let first = TcpListener::bind(first_addr).await.unwrap();
+    let second = TcpListener::bind(second_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(first_addr)
+        .remote(second_addr)
+        .remote_mode(RemoteMode::Merge)
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut first_stream, _) = first.accept().await.unwrap();
+    let (mut second_stream, _) = second.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    first_stream.write_all(b"one").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    second_stream.write_all(b"two").await.unwrap();
+
+    let mut received = [0u8; 6];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&received, b"onetwo");
+}
+
+#[test_log::test(tokio::test)]
+async fn failover_mode_switches_to_the_next_remote() {
+    let listener_addr = "127.0.0.1:9125";
+    let primary_addr = "127.0.0.1:9126";
+    let secondary_addr = "127.0.0.1:9127";
+
+    let primary = TcpListener::bind(primary_addr).await.unwrap();
+    let secondary = TcpListener::bind(secondary_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(primary_addr.to_string()));
+    config.remotes.push(Remote::Tcp(secondary_addr.to_string()));
+    config.remote_mode = RemoteMode::Failover;
+    config.backoff.initial = Duration::from_millis(10);
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    let (mut primary_stream, _) = primary.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // only the primary is read from while it is up
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), secondary.accept())
+            .await
+            .is_err()
+    );
+
+    primary_stream.write_all(b"one").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(primary_stream);
+    drop(primary);
+
+    let (mut secondary_stream, _) = secondary.accept().await.unwrap();
+    secondary_stream.write_all(b"two").await.unwrap();
+
+    let mut received = [0u8; 6];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&received, b"onetwo");
+}
+
+#[test_log::test(tokio::test)]
+async fn failover_mode_gives_up_when_no_remote_is_left() {
+    // nothing listens on either remote
+    let mut config = Config::new("127.0.0.1:9128", Remote::Tcp("127.0.0.1:9129".to_string()));
+    config
+        .remotes
+        .push(Remote::Tcp("127.0.0.1:9130".to_string()));
+    config.remote_mode = RemoteMode::Failover;
+    config.backoff.initial = Duration::from_millis(10);
+    config.backoff.max_attempts = Some(2);
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        Broadcaster::new(config).run(CancellationToken::new()), // <- function under test
+    )
+    .await
+    .unwrap();
+
+    assert!(matches!(result, Err(BroadcastError::Connect { .. })));
+}
+
+#[test_log::test(tokio::test)]
+async fn udp_targets_receive_broadcast_datagrams() {
+    let remote_addr = "127.0.0.1:9131";
+    let first_target = UdpSocket::bind("127.0.0.1:9132").await.unwrap();
+    let second_target = UdpSocket::bind("127.0.0.1:9133").await.unwrap();
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local("127.0.0.1:0")
+        .local_proto(LocalProto::Udp)
+        .udp_target("127.0.0.1:9132")
+        .udp_target("127.0.0.1:9133")
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    remote_stream.write_all(b"datagram").await.unwrap();
+
+    for target in [&first_target, &second_target] {
+        let mut received = [0u8; 64];
+        let n = tokio::time::timeout(Duration::from_secs(5), target.recv(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&received[..n], b"datagram");
+    }
+}
+
+#[test]
+fn builder_requires_udp_targets_with_the_udp_protocol() {
+    let result = Broadcaster::builder()
+        .local("0.0.0.0:0")
+        .local_proto(LocalProto::Udp)
+        .remote("feed:9092")
+        .build(); // <- function under test
+
+    assert!(matches!(result, Err(BuildError::Missing("udp target"))));
+}
+
+#[test_log::test(tokio::test)]
+async fn udp_remote_proto_bridges_datagrams_to_tcp_clients() {
+    let listener_addr = "127.0.0.1:9134";
+    let remote_addr = "127.0.0.1:9135";
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .remote_proto(RemoteProto::Udp)
+        .build()
+        .unwrap();
+    assert_eq!(
+        broadcaster.config().remotes,
+        [Remote::Udp(remote_addr.to_string())]
+    );
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    // give `Broadcaster` a break to bind
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sender.send_to(b"first", remote_addr).await.unwrap();
+    sender.send_to(b"second", remote_addr).await.unwrap();
+
+    let mut received = [0u8; 11];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&received, b"firstsecond");
+}
+
+#[test]
+fn remotes_without_protocol_use_the_default() {
+    let remote = Remote::parse_with("feed:9092", RemoteProto::Udp); // <- function under test
+    assert_eq!(remote, Ok(Remote::Udp("feed:9092".to_string())));
+
+    // an explicit protocol wins
+    let remote = Remote::parse_with("tcp://feed:9092", RemoteProto::Udp);
+    assert_eq!(remote, Ok(Remote::Tcp("feed:9092".to_string())));
+}
+
+#[test_log::test(tokio::test)]
+async fn length_prefixed_frames_are_forwarded_whole() {
+    let hub = Hub::new(16, 0);
+    let (mut rx, _) = hub.subscribe();
+    let (reader, mut writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+
+    let framing = Framing::LengthPrefixed {
+        width: 2,
+        endian: Endian::Little,
+    };
+    tokio::spawn(reader_to_tx(reader, hub, DEFAULT_BUFFER_SIZE, framing)); // <- function under test
+
+    // the second frame is split across writes
+    writer.write_all(b"\x05\x00hello\x06\x00wo").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    writer.write_all(b"rld!\x00\x00").await.unwrap();
+
+    assert_eq!(&rx.recv().await.unwrap()[..], b"\x05\x00hello");
+    assert_eq!(&rx.recv().await.unwrap()[..], b"\x06\x00world!");
+    assert_eq!(&rx.recv().await.unwrap()[..], b"\x00\x00");
+}
+
+#[test_log::test(tokio::test)]
+async fn length_prefixed_broadcast_round_trips_frames() {
+    let listener_addr = "127.0.0.1:9136";
+    let remote_addr = "127.0.0.1:9137";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .framing(Framing::LengthPrefixed {
+            width: 4,
+            endian: Endian::Big,
+        })
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let messages: [&[u8]; 3] = [b"first", b"", b"third message"];
+    let mut encoded = Vec::new();
+    for message in messages {
+        encoded.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        encoded.extend_from_slice(message);
+    }
+    remote_stream.write_all(&encoded).await.unwrap();
+
+    for message in messages {
+        let mut length = [0u8; 4];
+        client.read_exact(&mut length).await.unwrap();
+
+        let mut frame = vec![0u8; u32::from_be_bytes(length) as usize];
+        client.read_exact(&mut frame).await.unwrap();
+
+        assert_eq!(&frame[..], message);
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn frames_spanning_several_reads_reach_clients_intact() {
+    let listener_addr = "127.0.0.1:9230";
+    let remote_addr = "127.0.0.1:9231";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    // the frame is larger than the buffer, so it cannot come in a single read anyway
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .buffer_size(MIN_BUFFER_SIZE)
+        .framing(Framing::LengthPrefixed {
+            width: 2,
+            endian: Endian::Big,
+        })
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let payload: Vec<u8> = (0..150u8).collect();
+    let mut frame = (payload.len() as u16).to_be_bytes().to_vec();
+    frame.extend_from_slice(&payload);
+
+    for part in [&frame[..1], &frame[1..100], &frame[100..]] {
+        remote_stream.write_all(part).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    remote_stream.write_all(b"\x00\x02ok").await.unwrap();
+
+    let mut received = vec![0u8; frame.len() + 4];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received[..frame.len()], &frame[..]);
+    assert_eq!(&received[frame.len()..], b"\x00\x02ok");
+
+    let mut chunks = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if let Event::ChunkBroadcast { len, .. } = event {
+            chunks.push(len);
+        }
+    }
+    assert_eq!(chunks, [frame.len(), 4]);
+}
+
+#[test]
+fn builder_rejects_unsupported_length_widths() {
+    let result = Broadcaster::builder()
+        .local("0.0.0.0:8080")
+        .remote("feed:9092")
+        .framing(Framing::LengthPrefixed {
+            width: 3,
+            endian: Endian::Big,
+        })
+        .build(); // <- function under test
+
+    assert!(matches!(result, Err(BuildError::InvalidFraming(_))));
+}
+
+#[test_log::test(tokio::test)]
+async fn line_framing_only_forwards_complete_lines() {
+    let hub = Hub::new(16, 0);
+    let (mut rx, _) = hub.subscribe();
+    let (reader, mut writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+
+    let framing = Framing::Line {
+        delimiter: b'\n',
+        flush_partial: false,
+    };
+    let handle = tokio::spawn(reader_to_tx(reader, hub, DEFAULT_BUFFER_SIZE, framing)); // <- function under test
+
+    // lines split across reads
+    for part in [
+        &b"$GPGGA,1"[..],
+        b"23\n$GPRMC",
+        b",456\n$GP",
+        b"VTG,78\n",
+        b"partial",
+    ] {
+        writer.write_all(part).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    drop(writer);
+
+    assert_eq!(&rx.recv().await.unwrap()[..], b"$GPGGA,123\n");
+    assert_eq!(&rx.recv().await.unwrap()[..], b"$GPRMC,456\n");
+    assert_eq!(&rx.recv().await.unwrap()[..], b"$GPVTG,78\n");
+
+    // the trailing partial line is dropped
+    handle.await.unwrap().unwrap();
+    assert!(rx.try_recv().is_err());
+}
+
+#[test_log::test(tokio::test)]
+async fn line_framing_can_flush_the_partial_line_on_eof() {
+    let hub = Hub::new(16, 0);
+    let (mut rx, _) = hub.subscribe();
+    let (reader, mut writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+
+    let framing = Framing::Line {
+        delimiter: b';',
+        flush_partial: true,
+    };
+    let handle = tokio::spawn(reader_to_tx(reader, hub, DEFAULT_BUFFER_SIZE, framing)); // <- function under test
+
+    writer.write_all(b"one;two;thr").await.unwrap();
+    drop(writer);
+
+    handle.await.unwrap().unwrap();
+    assert_eq!(&rx.recv().await.unwrap()[..], b"one;");
+    assert_eq!(&rx.recv().await.unwrap()[..], b"two;");
+    assert_eq!(&rx.recv().await.unwrap()[..], b"thr");
+}
+
+#[test_log::test(tokio::test)]
+async fn transform_is_applied_before_broadcast() {
+    let listener_addr = "127.0.0.1:9138";
+    let remote_addr = "127.0.0.1:9139";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .transform(|input: Bytes| Bytes::from(input.to_ascii_uppercase()))
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"shout it 123").await.unwrap();
+
+    let mut received = [0u8; 12];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&received, b"SHOUT IT 123");
+}
+
+#[test_log::test(tokio::test)]
+async fn filtered_chunks_never_reach_clients() {
+    let listener_addr = "127.0.0.1:9140";
+    let remote_addr = "127.0.0.1:9141";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .framing(Framing::Line {
+            delimiter: b'\n',
+            flush_partial: false,
+        })
+        .filter(DropPrefix(Bytes::from_static(b"HEARTBEAT")))
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream
+        .write_all(b"HEARTBEAT 1\nprice 10\nHEARTBEAT 2\nprice 11\n")
+        .await
+        .unwrap();
+    drop(remote_stream);
+
+    let mut received = [0u8; 18];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&received, b"price 10\nprice 11\n");
+}
+
+#[test]
+fn hub_counts_filtered_chunks() {
+    let hub = Hub::new(16, 0).with_filter(crate::filter::SharedFilter::new(|chunk: &Bytes| {
+        chunk.len() > 1
+    }));
+    let (mut rx, _) = hub.subscribe();
+
+    hub.publish(Bytes::from_static(b"x")).unwrap(); // <- function under test
+    hub.publish(Bytes::from_static(b"kept")).unwrap();
+
+    assert_eq!(&rx.try_recv().unwrap()[..], b"kept");
+    assert_eq!(hub.metrics().chunks_filtered(), 1);
+}
+
+#[test_log::test(tokio::test)]
+async fn duplicated_frames_reach_clients_once() {
+    let listener_addr = "127.0.0.1:9219";
+    let remote_addr = "127.0.0.1:9220";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .framing(Framing::Line {
+            delimiter: b'\n',
+            flush_partial: false,
+        })
+        .dedup_window(2)
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.clone().run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"a\nb\na\nc\n").await.unwrap();
+    drop(remote_stream);
+
+    let mut received = [0u8; 6];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&received, b"a\nb\nc\n");
+    assert_eq!(broadcaster.metrics().chunks_deduplicated(), 1);
+}
+
+#[test]
+fn dedup_forgets_frames_past_the_window() {
+    let hub = Hub::new(16, 0).with_dedup(1);
+    let (mut rx, _) = hub.subscribe();
+
+    hub.publish(Bytes::from_static(b"a")).unwrap(); // <- function under test
+    hub.publish(Bytes::from_static(b"a")).unwrap();
+    hub.publish(Bytes::from_static(b"b")).unwrap();
+    hub.publish(Bytes::from_static(b"a")).unwrap();
+
+    assert_eq!(&rx.try_recv().unwrap()[..], b"a");
+    assert_eq!(&rx.try_recv().unwrap()[..], b"b");
+    assert_eq!(&rx.try_recv().unwrap()[..], b"a");
+    assert!(rx.try_recv().is_err());
+    assert_eq!(hub.metrics().chunks_deduplicated(), 1);
+}
+
+#[test]
+fn dedup_needs_framing() {
+    let result = Broadcaster::builder()
+        .local("127.0.0.1:0")
+        .remote("127.0.0.1:0")
+        .dedup_window(8)
+        .build(); // <- function under test
+
+    assert!(matches!(result, Err(BuildError::InvalidFraming(_))));
+}
+
+/// Value of the field `name` in a flat JSON record, without the quotes of a string.
+fn json_field(line: &str, name: &str) -> String {
+    let start = line.find(&format!("\"{name}\":")).unwrap() + name.len() + 3;
+    let rest = line[start..].trim_start_matches('"');
+    let end = rest.find(['"', ',', '}']).unwrap();
+    rest[..end].to_string()
+}
+
+#[test_log::test(tokio::test)]
+async fn clients_get_json_records_in_sequence() {
+    use base64::Engine;
+    use tokio::io::AsyncBufReadExt;
+
+    let listener_addr = "127.0.0.1:9223";
+    let remote_addr = "127.0.0.1:9224";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .framing(Framing::Line {
+            delimiter: b'\n',
+            flush_partial: false,
+        })
+        .output_format(OutputFormat::Json)
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"a\nbb\nccc\n").await.unwrap();
+
+    let mut lines = tokio::io::BufReader::new(client).lines();
+    let mut records = Vec::new();
+    for _ in 0..3 {
+        let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(
+            line.starts_with('{') && line.ends_with('}'),
+            "not a record: {line}"
+        );
+
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(json_field(&line, "data"))
+            .unwrap();
+        assert_eq!(
+            json_field(&line, "len").parse::<usize>().unwrap(),
+            data.len()
+        );
+        assert!(json_field(&line, "ts_ns").parse::<u128>().unwrap() > 0);
+
+        let seq: u64 = json_field(&line, "seq").parse().unwrap();
+        records.push((seq, data));
+    }
+
+    assert_eq!(
+        records,
+        vec![
+            (0, b"a\n".to_vec()),
+            (1, b"bb\n".to_vec()),
+            (2, b"ccc\n".to_vec())
+        ]
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn seq_headers_increment_by_one_per_chunk() {
+    let listener_addr = "127.0.0.1:9228";
+    let remote_addr = "127.0.0.1:9229";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .framing(Framing::Line {
+            delimiter: b'\n',
+            flush_partial: false,
+        })
+        .seq_header(true)
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"a\nb\nc\n").await.unwrap();
+
+    let mut headers = Vec::new();
+    for expected in [b"a\n", b"b\n", b"c\n"] {
+        let mut chunk = [0u8; 10];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut chunk))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let (header, data) = chunk.split_at(8);
+        headers.push(u64::from_be_bytes(header.try_into().unwrap()));
+        assert_eq!(data, expected);
+    }
+
+    assert_eq!(headers, [0, 1, 2]);
+}
+
+#[test]
+fn json_sequence_restarts_when_a_remote_connects() {
+    for (continued, expected) in [(false, "0"), (true, "2")] {
+        let hub = Hub::new(16, 0).with_json_records(continued);
+        let (mut rx, _) = hub.subscribe();
+
+        hub.publish(Bytes::from_static(b"a")).unwrap();
+        hub.publish(Bytes::from_static(b"b")).unwrap();
+        hub.restart_sequence(); // <- function under test
+        hub.publish(Bytes::from_static(b"c")).unwrap();
+
+        let records: Vec<Bytes> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        let last = std::str::from_utf8(&records[2]).unwrap();
+        assert_eq!(json_field(last, "seq"), expected);
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn nodelay_is_set_on_accepted_connections() {
+    let listener = TcpListener::bind("127.0.0.1:9142").await.unwrap();
+    let _client = TcpStream::connect("127.0.0.1:9142").await.unwrap();
+    let (accepted, _) = listener.accept().await.unwrap();
+
+    set_nodelay(&accepted, true); // <- function under test
+    assert!(accepted.nodelay().unwrap());
+
+    set_nodelay(&accepted, false);
+    assert!(!accepted.nodelay().unwrap());
+}
+
+#[test_log::test(tokio::test)]
+async fn listener_can_be_bound_again_right_away() {
+    let listener = bind_listener("127.0.0.1:9143", ListenOptions::default())
+        .await
+        .unwrap(); // <- function under test
+
+    // leave a connection behind in TIME_WAIT, closed from the listener side
+    let client = TcpStream::connect("127.0.0.1:9143").await.unwrap();
+    let (accepted, _) = listener.accept().await.unwrap();
+    drop(accepted);
+    drop(client);
+    drop(listener);
+
+    bind_listener("127.0.0.1:9143", ListenOptions::default())
+        .await
+        .unwrap(); // <- function under test
+}
+
+/// Publishes ten 4 byte chunks to a consumer that cannot take more than two of them at first and
+/// has room for two more in its queue, then lets it read whatever it got.
+async fn deliver_to_slow_reader(drop_policy: DropPolicy) -> (Vec<u8>, ClientStats) {
+    let hub = Hub::new(16, 0);
+    let cancel = CancellationToken::new();
+
+    let (mut reader, writer) = tokio::io::duplex(8);
+
+    let handle = tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions {
+            queue_size: 2,
+            drop_policy,
+            ..ClientOptions::default()
+        },
+        cancel.clone(),
+    )); // <- function under test
+
+    // give `tx_to_writer` a break to subscribe
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for i in 0..10 {
+        hub.publish(Bytes::from(format!("c{i}, "))).unwrap();
+    }
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    cancel.cancel();
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(2), reader.read_to_end(&mut received))
+        .await
+        .expect("consumer did not finish")
+        .unwrap();
+
+    (received, handle.await.unwrap())
+}
+
+#[test_log::test(tokio::test)]
+async fn full_queue_drops_the_oldest_chunks() {
+    let (received, stats) = deliver_to_slow_reader(DropPolicy::Oldest).await;
+
+    assert_eq!(received, b"c0, c1, c2, c8, c9, ");
+    assert_eq!(stats.chunks_dropped, 5);
+}
+
+#[test_log::test(tokio::test)]
+async fn full_queue_drops_the_newest_chunks() {
+    let (received, stats) = deliver_to_slow_reader(DropPolicy::Newest).await;
+
+    assert_eq!(received, b"c0, c1, c2, c3, c4, ");
+    assert_eq!(stats.chunks_dropped, 5);
+}
+
+#[test_log::test(tokio::test)]
+async fn full_queue_disconnects_the_client() {
+    let (received, stats) = deliver_to_slow_reader(DropPolicy::Disconnect).await;
+
+    assert_eq!(received, b"c0, c1, ");
+    assert_eq!(stats.bytes_sent, 8);
+    assert_eq!(stats.reason, DisconnectReason::QueueFull);
+}
+
+#[test_log::test(tokio::test)]
+async fn stalled_consumer_is_dropped_after_idle_timeout() {
+    let hub = Hub::new(64, 0);
+
+    let (mut reader, writer) = tokio::io::duplex(8);
+
+    let handle = tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions {
+            write_timeout: Duration::from_secs(10),
+            idle_timeout: Some(Duration::from_millis(300)),
+            ..ClientOptions::default()
+        },
+        CancellationToken::new(),
+    )); // <- function under test
+
+    // give `tx_to_writer` a break to subscribe
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let producer = tokio::spawn({
+        let hub = hub.clone();
+        async move {
+            for i in 0..u8::MAX {
+                let _ = hub.publish(Bytes::from(vec![i; 4]));
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+    });
+
+    // read once, then stall
+    let mut received = [0u8; 4];
+    reader.read_exact(&mut received).await.unwrap();
+
+    let stats = tokio::time::timeout(Duration::from_secs(2), handle)
+        .await
+        .expect("stalled consumer was not dropped")
+        .unwrap();
+    producer.abort();
+
+    assert!(stats.bytes_sent < 64);
+    assert_eq!(hub.metrics().clients_dropped(), 1);
+    assert_eq!(stats.reason, DisconnectReason::IdleTimeout);
+}
+
+#[test_log::test(tokio::test)]
+async fn throttled_consumer_is_flagged_slow_before_it_is_dropped() {
+    let hub = Hub::new(64, 0);
+
+    let (mut reader, writer) = tokio::io::duplex(8);
+
+    let handle = tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions {
+            write_timeout: Duration::from_secs(10),
+            idle_timeout: Some(Duration::from_millis(1500)),
+            slow_threshold: Some(32),
+            ..ClientOptions::default()
+        },
+        CancellationToken::new(),
+    )); // <- function under test
+
+    // give `tx_to_writer` a break to subscribe
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let producer = tokio::spawn({
+        let hub = hub.clone();
+        async move {
+            for i in 0..u8::MAX {
+                let _ = hub.publish(Bytes::from(vec![i; 4]));
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+    });
+
+    // reads a chunk every 100ms, five times slower than they come
+    let throttled = tokio::spawn(async move {
+        let mut received = [0u8; 4];
+        while reader.read_exact(&mut received).await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    });
+
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while hub.metrics().clients_slow() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("throttled consumer was not flagged as slow");
+    assert_eq!(hub.metrics().clients_dropped(), 0);
+
+    tokio::time::timeout(Duration::from_secs(3), handle)
+        .await
+        .expect("throttled consumer was not dropped")
+        .unwrap();
+    producer.abort();
+    throttled.abort();
+
+    assert_eq!(hub.metrics().clients_slow(), 1);
+    assert_eq!(hub.metrics().clients_dropped(), 1);
+}
+
+#[test_log::test(tokio::test(start_paused = true))]
+async fn token_bucket_refills_at_its_rate() {
+    let mut bucket = TokenBucket::new(1000); // <- type under test
+    let start = tokio::time::Instant::now();
+
+    // starts full, with a tenth of a second worth of tokens
+    assert_eq!(bucket.tokens(), 100.0);
+
+    bucket.refill(start + Duration::from_millis(50));
+    assert_eq!(bucket.tokens(), 100.0, "refilled past the burst size");
+
+    assert_eq!(
+        bucket.take(60, start + Duration::from_millis(50)),
+        Duration::ZERO
+    );
+    assert_eq!(bucket.tokens(), 40.0);
+
+    bucket.refill(start + Duration::from_millis(70));
+    assert_eq!(bucket.tokens(), 60.0);
+}
+
+#[test_log::test(tokio::test(start_paused = true))]
+async fn token_bucket_debt_is_paid_by_waiting() {
+    let mut bucket = TokenBucket::new(1000); // <- type under test
+    let start = tokio::time::Instant::now();
+
+    // 100 tokens available, the other 400 take 400ms to come
+    assert_eq!(bucket.take(500, start), Duration::from_millis(400));
+    assert_eq!(bucket.tokens(), -400.0);
+
+    // the next take adds to the debt, and waits it all out
+    bucket.acquire(100).await;
+    assert_eq!(
+        tokio::time::Instant::now() - start,
+        Duration::from_millis(500)
+    );
+}
+
+/// Reads from `reader` for `window`, returns how many bytes came in.
+async fn bytes_read_within<R: AsyncReadExt + Unpin>(reader: &mut R, window: Duration) -> usize {
+    let mut total = 0;
+    let mut buf = [0u8; 1024];
+
+    let _ = tokio::time::timeout(window, async {
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => total += n,
+            }
+        }
+    })
+    .await;
+
+    total
+}
+
+#[test_log::test(tokio::test)]
+async fn max_bandwidth_paces_the_remote() {
+    let listener_addr = "127.0.0.1:9144";
+    let remote_addr = "127.0.0.1:9145";
+    const RATE: u64 = 20_000;
+    const BUFFER_SIZE: usize = 1024;
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .buffer_size(BUFFER_SIZE)
+        .max_bandwidth(RATE)
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    tokio::spawn(async move {
+        let _ = remote_stream.write_all(&[7u8; 256 * 1024]).await;
+    });
+
+    let received = bytes_read_within(&mut client, Duration::from_secs(1)).await;
+
+    // the rate over the window, plus the burst, plus the read that goes into debt
+    assert!(
+        received <= RATE as usize + RATE as usize / 10 + BUFFER_SIZE,
+        "{received} bytes"
+    );
+    assert!(received >= RATE as usize / 2, "{received} bytes");
+}
+
+#[test_log::test(tokio::test)]
+async fn per_client_bandwidth_paces_the_writer() {
+    const RATE: u64 = 10_000;
+    const CHUNK: usize = 1000;
+
+    let hub = Hub::new(64, 0);
+    let (mut reader, writer) = tokio::io::duplex(DEFAULT_BUFFER_SIZE);
+
+    tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions {
+            rate_limit: Some(RATE),
+            ..ClientOptions::default()
+        },
+        CancellationToken::new(),
+    )); // <- function under test
+
+    // give `tx_to_writer` a break to subscribe
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for _ in 0..40 {
+        hub.publish(Bytes::from(vec![1u8; CHUNK])).unwrap();
+    }
+
+    let received = bytes_read_within(&mut reader, Duration::from_secs(1)).await;
+
+    assert!(
+        received <= RATE as usize + RATE as usize / 10 + CHUNK,
+        "{received} bytes"
+    );
+    assert!(received >= RATE as usize / 2, "{received} bytes");
+}
+
+#[test_log::test(tokio::test)]
+async fn smoothing_spreads_the_replay_over_time() {
+    const RATE: u64 = 100_000;
+    const HISTORY: usize = 20_000;
+
+    let hub = Hub::new(64, HISTORY);
+    let _ = hub.publish(Bytes::from(vec![1u8; HISTORY]));
+    let (mut reader, writer) = tokio::io::duplex(HISTORY);
+
+    tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions {
+            smooth_rate: Some(RATE),
+            ..ClientOptions::default()
+        },
+        CancellationToken::new(),
+    )); // <- function under test
+
+    // a fiftieth of a second worth at a time, not the whole history at once
+    let started = Instant::now();
+    let early = bytes_read_within(&mut reader, Duration::from_millis(50)).await;
+    assert!(early > 0, "nothing written");
+    assert!(early <= HISTORY / 2, "{early} bytes in a burst");
+
+    let mut rest = vec![0u8; HISTORY - early];
+    tokio::time::timeout(Duration::from_secs(1), reader.read_exact(&mut rest))
+        .await
+        .expect("history was not all written")
+        .unwrap();
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(150), "{elapsed:?}");
+}
+
+#[test]
+fn access_list_checks_ipv4_and_ipv6_rules() {
+    let access = AccessList {
+        allow: vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()],
+        deny: vec![
+            "10.0.66.0/24".parse().unwrap(),
+            "fd00:bad::/32".parse().unwrap(),
+        ],
+    };
+
+    assert!(access.permits("10.1.2.3".parse().unwrap())); // <- function under test
+    assert!(access.permits("fd00::1".parse().unwrap()));
+    assert!(
+        !access.permits("192.168.1.1".parse().unwrap()),
+        "not allowed"
+    );
+    assert!(
+        !access.permits("2001:db8::1".parse().unwrap()),
+        "not allowed"
+    );
+    assert!(!access.permits("10.0.66.7".parse().unwrap()), "deny wins");
+    assert!(!access.permits("fd00:bad::1".parse().unwrap()), "deny wins");
+    assert!(
+        !access.permits("::ffff:10.0.66.7".parse().unwrap()),
+        "mapped IPv4 matches the IPv4 rules"
+    );
+}
+
+#[test]
+fn empty_access_list_allows_all_but_denied() {
+    let access = AccessList {
+        deny: vec!["127.0.0.0/8".parse().unwrap()],
+        ..AccessList::default()
+    };
+
+    assert!(access.permits("192.168.1.1".parse().unwrap())); // <- function under test
+    assert!(access.permits("::1".parse().unwrap()));
+    assert!(!access.permits("127.0.0.1".parse().unwrap()));
+}
+
+#[test]
+fn builder_rejects_invalid_cidr() {
+    let result = Broadcaster::builder()
+        .local("localhost:8080")
+        .remote("localhost:8081")
+        .allow_cidr("10.0.0.0/33")
+        .build(); // <- function under test
+
+    assert!(matches!(result, Err(BuildError::InvalidCidr { .. })));
+}
+
+#[test_log::test(tokio::test)]
+async fn denied_peers_are_closed_right_away() {
+    let listener_addr = "127.0.0.1:9146";
+    let remote_addr = "127.0.0.1:9147";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .allow_cidr("127.0.0.0/8")
+        .deny_cidr("127.0.0.1/32")
+        .build()
+        .unwrap();
+    let running = tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"secret").await.unwrap();
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+        .await
+        .expect("denied peer was not closed")
+        .unwrap();
+
+    assert!(received.is_empty());
+    assert!(!running.is_finished());
+}
+
+/// Sends an admin command and reads its reply, up to the empty line ending it.
+async fn admin_command(admin: &mut tokio::io::BufReader<TcpStream>, command: &str) -> Vec<String> {
+    use tokio::io::AsyncBufReadExt;
+
+    admin
+        .get_mut()
+        .write_all(format!("{command}\n").as_bytes())
+        .await
+        .unwrap();
+
+    let mut reply = Vec::new();
+    loop {
+        let mut line = String::new();
+        admin.read_line(&mut line).await.unwrap();
+
+        match line.trim_end() {
+            "" => return reply,
+            line => reply.push(line.to_string()),
+        }
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn admin_lists_and_kicks_clients() {
+    let listener_addr = "127.0.0.1:9148";
+    let remote_addr = "127.0.0.1:9149";
+    let admin_addr = "127.0.0.1:9150";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .admin_addr(admin_addr)
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    let client_addr = client.local_addr().unwrap().to_string();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"hello").await.unwrap();
+    let mut received = [0u8; 5];
+    client.read_exact(&mut received).await.unwrap();
+
+    let mut admin = tokio::io::BufReader::new(TcpStream::connect(admin_addr).await.unwrap());
+
+    let list = admin_command(&mut admin, "list").await;
+    assert_eq!(list.len(), 1, "{list:?}");
+    assert!(list[0].starts_with(&format!("{client_addr} bytes_sent=5 ")));
+
+    let kick = admin_command(&mut admin, &format!("kick {client_addr}")).await;
+    assert_eq!(kick, [format!("kicked 1 client(s) at {client_addr}")]);
+
+    let mut rest = Vec::new();
+    tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut rest))
+        .await
+        .expect("kicked client was not disconnected")
+        .unwrap();
+
+    assert!(admin_command(&mut admin, "list").await.is_empty());
+    assert!(admin_command(&mut admin, "stats")
+        .await
+        .contains(&"clients_connected 0".to_string()));
+    assert!(admin_command(&mut admin, "dance").await[0].starts_with("error: unknown command"));
+}
+
+#[test_log::test(tokio::test)]
+async fn admin_pauses_and_resumes_the_broadcast() {
+    let listener_addr = "127.0.0.1:9225";
+    let remote_addr = "127.0.0.1:9226";
+    let admin_addr = "127.0.0.1:9227";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .admin_addr(admin_addr)
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut admin = tokio::io::BufReader::new(TcpStream::connect(admin_addr).await.unwrap());
+
+    assert_eq!(admin_command(&mut admin, "pause").await, ["paused"]);
+    assert_eq!(
+        admin_command(&mut admin, "pause").await,
+        ["error: already paused"]
+    );
+    assert!(admin_command(&mut admin, "stats")
+        .await
+        .contains(&"paused 1".to_string()));
+
+    remote_stream.write_all(b"during").await.unwrap();
+    let mut received = [0u8; 5];
+    let read =
+        tokio::time::timeout(Duration::from_millis(300), client.read_exact(&mut received)).await;
+    assert!(read.is_err(), "client got data while paused");
+
+    assert_eq!(admin_command(&mut admin, "resume").await, ["resumed"]);
+    remote_stream.write_all(b"after").await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received, b"after");
+}
+
+#[test_log::test(tokio::test)]
+async fn held_remotes_are_read_once_resumed() {
+    let hub = Hub::new(16, 0).with_pause_mode(PauseMode::Hold);
+    let (mut rx, _) = hub.subscribe();
+    let (mut remote, reader) = tokio::io::duplex(64);
+
+    assert!(hub.pause());
+    let reading = tokio::spawn(reader_to_tx(reader, hub.clone(), 64, Framing::Raw)); // <- function under test
+
+    remote.write_all(b"held").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(rx.try_recv().is_err(), "read from the remote while paused");
+
+    assert!(hub.resume());
+    let received = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received[..], b"held");
+
+    drop(remote);
+    reading.await.unwrap().unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn bidirectional_relays_client_data_to_the_remote() {
+    let listener_addr = "127.0.0.1:9151";
+    let remote_addr = "127.0.0.1:9152";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .bidirectional(true)
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut first = TcpStream::connect(listener_addr).await.unwrap();
+    let mut second = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    first.write_all(b"ping").await.unwrap();
+
+    let mut received = [0u8; 4];
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        remote_stream.read_exact(&mut received),
+    )
+    .await
+    .expect("remote did not get the client data")
+    .unwrap();
+    assert_eq!(&received, b"ping");
+
+    // data from the remote still reaches everyone, the sender included
+    remote_stream.write_all(b"pong").await.unwrap();
+
+    for client in [&mut first, &mut second] {
+        let mut received = [0u8; 4];
+        client.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"pong");
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn file_remote_is_broadcast_in_order() {
+    let listener_addr = "127.0.0.1:9153";
+    let contents: String = (0..40).map(|i| format!("line {i:02}\n")).collect();
+    let path = temp_file("source.txt", &contents);
+
+    // paced so it is still going when the client connects, which catches up from the replay
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote_proto(RemoteProto::File)
+        .remote(path.to_str().unwrap())
+        .buffer_size(64)
+        .file_pace(Duration::from_millis(50))
+        .replay_bytes(contents.len())
+        .build()
+        .unwrap();
+    let running = tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+        .await
+        .expect("broadcaster did not stop at the end of the file")
+        .unwrap();
+
+    assert_eq!(String::from_utf8(received).unwrap(), contents);
+    assert!(running.await.unwrap().is_ok());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn file_remote_can_loop() {
+    let hub = Hub::new(16, 0);
+    let (mut rx, _) = hub.subscribe();
+
+    let path = temp_file("loop.txt", "abc");
+    let remote = Remote::File(path.clone());
+    let config = Config {
+        file_loop: true,
+        file_pace: Duration::from_millis(5),
+        ..Config::new("127.0.0.1:0", remote)
+    };
+
+    let producer = tokio::spawn({
+        let hub = hub.clone();
+        async move {
+            let cancel = CancellationToken::new();
+            producer::remotes_to_tx(&config, hub, &cancel).await // <- function under test
+        }
+    });
+
+    let mut received = Vec::new();
+    while received.len() < 9 {
+        received.extend_from_slice(&rx.recv().await.unwrap());
+    }
+    producer.abort();
+
+    assert_eq!(&received[..9], b"abcabcabc");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn tee_file_matches_what_clients_get() {
+    let listener_addr = "127.0.0.1:9154";
+    let remote_addr = "127.0.0.1:9155";
+    let tee_path = std::env::temp_dir().join(format!("tcp-broadcast-{}-tee", std::process::id()));
+    let _ = std::fs::remove_file(&tee_path);
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .tee_file(&tee_path)
+        .filter(|chunk: &Bytes| !chunk.starts_with(b"#"))
+        .framing(Framing::Line {
+            delimiter: b'\n',
+            flush_partial: false,
+        })
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream
+        .write_all(b"one\n# left out\ntwo\nthree\n")
+        .await
+        .unwrap();
+
+    let mut received = [0u8; 14];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+
+    assert_eq!(&received, b"one\ntwo\nthree\n");
+    assert_eq!(std::fs::read(&tee_path).unwrap(), received);
+
+    std::fs::remove_file(tee_path).unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn capture_file_has_a_record_per_chunk() {
+    use base64::Engine;
+
+    let listener_addr = "127.0.0.1:9203";
+    let remote_addr = "127.0.0.1:9204";
+    let capture_path =
+        std::env::temp_dir().join(format!("tcp-broadcast-{}-capture", std::process::id()));
+    let _ = std::fs::remove_file(&capture_path);
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .capture_file(&capture_path, CaptureFormat::Jsonl)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let chunks: [&[u8]; 3] = [b"one", b"two\n", b"three"];
+    for chunk in chunks {
+        remote_stream.write_all(chunk).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let mut received = [0u8; 12];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+
+    let capture = std::fs::read_to_string(&capture_path).unwrap();
+    let records: Vec<(u128, Vec<u8>)> = capture
+        .lines()
+        .map(|line| {
+            let field = |name: &str| {
+                let start = line.find(&format!("\"{name}\":")).unwrap() + name.len() + 3;
+                let rest = line[start..].trim_start_matches('"');
+                let end = rest.find(['"', ',', '}']).unwrap();
+                rest[..end].to_string()
+            };
+            let data = base64::engine::general_purpose::STANDARD
+                .decode(field("data"))
+                .unwrap();
+            assert_eq!(field("len").parse::<usize>().unwrap(), data.len());
+            (field("ts_ns").parse().unwrap(), data)
+        })
+        .collect();
+
+    let data: Vec<&[u8]> = records.iter().map(|(_, data)| &data[..]).collect();
+    assert_eq!(data, chunks);
+    assert!(records.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert_eq!(&received, b"onetwo\nthree");
+
+    std::fs::remove_file(capture_path).unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn clients_on_every_listener_get_the_same_chunks() {
+    let listener_addr = "127.0.0.1:9205";
+    let other_addr = "127.0.0.1:9206";
+    let remote_addr = "127.0.0.1:9207";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .listener(LocalListener::new(other_addr))
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    let mut other = TcpStream::connect(other_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"same chunk").await.unwrap();
+
+    for stream in [&mut client, &mut other] {
+        let mut received = [0u8; 10];
+        tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&received, b"same chunk");
+    }
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+}
+
+#[test]
+fn listener_settings_parse() {
+    let listener: LocalListener = "127.0.0.1:9000,cert_file=c.pem,key_file=k.pem,auth_token=t"
+        .parse()
+        .unwrap(); // <- function under test
+
+    assert_eq!(listener.address, "127.0.0.1:9000");
+    assert_eq!(
+        listener.tls,
+        Some(LocalTls {
+            cert_file: "c.pem".into(),
+            key_file: "k.pem".into(),
+        })
+    );
+    assert_eq!(listener.auth_token.as_deref(), Some("t"));
+
+    // as a table in the config file, with the keys sorted
+    let listener: LocalListener = "address=127.0.0.1:9000".parse().unwrap();
+    assert_eq!(listener, LocalListener::new("127.0.0.1:9000"));
+
+    assert!("127.0.0.1:9000,cert_file=c.pem"
+        .parse::<LocalListener>()
+        .is_err());
+    assert!("127.0.0.1:9000,port=1".parse::<LocalListener>().is_err());
+}
+
+#[test_log::test(tokio::test)]
+async fn check_passes_and_leaves_nothing_bound() {
+    let listener_addr = "127.0.0.1:9208";
+    let remote_addr = "127.0.0.1:9209";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+
+    broadcaster.check().await.unwrap(); // <- function under test
+
+    // the remote got a connection, and the local address is free again
+    tokio::time::timeout(Duration::from_secs(1), remote.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    TcpListener::bind(listener_addr).await.unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn check_fails_on_an_unresolvable_remote() {
+    let broadcaster = Broadcaster::builder()
+        .local("127.0.0.1:9210")
+        .remote("nowhere.invalid:9000")
+        .build()
+        .unwrap();
+
+    let result = broadcaster.check().await; // <- function under test
+
+    let error = result.unwrap_err();
+    assert!(matches!(error, BroadcastError::Resolve { .. }));
+    assert!(error
+        .to_string()
+        .contains("failed to resolve nowhere.invalid:9000"));
+    TcpListener::bind("127.0.0.1:9210").await.unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn clients_read_the_banner_first() {
+    let listener_addr = "127.0.0.1:9211";
+    let remote_addr = "127.0.0.1:9212";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .banner(&b"HELLO v1\n"[..])
+        .replay_bytes(64)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    // broadcast before the client connects, so it is in the replay history
+    remote_stream.write_all(b"old ").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    remote_stream.write_all(b"new").await.unwrap();
+
+    let mut received = [0u8; 16];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+
+    assert_eq!(&received, b"HELLO v1\nold new");
+}
+
+#[test_log::test(tokio::test)]
+async fn getters_follow_the_running_broadcast() {
+    let listener_addr = "127.0.0.1:9213";
+    let remote_addr = "127.0.0.1:9214";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+    assert_eq!(broadcaster.client_count(), 0);
+    assert!(!broadcaster.remote_connected());
+
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.clone().run(cancel.clone()));
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"0123456789").await.unwrap();
+
+    let mut received = [0u8; 10];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+
+    // <- functions under test
+    assert_eq!(broadcaster.client_count(), 1);
+    assert!(broadcaster.remote_connected());
+    assert_eq!(broadcaster.bytes_received(), 10);
+    assert_eq!(broadcaster.bytes_sent(), 10);
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+
+    assert!(!broadcaster.remote_connected());
+    assert_eq!(broadcaster.client_count(), 0);
+}
+
+#[test_log::test(tokio::test)]
+async fn round_robin_alternates_chunks_between_clients() {
+    let listener_addr = "127.0.0.1:9215";
+    let remote_addr = "127.0.0.1:9216";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .distribution(Distribution::RoundRobin)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    set_nodelay(&remote_stream, true);
+    let mut first = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut second = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for chunk in [b"a1", b"b1", b"a2", b"b2"] {
+        remote_stream.write_all(chunk).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    for (client, expected) in [(&mut first, b"a1a2"), (&mut second, b"b1b2")] {
+        let mut received = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&received, expected);
+    }
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn stdout_gets_the_broadcast_and_survives_a_closed_pipe() {
+    let hub = Hub::new(16, 0);
+    // stands in for stdout, the end of a pipeline
+    let (mut pipe, writer) = tokio::io::duplex(64);
+
+    let tee = tee::Tee::new(writer, "stdout", &hub);
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(tee.run(cancel.clone())); // <- function under test
+
+    hub.publish(Bytes::from_static(b"one ")).unwrap();
+    hub.publish(Bytes::from_static(b"two")).unwrap();
+
+    // flushed as soon as it caught up, without waiting for the interval
+    let mut received = [0u8; 7];
+    tokio::time::timeout(Duration::from_millis(500), pipe.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received, b"one two");
+
+    // the reader going away does not stop the broadcast, or the copy
+    drop(pipe);
+    hub.publish(Bytes::from_static(b"three")).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!running.is_finished());
+
+    cancel.cancel();
+    tokio::time::timeout(Duration::from_secs(1), running)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn websocket_clients_get_binary_messages() {
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let listener_addr = "127.0.0.1:9156";
+    let remote_addr = "127.0.0.1:9157";
+    let ws_addr = "127.0.0.1:9158";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .ws_addr(ws_addr)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{ws_addr}"))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"hello").await.unwrap();
+
+    let message = tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(message, Message::binary(&b"hello"[..]));
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn sse_clients_get_data_events() {
+    let listener_addr = "127.0.0.1:9159";
+    let remote_addr = "127.0.0.1:9160";
+    let sse_addr = "127.0.0.1:9161";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .sse_addr(sse_addr)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let mut client = TcpStream::connect(sse_addr).await.unwrap();
+    client
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\n\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"hello").await.unwrap();
+
+    let expected = "data: aGVsbG8=\n\n";
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        let mut buf = [0u8; 1024];
+        while !String::from_utf8_lossy(&response).contains(expected) {
+            let n = client.read(&mut buf).await.unwrap();
+            assert_ne!(n, 0, "connection closed before the event");
+            response.extend_from_slice(&buf[..n]);
+        }
+    })
+    .await
+    .unwrap();
+
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/event-stream\r\n"));
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+}
+
+async fn receive_compressed(compression: Compression, listener_addr: &str, remote_addr: &str) {
+    use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+    use tokio::io::{AsyncRead, BufReader};
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .compression(compression)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let client = BufReader::new(TcpStream::connect(listener_addr).await.unwrap());
+    let mut client: std::pin::Pin<Box<dyn AsyncRead + Send>> = match compression {
+        Compression::Gzip => Box::pin(GzipDecoder::new(client)),
+        Compression::Zstd => Box::pin(ZstdDecoder::new(client)),
+    };
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"hello ").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    remote_stream.write_all(b"world").await.unwrap();
+
+    // each chunk is flushed, so it can be decompressed while the stream is still going
+    let mut received = [0u8; 11];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received, b"hello world");
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+
+    // and the stream is ended properly on shutdown
+    let mut rest = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut rest))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test_log::test(tokio::test)]
+async fn gzip_clients_recover_the_stream() {
+    receive_compressed(Compression::Gzip, "127.0.0.1:9162", "127.0.0.1:9163").await;
+}
+
+#[test_log::test(tokio::test)]
+async fn zstd_clients_recover_the_stream() {
+    receive_compressed(Compression::Zstd, "127.0.0.1:9164", "127.0.0.1:9165").await;
+}
+
+/// Connects a client that sends `token`, if any, and returns what it gets once "hello" is
+/// broadcast, empty if it was disconnected.
+async fn authenticate_with(
+    token: Option<&[u8]>,
+    listener_addr: &str,
+    remote_addr: &str,
+) -> Vec<u8> {
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .auth_token("secret")
+        .auth_timeout(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    if let Some(token) = token {
+        client.write_all(token).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    remote_stream.write_all(b"hello").await.unwrap();
+
+    let mut received = Vec::new();
+    let mut buf = [0u8; 5];
+    let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap_or(0);
+    received.extend_from_slice(&buf[..n]);
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+
+    received
+}
+
+#[test_log::test(tokio::test)]
+async fn clients_with_the_token_join() {
+    let received = authenticate_with(Some(b"secret\n"), "127.0.0.1:9166", "127.0.0.1:9167").await;
+
+    assert_eq!(received, b"hello");
+}
+
+#[test_log::test(tokio::test)]
+async fn clients_with_a_wrong_token_are_disconnected() {
+    let received = authenticate_with(Some(b"secrets\n"), "127.0.0.1:9168", "127.0.0.1:9169").await;
+
+    assert!(received.is_empty());
+}
+
+#[test_log::test(tokio::test)]
+async fn clients_without_a_token_are_disconnected() {
+    let received = authenticate_with(None, "127.0.0.1:9170", "127.0.0.1:9171").await;
+
+    assert!(received.is_empty());
+}
+
+#[cfg(unix)]
+#[test_log::test(tokio::test)]
+async fn unix_socket_clients_get_the_broadcast() {
+    let remote_addr = "127.0.0.1:9172";
+    let path = std::env::temp_dir().join(format!("tcp-broadcast-{}.sock", std::process::id()));
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local_uds(&path)
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"hello").await.unwrap();
+
+    let mut received = [0u8; 5];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received, b"hello");
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[test_log::test(tokio::test)]
+async fn clients_are_handed_over_to_the_next_broadcaster() {
+    let listener_addr = "127.0.0.1:9221";
+    let remote_addr = "127.0.0.1:9222";
+    let path = std::env::temp_dir().join(format!(
+        "tcp-broadcast-{}-handover.sock",
+        std::process::id()
+    ));
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+    let builder = || {
+        Broadcaster::builder()
+            .local(listener_addr)
+            .remote(remote_addr)
+            .handover_socket(&path)
+            .build()
+            .unwrap()
+    };
+
+    let first = builder();
+    let first_running = tokio::spawn(first.clone().run(CancellationToken::new()));
+    let (mut first_remote, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    first_remote.write_all(b"before\n").await.unwrap();
+
+    let mut received = [0u8; 7];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received, b"before\n");
+
+    // the second one waits at the handover socket until the first is asked to hand over
+    let second = builder();
+    let cancel = CancellationToken::new();
+    let second_running = tokio::spawn(second.clone().run(cancel.clone()));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    first.hand_over(); // <- function under test
+    tokio::time::timeout(Duration::from_secs(5), first_running)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+
+    let (mut second_remote, _) = remote.accept().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(second.client_count(), 1);
+
+    second_remote.write_all(b"after\n").await.unwrap();
+
+    let mut received = [0u8; 6];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received, b"after\n");
+
+    // and it waits for a successor of its own
+    assert!(path.exists());
+
+    cancel.cancel();
+    second_running.await.unwrap().unwrap();
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn handover_needs_plain_consumers() {
+    let result = Broadcaster::builder()
+        .local("127.0.0.1:0")
+        .remote("127.0.0.1:0")
+        .handover_socket("/tmp/unused.sock")
+        .compression(Compression::Gzip)
+        .build(); // <- function under test
+
+    assert!(matches!(result, Err(BuildError::InvalidHandover(_))));
+}
+
+#[test_log::test(tokio::test)]
+async fn ipv6_clients_get_the_broadcast() {
+    let listener_addr = "[::1]:9173";
+    let remote_addr = "[::1]:9174";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, remote_peer) = remote.accept().await.unwrap();
+    assert!(remote_peer.is_ipv6());
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"hello").await.unwrap();
+
+    let mut received = [0u8; 5];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received, b"hello");
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn dual_stack_listeners_accept_ipv4_peers() {
+    let options = ListenOptions {
+        dual_stack: true,
+        ..ListenOptions::default()
+    };
+    let listener = bind_listener("[::]:9175", options).await.unwrap(); // <- function under test
+
+    let client = TcpStream::connect("127.0.0.1:9175").await.unwrap();
+    let (_, peer) = listener.accept().await.unwrap();
+    assert_eq!(peer.ip().to_canonical(), client.local_addr().unwrap().ip());
+
+    let listener = bind_listener("[::]:9176", ListenOptions::default())
+        .await
+        .unwrap(); // <- function under test
+
+    assert!(TcpStream::connect("127.0.0.1:9176").await.is_err());
+    drop(listener);
+}
+
+#[test_log::test(tokio::test)]
+async fn keepalive_is_set_on_accepted_clients() {
+    let listener = TcpListener::bind("127.0.0.1:9177").await.unwrap();
+    let _client = TcpStream::connect("127.0.0.1:9177").await.unwrap();
+    let (accepted, _) = listener.accept().await.unwrap();
+
+    let config = Config {
+        keepalive: Some(Keepalive {
+            idle: Duration::from_secs(7),
+            interval: Duration::from_secs(2),
+            retries: 4,
+        }),
+        ..Config::default()
+    };
+    <TcpListener as Accept>::configure(&accepted, &config); // <- function under test
+
+    let socket = socket2::SockRef::from(&accepted);
+    assert!(socket.keepalive().unwrap());
+    assert_eq!(socket.tcp_keepalive_time().unwrap(), Duration::from_secs(7));
+    assert_eq!(
+        socket.tcp_keepalive_interval().unwrap(),
+        Duration::from_secs(2)
+    );
+    assert_eq!(socket.tcp_keepalive_retries().unwrap(), 4);
+}
+
+#[test_log::test(tokio::test)]
+async fn keepalive_is_set_on_the_remote_connection() {
+    let listener = TcpListener::bind("127.0.0.1:9217").await.unwrap();
+    let remote = TcpStream::connect("127.0.0.1:9217").await.unwrap();
+    let _accepted = listener.accept().await.unwrap();
+
+    let config = Config {
+        remote_keepalive: Some(Keepalive {
+            idle: Duration::from_secs(9),
+            interval: Duration::from_secs(3),
+            retries: 5,
+        }),
+        ..Config::default()
+    };
+    producer::configure_remote(&remote, &config); // <- function under test
+
+    let socket = socket2::SockRef::from(&remote);
+    assert!(socket.keepalive().unwrap());
+    assert_eq!(socket.tcp_keepalive_time().unwrap(), Duration::from_secs(9));
+    assert_eq!(
+        socket.tcp_keepalive_interval().unwrap(),
+        Duration::from_secs(3)
+    );
+    assert_eq!(socket.tcp_keepalive_retries().unwrap(), 5);
+}
+
+#[test_log::test(tokio::test)]
+async fn accepts_stay_under_the_configured_rate() {
+    let listener_addr = "127.0.0.1:9178";
+    let listener = TcpListener::bind(listener_addr).await.unwrap();
+
+    let hub = Hub::new(16, 0);
+    let config = Config {
+        max_accepts_per_sec: Some(20),
+        accept_overflow: AcceptOverflow::Close,
+        ..Config::default()
+    };
+    let cancel = CancellationToken::new();
+    let serving = tokio::spawn({
+        let hub = hub.clone();
+        let cancel = cancel.clone();
+        async move { tx_to_streams(listener, hub, &config, None, cancel).await }
+        // <- function under test
+    });
+
+    let started = Instant::now();
+    let mut clients = Vec::new();
+    for _ in 0..50 {
+        clients.push(TcpStream::connect(listener_addr).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    // a burst of 2, then 20 per second
+    let admitted = hub.connected().len();
+    assert!(admitted >= 2, "only {admitted} admitted");
+    assert!(
+        admitted as f64 <= 3.0 + 20.0 * elapsed,
+        "{admitted} admitted in {elapsed}s"
+    );
+
+    cancel.cancel();
+    serving.await.unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn bind_is_retried_until_the_port_is_released() {
+    let listener_addr = "127.0.0.1:9179";
+    let remote_addr = "127.0.0.1:9180";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+    let holder = TcpListener::bind(listener_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .bind_retries(20)
+        .backoff(Backoff {
+            initial: Duration::from_millis(50),
+            max: Duration::from_millis(50),
+            ..Backoff::default()
+        })
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let running = tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!running.is_finished());
+    drop(holder);
+
+    let (mut remote_stream, _) = tokio::time::timeout(Duration::from_secs(5), remote.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"hello").await.unwrap();
+
+    let mut received = [0u8; 5];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received, b"hello");
+
+    cancel.cancel();
+    running.await.unwrap().unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn bind_gives_up_after_the_retries() {
+    let holder = TcpListener::bind("127.0.0.1:9181").await.unwrap();
+
+    let backoff = Backoff {
+        initial: Duration::from_millis(10),
+        ..Backoff::default()
+    };
+    let result = bind_with_backoff(
+        "127.0.0.1:9181",
+        ListenOptions::default(),
+        &backoff,
+        2,
+        &CancellationToken::new(),
+    )
+    .await; // <- function under test
+
+    assert!(matches!(result, Err(BroadcastError::Bind { .. })));
+    drop(holder);
+}
+
+#[cfg(unix)]
+#[test_log::test(tokio::test)]
+async fn inherited_listener_is_used() {
+    use std::os::unix::io::IntoRawFd;
+
+    // stands for the socket systemd would pass
+    let passed = std::net::TcpListener::bind("127.0.0.1:9182").unwrap();
+    let fd = passed.into_raw_fd();
+    let pid = std::process::id().to_string();
+
+    let listener = net::inherited_listener(Some(&pid), Some("1"), fd) // <- function under test
+        .unwrap()
+        .unwrap();
+    assert_eq!(listener.local_addr().unwrap().port(), 9182);
+
+    let mut client = TcpStream::connect("127.0.0.1:9182").await.unwrap();
+    let (mut accepted, _) = listener.accept().await.unwrap();
+    accepted.write_all(b"hello").await.unwrap();
+
+    let mut received = [0u8; 5];
+    client.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"hello");
+}
+
+#[cfg(unix)]
+#[test]
+fn listeners_passed_elsewhere_are_ignored() {
+    let other_pid = (std::process::id() + 1).to_string();
+
+    assert!(
+        net::inherited_listener(Some(&other_pid), Some("1"), -1) // <- function under test
+            .unwrap()
+            .is_none()
+    );
+    assert!(net::inherited_listener(None, None, -1).unwrap().is_none());
+    assert!(net::inherited_listener(None, Some("0"), -1)
+        .unwrap()
+        .is_none());
+    assert!(net::inherited_listener(None, Some("x"), -1).is_err());
+}
+
+/// Stand in for the arguments of the binary, which is not part of the library.
+#[derive(clap::Parser, Debug)]
+struct LayeredArgs {
+    #[arg(long)]
+    config: Option<std::path::PathBuf>,
+    #[arg(long, default_value_t = 1)]
+    a: u32,
+    #[arg(long, default_value_t = 1)]
+    b: u32,
+    #[arg(long, default_value_t = 1)]
+    c: u32,
+    #[arg(long)]
+    verbose: bool,
+    #[arg(long)]
+    allow: Vec<String>,
+    #[arg(long, env = "LAYERED_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+}
+
+fn config_file(name: &str, contents: &str) -> std::path::PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("tcp-broadcast-{}-{name}.toml", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn command_line_overrides_config_file_overrides_defaults() {
+    use clap::{CommandFactory, FromArgMatches};
+
+    let path = config_file(
+        "precedence",
+        "a = 2\nb = 2\nverbose = true\nallow = [\"x\", \"y\"]\n",
+    );
+    let command = LayeredArgs::command();
+    let args = ["bin", "--config", path.to_str().unwrap(), "--a", "3"]
+        .map(std::ffi::OsString::from)
+        .to_vec();
+
+    let layered = layer_config_file(&command, "config", args).unwrap(); // <- function under test
+    let matches = command.clone().try_get_matches_from(&layered.args).unwrap();
+    let parsed = LayeredArgs::from_arg_matches(&matches).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!((parsed.a, parsed.b, parsed.c), (3, 2, 1));
+    assert!(parsed.verbose);
+    assert_eq!(parsed.allow, ["x", "y"]);
+
+    let sources = value_sources(&command, &matches, &layered);
+    let source = |id: &str| sources.iter().find(|(i, _, _)| i == id).unwrap().2;
+    assert_eq!(source("a"), Source::CommandLine);
+    assert_eq!(source("b"), Source::ConfigFile);
+    assert_eq!(source("c"), Source::Default);
+    assert_eq!(source("allow"), Source::ConfigFile);
+}
+
+#[test]
+fn config_file_rejects_unknown_keys() {
+    use clap::CommandFactory;
+
+    let path = config_file("unknown", "a = 2\nnope = 1\n");
+    let args = ["bin", "--config", path.to_str().unwrap()]
+        .map(std::ffi::OsString::from)
+        .to_vec();
+
+    let layered = layer_config_file(&LayeredArgs::command(), "config", args); // <- function under test
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(layered, Err(ConfigFileError::UnknownKey(key)) if key == "nope"));
+}
+
+#[test]
+fn no_config_file_leaves_arguments_alone() {
+    use clap::CommandFactory;
+
+    let args = ["bin", "--a", "3"].map(std::ffi::OsString::from).to_vec();
+
+    let layered = layer_config_file(&LayeredArgs::command(), "config", args.clone()).unwrap(); // <- function under test
+
+    assert_eq!(layered.args, args);
+    assert!(layered.from_file.is_empty());
+}
+
+#[test]
+fn effective_configuration_is_json_with_secrets_redacted() {
+    use clap::CommandFactory;
+
+    let command = LayeredArgs::command();
+    let args = ["bin", "--a", "3", "--allow", "x", "--token", "s3cret"]
+        .map(std::ffi::OsString::from)
+        .to_vec();
+    let layered = layer_config_file(&command, "config", args).unwrap();
+    let matches = command.clone().try_get_matches_from(&layered.args).unwrap();
+
+    let json = effective_json(&command, &matches, &layered); // <- function under test
+
+    assert_eq!(
+        json,
+        concat!(
+            r#"{"a":{"value":"3","source":"command line"},"#,
+            r#""b":{"value":"1","source":"default"},"#,
+            r#""c":{"value":"1","source":"default"},"#,
+            r#""verbose":{"value":"false","source":"default"},"#,
+            r#""allow":{"value":["x"],"source":"command line"},"#,
+            r#""token":{"value":"<redacted>","source":"command line"}}"#
+        )
+    );
+    assert!(!json.contains("s3cret"));
+}
+
+async fn next_event(events: &mut tokio::sync::broadcast::Receiver<Event>) -> Event {
+    tokio::time::timeout(Duration::from_secs(2), events.recv())
+        .await
+        .expect("no event")
+        .unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn events_follow_the_lifecycle_of_remotes_and_consumers() {
+    let listener_addr = "127.0.0.1:9183";
+    let remote_addr = "127.0.0.1:9184";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.reconnect = false;
+    let broadcaster = Broadcaster::new(config);
+    let mut events = broadcaster.events(); // <- function under test
+    let handle = tokio::spawn(broadcaster.run(CancellationToken::new()));
+
+    let addr = listener_addr.parse().unwrap();
+    assert_eq!(next_event(&mut events).await, Event::Listening { addr });
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let remote = Remote::Tcp(remote_addr.to_string());
+    assert_eq!(
+        next_event(&mut events).await,
+        Event::RemoteConnected {
+            remote: remote.clone()
+        }
+    );
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    let addr = client.local_addr().unwrap();
+    assert_eq!(
+        next_event(&mut events).await,
+        Event::ClientConnected { addr }
+    );
+
+    remote_stream.write_all(b"hello").await.unwrap();
+    assert_eq!(
+        next_event(&mut events).await,
+        Event::ChunkBroadcast { len: 5, clients: 1 }
+    );
+
+    drop(remote_stream);
+    assert_eq!(
+        next_event(&mut events).await,
+        Event::RemoteDisconnected { remote }
+    );
+
+    let mut received = Vec::new();
+    client.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, b"hello");
+    assert_eq!(
+        next_event(&mut events).await,
+        Event::ClientDisconnected {
+            addr,
+            bytes_sent: 5
+        }
+    );
+
+    handle.await.unwrap().unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn stalled_remote_is_reconnected_after_the_read_timeout() {
+    let listener_addr = "127.0.0.1:9185";
+    let remote_addr = "127.0.0.1:9186";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.remote_read_timeout = Some(Duration::from_millis(500));
+    config.backoff.initial = Duration::from_millis(10);
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    // accepted and never written to, nor closed
+    let (_silent, _) = remote.accept().await.unwrap();
+
+    let (mut second, _) = tokio::time::timeout(Duration::from_secs(2), remote.accept())
+        .await
+        .expect("stalled remote was not reconnected")
+        .unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    second.write_all(b"back").await.unwrap();
+
+    let mut received = [0u8; 4];
+    client.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"back");
+}
+
+#[test_log::test(tokio::test)]
+async fn listening_on_port_zero_reports_the_port_picked() {
+    let remote = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let remote_addr = remote.local_addr().unwrap().to_string();
+
+    let broadcaster = Broadcaster::new(Config::new("127.0.0.1:0", Remote::Tcp(remote_addr)));
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let Event::Listening { addr } = next_event(&mut events).await else {
+        panic!("not listening first");
+    };
+    assert_ne!(addr.port(), 0);
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    remote_stream.write_all(b"picked").await.unwrap();
+
+    let mut received = [0u8; 6];
+    client.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"picked");
+}
+
+#[test_log::test(tokio::test)]
+async fn framed_clients_get_the_sentinel_last_on_shutdown() {
+    let listener_addr = "127.0.0.1:9187";
+    let remote_addr = "127.0.0.1:9188";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+    let cancel = CancellationToken::new();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.framing = Framing::LengthPrefixed {
+        width: 2,
+        endian: Endian::Big,
+    };
+    config.shutdown_sentinel = Some(Bytes::new());
+    let handle = tokio::spawn(Broadcaster::new(config).run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"\x00\x03abc").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    cancel.cancel();
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+        .await
+        .expect("client was not closed")
+        .unwrap();
+
+    // the zero-length frame right before EOF
+    assert_eq!(received, b"\x00\x03abc\x00\x00");
+    handle.await.unwrap().unwrap();
+}
+
+#[test]
+fn sentinels_are_framed_like_messages() {
+    let line = Framing::Line {
+        delimiter: b'\n',
+        flush_partial: false,
+    };
+    let length = Framing::LengthPrefixed {
+        width: 4,
+        endian: Endian::Little,
+    };
+
+    assert_eq!(&line.frame(b"EOF")[..], b"EOF\n"); // <- function under test
+    assert_eq!(&length.frame(b"EOF")[..], b"\x03\x00\x00\x00EOF"); // <- function under test
+}
+
+#[test]
+fn builder_rejects_sentinels_without_framing() {
+    let result = Broadcaster::builder()
+        .local("0.0.0.0:8080")
+        .remote("feed:9092")
+        .shutdown_sentinel("EOF")
+        .build(); // <- function under test
+
+    assert!(matches!(result, Err(BuildError::InvalidFraming(_))));
+}
+
+/// Minimal SOCKS5 proxy with username and password authentication, for a single connection to
+/// an IPv4 target. Returns the credentials and target it was given.
+async fn socks5_stub(proxy: TcpListener) -> (Vec<u8>, Vec<u8>, SocketAddr) {
+    let (mut client, _) = proxy.accept().await.unwrap();
+
+    let mut greeting = [0u8; 2];
+    client.read_exact(&mut greeting).await.unwrap();
+    let mut methods = vec![0u8; greeting[1] as usize];
+    client.read_exact(&mut methods).await.unwrap();
+    assert!(methods.contains(&2), "no username and password method");
+    client.write_all(&[5, 2]).await.unwrap();
+
+    let field = |len: u8| vec![0u8; len as usize];
+    let _version = client.read_u8().await.unwrap();
+    let mut username = field(client.read_u8().await.unwrap());
+    client.read_exact(&mut username).await.unwrap();
+    let mut password = field(client.read_u8().await.unwrap());
+    client.read_exact(&mut password).await.unwrap();
+    client.write_all(&[1, 0]).await.unwrap();
+
+    let mut request = [0u8; 10];
+    client.read_exact(&mut request).await.unwrap();
+    assert_eq!(
+        request[..4],
+        [5, 1, 0, 1],
+        "not a connect to an IPv4 address"
+    );
+    let ip: [u8; 4] = request[4..8].try_into().unwrap();
+    let port = u16::from_be_bytes([request[8], request[9]]);
+    let target = SocketAddr::from((ip, port));
+
+    let mut upstream = TcpStream::connect(target).await.unwrap();
+    client
+        .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+        .await
+        .unwrap();
+
+    tokio::spawn(async move {
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+    });
+
+    (username, password, target)
+}
+
+#[test_log::test(tokio::test)]
+async fn remote_is_reached_through_the_socks5_proxy() {
+    let listener_addr = "127.0.0.1:9189";
+    let remote_addr = "127.0.0.1:9190";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+    // on a port of its own, the backoff tests take the fixed ones nearby
+    let proxy_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = proxy_listener.local_addr().unwrap();
+    let proxy = socks5_stub(proxy_listener);
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.remote_socks5 = Some(Socks5Proxy {
+        address: proxy_addr.to_string(),
+        credentials: Some(("user".to_string(), "secret".to_string())),
+    });
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    let (username, password, target) = proxy.await;
+    assert_eq!(username, b"user");
+    assert_eq!(password, b"secret");
+    assert_eq!(target, remote_addr.parse().unwrap());
+
+    let (mut remote_stream, peer) = remote.accept().await.unwrap();
+    assert_eq!(peer.ip(), std::net::Ipv4Addr::LOCALHOST);
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    remote_stream.write_all(b"proxied").await.unwrap();
+
+    let mut received = [0u8; 7];
+    client.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"proxied");
+}
+
+#[test_log::test(tokio::test)]
+async fn verifying_clients_detect_corrupted_chunks() {
+    let remote_addr = "127.0.0.1:9193";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    // on a port of its own, a backoff test expects nothing to listen on the fixed one nearby
+    let mut config = Config::new("127.0.0.1:0", Remote::Tcp(remote_addr.to_string()));
+    config.framing = Framing::Line {
+        delimiter: b'\n',
+        flush_partial: false,
+    };
+    config.checksum = Some(Checksum::Crc32);
+    let broadcaster = Broadcaster::new(config);
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let listener_addr = loop {
+        if let Event::Listening { addr } = next_event(&mut events).await {
+            break addr;
+        }
+    };
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    remote_stream.write_all(b"one\n").await.unwrap();
+
+    let length = client.read_u32().await.unwrap() as usize;
+    let mut frame = (length as u32).to_be_bytes().to_vec();
+    frame.resize(4 + length + Checksum::Crc32.size(), 0);
+    client.read_exact(&mut frame[4..]).await.unwrap();
+
+    assert_eq!(Checksum::Crc32.verify(&frame), Ok(&b"one\n"[..]));
+
+    frame[5] ^= 0x20;
+    assert!(matches!(
+        Checksum::Crc32.verify(&frame),
+        Err(IntegrityError::Mismatch { .. })
+    ));
+}
+
+#[test]
+fn sealed_frames_verify_only_when_whole() {
+    let frame = Checksum::Xxhash.seal(b"payload"); // <- function under test
+
+    assert_eq!(frame.len(), 4 + 7 + 8);
+    assert_eq!(Checksum::Xxhash.verify(&frame), Ok(&b"payload"[..]));
+    assert_eq!(
+        Checksum::Xxhash.verify(&frame[..frame.len() - 1]),
+        Err(IntegrityError::Truncated)
+    );
+    assert_eq!(Checksum::Xxhash.verify(&[]), Err(IntegrityError::Truncated));
+}
+
+async fn http_get(addr: &str, path: &str) -> String {
+    let mut http = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    http.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    http.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[test_log::test(tokio::test)]
+async fn health_follows_the_connection_to_the_remote() {
+    let listener_addr = "127.0.0.1:9194";
+    let remote_addr = "127.0.0.1:9195";
+    let metrics_addr = "127.0.0.1:9196";
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.metrics_addr = Some(metrics_addr.to_string());
+    config.backoff.initial = Duration::from_millis(50);
+    config.backoff.max = Duration::from_millis(50);
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // nothing to connect to yet
+    let response = http_get(metrics_addr, "/healthz").await;
+    assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+    let (remote_stream, _) = remote.accept().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let response = http_get(metrics_addr, "/healthz").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+    // back to reconnecting
+    drop(remote_stream);
+    drop(remote);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let response = http_get(metrics_addr, "/healthz").await;
+    assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+}
+
+#[test_log::test(tokio::test)]
+async fn remote_is_recycled_on_schedule_keeping_the_clients() {
+    let listener_addr = "127.0.0.1:9197";
+    let remote_addr = "127.0.0.1:9198";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.remote_max_lifetime = Some(Duration::from_millis(500));
+    // a deliberate refresh, done even without reconnection
+    config.reconnect = false;
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    let (mut first, _) = remote.accept().await.unwrap();
+    let accepted_at = tokio::time::Instant::now();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    first.write_all(b"old").await.unwrap();
+
+    let (mut second, _) = tokio::time::timeout(Duration::from_secs(2), remote.accept())
+        .await
+        .expect("remote was not recycled")
+        .unwrap();
+    assert!(accepted_at.elapsed() >= Duration::from_millis(500));
+
+    // the old connection is closed, the new one takes over for the same client
+    let mut rest = Vec::new();
+    first.read_to_end(&mut rest).await.unwrap();
+    second.write_all(b"new").await.unwrap();
+
+    let mut received = [0u8; 6];
+    client.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"oldnew");
+}
+
+#[test_log::test(tokio::test)]
+async fn remote_reads_keep_going_while_a_client_is_blocked() {
+    let listener_addr = "127.0.0.1:9199";
+    let remote_addr = "127.0.0.1:9200";
+    const TOTAL: usize = 32 * 1024 * 1024;
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    // the blocked client is neither dropped nor timed out, and nobody lags behind
+    config.drop_policy = DropPolicy::Oldest;
+    config.write_timeout = Duration::from_secs(60);
+    config.broadcast_capacity = 16 * 1024;
+    config.client_queue_size = 16 * 1024;
+    config.reconnect = false;
+    tokio::spawn(Broadcaster::new(config).run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let _blocked = TcpStream::connect(listener_addr).await.unwrap();
+    let mut reader = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let reading = tokio::spawn(async move {
+        let mut received = 0;
+        let mut buffer = vec![0u8; 64 * 1024];
+        while received < TOTAL {
+            received += reader.read(&mut buffer).await.unwrap();
+        }
+        received
+    });
+
+    // far more than the socket buffers of the blocked client can hold
+    let chunk = vec![7u8; 64 * 1024];
+    let writing = async {
+        for _ in 0..TOTAL / chunk.len() {
+            remote_stream.write_all(&chunk).await.unwrap();
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(20), writing)
+        .await
+        .expect("remote reads were held up by the blocked client");
+
+    let received = tokio::time::timeout(Duration::from_secs(20), reading)
+        .await
+        .expect("reading client did not get everything")
+        .unwrap();
+    assert_eq!(received, TOTAL);
+}
+
+#[test_log::test(tokio::test)]
+async fn small_reads_are_coalesced_into_fewer_chunks() {
+    let listener_addr = "127.0.0.1:9201";
+    let remote_addr = "127.0.0.1:9202";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.coalesce = Some(Coalesce {
+        max_delay: Duration::from_millis(50),
+        max_bytes: 64 * 1024,
+    });
+    config.reconnect = false;
+    let broadcaster = Broadcaster::new(config);
+    let mut events = broadcaster.events();
+    let handle = tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    remote_stream.set_nodelay(true).unwrap();
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for i in 0..100u8 {
+        remote_stream.write_all(&[i; 10]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    // what is still batched goes out when the remote closes
+    drop(remote_stream);
+
+    let mut received = Vec::new();
+    client.read_to_end(&mut received).await.unwrap();
+    let sent: Vec<u8> = (0..100u8).flat_map(|i| [i; 10]).collect();
+    assert_eq!(received, sent);
+
+    handle.await.unwrap().unwrap();
+
+    let mut chunks = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if let Event::ChunkBroadcast { len, .. } = event {
+            chunks.push(len);
+        }
+    }
+    assert_eq!(chunks.iter().sum::<usize>(), 1000);
+    assert!(chunks.len() < 20, "{} chunks", chunks.len());
+}
+
+#[test_log::test(tokio::test)]
+async fn proxy_protocol_header_gives_the_consumer_address() {
+    let listener_addr = "127.0.0.1:9232";
+    let remote_addr = "127.0.0.1:9233";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    // the load balancer is local, only the consumers behind it are allowed
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .proxy_protocol(true)
+        .allow_cidr("203.0.113.0/24")
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    client
+        .write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 56324 9232\r\n")
+        .await
+        .unwrap();
+
+    let addr = loop {
+        if let Event::ClientConnected { addr } = next_event(&mut events).await {
+            break addr;
+        }
+    };
+    assert_eq!(addr, "203.0.113.7:56324".parse().unwrap());
+
+    remote_stream.write_all(b"hello").await.unwrap();
+
+    let mut received = [0u8; 5];
+    client.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"hello");
+}
+
+#[test_log::test(tokio::test)]
+async fn proxy_protocol_v2_addresses_are_checked_against_the_access_rules() {
+    let listener_addr = "127.0.0.1:9234";
+    let remote_addr = "127.0.0.1:9235";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .proxy_protocol(true)
+        .deny_cidr("10.0.0.0/8")
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+
+    // PROXY over TCP4, from 10.1.2.3:4321 to 127.0.0.1:9234
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+    header.extend([10, 1, 2, 3, 127, 0, 0, 1]);
+    header.extend(4321u16.to_be_bytes());
+    header.extend(9234u16.to_be_bytes());
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    client.write_all(&header).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    remote_stream.write_all(b"secret").await.unwrap();
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+        .await
+        .expect("denied consumer was not closed")
+        .unwrap();
+    assert!(received.is_empty());
+}
+
+#[test_log::test(tokio::test)]
+async fn remotes_are_not_read_until_enough_clients_connect() {
+    let listener_addr = "127.0.0.1:9236";
+    let remote_addr = "127.0.0.1:9237";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let mut config = Config::new(listener_addr, Remote::Tcp(remote_addr.to_string()));
+    config.min_clients = 1;
+    let broadcaster = Broadcaster::new(config);
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    remote_stream.write_all(b"early").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+
+    // read only once the client is there, so it gets what was sent before
+    let mut received = [0u8; 5];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .expect("remote was not read once the client connected")
+        .unwrap();
+    assert_eq!(&received, b"early");
+
+    let mut seen = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        match event {
+            Event::ClientConnected { .. } => seen.push("connected"),
+            Event::ChunkBroadcast { .. } => seen.push("broadcast"),
+            _ => {}
+        }
+    }
+    assert_eq!(seen, ["connected", "broadcast"]);
+}
+
+#[test_log::test(tokio::test)]
+async fn connecting_to_itself_is_refused_up_front() {
+    for (local, remote) in [
+        ("127.0.0.1:9238", "127.0.0.1:9238"),
+        ("0.0.0.0:9239", "127.0.0.1:9239"),
+    ] {
+        let broadcaster = Broadcaster::builder()
+            .local(local)
+            .remote(remote)
+            .build()
+            .unwrap();
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(2),
+            broadcaster.run(CancellationToken::new()), // <- function under test
+        )
+        .await
+        .expect("connecting to itself was not refused");
+
+        let Err(e @ BroadcastError::SelfConnect { .. }) = result else {
+            panic!("expected a self connect error, got {result:?}");
+        };
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "remote {remote} is the local address {local} consumers connect to, the \
+                 broadcaster would connect to itself"
+            )
+        );
+    }
+}
+
+/// Log lines written while it is the default subscriber of the thread, with the tasks of a
+/// current thread runtime.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    fn lines(&self) -> Vec<String> {
+        let logs = self.0.lock().unwrap();
+        String::from_utf8_lossy(&logs)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn stats_lines_are_logged_periodically() {
+    let listener_addr = "127.0.0.1:9240";
+    let remote_addr = "127.0.0.1:9241";
+
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .finish();
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .stats_interval(Duration::from_millis(300))
+        .build()
+        .unwrap();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut received = vec![0u8; 10 * 1000];
+    let reading = async { client.read_exact(&mut received).await.unwrap() };
+    let writing = async {
+        for _ in 0..10 {
+            remote_stream.write_all(&[7; 1000]).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    };
+    tokio::join!(reading, writing);
+    tokio::time::sleep(Duration::from_millis(400)).await;
+
+    let stats: Vec<String> = logs
+        .lines()
+        .into_iter()
+        .filter(|line| line.contains(" stats: "))
+        .collect();
+    assert!(!stats.is_empty(), "no stats logged");
+
+    // the bytes read and written show in one of them at least, as a rate over the interval
+    let rates = |line: &str| -> Option<(u64, u64)> {
+        let summary = line.split(" stats: ").nth(1)?;
+        let numbers: Vec<u64> = summary
+            .split(' ')
+            .filter_map(|word| word.parse().ok())
+            .collect();
+        Some((numbers[1], numbers[2]))
+    };
+    assert!(
+        stats
+            .iter()
+            .filter_map(|line| rates(line))
+            .any(|(bytes_in, bytes_out)| { bytes_in > 0 && bytes_out > 0 && bytes_in <= 100_000 }),
+        "{stats:?}"
+    );
+    assert!(
+        stats.iter().any(|line| line.contains("1 clients")),
+        "{stats:?}"
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn half_closed_clients_still_get_the_broadcast() {
+    for (bidirectional, listener_addr, remote_addr) in [
+        (false, "127.0.0.1:9242", "127.0.0.1:9243"),
+        (true, "127.0.0.1:9244", "127.0.0.1:9245"),
+    ] {
+        let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+        let broadcaster = Broadcaster::builder()
+            .local(listener_addr)
+            .remote(remote_addr)
+            .bidirectional(bidirectional)
+            .build()
+            .unwrap();
+        tokio::spawn(broadcaster.clone().run(CancellationToken::new())); // <- function under test
+
+        let (mut remote_stream, _) = remote.accept().await.unwrap();
+        let mut client = TcpStream::connect(listener_addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        client.shutdown().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        for message in [b"first", b"after"] {
+            remote_stream.write_all(message).await.unwrap();
+
+            let mut received = [0u8; 5];
+            tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+                .await
+                .expect("half closed client got nothing")
+                .unwrap();
+            assert_eq!(&received, message);
+        }
+
+        assert_eq!(broadcaster.metrics().clients_connected(), 1);
+        assert_eq!(broadcaster.metrics().clients_dropped(), 0);
+    }
+}
+
+#[cfg(feature = "http-remote")]
+#[test_log::test(tokio::test)]
+async fn server_sent_events_are_relayed_to_tcp_clients() {
+    use tokio::io::AsyncBufReadExt;
+
+    let listener_addr = "127.0.0.1:9246";
+    let remote_addr = "127.0.0.1:9247";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(format!("http-sse://{remote_addr}/events"))
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for _ in 0..2 {
+        let (remote_stream, _) = remote.accept().await.unwrap();
+        let mut remote_stream = tokio::io::BufReader::new(remote_stream);
+
+        let mut request = Vec::new();
+        loop {
+            let mut line = String::new();
+            remote_stream.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            request.push(line);
+        }
+        assert_eq!(request[0], "GET /events HTTP/1.1\r\n");
+
+        // a comment, then events in chunks split halfway through one
+        let body: &[&[u8]] = &[b": hi\n\ndata: hello\n\ndata: wor", b"ld\r\n\r\n"];
+        let mut response =
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\r\n"
+                .to_vec();
+        for chunk in body {
+            response.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+            response.extend(*chunk);
+            response.extend(b"\r\n");
+        }
+        response.extend(b"0\r\n\r\n");
+        remote_stream.write_all(&response).await.unwrap();
+
+        let mut received = [0u8; 12];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+            .await
+            .expect("events were not relayed")
+            .unwrap();
+        assert_eq!(&received, b"hello\nworld\n");
+
+        // the stream ending gets it requested again
+    }
+}
+
+/// Has a client fall behind a broadcast channel of two chunks, a burst of lines published at once,
+/// then sends a last line, returns what the client got and the lines logged.
+async fn lag_behind(
+    lag_policy: LagPolicy,
+    listener_addr: &str,
+    remote_addr: &str,
+) -> (Vec<u8>, u64, Vec<String>) {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .finish();
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .framing(Framing::Line {
+            delimiter: b'\n',
+            flush_partial: false,
+        })
+        .broadcast_capacity(2)
+        .drop_policy(DropPolicy::Oldest)
+        .lag_policy(lag_policy)
+        .reconnect(false)
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.clone().run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // every line is published before the client gets to take any
+    let burst: String = (0..100).map(|i| format!("{i}\n")).collect();
+    remote_stream.write_all(burst.as_bytes()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    remote_stream.write_all(b"end\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(remote_stream);
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+        .await
+        .expect("client was not closed")
+        .unwrap();
+
+    (
+        received,
+        broadcaster.metrics().chunks_lagged(),
+        logs.lines(),
+    )
+}
+
+#[tokio::test]
+async fn lagging_clients_resync_with_the_gap_logged() {
+    let (received, lagged, logs) =
+        lag_behind(LagPolicy::Resync, "127.0.0.1:9248", "127.0.0.1:9249").await;
+
+    assert!(received.ends_with(b"end\n"), "{received:?}");
+    assert!(received.len() < 100 * 3, "nothing was skipped");
+    assert!(lagged > 0);
+    assert!(
+        logs.iter()
+            .any(|line| line.contains("chunks behind, skipping them")),
+        "{logs:?}"
+    );
+}
+
+#[tokio::test]
+async fn lagging_clients_are_disconnected() {
+    let (received, lagged, logs) =
+        lag_behind(LagPolicy::Disconnect, "127.0.0.1:9250", "127.0.0.1:9251").await;
+
+    assert!(!received.ends_with(b"end\n"), "{received:?}");
+    assert!(lagged > 0);
+    assert!(
+        logs.iter()
+            .any(|line| line.contains("chunks behind, dropping receiver")),
+        "{logs:?}"
+    );
+}
+
+#[tokio::test]
+async fn lagging_clients_are_only_counted() {
+    let (received, lagged, logs) =
+        lag_behind(LagPolicy::CountOnly, "127.0.0.1:9252", "127.0.0.1:9253").await;
+
+    assert!(received.ends_with(b"end\n"), "{received:?}");
+    assert!(lagged > 0);
+    assert!(
+        !logs.iter().any(|line| line.contains("chunks behind")),
+        "{logs:?}"
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn first_byte_latency_is_recorded_for_each_client() {
+    let listener_addr = "127.0.0.1:9254";
+    let remote_addr = "127.0.0.1:9255";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.clone().run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+    assert_eq!(broadcaster.metrics().first_byte_count(), 0);
+
+    // the client waits for the remote to send anything
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    remote_stream.write_all(b"hello").await.unwrap();
+    let mut received = [0u8; 5];
+    client.read_exact(&mut received).await.unwrap();
+
+    // only the first write counts
+    remote_stream.write_all(b"world").await.unwrap();
+    client.read_exact(&mut received).await.unwrap();
+
+    let metrics = broadcaster.metrics();
+    assert_eq!(metrics.first_byte_count(), 1);
+    let latency = metrics.first_byte_sum();
+    assert!(
+        latency >= Duration::from_millis(150) && latency < Duration::from_secs(2),
+        "{latency:?}"
+    );
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("# TYPE tcp_broadcast_first_byte_seconds histogram\n"));
+    assert!(rendered.contains("tcp_broadcast_first_byte_seconds_bucket{le=\"0.1\"} 0\n"));
+    assert!(rendered.contains("tcp_broadcast_first_byte_seconds_bucket{le=\"+Inf\"} 1\n"));
+    assert!(rendered.contains("tcp_broadcast_first_byte_seconds_count 1\n"));
+}
+
+#[test_log::test]
+fn oversized_length_prefixes_fail_before_anything_is_buffered() {
+    let framing = Framing::LengthPrefixed {
+        width: 4,
+        endian: Endian::Big,
+    };
+    let mut decoder = framing.decoder(1024); // <- function under test
+
+    // almost 4 GiB announced, a few bytes of it sent
+    let mut src = BytesMut::from(&b"\xff\xff\xff\xf0payload"[..]);
+    let error = decoder.decode(&mut src).unwrap_err();
+
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("1024 bytes"), "{error}");
+    assert!(src.capacity() < 1024, "{} bytes reserved", src.capacity());
+}
+
+#[test_log::test(tokio::test)]
+async fn oversized_messages_get_the_remote_reconnected() {
+    let listener_addr = "127.0.0.1:9256";
+    let remote_addr = "127.0.0.1:9257";
+
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local(listener_addr)
+        .remote(remote_addr)
+        .framing(Framing::LengthPrefixed {
+            width: 4,
+            endian: Endian::Big,
+        })
+        .max_message_size(1024)
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.clone().run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // the connection is closed rather than waiting for the rest of the message
+    remote_stream
+        .write_all(b"\xff\xff\xff\xf0payload")
+        .await
+        .unwrap();
+    let mut rest = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), remote_stream.read_to_end(&mut rest))
+        .await
+        .expect("remote connection was not closed")
+        .unwrap();
+
+    let (mut remote_stream, _) = tokio::time::timeout(Duration::from_secs(5), remote.accept())
+        .await
+        .expect("remote was not reconnected")
+        .unwrap();
+    remote_stream
+        .write_all(b"\x00\x00\x00\x02ok")
+        .await
+        .unwrap();
+
+    let mut received = [0u8; 6];
+    client.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"\x00\x00\x00\x02ok");
+    assert_eq!(broadcaster.metrics().remote_reconnects(), 1);
+}
+
+/// Has the remote send `early` before any client connects to a broadcaster built by `builder` and
+/// `late` once one did, returns what that client got.
+async fn first_client_gets(
+    builder: BroadcasterBuilder,
+    listener_addr: &str,
+    remote_addr: &str,
+) -> Vec<u8> {
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = builder
+        .local(listener_addr)
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.clone().run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    remote_stream.write_all(b"early").await.unwrap();
+    while broadcaster.metrics().bytes_received() < 5 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    remote_stream.write_all(b"late").await.unwrap();
+
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 64];
+    while !received.ends_with(b"late") {
+        let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buffer))
+            .await
+            .expect("client did not get the late bytes")
+            .unwrap();
+        assert_ne!(n, 0, "client closed after {received:?}");
+        received.extend_from_slice(&buffer[..n]);
+    }
+    received
+}
+
+#[test_log::test(tokio::test)]
+async fn bytes_read_without_clients_are_discarded() {
+    let received = first_client_gets(
+        Broadcaster::builder().no_clients_policy(NoClientsPolicy::Discard),
+        "127.0.0.1:9258",
+        "127.0.0.1:9259",
+    )
+    .await;
+
+    assert_eq!(received, b"late");
+}
+
+#[test_log::test(tokio::test)]
+async fn bytes_read_without_clients_are_kept_for_the_first_one() {
+    let received = first_client_gets(
+        Broadcaster::builder().no_clients_policy(NoClientsPolicy::Buffer),
+        "127.0.0.1:9260",
+        "127.0.0.1:9261",
+    )
+    .await;
+    assert_eq!(received, b"earlylate");
+
+    // only the most recent bytes past the bound
+    let received = first_client_gets(
+        Broadcaster::builder()
+            .no_clients_policy(NoClientsPolicy::Buffer)
+            .no_clients_buffer(4),
+        "127.0.0.1:9262",
+        "127.0.0.1:9263",
+    )
+    .await;
+    assert_eq!(received, b"arlylate");
+}
+
+#[test_log::test(tokio::test)]
+async fn bytes_read_without_clients_are_kept_with_a_tee_subscribed() {
+    let tee_path =
+        std::env::temp_dir().join(format!("tcp-broadcast-{}-buffer-tee", std::process::id()));
+    let _ = std::fs::remove_file(&tee_path);
+
+    let received = first_client_gets(
+        Broadcaster::builder()
+            .no_clients_policy(NoClientsPolicy::Buffer)
+            .tee_file(&tee_path),
+        "127.0.0.1:9291",
+        "127.0.0.1:9292",
+    )
+    .await;
+    assert_eq!(received, b"earlylate");
+
+    let _ = std::fs::remove_file(&tee_path);
+}
+
+#[test_log::test(tokio::test)]
+async fn bytes_read_without_clients_are_kept_for_the_first_turn() {
+    let received = first_client_gets(
+        Broadcaster::builder()
+            .no_clients_policy(NoClientsPolicy::Buffer)
+            .distribution(Distribution::RoundRobin),
+        "127.0.0.1:9293",
+        "127.0.0.1:9294",
+    )
+    .await;
+    assert_eq!(received, b"earlylate");
+}
+
+#[test_log::test(tokio::test)]
+async fn auto_buffer_size_follows_the_socket_receive_buffer() {
+    let listener = TcpListener::bind("127.0.0.1:9264").await.unwrap();
+    let stream = TcpStream::connect("127.0.0.1:9264").await.unwrap();
+    socket2::SockRef::from(&stream)
+        .set_recv_buffer_size(256 * 1024)
+        .unwrap();
+    let receive_buffer = socket2::SockRef::from(&stream).recv_buffer_size().unwrap();
+    drop(listener);
+
+    let mut config = Config::new("127.0.0.1:9265", Remote::Tcp("127.0.0.1:9264".to_string()));
+    config.buffer_size = 4096;
+
+    // fixed unless asked otherwise
+    let socket = Some(socket2::SockRef::from(&stream));
+    assert_eq!(producer::read_buffer_size(&config, socket), 4096); // <- function under test
+
+    config.auto_buffer_size = true;
+    let socket = Some(socket2::SockRef::from(&stream));
+    assert_eq!(producer::read_buffer_size(&config, socket), receive_buffer);
+    assert_ne!(receive_buffer, 4096);
+
+    // the fixed size is the fallback without a socket
+    assert_eq!(producer::read_buffer_size(&config, None), 4096);
+}
+
+#[test_log::test(tokio::test)]
+async fn max_bytes_shuts_down_once_relayed() {
+    let remote = TcpListener::bind("127.0.0.1:9266").await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local("127.0.0.1:9267")
+        .remote("127.0.0.1:9266")
+        .max_bytes(10)
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    let running = tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let mut client = TcpStream::connect("127.0.0.1:9267").await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // the remote stays connected, and would be reconnected to otherwise
+    remote_stream
+        .write_all(b"0123456789abcdefghijklmno")
+        .await
+        .unwrap();
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+        .await
+        .expect("client was not closed")
+        .unwrap();
+    assert_eq!(received, b"0123456789");
+
+    let result = tokio::time::timeout(Duration::from_secs(5), running)
+        .await
+        .expect("broadcaster did not shut down")
+        .unwrap();
+    assert!(result.is_ok(), "{result:?}");
+}
+
+/// Connects a client to a broadcaster built by `builder`, returns its address and the lines logged
+/// meanwhile.
+async fn accepted_client_logs(
+    builder: BroadcasterBuilder,
+    listener_addr: &str,
+    remote_addr: &str,
+) -> (std::net::SocketAddr, Vec<String>) {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .finish();
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let _remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = builder
+        .local(listener_addr)
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    let cancel = CancellationToken::new();
+    tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let client = TcpStream::connect(listener_addr).await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    cancel.cancel();
+
+    (client.local_addr().unwrap(), logs.lines())
+}
+
+#[tokio::test]
+async fn accepted_clients_are_logged_with_their_address() {
+    let (addr, logs) =
+        accepted_client_logs(Broadcaster::builder(), "127.0.0.1:9268", "127.0.0.1:9269").await;
+
+    let accepted = format!("accepted client {addr}");
+    assert!(
+        logs.iter().any(|line| line.ends_with(&accepted)),
+        "{logs:?}"
+    );
+}
+
+#[tokio::test]
+async fn accepted_clients_are_logged_with_their_name() {
+    let lookups = Arc::new(AtomicUsize::new(0));
+    let resolver = {
+        let lookups = lookups.clone();
+        move |ip: std::net::IpAddr| {
+            lookups.fetch_add(1, Ordering::Relaxed);
+            assert!(ip.is_loopback());
+            Ok("stub.example".to_string())
+        }
+    };
+
+    let builder = Broadcaster::builder()
+        .reverse_dns(true)
+        .reverse_resolver(resolver);
+    let (addr, logs) = accepted_client_logs(builder, "127.0.0.1:9270", "127.0.0.1:9271").await;
+
+    let accepted = format!("accepted client {addr} (stub.example)");
+    assert!(
+        logs.iter().any(|line| line.ends_with(&accepted)),
+        "{logs:?}"
+    );
+    assert_eq!(lookups.load(Ordering::Relaxed), 1);
+
+    // a failed lookup leaves the address alone
+    let builder = Broadcaster::builder()
+        .reverse_dns(true)
+        .reverse_resolver(|_| Err(std::io::Error::other("no name")));
+    let (addr, logs) = accepted_client_logs(builder, "127.0.0.1:9272", "127.0.0.1:9273").await;
+
+    let accepted = format!("accepted client {addr}");
+    assert!(
+        logs.iter().any(|line| line.ends_with(&accepted)),
+        "{logs:?}"
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn broadcasts_from_an_injected_source() {
+    let (mut source, read_side) = tokio::io::duplex(64);
+    let config = Config {
+        local: "127.0.0.1:9274".to_string(),
+        ..Config::default()
+    };
+
+    let broadcaster = Broadcaster::from_source(read_side, config); // <- function under test
+    let mut events = broadcaster.events();
+    let running = tokio::spawn(broadcaster.clone().run(CancellationToken::new()));
+
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let mut client = TcpStream::connect("127.0.0.1:9274").await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    source.write_all(b"in memory").await.unwrap();
+    drop(source);
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+        .await
+        .expect("client was not closed")
+        .unwrap();
+    assert_eq!(received, b"in memory");
+
+    let result = running.await.unwrap();
+    assert!(result.is_ok(), "{result:?}");
+
+    // the source is gone with the first run
+    assert!(broadcaster.run(CancellationToken::new()).await.is_err());
+}
+
+#[test_log::test(tokio::test)]
+async fn replays_are_staggered_across_clients_connecting_at_once() {
+    const STAGGER: Duration = Duration::from_millis(200);
+
+    let remote = TcpListener::bind("127.0.0.1:9275").await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local("127.0.0.1:9276")
+        .remote("127.0.0.1:9275")
+        .replay_bytes(64)
+        .replay_stagger(STAGGER)
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    let cancel = CancellationToken::new();
+    tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+
+    // broadcast before the clients connect, so it is in the replay history
+    remote_stream.write_all(b"history").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let start = std::time::Instant::now();
+    let replays = (0..3).map(|_| async move {
+        let mut client = TcpStream::connect("127.0.0.1:9276").await.unwrap();
+        let mut received = [0u8; 7];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&received, b"history");
+        start.elapsed()
+    });
+    let mut replayed_after = futures_util::future::join_all(replays).await;
+    replayed_after.sort();
+    cancel.cancel();
+
+    // the first one right away, the others a turn after each other
+    assert!(replayed_after[0] < STAGGER, "{replayed_after:?}");
+    assert!(
+        replayed_after[1] - replayed_after[0] >= STAGGER - Duration::from_millis(50),
+        "{replayed_after:?}"
+    );
+    assert!(
+        replayed_after[2] - replayed_after[1] >= STAGGER - Duration::from_millis(50),
+        "{replayed_after:?}"
+    );
+}
+
+/// Publishes a chunk to a consumer writing to `writer`, returns how it ended and the hub.
+async fn disconnect_with(
+    writer: tokio::io::DuplexStream,
+    write_timeout: Duration,
+) -> (ClientStats, Hub) {
+    let hub = Hub::new(16, 0);
+
+    let handle = tokio::spawn(tx_to_writer(
+        writer,
+        hub.clone(),
+        ClientOptions {
+            write_timeout,
+            ..ClientOptions::default()
+        },
+        CancellationToken::new(),
+    )); // <- function under test
+
+    // give `tx_to_writer` a break to subscribe
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    hub.publish(Bytes::from_static(b"more than the duplex takes"))
+        .unwrap();
+
+    let stats = tokio::time::timeout(Duration::from_secs(2), handle)
+        .await
+        .expect("consumer was not dropped")
+        .unwrap();
+    (stats, hub)
+}
+
+#[test_log::test(tokio::test)]
+async fn write_errors_are_recorded_as_such() {
+    let (reader, writer) = tokio::io::duplex(8);
+    drop(reader);
+
+    let (stats, hub) = disconnect_with(writer, Duration::from_secs(5)).await;
+
+    assert_eq!(stats.reason, DisconnectReason::WriteError);
+    let metrics = hub.metrics();
+    assert_eq!(metrics.disconnects(DisconnectReason::WriteError), 1);
+    assert_eq!(metrics.disconnects(DisconnectReason::WriteTimeout), 0);
+    assert_eq!(metrics.clients_dropped(), 1);
+    assert!(metrics
+        .render()
+        .contains("tcp_broadcast_client_disconnects_total{reason=\"write_error\"} 1\n"));
+}
+
+#[test_log::test(tokio::test)]
+async fn write_timeouts_are_recorded_as_such() {
+    // never read, so the chunk never fits
+    let (_reader, writer) = tokio::io::duplex(8);
+
+    let (stats, hub) = disconnect_with(writer, Duration::from_millis(200)).await;
+
+    assert_eq!(stats.reason, DisconnectReason::WriteTimeout);
+    let metrics = hub.metrics();
+    assert_eq!(metrics.disconnects(DisconnectReason::WriteTimeout), 1);
+    assert_eq!(metrics.disconnects(DisconnectReason::WriteError), 0);
+    assert!(metrics
+        .render()
+        .contains("tcp_broadcast_client_disconnects_total{reason=\"write_timeout\"} 1\n"));
+}
+
+#[test_log::test(tokio::test)]
+async fn websocket_closes_are_recorded_as_such() {
+    let remote = TcpListener::bind("127.0.0.1:9295").await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local("127.0.0.1:9296")
+        .remote("127.0.0.1:9295")
+        .ws_addr("127.0.0.1:9297")
+        .build()
+        .unwrap();
+    let watched = broadcaster.clone();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (_remote_stream, _) = remote.accept().await.unwrap();
+    let (mut client, _) = tokio_tungstenite::connect_async("ws://127.0.0.1:9297")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    client.close(None).await.unwrap();
+
+    let metrics = watched.metrics();
+    let closed = async {
+        while metrics.disconnects(DisconnectReason::ClientClosed) == 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), closed)
+        .await
+        .expect("close was not recorded");
+    assert_eq!(metrics.disconnects(DisconnectReason::WriteError), 0);
+    assert!(metrics
+        .render()
+        .contains("tcp_broadcast_client_disconnects_total{reason=\"client_closed\"} 1\n"));
+}
+
+#[test_log::test(tokio::test)]
+async fn remote_connections_originate_from_the_bind_address() {
+    let remote = TcpListener::bind("127.0.0.1:9277").await.unwrap();
+    let bind_addr: std::net::IpAddr = "127.0.0.2".parse().unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local("127.0.0.1:9278")
+        .remote("127.0.0.1:9277")
+        .remote_bind_addr(bind_addr)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (_remote_stream, peer) = tokio::time::timeout(Duration::from_secs(5), remote.accept())
+        .await
+        .expect("remote was not connected to")
+        .unwrap();
+    cancel.cancel();
+
+    assert_eq!(peer.ip(), bind_addr);
+}
+
+#[test_log::test(tokio::test)]
+async fn admin_swaps_the_remote_keeping_the_clients() {
+    let first = TcpListener::bind("127.0.0.1:9279").await.unwrap();
+    let second = TcpListener::bind("127.0.0.1:9280").await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local("127.0.0.1:9281")
+        .remote("127.0.0.1:9279")
+        .admin_addr("127.0.0.1:9282")
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut first_stream, _) = first.accept().await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let mut client = TcpStream::connect("127.0.0.1:9281").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    first_stream.write_all(b"first ").await.unwrap();
+    let mut received = [0u8; 6];
+    client.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"first ");
+
+    let mut admin = tokio::io::BufReader::new(TcpStream::connect("127.0.0.1:9282").await.unwrap());
+
+    // nothing changes for a remote that cannot be
+    let reply = admin_command(&mut admin, "remote set nowhere.invalid:1").await;
+    assert!(reply[0].starts_with("error: invalid remote"), "{reply:?}");
+
+    let reply = admin_command(&mut admin, "remote set 127.0.0.1:9280").await;
+    assert_eq!(reply, ["switching to remote tcp://127.0.0.1:9280"]);
+
+    let (mut second_stream, _) = tokio::time::timeout(Duration::from_secs(5), second.accept())
+        .await
+        .expect("new remote was not connected to")
+        .unwrap();
+
+    // the first one is let go of
+    let mut rest = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), first_stream.read_to_end(&mut rest))
+        .await
+        .expect("old remote was not closed")
+        .unwrap();
+
+    second_stream.write_all(b"second").await.unwrap();
+    let mut received = [0u8; 6];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .expect("client got nothing from the new remote")
+        .unwrap();
+    assert_eq!(&received, b"second");
+}
+
+#[test_log::test(tokio::test)]
+async fn admin_swaps_publish_the_pending_batch_first() {
+    let first = TcpListener::bind("127.0.0.1:9298").await.unwrap();
+    let second = TcpListener::bind("127.0.0.1:9299").await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local("127.0.0.1:9300")
+        .remote("127.0.0.1:9298")
+        .admin_addr("127.0.0.1:9301")
+        .coalesce(Duration::from_secs(60), 1024)
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut first_stream, _) = first.accept().await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let mut client = TcpStream::connect("127.0.0.1:9300").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // held in the batch, far from full and from its deadline
+    first_stream.write_all(b"batched").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut admin = tokio::io::BufReader::new(TcpStream::connect("127.0.0.1:9301").await.unwrap());
+    let reply = admin_command(&mut admin, "remote set 127.0.0.1:9299").await;
+    assert_eq!(reply, ["switching to remote tcp://127.0.0.1:9299"]);
+    let _second_stream = second.accept().await.unwrap();
+
+    let mut received = [0u8; 7];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .expect("the batch was lost in the switch")
+        .unwrap();
+    assert_eq!(&received, b"batched");
+}
+
+#[test_log::test(tokio::test)]
+async fn admin_sessions_with_overlong_lines_are_dropped() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_addr = listener.local_addr().unwrap();
+    tokio::spawn(serve_admin(listener, Hub::new(1, 0))); // <- function under test
+
+    let mut admin = TcpStream::connect(admin_addr).await.unwrap();
+    let _ = admin.write_all(&vec![b'a'; 64 * 1024]).await;
+
+    let mut reply = Vec::new();
+    let _ = tokio::time::timeout(Duration::from_secs(5), admin.read_to_end(&mut reply))
+        .await
+        .expect("session was not dropped");
+
+    assert!(reply.is_empty(), "{:?}", String::from_utf8_lossy(&reply));
+}
+
+#[test_log::test(tokio::test)]
+async fn admin_refuses_remotes_other_than_tcp() {
+    let hub = Hub::new(1, 0);
+
+    for remote in [
+        "file:///etc/shadow",
+        "udp://127.0.0.1:9295",
+        "http-sse://127.0.0.1:9295/",
+    ] {
+        let reply = admin::run_command(&format!("remote set {remote}"), &hub).await; // <- function under test
+
+        assert!(reply.starts_with("error: invalid remote"), "{reply:?}");
+        assert!(reply.contains("unsupported protocol"), "{reply:?}");
+    }
+}
+
+/// Time a chunk sent by the remote takes to reach a client of a broadcaster built by `builder`.
+async fn delivery_time(
+    builder: BroadcasterBuilder,
+    listener_addr: &str,
+    remote_addr: &str,
+) -> Duration {
+    let remote = TcpListener::bind(remote_addr).await.unwrap();
+
+    let broadcaster = builder
+        .local(listener_addr)
+        .remote(remote_addr)
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    let cancel = CancellationToken::new();
+    tokio::spawn(broadcaster.run(cancel.clone())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let mut client = TcpStream::connect(listener_addr).await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let sent = std::time::Instant::now();
+    remote_stream.write_all(b"tick").await.unwrap();
+
+    let mut received = [0u8; 4];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .expect("client got nothing")
+        .unwrap();
+    let elapsed = sent.elapsed();
+    assert_eq!(&received, b"tick");
+
+    cancel.cancel();
+    elapsed
+}
+
+#[test_log::test(tokio::test)]
+async fn injected_delay_holds_chunks_back() {
+    let prompt = delivery_time(Broadcaster::builder(), "127.0.0.1:9284", "127.0.0.1:9283").await;
+
+    let delayed = delivery_time(
+        Broadcaster::builder()
+            .inject_delay(Duration::from_millis(300))
+            .inject_jitter(Duration::from_millis(50)),
+        "127.0.0.1:9286",
+        "127.0.0.1:9285",
+    )
+    .await;
+
+    assert!(delayed >= Duration::from_millis(300), "{delayed:?}");
+    assert!(delayed > prompt, "{delayed:?} not later than {prompt:?}");
+}
+
+#[test_log::test(tokio::test)]
+async fn reset_remotes_get_reconnected() {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .finish();
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let remote = TcpListener::bind("127.0.0.1:9287").await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local("127.0.0.1:9288")
+        .remote("127.0.0.1:9287")
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.clone().run(CancellationToken::new())); // <- function under test
+
+    let (remote_stream, _) = remote.accept().await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+    let mut client = TcpStream::connect("127.0.0.1:9288").await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // closing with a zero linger sends a reset rather than ending the stream
+    socket2::SockRef::from(&remote_stream)
+        .set_linger(Some(Duration::ZERO))
+        .unwrap();
+    drop(remote_stream);
+
+    let (mut remote_stream, _) = tokio::time::timeout(Duration::from_secs(5), remote.accept())
+        .await
+        .expect("remote was not reconnected")
+        .unwrap();
+    remote_stream.write_all(b"after reset").await.unwrap();
+
+    let mut received = [0u8; 11];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received))
+        .await
+        .expect("client got nothing from the new connection")
+        .unwrap();
+    assert_eq!(&received, b"after reset");
+    assert_eq!(broadcaster.metrics().remote_reconnects(), 1);
+
+    let lines = logs.lines();
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("(ConnectionReset)") && line.contains("reconnecting")),
+        "{lines:?}"
+    );
+}
+
+#[test]
+fn only_errors_a_new_connection_gets_over_are_transient() {
+    for kind in [
+        ErrorKind::ConnectionReset,
+        ErrorKind::BrokenPipe,
+        ErrorKind::TimedOut,
+        ErrorKind::InvalidData,
+    ] {
+        assert!(producer::transient(&kind.into()), "{kind:?}"); // <- function under test
+    }
+
+    for kind in [ErrorKind::PermissionDenied, ErrorKind::Unsupported] {
+        assert!(!producer::transient(&kind.into()), "{kind:?}");
+    }
+}
+
+/// Reader failing every read with an error of its kind.
+struct FailingRead(ErrorKind);
+
+impl tokio::io::AsyncRead for FailingRead {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+        _: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Err(self.0.into()))
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn reads_failing_while_the_remote_is_out_of_reach_get_it_reconnected() {
+    for kind in [
+        ErrorKind::HostUnreachable,
+        ErrorKind::NetworkUnreachable,
+        ErrorKind::NetworkDown,
+        ErrorKind::ConnectionRefused,
+    ] {
+        let hub = Hub::new(16, 0);
+
+        let error = reader_to_tx(FailingRead(kind), hub, MIN_BUFFER_SIZE, Framing::Raw)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), kind);
+        assert!(producer::transient(&error), "{kind:?}"); // <- function under test
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn clients_only_get_the_frames_of_their_topic() {
+    use tokio::io::AsyncBufReadExt;
+
+    let remote = TcpListener::bind("127.0.0.1:9289").await.unwrap();
+
+    let broadcaster = Broadcaster::builder()
+        .local("127.0.0.1:9290")
+        .remote("127.0.0.1:9289")
+        .framing(Framing::Line {
+            delimiter: b'\n',
+            flush_partial: false,
+        })
+        .topics(true)
+        .build()
+        .unwrap();
+    let mut events = broadcaster.events();
+    tokio::spawn(broadcaster.run(CancellationToken::new())); // <- function under test
+
+    let (mut remote_stream, _) = remote.accept().await.unwrap();
+    while !matches!(next_event(&mut events).await, Event::Listening { .. }) {}
+
+    let mut clients = Vec::new();
+    for topic in ["prices", "trades", ""] {
+        let mut client = TcpStream::connect("127.0.0.1:9290").await.unwrap();
+        client
+            .write_all(format!("{topic}\n").as_bytes())
+            .await
+            .unwrap();
+        while !matches!(next_event(&mut events).await, Event::ClientConnected { .. }) {}
+        clients.push(tokio::io::BufReader::new(client));
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    remote_stream
+        .write_all(b"prices 101.5\ntrades 7@101\nuntagged\nprices 102\ntrades_old 1\n")
+        .await
+        .unwrap();
+
+    let expected: [&[&str]; 3] = [
+        &["prices 101.5\n", "prices 102\n"],
+        &["trades 7@101\n"],
+        &[
+            "prices 101.5\n",
+            "trades 7@101\n",
+            "untagged\n",
+            "prices 102\n",
+            "trades_old 1\n",
+        ],
+    ];
+    for (client, expected) in clients.iter_mut().zip(expected) {
+        for line in expected {
+            let mut received = String::new();
+            tokio::time::timeout(Duration::from_secs(5), client.read_line(&mut received))
+                .await
+                .expect("client got nothing")
+                .unwrap();
+            assert_eq!(&received, line);
+        }
+    }
+
+    // nothing else of the first two topics was sent
+    remote_stream.write_all(b"prices 103\n").await.unwrap();
+    let mut received = String::new();
+    clients[0].read_line(&mut received).await.unwrap();
+    assert_eq!(received, "prices 103\n");
+    let mut received = [0u8; 1];
+    let more = tokio::time::timeout(Duration::from_millis(200), clients[1].read(&mut received));
+    assert!(more.await.is_err(), "the trades client got more");
+}
+
+#[test]
+fn topics_need_framing() {
+    let result = Broadcaster::builder()
+        .local("127.0.0.1:0")
+        .remote("127.0.0.1:0")
+        .topics(true)
+        .build(); // <- function under test
+
+    assert!(matches!(result, Err(BuildError::InvalidFraming(_))));
+}
@@ -0,0 +1,171 @@
+use crate::BroadcastError;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{debug, warn};
+
+/// TLS settings for the connection to a TCP remote.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteTls {
+    /// PEM file with the CAs to trust instead of the system ones
+    pub ca_file: Option<PathBuf>,
+    /// name to verify the remote certificate against, defaults to the host of the remote
+    pub sni: Option<String>,
+}
+
+/// TLS settings for the local listener consumers connect to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalTls {
+    /// PEM file with the certificate chain presented to consumers
+    pub cert_file: PathBuf,
+    /// PEM file with the private key of the certificate
+    pub key_file: PathBuf,
+}
+
+/// Builds the server side of the TLS sessions with consumers.
+pub(crate) fn local_acceptor(tls: &LocalTls) -> Result<TlsAcceptor, BroadcastError> {
+    let certs = load_certs(&tls.cert_file)?;
+    let key = load_key(&tls.key_file)?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| BroadcastError::Certificate {
+            path: tls.key_file.clone(),
+            source: io::Error::new(io::ErrorKind::InvalidData, e),
+        })?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Client side of the TLS session with the remote, built once and reused on every reconnection.
+#[derive(Clone)]
+pub(crate) struct RemoteConnector {
+    connector: TlsConnector,
+    sni: Option<String>,
+}
+
+impl RemoteConnector {
+    pub(crate) fn new(tls: &RemoteTls) -> Result<Self, BroadcastError> {
+        let roots = match &tls.ca_file {
+            Some(path) => custom_roots(path)?,
+            None => system_roots(),
+        };
+
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            sni: tls.sni.clone(),
+        })
+    }
+
+    /// Runs the TLS handshake over an already connected stream to `address`.
+    pub(crate) async fn connect(
+        &self,
+        address: &str,
+        stream: TcpStream,
+    ) -> Result<TlsStream<TcpStream>, BroadcastError> {
+        let tls_error = |source| BroadcastError::Tls {
+            address: address.to_string(),
+            source,
+        };
+
+        let name = match &self.sni {
+            Some(sni) => sni.as_str(),
+            None => address.rsplit_once(':').map_or(address, |(host, _)| host),
+        };
+        let name = name.trim_start_matches('[').trim_end_matches(']');
+
+        let server_name = ServerName::try_from(name.to_string())
+            .map_err(|e| tls_error(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+
+        debug!("TLS handshake with {address} as {name}");
+
+        self.connector
+            .connect(server_name, stream)
+            .await
+            .map_err(tls_error)
+    }
+}
+
+/// Loads every certificate of a PEM file.
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, BroadcastError> {
+    let certificate_error = |source| BroadcastError::Certificate {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let mut reader = BufReader::new(File::open(path).map_err(certificate_error)?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(certificate_error)?;
+
+    if certs.is_empty() {
+        return Err(certificate_error(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no certificates found",
+        )));
+    }
+
+    Ok(certs)
+}
+
+/// Loads the first private key of a PEM file.
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, BroadcastError> {
+    let certificate_error = |source| BroadcastError::Certificate {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let mut reader = BufReader::new(File::open(path).map_err(certificate_error)?);
+
+    rustls_pemfile::private_key(&mut reader)
+        .map_err(certificate_error)?
+        .ok_or_else(|| {
+            certificate_error(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no private key found",
+            ))
+        })
+}
+
+fn custom_roots(path: &Path) -> Result<RootCertStore, BroadcastError> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(|e| BroadcastError::Certificate {
+            path: path.to_path_buf(),
+            source: io::Error::new(io::ErrorKind::InvalidData, e),
+        })?;
+    }
+
+    Ok(roots)
+}
+
+fn system_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+
+    for e in native.errors {
+        warn!("loading system certificates: {e}");
+    }
+
+    let (added, ignored) = roots.add_parsable_certificates(native.certs);
+    debug!("loaded {added} system certificates, ignored {ignored}");
+
+    roots
+}
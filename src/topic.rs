@@ -0,0 +1,44 @@
+use crate::auth::read_line;
+use crate::{timed_out, Framing};
+use std::io::{self, Error, ErrorKind};
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio_util::bytes::Bytes;
+
+/// Longest topic a consumer can subscribe to.
+const MAX_TOPIC: usize = 255;
+
+/// What ends the topic a message starts with.
+const SEPARATOR: u8 = b' ';
+
+/// Reads the topic a TCP consumer subscribes to, the line it sends as it connects, failing if it
+/// is late or too long. An empty line subscribes it to every frame, and gives none.
+///
+/// As with the token, nothing the consumer sends after the newline is taken.
+pub(crate) async fn read_topic<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    timeout: Duration,
+) -> io::Result<Option<Bytes>> {
+    let line = tokio::time::timeout(timeout, read_line(stream, MAX_TOPIC))
+        .await
+        .map_err(|_| timed_out("subscription", timeout))??
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "topic too long"))?;
+
+    Ok((!line.is_empty()).then(|| Bytes::from(line)))
+}
+
+/// Topic a frame is tagged with: what its message, after the length prefix if any, starts with,
+/// up to the first space. Frames without a space have none, and only go to consumers subscribed
+/// to every frame.
+///
+/// With line framing, `prices 101.5\n` is of the `prices` topic, and goes out as is, topic
+/// included.
+pub(crate) fn topic_of(frame: &[u8], framing: Framing) -> Option<&[u8]> {
+    let message = match framing {
+        Framing::LengthPrefixed { width, .. } => frame.get(width..)?,
+        Framing::Raw | Framing::Line { .. } => frame,
+    };
+
+    let end = message.iter().position(|&b| b == SEPARATOR)?;
+    Some(&message[..end])
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+use std::sync::Arc;
+use tokio_util::bytes::Bytes;
+
+/// Modifies each chunk before it is broadcast, eg. to prepend a timestamp or redact it.
+///
+/// Closures taking and returning [`Bytes`] are transforms too.
+pub trait Transform: Send + Sync {
+    fn apply(&self, input: Bytes) -> Bytes;
+}
+
+impl<F> Transform for F
+where
+    F: Fn(Bytes) -> Bytes + Send + Sync,
+{
+    fn apply(&self, input: Bytes) -> Bytes {
+        self(input)
+    }
+}
+
+/// Transform leaving chunks untouched, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Identity;
+
+impl Transform for Identity {
+    fn apply(&self, input: Bytes) -> Bytes {
+        input
+    }
+}
+
+/// A transform that can be cloned and printed along the rest of the settings.
+#[derive(Clone)]
+pub(crate) struct SharedTransform(Arc<dyn Transform>);
+
+impl SharedTransform {
+    pub(crate) fn new(transform: impl Transform + 'static) -> Self {
+        Self(Arc::new(transform))
+    }
+
+    pub(crate) fn apply(&self, input: Bytes) -> Bytes {
+        self.0.apply(input)
+    }
+}
+
+impl Default for SharedTransform {
+    fn default() -> Self {
+        Self::new(Identity)
+    }
+}
+
+impl fmt::Debug for SharedTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Transform")
+    }
+}
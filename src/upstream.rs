@@ -0,0 +1,74 @@
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Mutex};
+use tokio_util::bytes::{BufMut, Bytes, BytesMut};
+use tracing::{debug, instrument};
+
+/// Number of chunks from consumers waiting to be written to the remote.
+const UPSTREAM_CAPACITY: usize = 64;
+
+/// Channel carrying what consumers write back to the remote, in bidirectional mode.
+///
+/// Each consumer has at most one chunk waiting to get in, and waiting consumers get in in the order
+/// they arrived, so a chatty consumer takes turns with the rest instead of crowding them out.
+#[derive(Debug, Clone)]
+pub(crate) struct Upstream {
+    tx: mpsc::Sender<Bytes>,
+    rx: Arc<Mutex<mpsc::Receiver<Bytes>>>,
+}
+
+impl Upstream {
+    pub(crate) fn new() -> Self {
+        let (tx, rx) = mpsc::channel(UPSTREAM_CAPACITY);
+
+        Self {
+            tx,
+            rx: Arc::new(Mutex::new(rx)),
+        }
+    }
+
+    /// Reads from a consumer into the channel, `buffer_size` bytes at most at a time, until the
+    /// consumer stops sending.
+    #[instrument(skip_all)]
+    pub(crate) async fn relay_from<R: AsyncRead + Unpin>(
+        &self,
+        mut reader: R,
+        buffer_size: usize,
+    ) -> io::Result<()> {
+        let mut buffer = BytesMut::with_capacity(buffer_size);
+
+        loop {
+            buffer.reserve(buffer_size);
+
+            let n = reader
+                .read_buf(&mut (&mut buffer).limit(buffer_size))
+                .await?;
+            if n == 0 {
+                debug!("consumer stopped sending");
+                return Ok(());
+            }
+
+            debug!("relaying {n} bytes to the remote");
+            if self.tx.send(buffer.split().freeze()).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Writes what comes through the channel to the remote, returns only if writing fails.
+    ///
+    /// Only one remote gets the data at a time, the one connected first.
+    #[instrument(skip_all)]
+    pub(crate) async fn forward_to<W: AsyncWrite + Unpin>(&self, mut writer: W) -> io::Result<()> {
+        let mut rx = self.rx.lock().await;
+
+        while let Some(data) = rx.recv().await {
+            writer.write_all(&data).await?;
+            writer.flush().await?;
+        }
+
+        // every sender is gone, there is nothing left to write
+        std::future::pending().await
+    }
+}
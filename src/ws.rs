@@ -0,0 +1,143 @@
+use crate::hub::Delivered;
+use crate::{accept_consumers, timed_out, Config, DisconnectReason, Hub};
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use std::io::{self, Error};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tokio_util::bytes::Bytes;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, instrument, warn};
+
+type Sink = SplitSink<WebSocketStream<TcpStream>, Message>;
+
+/// Handles the transmission of data from the hub to WebSocket consumers, like browsers, each
+/// chunk as a binary message.
+///
+/// WebSocket consumers follow the same rules as TCP ones, see [`accept_consumers`]. The upgrade
+/// has `config.write_timeout` to complete, consumers that take longer are dropped.
+#[instrument(skip_all)]
+pub(crate) async fn tx_to_websockets(
+    listener: TcpListener,
+    hub: Hub,
+    config: &Config,
+    cancel: CancellationToken,
+) {
+    let write_timeout = config.write_timeout;
+
+    accept_consumers(
+        listener,
+        &hub,
+        config,
+        &cancel,
+        |stream, addr, delivered| {
+            let hub = hub.clone();
+            let cancel = cancel.clone();
+            async move {
+                match serve_websocket(stream, hub, write_timeout, delivered, cancel).await {
+                    Ok(()) => info!("websocket client {addr} disconnected"),
+                    Err(e) => warn!("websocket client {addr}: {e}, dropping it"),
+                }
+            }
+        },
+    )
+    .await
+}
+
+/// Upgrades the stream and writes the history and then the live chunks to it, until the consumer
+/// leaves or `cancel` is triggered and the data pending in the channel has been sent.
+async fn serve_websocket(
+    stream: TcpStream,
+    hub: Hub,
+    write_timeout: Duration,
+    delivered: Arc<Delivered>,
+    cancel: CancellationToken,
+) -> io::Result<()> {
+    let upgrade = tokio_tungstenite::accept_async(stream);
+    let ws = tokio::time::timeout(write_timeout, upgrade)
+        .await
+        .map_err(|_| timed_out("upgrade", write_timeout))?
+        .map_err(Error::other)?;
+
+    info!("websocket client connected");
+
+    let (mut sink, mut incoming) = ws.split();
+    let (mut rx, history, _present) = hub.join();
+
+    let writer = Writer {
+        hub: &hub,
+        write_timeout,
+        delivered: &delivered,
+    };
+
+    for data in history {
+        if let Err(e) = writer.send(&mut sink, data).await {
+            hub.metrics().dropped(DisconnectReason::write_failed(&e));
+            return Err(e);
+        }
+    }
+
+    loop {
+        let data = tokio::select! {
+            _ = cancel.cancelled() => break,
+            received = rx.recv() => match received {
+                Ok(data) => data,
+                Err(e) => {
+                    hub.metrics().dropped(match e {
+                        RecvError::Lagged(_) => DisconnectReason::Lagged,
+                        RecvError::Closed => DisconnectReason::Shutdown,
+                    });
+                    return Err(Error::other(format!("when receiving from the channel: {e}")));
+                }
+            },
+            // pings are answered on their own, anything else the consumer sends is ignored
+            message = incoming.next() => match message {
+                Some(Ok(Message::Close(_))) | None => {
+                    hub.metrics().disconnected(DisconnectReason::ClientClosed);
+                    return Ok(());
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(Error::other(e)),
+            },
+        };
+
+        if let Err(e) = writer.send(&mut sink, data).await {
+            hub.metrics().dropped(DisconnectReason::write_failed(&e));
+            return Err(e);
+        }
+    }
+
+    debug!("cancelled, draining pending data");
+
+    while let Ok(data) = rx.try_recv() {
+        writer.send(&mut sink, data).await?;
+    }
+    hub.metrics().disconnected(DisconnectReason::Shutdown);
+
+    sink.close().await.map_err(Error::other)
+}
+
+/// What every message sent needs, to time it out and account for it.
+struct Writer<'a> {
+    hub: &'a Hub,
+    write_timeout: Duration,
+    delivered: &'a Delivered,
+}
+
+impl Writer<'_> {
+    async fn send(&self, sink: &mut Sink, data: Bytes) -> io::Result<()> {
+        let n = data.len();
+        tokio::time::timeout(self.write_timeout, sink.send(Message::Binary(data)))
+            .await
+            .map_err(|_| timed_out("write", self.write_timeout))?
+            .map_err(Error::other)?;
+
+        self.hub.metrics().sent(n);
+        self.delivered.sent(n);
+        Ok(())
+    }
+}